@@ -0,0 +1,164 @@
+use std::io::{self, BufReader, Read};
+
+use crate::errors::{Error, Position, Result};
+use crate::parser::Event;
+use crate::writer::Writer;
+
+
+#[cfg_attr(not(any(feature = "cbor", feature = "msgpack")), allow(dead_code))]
+pub(crate) fn io_error(e: io::Error) -> Error {
+    Error::IO(e, Position::default())
+}
+
+/// Positions in binary input have no lines, just offsets.
+pub(crate) fn position(offset: usize) -> Position {
+    Position { line: 1, column: offset + 1, offset }
+}
+
+/// Encodes bytes as base64url without padding, the way RFC 8949 converts
+/// byte strings to JSON.
+pub(crate) fn base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            result.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    result
+}
+
+/// Formats a timestamp as a number of seconds with its fraction.
+#[cfg_attr(not(any(feature = "msgpack", feature = "bson")), allow(dead_code))]
+pub(crate) fn timestamp(seconds: i64, nanoseconds: u32) -> String {
+    let total = seconds as i128 * 1_000_000_000 + nanoseconds as i128;
+    let sign = if total < 0 { "-" } else { "" };
+    let (whole, fraction) = (total.abs() / 1_000_000_000, total.abs() % 1_000_000_000);
+    if fraction == 0 {
+        return format!("{}{}", sign, whole)
+    }
+    let fraction = format!("{:09}", fraction);
+    format!("{}{}.{}", sign, whole, fraction.trim_end_matches('0'))
+}
+
+/// Buffered input keeping track of the offset.
+pub(crate) struct Input<R: Read> {
+    input: BufReader<R>,
+    pub offset: usize,
+}
+
+impl<R: Read> Input<R> {
+    pub fn new(input: R) -> Input<R> {
+        Input { input: BufReader::new(input), offset: 0 }
+    }
+
+    /// Reads a byte, or `None` at the end of the input.
+    pub fn byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {
+                    self.offset += 1;
+                    return Ok(Some(byte[0]))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::IO(e, position(self.offset))),
+            }
+        }
+    }
+
+    /// Reads a byte which has to be there.
+    #[cfg_attr(not(any(feature = "cbor", feature = "bson")), allow(dead_code))]
+    pub fn next_byte(&mut self) -> Result<u8> {
+        self.byte()?.ok_or_else(|| Error::Incomplete(position(self.offset)))
+    }
+
+    pub fn exact<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.input.read_exact(&mut bytes).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Incomplete(position(self.offset)),
+            _ => Error::IO(e, position(self.offset)),
+        })?;
+        self.offset += N;
+        Ok(bytes)
+    }
+
+    /// Reads `len` bytes, failing with `Error::StringLimit` at `start` if
+    /// there are more than `limit` of them. A huge length in broken input
+    /// doesn't make it allocate more than there is.
+    pub fn bytes(&mut self, len: u64, limit: usize, start: usize) -> Result<Vec<u8>> {
+        if len > limit as u64 {
+            return Err(Error::StringLimit(position(start)))
+        }
+        let mut result = vec![];
+        let read = (&mut self.input).take(len).read_to_end(&mut result).map_err(|e| Error::IO(e, position(self.offset)))?;
+        self.offset += read;
+        if (read as u64) < len {
+            return Err(Error::Incomplete(position(self.offset)))
+        }
+        Ok(result)
+    }
+
+    pub fn text(&mut self, len: u64, limit: usize, start: usize) -> Result<String> {
+        let bytes = self.bytes(len, limit, start)?;
+        String::from_utf8(bytes).map_err(|e| Error::Utf8(e.utf8_error(), position(start)))
+    }
+}
+
+/// Checks that events form valid documents the way `Writer` does, for
+/// writers of other formats. Unlike `Writer`, it takes any number of
+/// top-level values, and non-finite numbers.
+#[cfg_attr(not(any(feature = "cbor", feature = "msgpack")), allow(dead_code))]
+pub(crate) struct Checker {
+    writer: Writer<io::Sink>,
+    depth: usize,
+    in_string: bool,
+    /// Number of top-level values.
+    values: usize,
+}
+
+#[cfg_attr(not(any(feature = "cbor", feature = "msgpack")), allow(dead_code))]
+impl Checker {
+    pub fn new() -> Checker {
+        Checker { writer: Checker::writer(), depth: 0, in_string: false, values: 0 }
+    }
+
+    fn writer() -> Writer<io::Sink> {
+        let mut writer = Writer::new(io::sink());
+        writer.set_non_finite(true);
+        writer
+    }
+
+    /// Whether the last event was a chunk of a string.
+    #[cfg_attr(not(feature = "cbor"), allow(dead_code))]
+    pub fn in_string(&self) -> bool {
+        self.in_string
+    }
+
+    pub fn check(&mut self, event: &Event) -> Result<()> {
+        // Each top-level value is checked on its own
+        let top = self.depth == 0 && !self.in_string && !matches!(event, Event::Comment(_));
+        if top && self.values > 0 {
+            self.writer = Checker::writer();
+        }
+        self.writer.write_event(event.clone())?;
+        if top {
+            self.values += 1;
+        }
+        match event {
+            Event::StartArray | Event::StartMap => self.depth += 1,
+            Event::EndArray | Event::EndMap => self.depth -= 1,
+            Event::StringChunk(_) => self.in_string = true,
+            Event::StringEnd => self.in_string = false,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Checks that the last document is complete and that there was one.
+    pub fn finish(self) -> Result<()> {
+        self.writer.finish().map(|_| ())
+    }
+}
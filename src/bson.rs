@@ -0,0 +1,318 @@
+use std::convert::TryFrom;
+use std::io::Read;
+
+use crate::binary::{base64url, position, timestamp, Input};
+use crate::builder::Located;
+use crate::errors::{Error, Position, Result};
+use crate::parser::{Limits, OwnedEvent};
+
+
+/// Smallest document: its length and the terminating zero.
+const MIN_DOCUMENT: usize = 5;
+
+/// Largest significand of a decimal128.
+const MAX_SIGNIFICAND: u128 = 9_999_999_999_999_999_999_999_999_999_999;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Converts an IEEE 754 decimal128 to the text of a JSON number the way
+/// the BSON specification formats them, or to a non-finite float.
+fn decimal128(bits: u128) -> OwnedEvent {
+    let negative = bits >> 127 != 0;
+    match (bits >> 122) & 0x1f {
+        0x1f => return OwnedEvent::Number(f64::NAN),
+        0x1e => return OwnedEvent::Number(if negative { f64::NEG_INFINITY } else { f64::INFINITY }),
+        _ => (),
+    }
+    let (exponent, significand) = if (bits >> 125) & 0b11 == 0b11 {
+        // Such significands are all too big, which means zero
+        ((bits >> 111) & 0x3fff, 0)
+    } else {
+        ((bits >> 113) & 0x3fff, bits & ((1 << 113) - 1))
+    };
+    let significand = if significand > MAX_SIGNIFICAND { 0 } else { significand };
+    let exponent = exponent as i64 - 6176;
+    let digits = significand.to_string();
+    let adjusted = exponent + digits.len() as i64 - 1;
+    let mut result = String::from(if negative { "-" } else { "" });
+    if exponent <= 0 && adjusted >= -6 {
+        let point = digits.len() as i64 + exponent;
+        if exponent == 0 {
+            result.push_str(&digits);
+        } else if point > 0 {
+            result.push_str(&digits[..point as usize]);
+            result.push('.');
+            result.push_str(&digits[point as usize..]);
+        } else {
+            result.push_str("0.");
+            result.push_str(&"0".repeat(-point as usize));
+            result.push_str(&digits);
+        }
+    } else {
+        result.push_str(&digits[..1]);
+        if digits.len() > 1 {
+            result.push('.');
+            result.push_str(&digits[1..]);
+        }
+        result.push_str(&format!("E{}{}", if adjusted >= 0 { "+" } else { "" }, adjusted));
+    }
+    OwnedEvent::RawNumber(result)
+}
+
+struct Frame {
+    array: bool,
+    /// Offset right after the document.
+    end: usize,
+}
+
+/// Reads BSON documents producing the same events as `Parser` does for
+/// JSON, so prefixes, items and the rest work on them as well. Documents
+/// are read as they come, so a `mongodump` file of any size takes little
+/// memory. Values are converted to what JSON has:
+///
+/// - Dates become numbers of seconds since the epoch, as
+///   `Event::RawNumber`, and so do decimals.
+/// - Object ids become hex strings, and binary data becomes base64url
+///   strings without padding.
+/// - Regular expressions become strings like `/pattern/options`, and
+///   JavaScript code becomes its text, without the scope.
+/// - Internal timestamps become `Event::Uint`.
+/// - Undefined and min and max keys become null.
+///
+/// ```
+/// use ijson::builder::Builder;
+/// use ijson::bson::BsonReader;
+///
+/// // {"a": [1, 2.5], "b": true}
+/// let data = b"\x23\x00\x00\x00\x04a\x00\x17\x00\x00\x00\x100\x00\x01\x00\x00\x00\
+///              \x011\x00\x00\x00\x00\x00\x00\x00\x04\x40\x00\x08b\x00\x01\x00";
+/// let value = BsonReader::new(&data[..]).items("").next().unwrap().unwrap();
+/// assert_eq!(value.to_string(), r#"{"a":[1,2.5],"b":true}"#);
+/// ```
+pub struct BsonReader<R: Read> {
+    input: Input<R>,
+    stack: Vec<Frame>,
+    /// Type and offset of the element whose key was just read.
+    value: Option<(u8, usize)>,
+    multi: bool,
+    limits: Limits,
+    events: usize,
+    /// A top-level document has been read.
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read> BsonReader<R> {
+    /// Reads a single document, failing with `Error::AdditionalData` on
+    /// anything after it.
+    pub fn new(input: R) -> BsonReader<R> {
+        BsonReader {
+            input: Input::new(input),
+            stack: vec![],
+            value: None,
+            multi: false,
+            limits: Limits::default(),
+            events: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Reads any number of documents one after another, which is what
+    /// `mongodump` writes.
+    pub fn new_multi(input: R) -> BsonReader<R> {
+        let mut reader = BsonReader::new(input);
+        reader.multi = true;
+        reader
+    }
+
+    /// Sets limits on the input, same as `Parser::set_limits`. Numbers have
+    /// no text, so `max_number` doesn't apply.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Reads a length which isn't negative.
+    fn length(&mut self, start: usize) -> Result<usize> {
+        let length = i32::from_le_bytes(self.input.exact()?);
+        usize::try_from(length).map_err(|_| Error::Custom(format!("Invalid length {}", length), position(start)))
+    }
+
+    /// Starts a document, of which `first` is the first byte of the length
+    /// if it's been read already.
+    fn open(&mut self, array: bool, first: Option<u8>) -> Result<OwnedEvent> {
+        let start = self.input.offset - first.map_or(0, |_| 1);
+        let length = match first {
+            Some(first) => {
+                let rest: [u8; 3] = self.input.exact()?;
+                i32::from_le_bytes([first, rest[0], rest[1], rest[2]]) as i64
+            }
+            None => i32::from_le_bytes(self.input.exact()?) as i64,
+        };
+        let end = start as i64 + length;
+        let outside = self.stack.last().is_some_and(|frame| end > frame.end as i64);
+        if length < MIN_DOCUMENT as i64 || outside {
+            return Err(Error::Custom(format!("Invalid document length {}", length), position(start)))
+        }
+        if self.stack.len() >= self.limits.max_depth {
+            return Err(Error::DepthLimit(position(start)))
+        }
+        self.stack.push(Frame { array, end: end as usize });
+        Ok(if array { OwnedEvent::StartArray } else { OwnedEvent::StartMap })
+    }
+
+    /// Reads a string prefixed with its length and ending with a zero.
+    fn string(&mut self) -> Result<String> {
+        let start = self.input.offset;
+        let length = self.length(start)?;
+        if length == 0 {
+            return Err(Error::Custom("Invalid string length 0".to_string(), position(start)))
+        }
+        let result = self.input.text(length as u64 - 1, self.limits.max_string, start)?;
+        if self.input.next_byte()? != 0 {
+            return Err(Error::Unterminated(position(start)))
+        }
+        Ok(result)
+    }
+
+    /// Reads a string ending with a zero, as keys are.
+    fn cstring(&mut self) -> Result<String> {
+        let start = self.input.offset;
+        let mut bytes = vec![];
+        loop {
+            let byte = self.input.next_byte()?;
+            if byte == 0 {
+                break
+            }
+            if bytes.len() >= self.limits.max_string {
+                return Err(Error::StringLimit(position(start)))
+            }
+            bytes.push(byte);
+        }
+        String::from_utf8(bytes).map_err(|e| Error::Utf8(e.utf8_error(), position(start)))
+    }
+
+    fn element(&mut self, kind: u8, start: usize) -> Result<OwnedEvent> {
+        Ok(match kind {
+            0x01 => OwnedEvent::Number(f64::from_le_bytes(self.input.exact()?)),
+            0x02 | 0x0d | 0x0e => OwnedEvent::String(self.string()?),
+            0x03 => self.open(false, None)?,
+            0x04 => self.open(true, None)?,
+            0x05 => {
+                let length = self.length(self.input.offset)?;
+                let _subtype = self.input.next_byte()?;
+                OwnedEvent::String(base64url(&self.input.bytes(length as u64, self.limits.max_string, start)?))
+            }
+            0x06 | 0x0a | 0x7f | 0xff => OwnedEvent::Null,
+            0x07 => OwnedEvent::String(hex(&self.input.exact::<12>()?)),
+            0x08 => match self.input.next_byte()? {
+                0 => OwnedEvent::Boolean(false),
+                1 => OwnedEvent::Boolean(true),
+                byte => return Err(Error::Unknown(vec![byte], position(self.input.offset - 1))),
+            },
+            0x09 => {
+                let milliseconds = i64::from_le_bytes(self.input.exact()?);
+                let nanoseconds = milliseconds.rem_euclid(1000) as u32 * 1_000_000;
+                OwnedEvent::RawNumber(timestamp(milliseconds.div_euclid(1000), nanoseconds))
+            }
+            0x0b => {
+                let pattern = self.cstring()?;
+                OwnedEvent::String(format!("/{}/{}", pattern, self.cstring()?))
+            }
+            0x0c => {
+                let _namespace = self.string()?;
+                OwnedEvent::String(hex(&self.input.exact::<12>()?))
+            }
+            0x0f => {
+                let offset = self.input.offset;
+                let end = offset + self.length(offset)?;
+                let code = self.string()?;
+                // The scope is skipped, having nowhere to go
+                let scope = self.input.offset;
+                let length = self.length(scope)?;
+                let rest = end.checked_sub(self.input.offset).filter(|_| length >= MIN_DOCUMENT && scope + length == end);
+                match rest {
+                    Some(rest) => self.input.bytes(rest as u64, usize::MAX, start)?,
+                    None => return Err(Error::Custom("Invalid code with scope".to_string(), position(offset))),
+                };
+                OwnedEvent::String(code)
+            }
+            0x10 => OwnedEvent::Int(i32::from_le_bytes(self.input.exact()?) as i64),
+            0x11 => OwnedEvent::Uint(u64::from_le_bytes(self.input.exact()?)),
+            0x12 => OwnedEvent::Int(i64::from_le_bytes(self.input.exact()?)),
+            0x13 => decimal128(u128::from_le_bytes(self.input.exact()?)),
+            _ => return Err(Error::Unknown(vec![kind], position(start))),
+        })
+    }
+
+    fn read_event(&mut self) -> Result<Option<OwnedEvent>> {
+        if let Some((kind, start)) = self.value.take() {
+            return self.element(kind, start).map(Some)
+        }
+        let start = self.input.offset;
+        let frame = match self.stack.last() {
+            Some(frame) => frame,
+            None => {
+                let first = match self.input.byte()? {
+                    Some(byte) => byte,
+                    None if self.multi || self.started => return Ok(None),
+                    None => return Err(Error::Incomplete(position(start))),
+                };
+                if self.started && !self.multi {
+                    return Err(Error::AdditionalData(position(start)))
+                }
+                self.started = true;
+                return self.open(false, Some(first)).map(Some)
+            }
+        };
+        let (array, end) = (frame.array, frame.end);
+        if start >= end {
+            return Err(Error::Custom("Document is longer than its length".to_string(), position(start)))
+        }
+        let kind = self.input.next_byte()?;
+        if kind == 0 {
+            if self.input.offset != end {
+                return Err(Error::Custom("Document is shorter than its length".to_string(), position(start)))
+            }
+            self.stack.pop();
+            return Ok(Some(if array { OwnedEvent::EndArray } else { OwnedEvent::EndMap }))
+        }
+        // Keys of arrays are their indexes, which aren't needed
+        let key = self.cstring()?;
+        if array {
+            return self.element(kind, start).map(Some)
+        }
+        self.value = Some((kind, start));
+        Ok(Some(OwnedEvent::Key(key)))
+    }
+}
+
+impl<R: Read> Located for BsonReader<R> {
+    fn location(&self) -> Position {
+        position(self.input.offset)
+    }
+}
+
+impl<R: Read> Iterator for BsonReader<R> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let result = self.read_event().transpose();
+        match result {
+            Some(Ok(_)) => {
+                self.events += 1;
+                if self.events > self.limits.max_events {
+                    self.done = true;
+                    return Some(Err(Error::EventLimit(position(self.input.offset))))
+                }
+            }
+            _ => self.done = true,
+        }
+        result
+    }
+}
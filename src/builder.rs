@@ -1,97 +1,868 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::Write;
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::mem;
 use std::result;
 
 use rustc_serialize::json;
 use rustc_serialize::json::Json;
 use rustc_serialize::Decodable;
+#[cfg(feature = "hash")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "hash")]
+use sha2::Sha256;
 
-use ::parser::Event;
-use ::errors::Result;
+use crate::lexer::{Lexeme, raw_number_lexeme};
+use crate::ordered::OrderedJson;
+use crate::writer::{Tee, Writer};
+use crate::parser::OwnedEvent;
+use crate::errors::{Error, Position, Result};
+use crate::path::{Path, Segment, Tracker, dotted_steps};
+#[cfg(feature = "serde")]
+use crate::value::{Values, ItemsAs};
 
 
-pub trait EventIterator: Iterator<Item=Result<Event>> {}
-impl<T: Iterator<Item=Result<Event>>> EventIterator for T {}
+/// Any iterator of owned events, such as the one returned by
+/// `Parser::into_iter`.
+pub trait EventIterator: Iterator<Item=Result<OwnedEvent>> {}
+impl<T: Iterator<Item=Result<OwnedEvent>>> EventIterator for T {}
 
+/// Event streams which know where they are in the input, for errors found
+/// in their events, such as by `Deserializer`, to point there.
+pub trait Located {
+    /// Position right after the last event.
+    fn location(&self) -> Position;
+}
+
+impl<E: Located> Located for &mut E {
+    fn location(&self) -> Position {
+        (**self).location()
+    }
+}
+
+/// Selects events under a prefix, see `Builder::prefix`. Once it returns
+/// `None` it keeps doing so, even over events that would go on, which
+/// makes it end at the first error of a parser unless it's set to
+/// `OnError::Skip`.
 pub struct Prefix<E: EventIterator> {
-    reference: Vec<String>,
-    path: Vec<String>,
+    reference: Path,
+    path: Tracker,
     parser: E,
+    done: bool,
 }
 
 impl<E: EventIterator> Prefix<E> {
-    fn matches(&mut self, event: &Event) -> bool {
-        match *event {
-            Event::Key(_) | Event::EndMap | Event::EndArray => {
-                self.path.pop();
+    fn matches(&mut self, event: &OwnedEvent) -> bool {
+        self.path.enter(event);
+        let result = self.reference.matches(&self.path.steps);
+        self.path.leave(event);
+        result
+    }
+}
+
+impl<E: EventIterator + Located> Located for Prefix<E> {
+    fn location(&self) -> Position {
+        self.parser.location()
+    }
+}
+
+impl<E: EventIterator> Iterator for Prefix<E> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        while let Some(r) = self.parser.next() {
+            let event = itry!(r.map_err(|e| e.with_path(self.path.error_path())));
+            if self.matches(&event) {
+                return Some(Ok(event))
             }
-            _ => (),
         }
+        self.done = true;
+        None
+    }
+}
+
+impl<E: EventIterator> FusedIterator for Prefix<E> {}
+
+/// Selects events under several prefixes in one pass, tagging each event
+/// with the index of the matched prefix. An event matching more than one
+/// prefix is yielded once for each of them.
+pub struct MultiPrefix<E: EventIterator> {
+    references: Vec<Path>,
+    path: Tracker,
+    pending: VecDeque<(usize, OwnedEvent)>,
+    parser: E,
+}
 
-        let result = self.path.starts_with(&self.reference);
+impl<E: EventIterator> Iterator for MultiPrefix<E> {
+    type Item = Result<(usize, OwnedEvent)>;
 
-        match *event {
-            Event::Key(ref value) => self.path.push(value.clone()),
-            Event::StartMap => self.path.push("".to_owned()),
-            Event::StartArray => self.path.push("item".to_owned()),
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let event = itry!(self.parser.next()?.map_err(|e| e.with_path(self.path.error_path())));
+            self.path.enter(&event);
+            for (index, reference) in self.references.iter().enumerate() {
+                if reference.matches(&self.path.steps) {
+                    self.pending.push_back((index, event.clone()));
+                }
+            }
+            self.path.leave(&event);
+        }
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+/// Keeps values under any of several prefixes along with the containers
+/// enclosing them, dropping everything else, so the result is still a valid
+/// document. Containers are kept only if something in them is selected,
+/// except the top-level one, so arrays lose elements that weren't selected.
+pub struct Project<E: EventIterator> {
+    references: Vec<Path>,
+    path: Tracker,
+    /// For each open container not selected as a whole, whether its opening
+    /// event has been yielded.
+    open: Vec<bool>,
+    /// Events opening containers and keys held back until something under
+    /// them is selected.
+    held: Vec<OwnedEvent>,
+    ready: VecDeque<OwnedEvent>,
+    events: E,
+}
+
+impl<E: EventIterator> Project<E> {
+    /// Drops a held key whose value turned out to have nothing selected.
+    fn drop_key(&mut self) {
+        if let Some(OwnedEvent::Key(_)) = self.held.last() {
+            self.held.pop();
+        }
+    }
+
+    fn process(&mut self, event: OwnedEvent) {
+        self.path.enter(&event);
+        let selected = self.references.iter().any(|r| r.matches(&self.path.steps));
+        self.path.leave(&event);
+        if selected {
+            self.ready.extend(self.held.drain(..));
+            self.open.iter_mut().for_each(|open| *open = true);
+            self.ready.push_back(event);
+            return
+        }
+        match event {
+            OwnedEvent::StartMap | OwnedEvent::StartArray => {
+                if self.open.is_empty() {
+                    self.open.push(true);
+                    self.ready.push_back(event);
+                } else {
+                    self.open.push(false);
+                    self.held.push(event);
+                }
+            }
+            OwnedEvent::Key(_) => {
+                self.drop_key();
+                self.held.push(event);
+            }
+            OwnedEvent::EndMap | OwnedEvent::EndArray => {
+                self.drop_key();
+                if self.open.pop().unwrap_or(false) {
+                    self.ready.push_back(event);
+                } else {
+                    self.held.pop();
+                }
+            }
             _ => (),
         }
+    }
+}
 
-        result
+impl<E: EventIterator> Iterator for Project<E> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ready.is_empty() {
+            let event = itry!(self.events.next()?);
+            self.process(event);
+        }
+        self.ready.pop_front().map(Ok)
     }
 }
 
-impl<E: EventIterator> Iterator for Prefix<E> {
-    type Item = Result<Event>;
+/// What `Redact` puts in place of a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mask {
+    Null,
+    /// A fixed string.
+    Placeholder(String),
+    /// HMAC-SHA256 of the compact JSON of the value with the key, as
+    /// a string of 64 hex digits. Equal values get equal hashes, so records
+    /// can still be correlated, and they stay the same across versions.
+    /// Without the key, values can't be found by hashing guesses of them.
+    #[cfg(feature = "hash")]
+    Hash(Vec<u8>),
+}
+
+/// Feeds the output of a `Writer` to HMAC.
+#[cfg(feature = "hash")]
+struct HashWriter(Hmac<Sha256>);
+
+#[cfg(feature = "hash")]
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Replaces values at any of several prefixes with a `Mask`, passing
+/// everything else through.
+pub struct Redact<E: EventIterator> {
+    references: Vec<Path>,
+    mask: Mask,
+    path: Tracker,
+    events: E,
+}
+
+impl<E: EventIterator> Redact<E> {
+    /// Consumes the value starting with `event` and returns its replacement.
+    fn replace(&mut self, mut event: OwnedEvent) -> Result<OwnedEvent> {
+        #[cfg(feature = "hash")]
+        let mut writer = match self.mask {
+            Mask::Hash(ref key) => {
+                let hmac = Hmac::new_from_slice(key).expect("HMAC takes keys of any length");
+                Some(Writer::new(HashWriter(hmac)))
+            }
+            _ => None,
+        };
+        let mut depth = 0usize;
+        loop {
+            #[cfg(feature = "hash")]
+            if let Some(writer) = writer.as_mut() {
+                writer.write_event(event.as_event())?;
+            }
+            let done = match event {
+                OwnedEvent::StartMap | OwnedEvent::StartArray => {
+                    depth += 1;
+                    false
+                }
+                OwnedEvent::EndMap | OwnedEvent::EndArray => {
+                    depth = depth.checked_sub(1).ok_or_else(unexpected)?;
+                    depth == 0
+                }
+                OwnedEvent::Key(_) | OwnedEvent::Comment(_) | OwnedEvent::StringChunk(_) => false,
+                _ => depth == 0,
+            };
+            self.path.leave(&event);
+            if done {
+                break
+            }
+            event = self.events.next().unwrap_or_else(|| Err(unexpected()))?;
+            self.path.enter(&event);
+        }
+        #[cfg(feature = "hash")]
+        if let Some(writer) = writer {
+            let hash = writer.finish()?.0.finalize().into_bytes();
+            return Ok(OwnedEvent::String(hash.iter().map(|b| format!("{:02x}", b)).collect()))
+        }
+        Ok(match self.mask {
+            Mask::Placeholder(ref value) => OwnedEvent::String(value.clone()),
+            _ => OwnedEvent::Null,
+        })
+    }
+}
+
+impl<E: EventIterator> Iterator for Redact<E> {
+    type Item = Result<OwnedEvent>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(r) = self.parser.next() {
-            let event = itry!(r);
-            if self.matches(&event) {
-                return Some(Ok(event))
+        let event = itry!(self.events.next()?);
+        self.path.enter(&event);
+        let value = !matches!(event,
+            OwnedEvent::Key(_) | OwnedEvent::EndMap | OwnedEvent::EndArray |
+            OwnedEvent::Comment(_) | OwnedEvent::StringEnd
+        );
+        if value && self.references.iter().any(|r| self.path.at(r)) {
+            return Some(self.replace(event))
+        }
+        self.path.leave(&event);
+        Some(Ok(event))
+    }
+}
+
+/// Rewrites keys of objects, see `Builder::rename_keys`.
+pub struct RenameKeys<E: EventIterator, F: FnMut(&str) -> Option<String>> {
+    rename: F,
+    events: E,
+}
+
+impl<E: EventIterator, F: FnMut(&str) -> Option<String>> Iterator for RenameKeys<E, F> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match itry!(self.events.next()?) {
+            OwnedEvent::Key(k) => Some(Ok(OwnedEvent::Key((self.rename)(&k).unwrap_or(k)))),
+            event => Some(Ok(event)),
+        }
+    }
+}
+
+/// Yields scalars in values at a prefix with their dotted paths relative to
+/// the value, see `Builder::flat_items`.
+pub struct FlatItems<E: EventIterator> {
+    reference: Path,
+    path: Tracker,
+    events: E,
+}
+
+impl<E: EventIterator> Iterator for FlatItems<E> {
+    type Item = Result<(String, OwnedEvent)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = itry!(self.events.next()?);
+            self.path.enter(&event);
+            let scalar = !matches!(event,
+                OwnedEvent::StartMap | OwnedEvent::EndMap | OwnedEvent::StartArray | OwnedEvent::EndArray |
+                OwnedEvent::Key(_) | OwnedEvent::Comment(_) | OwnedEvent::StringEnd
+            );
+            if !scalar || !self.reference.matches(&self.path.steps) {
+                self.path.leave(&event);
+                continue
             }
+            let name = dotted_steps(&self.path.steps[self.reference.segments().len()..]);
+            let event = match event {
+                OwnedEvent::StringChunk(v) => OwnedEvent::String(itry!(join_chunks(&mut self.events, v))),
+                event => event,
+            };
+            self.path.leave(&event);
+            return Some(Ok((name, event)))
         }
-        None
     }
 }
 
-pub struct Items<E> where E: EventIterator {
+/// Builds the events of nested containers out of values with dotted paths.
+#[derive(Default)]
+struct Nester {
+    /// Open containers, whether each is an object and the step to its
+    /// current value.
+    stack: Vec<(bool, Segment)>,
+    ready: VecDeque<OwnedEvent>,
+}
+
+impl Nester {
+    fn add(&mut self, name: &str, value: OwnedEvent) -> Result<()> {
+        if matches!(value,
+            OwnedEvent::StartMap | OwnedEvent::EndMap | OwnedEvent::StartArray | OwnedEvent::EndArray |
+            OwnedEvent::Key(_) | OwnedEvent::Comment(_) | OwnedEvent::StringChunk(_) | OwnedEvent::StringEnd
+        ) {
+            return Err(unexpected())
+        }
+        let path = Path::parse(name);
+        let segments = path.segments();
+        let wildcard = |segment: &Segment| match segment {
+            Segment::Token(t) => t == "item",
+            segment => matches!(segment, Segment::AnyItem | Segment::Wildcard),
+        };
+        if segments.iter().any(wildcard) {
+            return Err(Error::Custom(format!("Can't unflatten a wildcard path {:?}", name), Position::default()))
+        }
+        let is_key = |segment: &Segment| matches!(segment, Segment::Key(_));
+        // A value can't go where the previous one or its container was,
+        // such as `a` followed by `a.b`, or `a.b` by `a` or `a.0`
+        let same = self.stack.iter().zip(segments)
+            .take_while(|((object, step), segment)| *object == is_key(segment) && step == *segment)
+            .count();
+        if same > 0 && (same == self.stack.len() || same == segments.len() || self.stack[same].0 != is_key(&segments[same])) {
+            return Err(Error::Custom(format!("Path {:?} overlaps with a previous one", name), Position::default()))
+        }
+        // Containers shared with the previous path stay open
+        let mut shared = 0;
+        while shared < self.stack.len() && shared < segments.len() && self.stack[shared].0 == is_key(&segments[shared]) {
+            shared += 1;
+            if self.stack[shared - 1].1 != segments[shared - 1] {
+                break
+            }
+        }
+        self.close_to(shared);
+        if shared > 0 {
+            let segment = &segments[shared - 1];
+            self.stack[shared - 1].1 = segment.clone();
+            if let Segment::Key(k) = segment {
+                self.ready.push_back(OwnedEvent::Key(k.clone()));
+            }
+        }
+        for segment in &segments[shared..] {
+            self.ready.push_back(if is_key(segment) { OwnedEvent::StartMap } else { OwnedEvent::StartArray });
+            self.stack.push((is_key(segment), segment.clone()));
+            if let Segment::Key(k) = segment {
+                self.ready.push_back(OwnedEvent::Key(k.clone()));
+            }
+        }
+        self.ready.push_back(value);
+        Ok(())
+    }
+
+    /// Closes containers deeper than `depth`.
+    fn close_to(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            let (object, _) = self.stack.pop().unwrap();
+            self.ready.push_back(if object { OwnedEvent::EndMap } else { OwnedEvent::EndArray });
+        }
+    }
+}
+
+/// Turns scalars with dotted paths, such as produced by
+/// `Builder::flat_items`, back into a nested document. Values under the
+/// same container must come together. Array indices only tell elements
+/// apart, the elements come in the order of the pairs. A path going into
+/// or replacing the previous value, such as `a` and then `a.b`, is an error.
+pub struct Unflatten<I> where I: Iterator<Item=Result<(String, OwnedEvent)>> {
+    pairs: I,
+    nester: Nester,
+}
+
+impl<I> Unflatten<I> where I: Iterator<Item=Result<(String, OwnedEvent)>> {
+    pub fn new(pairs: I) -> Self {
+        Unflatten {
+            pairs,
+            nester: Nester::default(),
+        }
+    }
+}
+
+impl<I> Iterator for Unflatten<I> where I: Iterator<Item=Result<(String, OwnedEvent)>> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.nester.ready.is_empty() {
+            match self.pairs.next() {
+                Some(result) => {
+                    let (name, value) = itry!(result);
+                    itry!(self.nester.add(&name, value));
+                }
+                None => {
+                    self.nester.close_to(0);
+                    return self.nester.ready.pop_front().map(Ok)
+                }
+            }
+        }
+        self.nester.ready.pop_front().map(Ok)
+    }
+}
+
+/// Turns each top-level flat object with dotted keys into a nested one,
+/// see `Builder::unflatten`.
+pub struct UnflattenObjects<E: EventIterator> {
     events: E,
+    nester: Nester,
+    key: Option<String>,
+    /// Whether a flat object is open.
+    open: bool,
+    /// Whether nothing came from the flat object yet.
+    empty: bool,
+}
+
+impl<E: EventIterator> UnflattenObjects<E> {
+    fn process(&mut self, event: OwnedEvent) -> Result<()> {
+        let not_flat = || Error::Custom("Expected a flat object".to_string(), Position::default());
+        match event {
+            OwnedEvent::Comment(_) => (),
+            OwnedEvent::StartMap if !self.open => {
+                self.open = true;
+                self.empty = true;
+            }
+            OwnedEvent::Key(k) if self.open => self.key = Some(k),
+            OwnedEvent::EndMap if self.open => {
+                self.open = false;
+                self.nester.close_to(0);
+                if self.empty {
+                    self.nester.ready.extend([OwnedEvent::StartMap, OwnedEvent::EndMap]);
+                }
+            }
+            OwnedEvent::StringChunk(v) => {
+                let value = join_chunks(&mut self.events, v)?;
+                return self.process(OwnedEvent::String(value))
+            }
+            value => {
+                let key = self.key.take().filter(|_| self.open).ok_or_else(not_flat)?;
+                self.nester.add(&key, value).map_err(|e| match e {
+                    Error::UnexpectedEvent(_) => not_flat(),
+                    e => e,
+                })?;
+                self.empty = false;
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<E> Iterator for Items<E> where E: EventIterator {
-    type Item = Result<Json>;
+impl<E: EventIterator> Iterator for UnflattenObjects<E> {
+    type Item = Result<OwnedEvent>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.events.next() {
-            None => None,
-            Some(result) => match itry!(result) {
-                Event::EndMap | Event::EndArray => None,
-                Event::StartMap => {
-                    let mut object = BTreeMap::new();
-                    while let Some(result) = self.events.next() {
-                        match itry!(result) {
-                            Event::EndMap => break,
-                            Event::Key(k) => {
-                                let result = self.next().expect("Expected more events after a Key event");
-                                object.insert(k, itry!(result));
-                            }
-                            _ => unreachable!(),
-                        }
+        while self.nester.ready.is_empty() {
+            let event = itry!(self.events.next()?);
+            itry!(self.process(event));
+        }
+        self.nester.ready.pop_front().map(Ok)
+    }
+}
+
+/// Annotates events with their dotted paths in the document, the same as
+/// `parse()` in Python ijson.
+pub struct Parse<E: EventIterator> {
+    path: Tracker,
+    events: E,
+}
+
+impl<E: EventIterator> Iterator for Parse<E> {
+    type Item = Result<(String, OwnedEvent)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = itry!(self.events.next()?);
+        self.path.enter(&event);
+        let path = self.path.dotted();
+        self.path.leave(&event);
+        Some(Ok((path, event)))
+    }
+}
+
+/// The error for an event sequence that a parser wouldn't produce, such as
+/// a value without a key inside an object.
+pub(crate) fn unexpected() -> Error {
+    Error::UnexpectedEvent(Position::default())
+}
+
+/// Gives an error found in events, made without knowing where it is, the
+/// position of the stream where it was found.
+pub(crate) fn locate(e: Error, position: Position) -> Error {
+    match e {
+        Error::UnexpectedEvent(default) if default == Position::default() => Error::UnexpectedEvent(position),
+        Error::DuplicateKey(key, default) if default == Position::default() => Error::DuplicateKey(key, position),
+        Error::Number(e, default) if default == Position::default() => Error::Number(e, position),
+        Error::Custom(message, default) if default == Position::default() => Error::Custom(message, position),
+        e => e,
+    }
+}
+
+/// Skips events until `depth` containers are closed, or the stream fails
+/// or ends.
+pub(crate) fn skip_containers<E: EventIterator>(events: &mut E, mut depth: usize) {
+    while depth > 0 {
+        match events.next() {
+            Some(Ok(OwnedEvent::StartMap)) | Some(Ok(OwnedEvent::StartArray)) => depth += 1,
+            Some(Ok(OwnedEvent::EndMap)) | Some(Ok(OwnedEvent::EndArray)) => depth -= 1,
+            Some(Ok(_)) => (),
+            _ => return,
+        }
+    }
+}
+
+/// Joins the rest of a string that came in chunks to its first chunk, see
+/// `Parser::set_string_chunk_size`.
+pub(crate) fn join_chunks<E: EventIterator>(events: &mut E, mut value: String) -> Result<String> {
+    for result in events {
+        match result? {
+            OwnedEvent::StringChunk(chunk) => value.push_str(&chunk),
+            OwnedEvent::StringEnd => return Ok(value),
+            _ => return Err(unexpected()),
+        }
+    }
+    Err(unexpected())
+}
+
+/// What builders do with a key repeated in an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Keep the value that came first.
+    First,
+    /// Keep the value that came last, the default.
+    Last,
+    /// Fail with `Error::DuplicateKey`, skipping the rest of the value.
+    Error,
+    /// Gather all values of the key into an array, in document order.
+    Collect,
+}
+
+/// A tree of values that `Items` can build: `Json`, or `OrderedJson` to
+/// keep object members in document order.
+pub trait Node: From<Json> {
+    /// An object under construction.
+    type Object: Default;
+
+    fn array(values: Vec<Self>) -> Self;
+    fn object(object: Self::Object) -> Self;
+    /// The value under `key` if it's already in the object.
+    fn member<'a>(object: &'a mut Self::Object, key: &str) -> Option<&'a mut Self>;
+    fn insert(object: &mut Self::Object, key: String, value: Self);
+    /// The elements of the value if it's an array.
+    fn elements(&mut self) -> Option<&mut Vec<Self>>;
+}
+
+impl Node for Json {
+    type Object = BTreeMap<String, Json>;
+
+    fn array(values: Vec<Json>) -> Json {
+        Json::Array(values)
+    }
+
+    fn object(object: BTreeMap<String, Json>) -> Json {
+        Json::Object(object)
+    }
+
+    fn member<'a>(object: &'a mut BTreeMap<String, Json>, key: &str) -> Option<&'a mut Json> {
+        object.get_mut(key)
+    }
+
+    fn insert(object: &mut BTreeMap<String, Json>, key: String, value: Json) {
+        object.insert(key, value);
+    }
+
+    fn elements(&mut self) -> Option<&mut Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Builds values out of an event stream, one per top-level value. Objects
+/// are `BTreeMap`s in `Json`, which sorts their keys, see
+/// `Builder::ordered_items` for keeping the order of the document. A value
+/// that can't be built, such as one with a duplicate key rejected by
+/// `DuplicateKeys::Error`, is skipped to its end, so the next one comes
+/// after it. Like `Prefix`, it keeps returning `None` once it has.
+pub struct Items<E, V = Json> where E: EventIterator {
+    events: E,
+    duplicate_keys: DuplicateKeys,
+    locate: fn(&E) -> Position,
+    done: bool,
+    marker: PhantomData<V>,
+}
+
+impl<E> Items<E> where E: EventIterator {
+    pub fn new(events: E) -> Self {
+        Items::with_node(events)
+    }
+}
+
+impl<E, V> Items<E, V> where E: EventIterator {
+    fn with_node(events: E) -> Self {
+        Items {
+            events,
+            duplicate_keys: DuplicateKeys::Last,
+            locate: |_| Position::default(),
+            done: false,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn set_duplicate_keys(&mut self, value: DuplicateKeys) {
+        self.duplicate_keys = value;
+    }
+
+    /// Makes errors found while building values, such as duplicate keys,
+    /// point at the event where they were found. Without it they have the
+    /// default position, as an event stream doesn't generally know where
+    /// it is in the input.
+    pub fn with_location(mut self) -> Self where E: Located {
+        self.locate = E::location;
+        self
+    }
+
+    /// Locates an error found while building a value and skips the rest of
+    /// the value, `depth` containers deep.
+    fn fail(&mut self, e: Error, depth: usize) -> Error {
+        let e = locate(e, (self.locate)(&self.events));
+        skip_containers(&mut self.events, depth);
+        e
+    }
+}
+
+/// A container being built by `Items`.
+enum Frame<V: Node> {
+    Array(Vec<V>),
+    Object {
+        object: V::Object,
+        /// The key awaiting its value.
+        key: Option<String>,
+        /// Keys whose values are gathered by `DuplicateKeys::Collect`.
+        collected: BTreeSet<String>,
+    },
+}
+
+impl<V: Node> Frame<V> {
+    fn object() -> Frame<V> {
+        Frame::Object {
+            object: V::Object::default(),
+            key: None,
+            collected: BTreeSet::new(),
+        }
+    }
+
+    fn push(&mut self, value: V, duplicate_keys: DuplicateKeys) -> Result<()> {
+        let (object, key, collected) = match self {
+            Frame::Array(array) => {
+                array.push(value);
+                return Ok(())
+            }
+            Frame::Object { object, key, collected } => (object, key.take().ok_or_else(unexpected)?, collected),
+        };
+        let existing = match V::member(object, &key) {
+            Some(existing) => existing,
+            None => {
+                V::insert(object, key, value);
+                return Ok(())
+            }
+        };
+        match duplicate_keys {
+            DuplicateKeys::First => (),
+            DuplicateKeys::Last => *existing = value,
+            DuplicateKeys::Error => return Err(Error::DuplicateKey(key, Position::default())),
+            DuplicateKeys::Collect => {
+                if collected.insert(key) {
+                    let first = mem::replace(existing, V::array(vec![]));
+                    *existing = V::array(vec![first]);
+                }
+                if let Some(values) = existing.elements() {
+                    values.push(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the container on its terminating event.
+    fn close(self, event: &OwnedEvent) -> Result<V> {
+        match (self, event) {
+            (Frame::Array(array), OwnedEvent::EndArray) => Ok(V::array(array)),
+            (Frame::Object { object, key: None, .. }, OwnedEvent::EndMap) => Ok(V::object(object)),
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+/// Converts an event of a scalar value, joining a chunked string.
+fn scalar<E: EventIterator>(events: &mut E, event: OwnedEvent) -> Result<Json> {
+    Ok(match event {
+        OwnedEvent::Null => Json::Null,
+        OwnedEvent::Boolean(v) => Json::Boolean(v),
+        OwnedEvent::String(v) => Json::String(v),
+        OwnedEvent::StringChunk(v) => Json::String(join_chunks(events, v)?),
+        OwnedEvent::Bytes(v) => Json::String(String::from_utf8_lossy(&v).into_owned()),
+        OwnedEvent::Number(v) => Json::F64(v),
+        OwnedEvent::Int(v) => Json::I64(v),
+        OwnedEvent::Uint(v) => Json::U64(v),
+        OwnedEvent::RawNumber(v) => match raw_number_lexeme(&v).map_err(|e| Error::Number(e, Position::default()))? {
+            Lexeme::Int(v) => Json::I64(v),
+            Lexeme::Uint(v) => Json::U64(v),
+            Lexeme::Number(v) => Json::F64(v),
+            _ => return Err(unexpected()),
+        },
+        _ => return Err(unexpected()),
+    })
+}
+
+impl<E, V> Items<E, V> where E: EventIterator, V: Node {
+    /// Builds the next value, or returns `None` at the end of the events or
+    /// of the container they're in, leaving the rest for the next call.
+    fn build(&mut self) -> Option<Result<V>> {
+        // Nested containers are kept on a heap stack rather than built by
+        // recursion, so that deep documents can't overflow the call stack.
+        let mut stack: Vec<Frame<V>> = vec![];
+        loop {
+            let event = match self.events.next() {
+                Some(result) => itry!(result),
+                None if stack.is_empty() => return None,
+                // The stream ending inside a container means it was cut off
+                None => return Some(Err(unexpected())),
+            };
+            let value = match event {
+                OwnedEvent::EndMap | OwnedEvent::EndArray => match stack.pop() {
+                    Some(frame) => frame.close(&event),
+                    None => return None,
+                },
+                OwnedEvent::Comment(_) => continue,
+                OwnedEvent::StartMap => {
+                    stack.push(Frame::object());
+                    continue
+                }
+                OwnedEvent::StartArray => {
+                    stack.push(Frame::Array(vec![]));
+                    continue
+                }
+                OwnedEvent::Key(k) => match stack.last_mut() {
+                    Some(Frame::Object { key: key @ None, .. }) => {
+                        *key = Some(k);
+                        continue
                     }
-                    Some(Ok(Json::Object(object)))
+                    _ => Err(unexpected()),
+                },
+                event => scalar(&mut self.events, event).map(V::from),
+            };
+            let result = match (value, stack.last_mut()) {
+                (Ok(value), Some(frame)) => frame.push(value, self.duplicate_keys),
+                (Ok(value), None) => return Some(Ok(value)),
+                (Err(e), _) => Err(e),
+            };
+            if let Err(e) = result {
+                return Some(Err(self.fail(e, stack.len())))
+            }
+        }
+    }
+}
+
+impl<E, V> Iterator for Items<E, V> where E: EventIterator, V: Node {
+    type Item = Result<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let result = self.build();
+        self.done = result.is_none();
+        result
+    }
+}
+
+impl<E, V> FusedIterator for Items<E, V> where E: EventIterator, V: Node {}
+
+/// Yields key-value pairs of objects one by one, without building the
+/// whole object in memory.
+pub struct KVItems<E> where E: EventIterator {
+    items: Items<E>,
+}
+
+impl<E> KVItems<E> where E: EventIterator {
+    /// Sets the policy for repeated keys in the values. The pairs
+    /// themselves are yielded as they come, repeated keys included.
+    pub fn set_duplicate_keys(&mut self, value: DuplicateKeys) {
+        self.items.set_duplicate_keys(value);
+    }
+}
+
+impl<E> Iterator for KVItems<E> where E: EventIterator {
+    type Item = Result<(String, Json)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match itry!(self.items.events.next()?) {
+                OwnedEvent::Key(k) => {
+                    let value = self.items.build().unwrap_or_else(|| Err(unexpected()));
+                    return Some(Ok((k, itry!(value))))
                 }
-                Event::StartArray => {
-                    let mut array = vec![];
-                    while let Some(result) = self.next() {
-                        array.push(itry!(result));
+                OwnedEvent::StartArray => {
+                    // Not an object, skip the whole array
+                    while let Some(result) = self.items.build() {
+                        itry!(result);
                     }
-                    Some(Ok(Json::Array(array)))
                 }
-                Event::Null => Some(Ok(Json::Null)),
-                Event::Boolean(v) => Some(Ok(Json::Boolean(v))),
-                Event::String(v) => Some(Ok(Json::String(v))),
-                Event::Number(v) => Some(Ok(Json::F64(v))),
-                Event::Key(k) => panic!("Unexpected Key event: {}", k),
+                _ => (),
             }
         }
     }
@@ -99,19 +870,142 @@ impl<E> Iterator for Items<E> where E: EventIterator {
 
 pub trait Builder where Self: Sized + EventIterator {
 
-    fn prefix(self, prefix: &str) -> Prefix<Self>  {
+    /// Selects events under a dotted path, such as `docs.item.meta`, where
+    /// `item` stands for any array element and `*` for any key or element.
+    /// A number selects the array element at that position, as in `docs.3.meta`.
+    /// A backslash escapes the next character so that keys containing dots or
+    /// spelled like the special segments can be selected: `a\.b`, `\item`.
+    /// A prefix starting with `/` is a JSON Pointer, such as `/docs/0/meta`.
+    /// It can also be a `Path` built out of typed segments, see `prefix_path`.
+    /// Events outside the prefix are still parsed, as an event iterator has
+    /// no access to the input to skip it; `Parser::raw_items` skips values
+    /// that can't contain the prefix.
+    fn prefix(self, prefix: impl Into<Path>) -> Prefix<Self>  {
+        self.prefix_path(prefix.into())
+    }
+
+    /// Selects events under a path built out of typed segments.
+    fn prefix_path(self, path: Path) -> Prefix<Self> {
         Prefix {
-            reference: prefix.split_terminator(".").map(str::to_string).collect(),
-            path: vec![],
+            reference: path,
+            path: Tracker::default(),
             parser: self,
+            done: false,
         }
     }
 
-    fn items(self, prefix: &str) -> Items<Prefix<Self>> {
-        Items {
-            events: self.prefix(prefix),
+    fn multi_prefix<P: Into<Path>, I: IntoIterator<Item=P>>(self, prefixes: I) -> MultiPrefix<Self> {
+        MultiPrefix {
+            references: prefixes.into_iter().map(Into::into).collect(),
+            path: Tracker::default(),
+            pending: VecDeque::new(),
+            parser: self,
+        }
+    }
+
+    /// Keeps only values under any of `prefixes` and the containers they're
+    /// in, see `Project`. Combined with a `Writer` this selects fields of
+    /// a document in a stream.
+    fn project<P: Into<Path>, I: IntoIterator<Item=P>>(self, prefixes: I) -> Project<Self> {
+        Project {
+            references: prefixes.into_iter().map(Into::into).collect(),
+            path: Tracker::default(),
+            open: vec![],
+            held: vec![],
+            ready: VecDeque::new(),
+            events: self,
+        }
+    }
+
+    /// Replaces values at any of `prefixes` with `mask`, see `Redact`.
+    fn redact<P: Into<Path>, I: IntoIterator<Item=P>>(self, prefixes: I, mask: Mask) -> Redact<Self> {
+        Redact {
+            references: prefixes.into_iter().map(Into::into).collect(),
+            mask,
+            path: Tracker::default(),
+            events: self,
+        }
+    }
+
+    /// Passes every key of every object through `rename`, which returns a
+    /// new key or `None` to keep the key as is. Renaming by a map is
+    /// `rename_keys(|k| map.get(k).cloned())`.
+    fn rename_keys<F: FnMut(&str) -> Option<String>>(self, rename: F) -> RenameKeys<Self, F> {
+        RenameKeys {
+            rename,
+            events: self,
+        }
+    }
+
+    /// Flattens values at a prefix into their scalars paired with dotted
+    /// paths relative to the value, such as `address.city` or `tags.0`,
+    /// for exporting them as columns. Empty containers produce nothing.
+    /// Keys with dots and other special characters in the paths are
+    /// escaped, so they can be passed to `prefix` as is.
+    fn flat_items(self, prefix: impl Into<Path>) -> FlatItems<Self> {
+        FlatItems {
+            reference: prefix.into(),
+            path: Tracker::default(),
+            events: self,
+        }
+    }
+
+    /// Turns top-level flat objects with dotted keys, such as
+    /// `{"a.b": 1, "a.c.0": 2}`, into nested ones, `{"a": {"b": 1, "c": [2]}}`,
+    /// see `Unflatten`.
+    fn unflatten(self) -> UnflattenObjects<Self> {
+        UnflattenObjects {
+            events: self,
+            nester: Nester::default(),
+            key: None,
+            open: false,
+            empty: false,
         }
     }
+
+    fn parse(self) -> Parse<Self> {
+        Parse {
+            path: Tracker::default(),
+            events: self,
+        }
+    }
+
+    fn items(self, prefix: impl Into<Path>) -> Items<Prefix<Self>> {
+        Items::new(self.prefix(prefix))
+    }
+
+    /// Same as `items` but builds objects keeping their members in the
+    /// order of the document.
+    fn ordered_items(self, prefix: impl Into<Path>) -> Items<Prefix<Self>, OrderedJson> {
+        Items::with_node(self.prefix(prefix))
+    }
+
+    fn kvitems(self, prefix: impl Into<Path>) -> KVItems<Prefix<Self>> {
+        KVItems {
+            items: self.items(prefix),
+        }
+    }
+
+    /// Passes the events through unchanged while also writing them out,
+    /// for example to archive a normalized copy of the input while
+    /// processing it. Multiple top-level values are written on separate
+    /// lines. The first failed write is yielded as an error and ends the
+    /// iteration.
+    fn tee<W: Write>(self, writer: Writer<W>) -> Tee<Self, W> {
+        Tee::new(self, writer)
+    }
+
+    #[cfg(feature = "serde")]
+    fn values(self, prefix: impl Into<Path>) -> Values<Prefix<Self>> {
+        Values::new(self.prefix(prefix))
+    }
+
+    /// Deserializes values under `prefix` into `T`, see `ItemsAs`. Other
+    /// streams can be read with `ItemsAs::new`.
+    #[cfg(feature = "serde")]
+    fn items_as<T: serde::de::DeserializeOwned>(self, prefix: impl Into<Path>) -> ItemsAs<T, Prefix<Self>> where Self: Located {
+        ItemsAs::located(self.prefix(prefix))
+    }
 }
 
 impl<T> Builder for T where T: Sized + EventIterator {}
@@ -0,0 +1,425 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::binary::{base64url, io_error, position, Checker, Input};
+use crate::builder::Located;
+use crate::errors::{Error, Position, Result};
+use crate::parser::{Event, Limits, OwnedEvent};
+
+
+/// Converts a half-precision float.
+fn half(bits: u16) -> f64 {
+    let exponent = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        0x1f if mantissa == 0.0 => f64::INFINITY,
+        0x1f => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    if bits & 0x8000 != 0 { -value } else { value }
+}
+
+enum Frame {
+    Array { remaining: Option<u64> },
+    /// The next item is a key if `key` is set.
+    Map { remaining: Option<u64>, key: bool },
+    /// Chunks of a text string of indefinite length.
+    Text,
+}
+
+/// Reads CBOR (RFC 8949) producing the same events as `Parser` does for
+/// JSON, so prefixes, items and the rest work on it as well. Values are
+/// converted as RFC 8949 suggests for JSON:
+///
+/// - Integers become `Event::Int` or `Event::Uint`, and those too big for
+///   both, including bignums (tags 2 and 3) of up to 16 bytes,
+///   `Event::RawNumber`.
+/// - Byte strings become base64url strings without padding.
+/// - Text strings of indefinite length come in chunks, as
+///   `Event::StringChunk` and `Event::StringEnd`.
+/// - Map keys which are integers become their decimal text, other keys
+///   besides strings are an error.
+/// - `undefined` and other simple values become `null`, and other tags are
+///   ignored.
+///
+/// ```
+/// use ijson::builder::Builder;
+/// use ijson::cbor::CborReader;
+///
+/// // {"a": [1, -2.5], "b": h'00ff'}
+/// let data = b"\xa2\x61a\x82\x01\xf9\xc1\x00\x61b\x42\x00\xff";
+/// let value = CborReader::new(&data[..]).items("").next().unwrap().unwrap();
+/// assert_eq!(value.to_string(), r#"{"a":[1,-2.5],"b":"AP8"}"#);
+/// ```
+pub struct CborReader<R: Read> {
+    input: Input<R>,
+    stack: Vec<Frame>,
+    depth: usize,
+    multi: bool,
+    limits: Limits,
+    events: usize,
+    /// A top-level value has been read.
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read> CborReader<R> {
+    /// Reads a single top-level value, failing with `Error::AdditionalData`
+    /// on anything after it.
+    pub fn new(input: R) -> CborReader<R> {
+        CborReader {
+            input: Input::new(input),
+            stack: vec![],
+            depth: 0,
+            multi: false,
+            limits: Limits::default(),
+            events: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Reads a CBOR sequence (RFC 8742) of any number of top-level values.
+    pub fn new_multi(input: R) -> CborReader<R> {
+        let mut reader = CborReader::new(input);
+        reader.multi = true;
+        reader
+    }
+
+    /// Sets limits on the input, same as `Parser::set_limits`. Numbers have
+    /// no text, so `max_number` doesn't apply.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Reads the argument of an item with the given initial byte, `None`
+    /// for indefinite length.
+    fn argument(&mut self, byte: u8, start: usize) -> Result<Option<u64>> {
+        Ok(Some(match byte & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => u8::from_be_bytes(self.input.exact()?) as u64,
+            25 => u16::from_be_bytes(self.input.exact()?) as u64,
+            26 => u32::from_be_bytes(self.input.exact()?) as u64,
+            27 => u64::from_be_bytes(self.input.exact()?),
+            31 => return Ok(None),
+            _ => return Err(Error::Unknown(vec![byte], position(start))),
+        }))
+    }
+
+    /// Reads the chunks of a string of indefinite length, each of which
+    /// has to be a string of the same major type and a definite length.
+    fn chunks(&mut self, major: u8, start: usize) -> Result<Vec<u8>> {
+        let mut result = vec![];
+        loop {
+            let chunk = self.input.offset;
+            let byte = self.input.next_byte()?;
+            if byte == 0xff {
+                return Ok(result)
+            }
+            if byte >> 5 != major || byte & 0x1f == 31 {
+                return Err(Error::Unknown(vec![byte], position(chunk)))
+            }
+            let len = self.argument(byte, chunk)?.unwrap_or_default();
+            if result.len() as u64 + len > self.limits.max_string as u64 {
+                return Err(Error::StringLimit(position(start)))
+            }
+            result.extend(self.contents(len, chunk)?);
+        }
+    }
+
+    fn text(&self, bytes: Vec<u8>, start: usize) -> Result<String> {
+        String::from_utf8(bytes).map_err(|e| Error::Utf8(e.utf8_error(), position(start)))
+    }
+
+    fn contents(&mut self, len: u64, start: usize) -> Result<Vec<u8>> {
+        self.input.bytes(len, self.limits.max_string, start)
+    }
+
+    fn open(&mut self, frame: Frame, start: usize) -> Result<()> {
+        if self.depth >= self.limits.max_depth {
+            return Err(Error::DepthLimit(position(start)))
+        }
+        self.depth += 1;
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    fn close(&mut self) -> OwnedEvent {
+        self.depth -= 1;
+        match self.stack.pop() {
+            Some(Frame::Map { .. }) => OwnedEvent::EndMap,
+            _ => OwnedEvent::EndArray,
+        }
+    }
+
+    fn read_event(&mut self) -> Result<Option<OwnedEvent>> {
+        // Containers of a definite length end without a byte of their own
+        match self.stack.last_mut() {
+            Some(Frame::Array { remaining: Some(0) }) |
+            Some(Frame::Map { remaining: Some(0), key: true }) => return Ok(Some(self.close())),
+            _ => (),
+        }
+        let mut start = self.input.offset;
+        let mut byte = match self.input.byte()? {
+            Some(byte) => byte,
+            None if self.stack.is_empty() && (self.multi || self.started) => return Ok(None),
+            None => return Err(Error::Incomplete(position(self.input.offset))),
+        };
+        if self.stack.is_empty() {
+            if self.started && !self.multi {
+                return Err(Error::AdditionalData(position(start)))
+            }
+            self.started = true;
+        }
+
+        let key = match self.stack.last_mut() {
+            Some(Frame::Text) if byte == 0xff => {
+                self.stack.pop();
+                return Ok(Some(OwnedEvent::StringEnd))
+            }
+            Some(Frame::Text) if byte >> 5 == 3 && byte & 0x1f != 31 => {
+                let len = self.argument(byte, start)?.unwrap_or_default();
+                return Ok(Some(OwnedEvent::StringChunk(self.input.text(len, self.limits.max_string, start)?)))
+            }
+            Some(Frame::Text) => return Err(Error::Unknown(vec![byte], position(start))),
+            Some(Frame::Array { remaining: None }) |
+            Some(Frame::Map { remaining: None, key: true }) if byte == 0xff => return Ok(Some(self.close())),
+            Some(Frame::Array { remaining: Some(remaining) }) => {
+                *remaining -= 1;
+                false
+            }
+            Some(Frame::Map { remaining, key }) => {
+                if !*key {
+                    if let Some(remaining) = remaining {
+                        *remaining -= 1;
+                    }
+                }
+                *key = !*key;
+                !*key
+            }
+            _ => false,
+        };
+
+        // Tags are skipped, save for bignums
+        let mut tag = None;
+        while byte >> 5 == 6 {
+            tag = Some(self.argument(byte, start)?.ok_or_else(|| Error::Unknown(vec![byte], position(start)))?);
+            start = self.input.offset;
+            byte = self.input.next_byte()?;
+        }
+        let (major, info) = (byte >> 5, byte & 0x1f);
+        let argument = match (major, info) {
+            (7, _) => None,
+            (2..=5, 31) => None,
+            _ => Some(self.argument(byte, start)?.ok_or_else(|| Error::Unknown(vec![byte], position(start)))?),
+        };
+        let event = match (major, argument) {
+            (0, Some(n)) => match i64::try_from(n) {
+                Ok(n) => OwnedEvent::Int(n),
+                Err(_) => OwnedEvent::Uint(n),
+            },
+            (1, Some(n)) => match i64::try_from(n) {
+                Ok(n) => OwnedEvent::Int(-1 - n),
+                Err(_) => OwnedEvent::RawNumber(format!("-{}", n as u128 + 1)),
+            },
+            (2, len) => {
+                let bytes = match len {
+                    Some(len) => self.contents(len, start)?,
+                    None => self.chunks(2, start)?,
+                };
+                let n = bytes.iter().try_fold(0u128, |n, &b| n.checked_mul(256).map(|n| n | b as u128));
+                match (tag, n) {
+                    (Some(2), Some(n)) => OwnedEvent::RawNumber(n.to_string()),
+                    (Some(3), Some(n)) if n < u128::MAX => OwnedEvent::RawNumber(format!("-{}", n + 1)),
+                    _ => OwnedEvent::String(base64url(&bytes)),
+                }
+            }
+            (3, Some(len)) => OwnedEvent::String(self.input.text(len, self.limits.max_string, start)?),
+            // Keys have to be whole
+            (3, None) if key => {
+                let contents = self.chunks(3, start)?;
+                OwnedEvent::String(self.text(contents, start)?)
+            }
+            (3, None) => {
+                self.stack.push(Frame::Text);
+                OwnedEvent::StringChunk(String::new())
+            }
+            (4, remaining) => {
+                self.open(Frame::Array { remaining }, start)?;
+                OwnedEvent::StartArray
+            }
+            (5, remaining) => {
+                self.open(Frame::Map { remaining, key: true }, start)?;
+                OwnedEvent::StartMap
+            }
+            _ => match info {
+                20 => OwnedEvent::Boolean(false),
+                21 => OwnedEvent::Boolean(true),
+                0..=23 => OwnedEvent::Null,
+                24 => {
+                    self.input.exact::<1>()?;
+                    OwnedEvent::Null
+                }
+                25 => OwnedEvent::Number(half(u16::from_be_bytes(self.input.exact()?))),
+                26 => OwnedEvent::Number(f32::from_be_bytes(self.input.exact()?) as f64),
+                27 => OwnedEvent::Number(f64::from_be_bytes(self.input.exact()?)),
+                31 => return Err(Error::Unmatched(position(start))),
+                _ => return Err(Error::Unknown(vec![byte], position(start))),
+            },
+        };
+        if !key {
+            return Ok(Some(event))
+        }
+        match event {
+            OwnedEvent::String(s) | OwnedEvent::RawNumber(s) => Ok(Some(OwnedEvent::Key(s))),
+            OwnedEvent::Int(n) => Ok(Some(OwnedEvent::Key(n.to_string()))),
+            OwnedEvent::Uint(n) => Ok(Some(OwnedEvent::Key(n.to_string()))),
+            _ => Err(Error::Custom("Map keys have to be strings or integers".to_string(), position(start))),
+        }
+    }
+}
+
+impl<R: Read> Located for CborReader<R> {
+    fn location(&self) -> Position {
+        position(self.input.offset)
+    }
+}
+
+impl<R: Read> Iterator for CborReader<R> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let result = self.read_event().transpose();
+        match result {
+            Some(Ok(_)) => {
+                self.events += 1;
+                if self.events > self.limits.max_events {
+                    self.done = true;
+                    return Some(Err(Error::EventLimit(position(self.input.offset))))
+                }
+            }
+            _ => self.done = true,
+        }
+        result
+    }
+}
+
+/// Writes events as CBOR, checking that they form valid documents the
+/// same way `Writer` does. Containers and strings in chunks are written
+/// with indefinite lengths, so nothing is kept in memory. Floats which
+/// are the same as single precision are written as such, and `Event::Bytes`
+/// becomes a byte string. Several top-level values make a CBOR sequence.
+///
+/// ```
+/// use ijson::cbor::CborWriter;
+/// use ijson::parser::Parser;
+///
+/// let mut writer = CborWriter::new(vec![]);
+/// for event in Parser::from_slice(&br#"{"a": [1, -2.5]}"#[..]) {
+///     writer.write_event(event.unwrap().as_event()).unwrap();
+/// }
+/// assert_eq!(writer.finish().unwrap(), b"\xbf\x61a\x9f\x01\xfa\xc0\x20\x00\x00\xff\xff");
+/// ```
+pub struct CborWriter<W: Write> {
+    out: W,
+    checker: Checker,
+}
+
+impl<W: Write> CborWriter<W> {
+    pub fn new(out: W) -> CborWriter<W> {
+        CborWriter {
+            out,
+            checker: Checker::new(),
+        }
+    }
+
+    fn header(&mut self, major: u8, argument: u64) -> io::Result<()> {
+        let major = major << 5;
+        match argument {
+            0..=23 => self.out.write_all(&[major | argument as u8]),
+            24..=0xff => self.out.write_all(&[major | 24, argument as u8]),
+            0x100..=0xffff => {
+                self.out.write_all(&[major | 25])?;
+                self.out.write_all(&(argument as u16).to_be_bytes())
+            }
+            0x10000..=0xffff_ffff => {
+                self.out.write_all(&[major | 26])?;
+                self.out.write_all(&(argument as u32).to_be_bytes())
+            }
+            _ => {
+                self.out.write_all(&[major | 27])?;
+                self.out.write_all(&argument.to_be_bytes())
+            }
+        }
+    }
+
+    fn string(&mut self, major: u8, bytes: &[u8]) -> io::Result<()> {
+        self.header(major, bytes.len() as u64)?;
+        self.out.write_all(bytes)
+    }
+
+    fn number(&mut self, value: f64) -> io::Result<()> {
+        if value as f32 as f64 == value || value.is_nan() {
+            self.out.write_all(&[0xfa])?;
+            self.out.write_all(&(value as f32).to_be_bytes())
+        } else {
+            self.out.write_all(&[0xfb])?;
+            self.out.write_all(&value.to_be_bytes())
+        }
+    }
+
+    fn integer(&mut self, value: i128) -> io::Result<()> {
+        if value >= 0 {
+            self.header(0, value as u64)
+        } else {
+            self.header(1, (-1 - value) as u64)
+        }
+    }
+
+    /// Writes out a single event, failing if it is not valid in the current
+    /// position of the document.
+    pub fn write_event(&mut self, event: Event) -> Result<()> {
+        let in_string = self.checker.in_string();
+        self.checker.check(&event)?;
+        match event {
+            Event::Null => self.out.write_all(&[0xf6]),
+            Event::Boolean(b) => self.out.write_all(&[if b { 0xf5 } else { 0xf4 }]),
+            Event::String(s) | Event::Key(s) => self.string(3, s.as_bytes()),
+            Event::StringChunk(s) => {
+                if !in_string {
+                    self.out.write_all(&[0x7f]).map_err(io_error)?;
+                }
+                if s.is_empty() { Ok(()) } else { self.string(3, s.as_bytes()) }
+            }
+            Event::StringEnd => self.out.write_all(&[0xff]),
+            Event::Bytes(b) => self.string(2, b),
+            Event::Number(n) => self.number(n),
+            Event::Int(n) => self.integer(n as i128),
+            Event::Uint(n) => self.header(0, n),
+            Event::RawNumber(s) => match s.parse::<i128>() {
+                Ok(n) if (-(1 << 64)..1 << 64).contains(&n) => self.integer(n),
+                _ => match s.parse::<f64>() {
+                    Ok(n) => self.number(n),
+                    Err(_) => return Err(Error::Custom(format!("Can't write number {} in CBOR", s), Position::default())),
+                },
+            },
+            Event::StartArray => self.out.write_all(&[0x9f]),
+            Event::StartMap => self.out.write_all(&[0xbf]),
+            Event::EndArray | Event::EndMap => self.out.write_all(&[0xff]),
+            Event::Comment(_) => Ok(()),
+        }.map_err(io_error)?;
+        Ok(())
+    }
+
+    /// Checks that complete documents have been written, flushes the output
+    /// and returns it.
+    pub fn finish(mut self) -> Result<W> {
+        self.checker.finish()?;
+        self.out.flush().map_err(io_error)?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use rustc_serialize::json::Json;
+
+use crate::builder::{Builder, EventIterator};
+use crate::errors::Result;
+use crate::ordered::OrderedJson;
+use crate::path::{dotted_steps, Step};
+
+
+/// The first place where two documents differ, see `Compare`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// Number of the top-level value in the streams, starting with 0.
+    pub document: usize,
+    /// Dotted path of the value, as `Path::parse` reads it, with array
+    /// indices as numbers. Empty for a top-level value.
+    pub path: String,
+    /// The value on each side as compact JSON, or `None` where it's
+    /// missing.
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let side = |value: &Option<String>| value.clone().unwrap_or_else(|| "nothing".to_string());
+        let path = if self.path.is_empty() { "the top" } else { &self.path };
+        write!(f, "Document {} differs at {}: {} vs {}", self.document, path, side(&self.left), side(&self.right))
+    }
+}
+
+/// Values on both sides, either of them possibly missing.
+type Pair<'a> = (Option<&'a OrderedJson>, Option<&'a OrderedJson>);
+
+/// Numbers are equal if their values are, regardless of how they are
+/// spelled or stored.
+fn same_number(a: &OrderedJson, b: &OrderedJson) -> Option<bool> {
+    let integer = |value: &OrderedJson| match *value {
+        OrderedJson::I64(v) => Some(v as i128),
+        OrderedJson::U64(v) => Some(v as i128),
+        _ => None,
+    };
+    let float = |value: &OrderedJson| match *value {
+        OrderedJson::I64(v) => Some(v as f64),
+        OrderedJson::U64(v) => Some(v as f64),
+        OrderedJson::F64(v) => Some(v),
+        _ => None,
+    };
+    match (integer(a), integer(b)) {
+        (Some(a), Some(b)) => Some(a == b),
+        _ => Some(float(a)? == float(b)?),
+    }
+}
+
+/// Checks whether two streams of events make up the same documents, for
+/// round-trip tests and for validating transformations. Values are
+/// compared rather than events: strings in chunks equal whole ones,
+/// numbers are equal if their values are, comments don't count and, with
+/// `set_ignore_key_order`, neither does the order of object members.
+/// Top-level values are built one at a time.
+///
+/// ```
+/// use ijson::compare::Compare;
+/// use ijson::parser::Parser;
+///
+/// let left = Parser::from_slice(&br#"{"a": [1, 2.0], "b": null}"#[..]).into_iter();
+/// let right = Parser::from_slice(&br#"{"b": null, "a": [1, 2.5]}"#[..]).into_iter();
+/// let mut compare = Compare::new();
+/// compare.set_ignore_key_order(true);
+/// let difference = compare.events(left, right).unwrap().unwrap();
+/// assert_eq!(difference.path, "a.1");
+/// assert_eq!(difference.right.as_deref(), Some("2.5"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Compare {
+    ignore_key_order: bool,
+}
+
+impl Compare {
+    pub fn new() -> Compare {
+        Compare::default()
+    }
+
+    /// Makes objects equal if they have the same members in any order.
+    pub fn set_ignore_key_order(&mut self, value: bool) {
+        self.ignore_key_order = value;
+    }
+
+    /// Returns the first difference between the documents of two streams,
+    /// or `None` if they are the same.
+    pub fn events<A: EventIterator, B: EventIterator>(&self, left: A, right: B) -> Result<Option<Difference>> {
+        self.values(left.ordered_items(""), right.ordered_items(""))
+    }
+
+    /// Compares the document of a stream with a value.
+    pub fn value<A: EventIterator>(&self, events: A, value: &Json) -> Result<Option<Difference>> {
+        self.values(events.ordered_items(""), vec![Ok(OrderedJson::from(value.clone()))].into_iter())
+    }
+
+    fn values<A, B>(&self, mut left: A, mut right: B) -> Result<Option<Difference>>
+        where A: Iterator<Item=Result<OrderedJson>>, B: Iterator<Item=Result<OrderedJson>> {
+        for document in 0.. {
+            let (a, b) = match (left.next().transpose()?, right.next().transpose()?) {
+                (None, None) => return Ok(None),
+                (a, b) => (a, b),
+            };
+            let mut path = vec![];
+            let found = match (&a, &b) {
+                (Some(a), Some(b)) => self.difference(a, b, &mut path),
+                _ => Some((a.as_ref(), b.as_ref())),
+            };
+            if let Some((a, b)) = found {
+                return Ok(Some(Difference {
+                    document,
+                    path: dotted_steps(&path),
+                    left: a.map(OrderedJson::to_string),
+                    right: b.map(OrderedJson::to_string),
+                }))
+            }
+        }
+        unreachable!()
+    }
+
+    /// Finds the first pair of values that differ, leaving `path` at them.
+    /// Nested values are walked with a stack of their own rather than by
+    /// recursion, so that deep documents can't overflow the call stack.
+    fn difference<'a>(&self, a: &'a OrderedJson, b: &'a OrderedJson, path: &mut Vec<Step>) -> Option<Pair<'a>> {
+        // Pairs yet to compare, each with the length of the path of its
+        // container and its own step, the first one on top
+        let mut pending: Vec<(usize, Option<Step>, Pair<'a>)> = vec![(path.len(), None, (Some(a), Some(b)))];
+        while let Some((depth, step, pair)) = pending.pop() {
+            path.truncate(depth);
+            path.extend(step);
+            let (a, b) = match pair {
+                (Some(a), Some(b)) => (a, b),
+                pair => return Some(pair),
+            };
+            let differs = Some((Some(a), Some(b)));
+            let depth = path.len();
+            match (a, b) {
+                (OrderedJson::Array(a), OrderedJson::Array(b)) => {
+                    for i in (0..a.len().max(b.len())).rev() {
+                        pending.push((depth, Some(Step::Index(i)), (a.get(i), b.get(i))));
+                    }
+                }
+                (OrderedJson::Object(x), OrderedJson::Object(y)) if self.ignore_key_order => {
+                    let mut x: Vec<_> = x.iter().collect();
+                    let mut y: Vec<_> = y.iter().collect();
+                    x.sort_by(|m, n| m.0.cmp(&n.0));
+                    y.sort_by(|m, n| m.0.cmp(&n.0));
+                    let (mut x, mut y) = (x.into_iter().peekable(), y.into_iter().peekable());
+                    let mut members = vec![];
+                    loop {
+                        let order = match (x.peek(), y.peek()) {
+                            (None, None) => break,
+                            (Some(m), Some(n)) => m.0.cmp(&n.0),
+                            (Some(_), None) => Ordering::Less,
+                            (None, Some(_)) => Ordering::Greater,
+                        };
+                        let (m, n) = match order {
+                            Ordering::Less => (x.next(), None),
+                            Ordering::Greater => (None, y.next()),
+                            Ordering::Equal => (x.next(), y.next()),
+                        };
+                        let key = m.or(n).map(|member| member.0.clone()).unwrap_or_default();
+                        members.push((depth, Some(Step::Key(key)), (m.map(|m| &m.1), n.map(|n| &n.1))));
+                    }
+                    pending.extend(members.into_iter().rev());
+                }
+                (OrderedJson::Object(x), OrderedJson::Object(y)) => {
+                    if x.len() != y.len() || x.iter().zip(y).any(|(m, n)| m.0 != n.0) {
+                        return differs
+                    }
+                    for (m, n) in x.iter().zip(y).rev() {
+                        pending.push((depth, Some(Step::Key(m.0.clone())), (Some(&m.1), Some(&n.1))));
+                    }
+                }
+                _ => match same_number(a, b) {
+                    Some(true) => (),
+                    Some(false) => return differs,
+                    None if a == b => (),
+                    None => return differs,
+                },
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::builder::{join_chunks, unexpected, Builder, EventIterator};
+use crate::errors::{Error, Position, Result};
+use crate::parser::OwnedEvent;
+use crate::writer::Writer;
+
+
+/// Keys of an object and the text of their values, `None` for null.
+type Row = Vec<(String, Option<String>)>;
+
+/// Writes objects at a prefix as rows of CSV with a header, a column per
+/// key. Strings go into cells as they are, numbers as they're written in
+/// the input, and containers as compact JSON.
+///
+/// ```
+/// use ijson::csv::Export;
+/// use ijson::parser::Parser;
+///
+/// let data = br#"[{"a": 1, "b": "x"}, {"b": "y, z", "c": null}]"#;
+/// let mut out = vec![];
+/// Export::new().write(Parser::new(&data[..]).into_iter(), "item", &mut out).unwrap();
+/// assert_eq!(out, b"a,b,c\n1,x,\n,\"y, z\",\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Export {
+    delimiter: u8,
+    null: String,
+    columns: Option<Vec<String>>,
+    infer_rows: usize,
+    ignore_extra_keys: bool,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Export {
+            delimiter: b',',
+            null: String::new(),
+            columns: None,
+            infer_rows: 100,
+            ignore_extra_keys: false,
+        }
+    }
+}
+
+impl Export {
+    pub fn new() -> Export {
+        Export::default()
+    }
+
+    /// Separator of cells, `b'\t'` for TSV.
+    pub fn delimiter(mut self, value: u8) -> Self {
+        self.delimiter = value;
+        self
+    }
+
+    /// Text of null and missing values, empty by default.
+    pub fn null(mut self, value: &str) -> Self {
+        self.null = value.to_string();
+        self
+    }
+
+    /// Writes only these columns, in this order, ignoring other keys.
+    pub fn columns<S: Into<String>, I: IntoIterator<Item=S>>(mut self, value: I) -> Self {
+        self.columns = Some(value.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Number of rows held in memory to collect columns from, in the order
+    /// they appear, when they aren't given with `columns`. 100 by default.
+    ///
+    /// Rows are written as they come after these, so a key not seen in them
+    /// is an error only once the rows before it are written, unless it's
+    /// ignored with `ignore_extra_keys`.
+    pub fn infer_rows(mut self, value: usize) -> Self {
+        self.infer_rows = value;
+        self
+    }
+
+    /// Leaves out keys not in the columns found in the first rows, rather
+    /// than failing on them. Keys not in the columns given with `columns`
+    /// are always left out.
+    pub fn ignore_extra_keys(mut self, value: bool) -> Self {
+        self.ignore_extra_keys = value;
+        self
+    }
+
+    /// Writes objects at `prefix`, returning the number of rows written.
+    /// Nothing is written for no rows and no columns.
+    pub fn write<E: EventIterator, W: Write>(&self, events: E, prefix: &str, mut out: W) -> Result<usize> {
+        let mut events = events.prefix(prefix);
+        let mut sample = vec![];
+        let columns = match self.columns {
+            Some(ref columns) => columns.clone(),
+            None => {
+                let mut columns: Vec<String> = vec![];
+                while sample.len() < self.infer_rows {
+                    let row = match self.row(&mut events)? {
+                        Some(row) => row,
+                        None => break,
+                    };
+                    for (key, _) in &row {
+                        if !columns.contains(key) {
+                            columns.push(key.clone());
+                        }
+                    }
+                    sample.push(row);
+                }
+                columns
+            }
+        };
+        let index: HashMap<&str, usize> = columns.iter().enumerate().map(|(i, c)| (&c[..], i)).collect();
+        if !columns.is_empty() {
+            let header: Vec<&str> = columns.iter().map(|c| &c[..]).collect();
+            self.write_row(&mut out, &header)?;
+        }
+
+        let mut count = 0;
+        let mut cells = vec![String::new(); columns.len()];
+        let mut sample = sample.into_iter();
+        while let Some(row) = sample.next().map_or_else(|| self.row(&mut events), |row| Ok(Some(row)))? {
+            cells.iter_mut().for_each(|cell| cell.clone_from(&self.null));
+            for (key, value) in row {
+                match index.get(&key[..]) {
+                    Some(&i) => cells[i] = value.unwrap_or_else(|| self.null.clone()),
+                    None if self.columns.is_some() || self.ignore_extra_keys => (),
+                    None => return Err(Error::Custom(format!("Key {:?} is not in the columns", key), Position::default())),
+                }
+            }
+            let cells: Vec<&str> = cells.iter().map(|c| &c[..]).collect();
+            self.write_row(&mut out, &cells)?;
+            count += 1;
+        }
+        out.flush().map_err(|e| Error::IO(e, Position::default()))?;
+        Ok(count)
+    }
+
+    /// Reads the next object as its keys and the text of their cells.
+    fn row<E: EventIterator>(&self, events: &mut E) -> Result<Option<Row>> {
+        match next(events)? {
+            Some(OwnedEvent::StartMap) => (),
+            Some(_) => return Err(Error::Custom("Expected an object for a row".to_string(), Position::default())),
+            None => return Ok(None),
+        }
+        let mut row = vec![];
+        loop {
+            match next(events)? {
+                Some(OwnedEvent::Key(key)) => {
+                    let event = next(events)?.ok_or_else(unexpected)?;
+                    row.push((key, cell(events, event)?));
+                }
+                Some(OwnedEvent::EndMap) => return Ok(Some(row)),
+                _ => return Err(unexpected()),
+            }
+        }
+    }
+
+    /// Writes cells quoted where needed as per RFC 4180.
+    fn write_row<W: Write>(&self, out: &mut W, cells: &[&str]) -> Result<()> {
+        let mut line = vec![];
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                line.push(self.delimiter);
+            }
+            let quote = cell.bytes().any(|b| b == self.delimiter || b == b'"' || b == b'\n' || b == b'\r');
+            if quote {
+                line.push(b'"');
+                line.extend_from_slice(cell.replace('"', "\"\"").as_bytes());
+                line.push(b'"');
+            } else {
+                line.extend_from_slice(cell.as_bytes());
+            }
+        }
+        line.push(b'\n');
+        out.write_all(&line).map_err(|e| Error::IO(e, Position::default()))
+    }
+}
+
+/// The next event other than a comment.
+fn next<E: EventIterator>(events: &mut E) -> Result<Option<OwnedEvent>> {
+    events.find(|r| !matches!(r, Ok(OwnedEvent::Comment(_)))).transpose()
+}
+
+/// Text of the value starting with `event`: strings as they are, numbers
+/// as they're written, and containers as compact JSON.
+fn cell<E: EventIterator>(events: &mut E, event: OwnedEvent) -> Result<Option<String>> {
+    Ok(Some(match event {
+        OwnedEvent::Null => return Ok(None),
+        OwnedEvent::String(s) => s,
+        OwnedEvent::StringChunk(s) => join_chunks(events, s)?,
+        OwnedEvent::Bytes(b) => String::from_utf8_lossy(&b).into_owned(),
+        OwnedEvent::RawNumber(s) => s,
+        event => {
+            let mut writer = Writer::new(vec![]);
+            let mut depth = 0usize;
+            let mut event = event;
+            loop {
+                match event {
+                    OwnedEvent::StartMap | OwnedEvent::StartArray => depth += 1,
+                    OwnedEvent::EndMap | OwnedEvent::EndArray => depth = depth.checked_sub(1).ok_or_else(unexpected)?,
+                    _ => (),
+                }
+                writer.write_event(event.as_event())?;
+                // Pieces of a long string come up to `StringEnd`
+                if depth == 0 && !matches!(event, OwnedEvent::StringChunk(_)) {
+                    break
+                }
+                event = next(events)?.ok_or_else(unexpected)?;
+            }
+            String::from_utf8(writer.finish()?).map_err(|e| Error::Utf8(e.utf8_error(), Position::default()))?
+        }
+    }))
+}
@@ -0,0 +1,329 @@
+use std::io::Read;
+
+use serde::de::{self, Visitor, DeserializeSeed, IntoDeserializer};
+
+use crate::builder::{EventIterator, Located, join_chunks, locate};
+use crate::errors::{Error, Position, Result};
+use crate::parser::{Parser, Events, OwnedEvent};
+
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string(), Position::default())
+    }
+}
+
+/// A serde `Deserializer` working directly on an event stream, without
+/// building an intermediate tree of values.
+pub struct Deserializer<E: EventIterator> {
+    events: E,
+    peeked: Option<OwnedEvent>,
+    /// Finds the position of the current event for errors.
+    locate: fn(&E) -> Position,
+}
+
+impl<R: Read> Deserializer<Events<R>> {
+    pub fn new(reader: R) -> Self {
+        Deserializer::from_located(Parser::new(reader).into_iter())
+    }
+}
+
+impl<E: EventIterator> Deserializer<E> {
+    /// Creates a deserializer of any event stream, whose errors, not
+    /// knowing where it is in the input, have the default position.
+    pub fn from_events(events: E) -> Self {
+        Deserializer {
+            events,
+            peeked: None,
+            locate: |_| Position::default(),
+        }
+    }
+
+    /// Creates a deserializer whose errors point at the event where they
+    /// were found.
+    pub fn from_located(events: E) -> Self where E: Located {
+        Deserializer {
+            events,
+            peeked: None,
+            locate: E::location,
+        }
+    }
+
+    /// Creates a deserializer for a value starting with `event`, already
+    /// taken from `events`.
+    pub(crate) fn with_peeked(events: E, event: OwnedEvent) -> Self {
+        Deserializer {
+            events,
+            peeked: Some(event),
+            locate: |_| Position::default(),
+        }
+    }
+
+    fn locate(&self, e: Error) -> Error {
+        locate(e, (self.locate)(&self.events))
+    }
+
+    /// Checks that the event stream has been fully consumed.
+    pub fn end(&mut self) -> Result<()> {
+        let next = self.peeked.take().map(Ok)
+            .or_else(|| self.events.find(|r| !matches!(r, Ok(OwnedEvent::Comment(_)))));
+        match next {
+            None => Ok(()),
+            Some(Err(e)) => Err(e),
+            Some(Ok(event)) => Err(self.locate(de::Error::custom(format!("trailing {:?}", event)))),
+        }
+    }
+
+    fn next_event(&mut self) -> Result<OwnedEvent> {
+        if let Some(event) = self.peeked.take() {
+            return Ok(event)
+        }
+        loop {
+            match self.events.next() {
+                Some(Ok(OwnedEvent::Comment(_))) => (),
+                Some(Ok(OwnedEvent::StringChunk(s))) => return join_chunks(&mut self.events, s).map(OwnedEvent::String),
+                Some(result) => return result,
+                None => return Err(de::Error::custom("unexpected end of event stream")),
+            }
+        }
+    }
+
+    fn peek_event(&mut self) -> Result<&OwnedEvent> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.next_event()?);
+        }
+        Ok(self.peeked.as_ref().unwrap())
+    }
+
+    fn expect(&mut self, expected: OwnedEvent) -> Result<()> {
+        let event = self.next_event()?;
+        if event == expected {
+            Ok(())
+        } else {
+            Err(de::Error::custom(format!("expected {:?}, got {:?}", expected, event)))
+        }
+    }
+
+    fn skip_value(&mut self) -> Result<()> {
+        let mut depth = 0usize;
+        loop {
+            match self.next_event()? {
+                OwnedEvent::StartMap | OwnedEvent::StartArray => depth += 1,
+                OwnedEvent::EndMap | OwnedEvent::EndArray => depth -= 1,
+                OwnedEvent::Key(_) => continue,
+                _ => (),
+            }
+            if depth == 0 {
+                return Ok(())
+            }
+        }
+    }
+}
+
+fn visit_number<'de, V: Visitor<'de>>(value: f64, visitor: V) -> Result<V::Value> {
+    if value.fract() == 0.0 && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+        visitor.visit_i64(value as i64)
+    } else if value.fract() == 0.0 && value >= 0.0 && value < u64::MAX as f64 {
+        visitor.visit_u64(value as u64)
+    } else {
+        visitor.visit_f64(value)
+    }
+}
+
+impl<'de, E: EventIterator> de::Deserializer<'de> for &mut Deserializer<E> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let result = self.visit_any(visitor);
+        result.map_err(|e| self.locate(e))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let result = if *self.peek_event()? == OwnedEvent::Null {
+            self.next_event()?;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(&mut *self)
+        };
+        result.map_err(|e| self.locate(e))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let result = self.visit_enum(visitor);
+        result.map_err(|e| self.locate(e))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.skip_value()?;
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier
+    }
+}
+
+impl<E: EventIterator> Deserializer<E> {
+    fn visit_any<'de, V: Visitor<'de>>(&mut self, visitor: V) -> Result<V::Value> {
+        match self.next_event()? {
+            OwnedEvent::Null => visitor.visit_unit(),
+            OwnedEvent::Boolean(b) => visitor.visit_bool(b),
+            OwnedEvent::String(s) => visitor.visit_string(s),
+            OwnedEvent::Bytes(b) => visitor.visit_byte_buf(b),
+            OwnedEvent::Number(n) => visit_number(n, visitor),
+            OwnedEvent::Int(n) => visitor.visit_i64(n),
+            OwnedEvent::Uint(n) => visitor.visit_u64(n),
+            OwnedEvent::RawNumber(s) => {
+                if let Ok(n) = s.parse() {
+                    visitor.visit_i64(n)
+                } else if let Ok(n) = s.parse() {
+                    visitor.visit_u64(n)
+                } else {
+                    visitor.visit_f64(s.parse().map_err(de::Error::custom)?)
+                }
+            }
+            OwnedEvent::StartArray => {
+                let value = visitor.visit_seq(SeqAccess { de: &mut *self })?;
+                self.expect(OwnedEvent::EndArray)?;
+                Ok(value)
+            }
+            OwnedEvent::StartMap => {
+                let value = visitor.visit_map(MapAccess { de: &mut *self })?;
+                self.expect(OwnedEvent::EndMap)?;
+                Ok(value)
+            }
+            event => Err(de::Error::custom(format!("unexpected {:?}", event))),
+        }
+    }
+
+    fn visit_enum<'de, V: Visitor<'de>>(&mut self, visitor: V) -> Result<V::Value> {
+        match self.next_event()? {
+            OwnedEvent::String(s) => visitor.visit_enum(s.into_deserializer()),
+            OwnedEvent::StartMap => {
+                let value = visitor.visit_enum(EnumAccess { de: &mut *self })?;
+                self.expect(OwnedEvent::EndMap)?;
+                Ok(value)
+            }
+            event => Err(de::Error::custom(format!("expected enum, got {:?}", event))),
+        }
+    }
+}
+
+struct SeqAccess<'a, E: EventIterator> {
+    de: &'a mut Deserializer<E>,
+}
+
+impl<'de, 'a, E: EventIterator> de::SeqAccess<'de> for SeqAccess<'a, E> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if *self.de.peek_event()? == OwnedEvent::EndArray {
+            return Ok(None)
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+struct MapAccess<'a, E: EventIterator> {
+    de: &'a mut Deserializer<E>,
+}
+
+impl<'de, 'a, E: EventIterator> de::MapAccess<'de> for MapAccess<'a, E> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.de.next_event()? {
+            OwnedEvent::Key(k) => seed.deserialize(k.into_deserializer()).map(Some).map_err(|e| self.de.locate(e)),
+            event => {
+                self.de.peeked = Some(event);
+                Ok(None)
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+struct EnumAccess<'a, E: EventIterator> {
+    de: &'a mut Deserializer<E>,
+}
+
+impl<'de, 'a, E: EventIterator> de::EnumAccess<'de> for EnumAccess<'a, E> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        match self.de.next_event()? {
+            OwnedEvent::Key(k) => Ok((seed.deserialize(k.into_deserializer())?, self)),
+            event => Err(de::Error::custom(format!("expected enum variant, got {:?}", event))),
+        }
+    }
+}
+
+impl<'de, 'a, E: EventIterator> de::VariantAccess<'de> for EnumAccess<'a, E> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        self.de.expect(OwnedEvent::Null)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(&mut *self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(&mut *self.de, visitor)
+    }
+}
+
+macro_rules! forward_to_mut {
+    ($($method:ident($($arg:ident: $ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(mut self, $($arg: $ty,)* visitor: V) -> Result<V::Value> {
+                de::Deserializer::$method(&mut self, $($arg,)* visitor)
+            }
+        )*
+    }
+}
+
+/// Allows `T::deserialize(Deserializer::new(reader))` by forwarding to the
+/// `&mut Deserializer` implementation.
+impl<'de, E: EventIterator> de::Deserializer<'de> for Deserializer<E> {
+    type Error = Error;
+
+    forward_to_mut! {
+        deserialize_any(), deserialize_bool(), deserialize_i8(), deserialize_i16(),
+        deserialize_i32(), deserialize_i64(), deserialize_u8(), deserialize_u16(),
+        deserialize_u32(), deserialize_u64(), deserialize_f32(), deserialize_f64(),
+        deserialize_char(), deserialize_str(), deserialize_string(), deserialize_bytes(),
+        deserialize_byte_buf(), deserialize_option(), deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_seq(), deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(), deserialize_ignored_any(),
+    }
+}
+
+/// Deserializes a single value of type `T` from a reader.
+pub fn from_reader<R: Read, T: de::DeserializeOwned>(reader: R) -> Result<T> {
+    let mut deserializer = Deserializer::new(reader);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
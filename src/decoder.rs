@@ -0,0 +1,149 @@
+use std::io::{self, Read};
+
+
+/// Bytes read at once to recognize the encoding.
+const DETECT_SIZE: usize = 4;
+
+/// Whether the first bytes of input could still start differently encoded
+/// text depending on what comes next: a part of a byte order mark, or a
+/// single byte, which may be followed by a zero in UTF-16.
+fn ambiguous(start: &[u8]) -> bool {
+    matches!(start, [_] | [0xEF, 0xBB])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    /// Not known until the first bytes of input are read.
+    Unknown,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// Turns input into UTF-8 without a byte order mark. UTF-16 is detected by
+/// its BOM or, following RFC 4627, by zero bytes next to the first
+/// character of the document, which is always ASCII.
+pub(crate) struct Decoder<T: Read> {
+    pub inner: T,
+    encoding: Encoding,
+    /// Bytes of input not decoded yet.
+    raw: Vec<u8>,
+    /// UTF-8 not yet returned from `read`.
+    decoded: Vec<u8>,
+    decoded_pos: usize,
+}
+
+impl<T: Read> Decoder<T> {
+
+    pub fn new(inner: T) -> Decoder<T> {
+        Decoder {
+            inner,
+            encoding: Encoding::Unknown,
+            raw: vec![],
+            decoded: vec![],
+            decoded_pos: 0,
+        }
+    }
+
+    /// Returns the reader with the input taken from it but not returned
+    /// from `read` yet: decoded UTF-8 followed by bytes not decoded.
+    pub fn into_parts(mut self) -> (T, Vec<u8>) {
+        let mut rest = self.decoded.split_off(self.decoded_pos);
+        rest.append(&mut self.raw);
+        (self.inner, rest)
+    }
+
+    /// Makes the decoder pass input through untouched.
+    pub fn set_transparent(&mut self) {
+        self.encoding = Encoding::Utf8;
+    }
+
+    /// Appends up to `size` bytes of input to `raw`.
+    fn fill(&mut self, size: usize) -> io::Result<usize> {
+        let start = self.raw.len();
+        self.raw.resize(start + size, 0);
+        let result = self.inner.read(&mut self.raw[start..]);
+        self.raw.truncate(start + *result.as_ref().unwrap_or(&0));
+        result
+    }
+
+    /// Recognizes the encoding from the first read, and only reads more when
+    /// the first bytes are ambiguous, so that it doesn't wait for input which
+    /// may not come soon.
+    fn detect(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.raw.is_empty() || ambiguous(&self.raw) {
+            if self.fill(DETECT_SIZE.max(buf.len()))? == 0 {
+                break
+            }
+        }
+        let (encoding, bom) = match self.raw[..] {
+            [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+            [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+            [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+            [0, b, ..] if b != 0 => (Encoding::Utf16Be, 0),
+            [b, 0, ..] if b != 0 => (Encoding::Utf16Le, 0),
+            _ => (Encoding::Utf8, 0),
+        };
+        self.encoding = encoding;
+        self.raw.drain(..bom);
+        if encoding == Encoding::Utf8 {
+            self.decoded = std::mem::take(&mut self.raw);
+            self.decoded_pos = 0;
+        }
+        self.read(buf)
+    }
+
+    /// Decodes complete UTF-16 characters from `raw` into `decoded`.
+    fn decode(&mut self) -> io::Result<()> {
+        let unit = |pair: &[u8], encoding| match encoding {
+            Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+            _ => u16::from_be_bytes([pair[0], pair[1]]),
+        };
+        let units: Vec<u16> = self.raw.chunks_exact(2).map(|pair| unit(pair, self.encoding)).collect();
+        // A high surrogate at the end waits for its pair
+        let complete = match units.last() {
+            Some(0xD800..=0xDBFF) => units.len() - 1,
+            _ => units.len(),
+        };
+        self.decoded.clear();
+        self.decoded_pos = 0;
+        let mut encode_buffer = [0; 4];
+        for ch in char::decode_utf16(units[..complete].iter().cloned()) {
+            let ch = ch.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.decoded.extend_from_slice(ch.encode_utf8(&mut encode_buffer).as_bytes());
+        }
+        self.raw.drain(..complete * 2);
+        Ok(())
+    }
+
+    fn transcode(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            self.decode()?;
+            if !self.decoded.is_empty() {
+                return self.read(buf)
+            }
+            if self.fill(DETECT_SIZE.max(buf.len()))? == 0 {
+                if !self.raw.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete UTF-16 character"))
+                }
+                return Ok(0)
+            }
+        }
+    }
+}
+
+impl<T: Read> Read for Decoder<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.decoded_pos < self.decoded.len() {
+            let size = buf.len().min(self.decoded.len() - self.decoded_pos);
+            buf[..size].copy_from_slice(&self.decoded[self.decoded_pos..self.decoded_pos + size]);
+            self.decoded_pos += size;
+            return Ok(size)
+        }
+        match self.encoding {
+            Encoding::Unknown => self.detect(buf),
+            Encoding::Utf8 => self.inner.read(buf),
+            Encoding::Utf16Le | Encoding::Utf16Be => self.transcode(buf),
+        }
+    }
+}
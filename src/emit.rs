@@ -0,0 +1,187 @@
+use std::convert::TryFrom;
+use std::io;
+use std::vec;
+
+use crate::errors::{Error, Result};
+use crate::parser::OwnedEvent;
+use crate::writer::Writer;
+
+
+/// Numbers `Emit::number` takes, turned into the event a parser would
+/// produce for them.
+pub trait Number {
+    fn into_event(self) -> OwnedEvent;
+}
+
+macro_rules! signed {
+    ($($t:ty)*) => {$(
+        impl Number for $t {
+            fn into_event(self) -> OwnedEvent {
+                OwnedEvent::Int(self as i64)
+            }
+        }
+    )*}
+}
+
+signed!(i8 i16 i32 i64 u8 u16 u32);
+
+impl Number for u64 {
+    fn into_event(self) -> OwnedEvent {
+        match i64::try_from(self) {
+            Ok(value) => OwnedEvent::Int(value),
+            Err(_) => OwnedEvent::Uint(self),
+        }
+    }
+}
+
+impl Number for f32 {
+    fn into_event(self) -> OwnedEvent {
+        OwnedEvent::Number(self as f64)
+    }
+}
+
+impl Number for f64 {
+    fn into_event(self) -> OwnedEvent {
+        OwnedEvent::Number(self)
+    }
+}
+
+/// Builds a sequence of events by hand, checking each one the way
+/// `Writer` does, so a misplaced key or an unclosed container is reported
+/// instead of producing a broken stream. After the first wrong event the
+/// rest are ignored and the error is returned from `events`.
+///
+/// ```
+/// use ijson::builder::Builder;
+/// use ijson::emit::Emit;
+///
+/// let events = Emit::map()
+///     .key("a").number(1)
+///     .key("b").start_array().string("x").null().end()
+///     .end();
+/// let value = events.into_iter().items("").next().unwrap().unwrap();
+/// assert_eq!(value.to_string(), r#"{"a":1,"b":["x",null]}"#);
+///
+/// assert!(Emit::array().key("a").events().is_err());
+/// ```
+pub struct Emit {
+    events: Vec<OwnedEvent>,
+    /// Open containers, `true` for objects.
+    stack: Vec<bool>,
+    checker: Writer<io::Sink>,
+    error: Option<Error>,
+}
+
+impl Default for Emit {
+    fn default() -> Self {
+        Emit::new()
+    }
+}
+
+impl Emit {
+    /// Starts an empty sequence, for a top-level scalar.
+    pub fn new() -> Emit {
+        Emit {
+            events: vec![],
+            stack: vec![],
+            checker: Writer::new(io::sink()),
+            error: None,
+        }
+    }
+
+    /// Starts a sequence with an object, same as `Emit::new().start_map()`.
+    pub fn map() -> Emit {
+        Emit::new().start_map()
+    }
+
+    /// Starts a sequence with an array, same as
+    /// `Emit::new().start_array()`.
+    pub fn array() -> Emit {
+        Emit::new().start_array()
+    }
+
+    /// Adds any event.
+    pub fn event(mut self, event: OwnedEvent) -> Emit {
+        if self.error.is_some() {
+            return self
+        }
+        match self.checker.write_event(event.as_event()) {
+            Ok(()) => {
+                match event {
+                    OwnedEvent::StartMap => self.stack.push(true),
+                    OwnedEvent::StartArray => self.stack.push(false),
+                    OwnedEvent::EndMap | OwnedEvent::EndArray => {
+                        self.stack.pop();
+                    }
+                    _ => (),
+                }
+                self.events.push(event);
+            }
+            Err(e) => self.error = Some(e),
+        }
+        self
+    }
+
+    pub fn start_map(self) -> Emit {
+        self.event(OwnedEvent::StartMap)
+    }
+
+    pub fn start_array(self) -> Emit {
+        self.event(OwnedEvent::StartArray)
+    }
+
+    /// Closes the innermost open container.
+    pub fn end(self) -> Emit {
+        match self.stack.last() {
+            Some(true) => self.event(OwnedEvent::EndMap),
+            _ => self.event(OwnedEvent::EndArray),
+        }
+    }
+
+    pub fn key(self, key: &str) -> Emit {
+        self.event(OwnedEvent::Key(key.to_string()))
+    }
+
+    pub fn null(self) -> Emit {
+        self.event(OwnedEvent::Null)
+    }
+
+    pub fn boolean(self, value: bool) -> Emit {
+        self.event(OwnedEvent::Boolean(value))
+    }
+
+    pub fn string(self, value: &str) -> Emit {
+        self.event(OwnedEvent::String(value.to_string()))
+    }
+
+    /// Adds a number of any Rust numeric type: integers become
+    /// `Event::Int`, or `Event::Uint` if they don't fit it, and floats
+    /// `Event::Number`. Non-finite floats are rejected.
+    pub fn number<N: Number>(self, value: N) -> Emit {
+        self.event(value.into_event())
+    }
+
+    /// Returns the events, failing if one was wrong or the document isn't
+    /// complete.
+    pub fn events(self) -> Result<Vec<OwnedEvent>> {
+        if let Some(e) = self.error {
+            return Err(e)
+        }
+        self.checker.finish()?;
+        Ok(self.events)
+    }
+}
+
+impl IntoIterator for Emit {
+    type Item = Result<OwnedEvent>;
+    type IntoIter = vec::IntoIter<Result<OwnedEvent>>;
+
+    /// Yields the events, followed by the error `events` would return.
+    fn into_iter(self) -> Self::IntoIter {
+        let mut result: Vec<_> = self.events.iter().cloned().map(Ok).collect();
+        if let Err(e) = self.events() {
+            result.push(Err(e));
+        }
+        result.into_iter()
+    }
+}
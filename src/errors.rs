@@ -1,4 +1,5 @@
 use std::{io, str, error, fmt, result};
+use std::ops::BitOr;
 
 
 #[macro_export]
@@ -11,69 +12,425 @@ macro_rules! itry {
     }
 }
 
+/// A location in the source stream. Lines and columns are 1-based, columns
+/// and offsets are counted in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position { line: 1, column: 1, offset: 0 }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A window of input around the position of an error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub bytes: Vec<u8>,
+    /// Index in `bytes` where the error is.
+    pub caret: usize,
+    /// Whether there's input before and after `bytes`.
+    pub more_before: bool,
+    pub more_after: bool,
+}
+
+impl fmt::Display for Snippet {
+    /// Shows the snippet on one line with a caret under the error on the
+    /// next one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = |bytes: &[u8]| -> String {
+            String::from_utf8_lossy(bytes).chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+        };
+        let before = printable(&self.bytes[..self.caret]);
+        let after = printable(&self.bytes[self.caret..]);
+        let ellipsis = |more| if more { "…" } else { "" };
+        writeln!(f, "{}{}{}{}", ellipsis(self.more_before), before, after, ellipsis(self.more_after))?;
+        let indent = before.chars().count() + usize::from(self.more_before);
+        write!(f, "{:indent$}^", "", indent = indent)
+    }
+}
+
+/// What's wrong with a number rejected by `Error::Number`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumberError {
+    /// A leading plus, as in `+1`.
+    PlusSign,
+    /// A zero followed by more digits, as in `01`.
+    LeadingZero,
+    /// No digits before the decimal point, as in `.5` or `-`.
+    NoInteger,
+    /// No digits after the decimal point, as in `1.`.
+    NoFraction,
+    /// No digits in the exponent, as in `1e` or `1e+`.
+    NoExponent,
+    /// Anything after a complete number, as in `1.5.5`.
+    TrailingData,
+}
+
+impl fmt::Display for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            NumberError::PlusSign => "leading plus sign",
+            NumberError::LeadingZero => "leading zero",
+            NumberError::NoInteger => "no integer part",
+            NumberError::NoFraction => "no digits after decimal point",
+            NumberError::NoExponent => "no digits in exponent",
+            NumberError::TrailingData => "unexpected characters after number",
+        })
+    }
+}
+
+/// What the parser would have accepted where it found something else, see
+/// `Error::Unexpected`. Empty when an error doesn't come from the parser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Expected(u8);
+
+impl Expected {
+    pub const VALUE: Expected = Expected(1);
+    pub const KEY: Expected = Expected(2);
+    pub const COLON: Expected = Expected(4);
+    pub const COMMA: Expected = Expected(8);
+    pub const END_ARRAY: Expected = Expected(16);
+    pub const END_MAP: Expected = Expected(32);
+
+    const NAMES: [(Expected, &'static str); 6] = [
+        (Expected::VALUE, "a value"),
+        (Expected::KEY, "a key"),
+        (Expected::COLON, "`:`"),
+        (Expected::COMMA, "`,`"),
+        (Expected::END_ARRAY, "`]`"),
+        (Expected::END_MAP, "`}`"),
+    ];
+
+    pub fn contains(self, other: Expected) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Adds `other` if `condition` holds.
+    pub(crate) fn with(self, other: Expected, condition: bool) -> Expected {
+        if condition { self | other } else { self }
+    }
+}
+
+impl BitOr for Expected {
+    type Output = Expected;
+
+    fn bitor(self, other: Expected) -> Expected {
+        Expected(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Expected {
+    /// Lists the alternatives, as in "a value or `]`".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<_> = Expected::NAMES.iter()
+            .filter(|(expected, _)| self.contains(*expected))
+            .map(|(_, name)| *name)
+            .collect();
+        match names.split_last() {
+            None => Ok(()),
+            Some((last, [])) => f.write_str(last),
+            Some((last, rest)) => write!(f, "{} or {}", rest.join(", "), last),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
+    Unterminated(Position),
+    IO(io::Error, Position),
+    Unknown(Vec<u8>, Position),
+    Number(NumberError, Position),
+    /// A lexeme out of place, along with what would fit there.
+    Unexpected(Expected, Position),
+    Utf8(str::Utf8Error, Position),
+    Escape(Vec<u8>, Position),
+    /// An unescaped control character in a string.
+    ControlCharacter(u8, Position),
+    LoneSurrogate(u16, Position),
+    /// The input ended before the document was complete.
+    Incomplete(Position),
+    Unmatched(Position),
+    AdditionalData(Position),
+    DepthLimit(Position),
+    StringLimit(Position),
+    NumberLimit(Position),
+    EventLimit(Position),
+    UnexpectedEvent(Position),
+    /// A key repeated in an object, see `DuplicateKeys::Error` and
+    /// `Parser::set_reject_duplicate_keys`.
+    DuplicateKey(String, Position),
+    /// Parsing was stopped by `Parser::set_cancel_flag`.
+    Cancelled(Position),
+    Custom(String, Position),
+    /// An error with the input around it, see `Parser::set_error_context`.
+    Context(Box<Error>, Snippet),
+    /// An error with the dotted path of the value where it happened, added
+    /// by prefixes, see `Error::path`.
+    Path(Box<Error>, String),
+}
+
+/// The variant of an `Error` without its data, for matching errors
+/// regardless of context, see `Error::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
     Unterminated,
-    IO(io::Error),
-    Unknown(Vec<u8>),
+    IO,
+    Unknown,
+    Number(NumberError),
     Unexpected,
-    Utf8(str::Utf8Error),
-    Escape(Vec<u8>),
-    MoreLexemes,
+    Utf8,
+    Escape,
+    ControlCharacter,
+    LoneSurrogate,
+    Incomplete,
     Unmatched,
     AdditionalData,
+    DepthLimit,
+    StringLimit,
+    NumberLimit,
+    EventLimit,
+    UnexpectedEvent,
+    DuplicateKey,
+    Cancelled,
+    Custom,
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+impl Error {
+    /// The kind of the error, looking through `Error::Context`.
+    pub fn kind(&self) -> ErrorKind {
         match *self {
-            Error::Unterminated => write!(f, "{}", self),
-            Error::IO(_) => write!(f, "I/O Error: {}", self),
-            Error::Unknown(ref s) => write!(f, "Unknown lexeme: '{:?}'", s),
-            Error::Unexpected => write!(f, "Unexpected lexeme"),
-            Error::Utf8(ref e) => write!(f, "UTF8 Error: {}", e),
-            Error::Escape(ref s) => write!(f, "Malformed escape: {:?}", s),
-            Error::MoreLexemes => write!(f, "More lexemes expected"),
-            Error::Unmatched => write!(f, "Unmatched container terminator"),
-            Error::AdditionalData => write!(f, "Additional data in the source stream after parsed value"),
+            Error::Unterminated(_) => ErrorKind::Unterminated,
+            Error::IO(..) => ErrorKind::IO,
+            Error::Unknown(..) => ErrorKind::Unknown,
+            Error::Number(e, _) => ErrorKind::Number(e),
+            Error::Unexpected(..) => ErrorKind::Unexpected,
+            Error::Utf8(..) => ErrorKind::Utf8,
+            Error::Escape(..) => ErrorKind::Escape,
+            Error::ControlCharacter(..) => ErrorKind::ControlCharacter,
+            Error::LoneSurrogate(..) => ErrorKind::LoneSurrogate,
+            Error::Incomplete(_) => ErrorKind::Incomplete,
+            Error::Unmatched(_) => ErrorKind::Unmatched,
+            Error::AdditionalData(_) => ErrorKind::AdditionalData,
+            Error::DepthLimit(_) => ErrorKind::DepthLimit,
+            Error::StringLimit(_) => ErrorKind::StringLimit,
+            Error::NumberLimit(_) => ErrorKind::NumberLimit,
+            Error::EventLimit(_) => ErrorKind::EventLimit,
+            Error::UnexpectedEvent(_) => ErrorKind::UnexpectedEvent,
+            Error::DuplicateKey(..) => ErrorKind::DuplicateKey,
+            Error::Cancelled(_) => ErrorKind::Cancelled,
+            Error::Custom(..) => ErrorKind::Custom,
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.kind(),
         }
     }
-}
 
-impl error::Error for Error {
-    fn description(&self) -> &str {
+    /// The input at fault: the bytes of an unknown lexeme or a malformed
+    /// escape, a control character or a duplicate key.
+    pub fn offending_bytes(&self) -> Option<&[u8]> {
         match *self {
-            Error::Unterminated => "unterminated string",
-            Error::IO(ref e) => e.description(),
-            Error::Unknown(..) => "unknown lexeme",
-            Error::Unexpected => "unexpected lexeme",
-            Error::Utf8(ref e) => e.description(),
-            Error::Escape(..) => "malformed escape",
-            Error::MoreLexemes => "more lexemes expected",
-            Error::Unmatched => "unmatched container terminator",
-            Error::AdditionalData => "additional data",
+            Error::Unknown(ref bytes, _) | Error::Escape(ref bytes, _) => Some(bytes),
+            Error::ControlCharacter(ref byte, _) => Some(std::slice::from_ref(byte)),
+            Error::DuplicateKey(ref key, _) => Some(key.as_bytes()),
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.offending_bytes(),
+            _ => None,
+        }
+    }
+
+    /// What would have fit in place of an unexpected lexeme.
+    pub fn expected(&self) -> Option<Expected> {
+        match *self {
+            Error::Unexpected(expected, _) => Some(expected),
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.expected(),
+            _ => None,
         }
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    /// The path of the value where the error happened, as in `docs.3.id`,
+    /// if the error came through a prefix.
+    pub fn path(&self) -> Option<&str> {
         match *self {
-            Error::Unterminated => None,
-            Error::IO(ref e) => Some(e),
-            Error::Utf8(ref e) => Some(e),
+            Error::Path(_, ref path) => Some(path),
+            Error::Context(ref e, _) => e.path(),
             _ => None,
         }
     }
+
+    /// Adds the path of the value where the error happened, unless it has
+    /// one already.
+    pub(crate) fn with_path(self, path: String) -> Error {
+        match self.path() {
+            Some(_) => self,
+            None => Error::Path(Box::new(self), path),
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        match *self {
+            Error::Unterminated(pos) |
+            Error::IO(_, pos) |
+            Error::Unknown(_, pos) |
+            Error::Number(_, pos) |
+            Error::Unexpected(_, pos) |
+            Error::Utf8(_, pos) |
+            Error::Escape(_, pos) |
+            Error::ControlCharacter(_, pos) |
+            Error::LoneSurrogate(_, pos) |
+            Error::Incomplete(pos) |
+            Error::Unmatched(pos) |
+            Error::AdditionalData(pos) |
+            Error::DepthLimit(pos) |
+            Error::StringLimit(pos) |
+            Error::NumberLimit(pos) |
+            Error::EventLimit(pos) |
+            Error::UnexpectedEvent(pos) |
+            Error::DuplicateKey(_, pos) |
+            Error::Cancelled(pos) |
+            Error::Custom(_, pos) => pos,
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.position(),
+        }
+    }
+
+    /// Renders the error with the input around it if it's available:
+    ///
+    /// ```text
+    /// Unexpected lexeme at line 3, column 11: …"other" 1
+    ///                                               ^
+    /// ```
+    pub fn diagnostic(&self) -> String {
+        match *self {
+            Error::Path(ref e, _) => e.diagnostic(),
+            Error::Context(ref e, ref snippet) => {
+                let message = format!("{}: ", e);
+                let snippet = snippet.to_string();
+                let mut lines = snippet.lines();
+                let text = lines.next().unwrap_or("");
+                let caret = lines.next().unwrap_or("");
+                format!("{}{}\n{:indent$}{}", message, text, "", caret, indent = message.chars().count())
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    /// Renders a full diagnostic in the common format of compilers and
+    /// linters, naming the input `source_name` and listing what was
+    /// expected, with the input around the error if it's available:
+    ///
+    /// ```text
+    /// data.json:3:11: Unexpected lexeme, expected `,` or `}`
+    ///     …"other" 1
+    ///               ^
+    /// ```
+    pub fn render(&self, source_name: &str) -> String {
+        let position = self.position();
+        let mut result = format!("{}:{}:{}: {}", source_name, position.line, position.column, Message(self));
+        if let Some(path) = self.path().filter(|path| !path.is_empty()) {
+            result.push_str(&format!(" in {}", path));
+        }
+        let error = match *self {
+            Error::Path(ref e, _) => e,
+            _ => self,
+        };
+        // At the end of input there may be none of it left to show
+        match *error {
+            Error::Context(_, ref snippet) if !snippet.bytes.is_empty() => {
+                for line in snippet.to_string().lines() {
+                    result.push_str("\n    ");
+                    result.push_str(line);
+                }
+            }
+            _ => (),
+        }
+        result
+    }
 }
 
-impl From<io::Error> for Error {
-    fn from(e: io::Error) -> Self {
-        Error::IO(e)
+impl Error {
+    /// Whether the input was cut off in the middle of a document, as opposed
+    /// to being invalid. The same input followed by more data may parse.
+    pub fn is_incomplete(&self) -> bool {
+        match *self {
+            Error::Incomplete(_) | Error::Unterminated(_) => true,
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.is_incomplete(),
+            _ => false,
+        }
+    }
+
+    /// Whether the error is a reader signalling that no input is available
+    /// at the moment.
+    pub fn is_would_block(&self) -> bool {
+        match *self {
+            Error::IO(ref e, _) => e.kind() == io::ErrorKind::WouldBlock,
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.is_would_block(),
+            _ => false,
+        }
+    }
+}
+
+/// The message of an error without its position.
+struct Message<'a>(&'a Error);
+
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Error::Unterminated(_) => write!(f, "Unterminated string"),
+            Error::IO(ref e, _) => write!(f, "I/O Error: {}", e),
+            Error::Unknown(ref s, _) => write!(f, "Unknown lexeme: '{}'", String::from_utf8_lossy(s)),
+            Error::Number(e, _) => write!(f, "Malformed number: {}", e),
+            Error::Unexpected(expected, _) if !expected.is_empty() => write!(f, "Unexpected lexeme, expected {}", expected),
+            Error::Unexpected(..) => write!(f, "Unexpected lexeme"),
+            Error::Utf8(ref e, _) => write!(f, "UTF8 Error: {}", e),
+            Error::Escape(ref s, _) => write!(f, "Malformed escape: '{}'", String::from_utf8_lossy(s)),
+            Error::ControlCharacter(b, _) => write!(f, "Unescaped control character 0x{:02X} in string", b),
+            Error::LoneSurrogate(v, _) => write!(f, "Unpaired UTF-16 surrogate: \\u{:04X}", v),
+            Error::Incomplete(_) => write!(f, "Incomplete document"),
+            Error::Unmatched(_) => write!(f, "Unmatched container terminator"),
+            Error::AdditionalData(_) => write!(f, "Additional data in the source stream after parsed value"),
+            Error::DepthLimit(_) => write!(f, "Maximum nesting depth exceeded"),
+            Error::StringLimit(_) => write!(f, "Maximum string length exceeded"),
+            Error::NumberLimit(_) => write!(f, "Maximum number length exceeded"),
+            Error::EventLimit(_) => write!(f, "Maximum number of events exceeded"),
+            Error::UnexpectedEvent(_) => write!(f, "Unexpected event"),
+            Error::DuplicateKey(ref k, _) => write!(f, "Duplicate key {:?}", k),
+            Error::Cancelled(_) => write!(f, "Parsing cancelled"),
+            Error::Custom(ref s, _) => write!(f, "{}", s),
+            Error::Context(ref e, _) | Error::Path(ref e, _) => write!(f, "{}", Message(e)),
+        }
     }
 }
 
-impl From<str::Utf8Error> for Error {
-    fn from(e: str::Utf8Error) -> Self {
-        Error::Utf8(e)
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        write!(f, "{} at {}", Message(self), self.position())?;
+        match self.path() {
+            Some(path) if !path.is_empty() => write!(f, " in {}", path),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::IO(ref e, _) => Some(e),
+            Error::Utf8(ref e, _) => Some(e),
+            Error::Context(ref e, _) | Error::Path(ref e, _) => e.source(),
+            _ => None,
+        }
     }
 }
 
@@ -0,0 +1,517 @@
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::str;
+
+use memchr::{memchr, memchr_iter, memrchr};
+
+use crate::builder::Located;
+use crate::errors::{Error, Expected, Position, Result};
+use crate::lexer::{check_number, is_whitespace, number_lexeme, Lexeme};
+use crate::parser::{Event, Limits, OwnedEvent};
+
+
+/// Bytes classified at once by the first stage.
+const BLOCK: usize = 64;
+
+/// Characters of a block of input, a bit per byte.
+#[derive(Default)]
+struct Masks {
+    quote: u64,
+    backslash: u64,
+    structural: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+fn classify(block: &[u8; BLOCK]) -> Masks {
+    use std::arch::x86_64::*;
+
+    let mut masks = Masks::default();
+    // SSE2 is always there on x86_64
+    unsafe {
+        for i in 0..BLOCK / 16 {
+            let chunk = _mm_loadu_si128(block.as_ptr().add(i * 16) as *const __m128i);
+            let eq = |byte: u8| _mm_cmpeq_epi8(chunk, _mm_set1_epi8(byte as i8));
+            let bits = |v: __m128i| (_mm_movemask_epi8(v) as u16 as u64) << (i * 16);
+            let brackets = _mm_or_si128(
+                _mm_or_si128(eq(b'{'), eq(b'}')),
+                _mm_or_si128(eq(b'['), eq(b']')),
+            );
+            masks.quote |= bits(eq(b'"'));
+            masks.backslash |= bits(eq(b'\\'));
+            masks.structural |= bits(_mm_or_si128(brackets, _mm_or_si128(eq(b','), eq(b':'))));
+        }
+    }
+    masks
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn classify(block: &[u8; BLOCK]) -> Masks {
+    let mut masks = Masks::default();
+    for (i, &byte) in block.iter().enumerate() {
+        let bit = 1 << i;
+        match byte {
+            b'"' => masks.quote |= bit,
+            b'\\' => masks.backslash |= bit,
+            b'{' | b'}' | b'[' | b']' | b',' | b':' => masks.structural |= bit,
+            _ => (),
+        }
+    }
+    masks
+}
+
+/// Bits of characters escaped by a backslash before them. `carry` tells
+/// whether the first one is escaped by the end of the previous block and is
+/// updated for the next one.
+fn escaped(mut backslash: u64, carry: &mut bool) -> u64 {
+    let mut escaped = 0;
+    if *carry {
+        escaped = 1;
+        backslash &= !1;
+    }
+    *carry = false;
+    while backslash != 0 {
+        let i = backslash.trailing_zeros();
+        backslash &= backslash - 1;
+        if i == 63 {
+            *carry = true;
+            break
+        }
+        escaped |= 1 << (i + 1);
+        backslash &= !(1 << (i + 1));
+    }
+    escaped
+}
+
+/// Sets every bit to the parity of the bits up to it, which turns bits of
+/// quotes into bits of string contents along with opening quotes.
+fn prefix_xor(mut bits: u64) -> u64 {
+    for shift in [1, 2, 4, 8, 16, 32].iter() {
+        bits ^= bits << shift;
+    }
+    bits
+}
+
+/// Offsets of unescaped quotes and of structural characters outside strings,
+/// in order.
+fn structural_index(data: &[u8]) -> Vec<usize> {
+    let mut index = Vec::with_capacity(data.len() / 8);
+    let mut carry = false;
+    let mut in_string = 0u64;
+    let mut padded = [b' '; BLOCK];
+    for (n, chunk) in data.chunks(BLOCK).enumerate() {
+        let block: &[u8; BLOCK] = match chunk.try_into() {
+            Ok(block) => block,
+            Err(_) => {
+                padded[..chunk.len()].copy_from_slice(chunk);
+                &padded
+            }
+        };
+        let masks = classify(block);
+        let quotes = masks.quote & !escaped(masks.backslash, &mut carry);
+        let inside = prefix_xor(quotes) ^ in_string;
+        in_string = 0u64.wrapping_sub(inside >> 63);
+        let mut bits = (masks.structural & !inside) | quotes;
+        while bits != 0 {
+            index.push(n * BLOCK + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+    }
+    index
+}
+
+fn position_at(data: &[u8], offset: usize) -> Position {
+    let before = &data[..offset];
+    let line_start = memrchr(b'\n', before).map_or(0, |i| i + 1);
+    Position {
+        line: memchr_iter(b'\n', before).count() + 1,
+        column: offset - line_start + 1,
+        offset,
+    }
+}
+
+fn hex4(data: &[u8], at: usize, end: usize) -> Option<u32> {
+    if at + 4 > end {
+        return None
+    }
+    data[at..at + 4].iter().try_fold(0, |value, &b| Some(value * 16 + (b as char).to_digit(16)?))
+}
+
+/// Decodes a `\uXXXX` escape at `at`, combining a surrogate pair, and
+/// returns the character with the length of the escapes.
+fn unicode_escape(data: &[u8], at: usize, end: usize) -> Result<(char, usize)> {
+    let position = || position_at(data, at);
+    let high = hex4(data, at + 2, end).ok_or_else(|| Error::Escape(vec![], position()))?;
+    let (value, len) = match high {
+        0xD800..=0xDBFF => {
+            let low = match data.get(at + 6..at + 8) {
+                Some(b"\\u") => hex4(data, at + 8, end).ok_or_else(|| Error::Escape(vec![], position()))?,
+                _ => return Err(Error::LoneSurrogate(high as u16, position())),
+            };
+            if !(0xDC00..=0xDFFF).contains(&low) {
+                return Err(Error::LoneSurrogate(high as u16, position()))
+            }
+            (0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00), 12)
+        }
+        0xDC00..=0xDFFF => return Err(Error::LoneSurrogate(high as u16, position())),
+        _ => (high, 6),
+    };
+    char::from_u32(value).map(|ch| (ch, len)).ok_or_else(|| Error::Escape(vec![], position()))
+}
+
+/// Decodes escapes of the string contents between `start` and `end` into
+/// `out`.
+fn unescape(data: &[u8], mut start: usize, end: usize, out: &mut Vec<u8>) -> Result<()> {
+    out.clear();
+    loop {
+        let next = memchr(b'\\', &data[start..end]).map_or(end, |i| start + i);
+        out.extend_from_slice(&data[start..next]);
+        if next == end {
+            return Ok(())
+        }
+        // A backslash before the closing quote would have escaped it
+        start = next + 2;
+        let ch = match data[next + 1] {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\x08',
+            b'f' => '\x0c',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let (ch, len) = unicode_escape(data, next, end)?;
+                start = next + len;
+                ch
+            }
+            byte => return Err(Error::Escape(vec![byte], position_at(data, next))),
+        };
+        out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+}
+
+/// Lines counted up to an offset, for positions of tokens, which come in
+/// order, to be found without counting from the start every time.
+#[derive(Clone, Copy)]
+struct Lines {
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Byte(u8),
+    /// A string with the offset of its closing quote.
+    String(usize),
+    Unterminated,
+    /// A number or a literal with the offset of its end.
+    Scalar(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    Value,
+    ValueOrEnd,
+    Key,
+    KeyOrEnd,
+    Colon,
+    CommaOrEnd,
+    Done,
+}
+
+/// Parses a single document in memory in two stages: the first one finds
+/// quotes and structural characters a block of 64 bytes at a time with SIMD
+/// instructions where available, the second one produces events going
+/// from one of them to the next one, looking only at strings and at scalar
+/// values in between. It yields the same events and errors as `Parser` with
+/// default settings, apart from an unknown word being reported as a whole
+/// and positions of some errors pointing at the start of the string.
+///
+/// ```
+/// use ijson::parser::{Event, Parser};
+///
+/// let mut parser = Parser::fast(&br#"{"a": [1, "b"]}"#[..]);
+/// assert_eq!(parser.next().unwrap().unwrap(), Event::StartMap);
+/// assert_eq!(parser.next().unwrap().unwrap(), Event::Key("a"));
+/// let rest: Vec<_> = parser.into_iter().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(rest.len(), 5);
+/// ```
+pub struct FastParser<'a> {
+    data: &'a [u8],
+    index: Vec<usize>,
+    /// Next entry of the index.
+    next: usize,
+    /// Offset past the last token.
+    pos: usize,
+    /// Open containers, `true` for objects.
+    stack: Vec<bool>,
+    expect: Expect,
+    scratch: Vec<u8>,
+    lines: Cell<Lines>,
+    limits: Limits,
+    raw_numbers: bool,
+    events: usize,
+    failed: bool,
+}
+
+impl<'a> FastParser<'a> {
+    /// Builds the index of the whole input at once, skipping a UTF-8 byte
+    /// order mark. Offsets in positions count from after it.
+    pub fn new(data: &'a [u8]) -> FastParser<'a> {
+        let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+        FastParser {
+            data,
+            index: structural_index(data),
+            next: 0,
+            pos: 0,
+            stack: vec![],
+            expect: Expect::Value,
+            scratch: vec![],
+            lines: Cell::new(Lines { offset: 0, line: 1, line_start: 0 }),
+            limits: Limits::default(),
+            raw_numbers: false,
+            events: 0,
+            failed: false,
+        }
+    }
+
+    /// See `Parser::set_limits`.
+    pub fn set_limits(&mut self, value: Limits) {
+        self.limits = value;
+    }
+
+    /// See `Parser::set_raw_numbers`.
+    pub fn set_raw_numbers(&mut self, value: bool) {
+        self.raw_numbers = value;
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let mut lines = self.lines.get();
+        if offset < lines.offset {
+            return position_at(self.data, offset)
+        }
+        let between = &self.data[lines.offset..offset];
+        lines.line += memchr_iter(b'\n', between).count();
+        if let Some(i) = memrchr(b'\n', between) {
+            lines.line_start = lines.offset + i + 1;
+        }
+        lines.offset = offset;
+        self.lines.set(lines);
+        Position { line: lines.line, column: offset - lines.line_start + 1, offset }
+    }
+
+    fn fail(&mut self, error: Error) -> Error {
+        self.failed = true;
+        error
+    }
+
+    fn next_token(&mut self) -> Option<(usize, Token)> {
+        let limit = self.index.get(self.next).copied().unwrap_or(self.data.len());
+        let mut start = self.pos;
+        while start < limit && is_whitespace(self.data[start]) {
+            start += 1;
+        }
+        self.pos = start;
+        if start < limit {
+            let end = self.data[start..limit].iter().position(|&b| is_whitespace(b)).map_or(limit, |i| start + i);
+            self.pos = end;
+            return Some((start, Token::Scalar(end)))
+        }
+        let at = *self.index.get(self.next)?;
+        self.next += 1;
+        if self.data[at] != b'"' {
+            self.pos = at + 1;
+            return Some((at, Token::Byte(self.data[at])))
+        }
+        // Nothing inside a string is indexed, so the next entry closes it
+        match self.index.get(self.next) {
+            Some(&close) => {
+                self.next += 1;
+                self.pos = close + 1;
+                Some((at, Token::String(close)))
+            }
+            None => {
+                self.pos = self.data.len();
+                Some((at, Token::Unterminated))
+            }
+        }
+    }
+
+    /// Checks the string between quotes at `open` and `close`, returning
+    /// whether it's decoded into `scratch`.
+    fn check_string(&mut self, open: usize, close: usize) -> Result<bool> {
+        let raw = &self.data[open + 1..close];
+        if raw.len() > self.limits.max_string {
+            return Err(Error::StringLimit(self.position(open)))
+        }
+        if let Some(i) = raw.iter().position(|&b| b < 0x20) {
+            return Err(Error::ControlCharacter(raw[i], self.position(open + 1 + i)))
+        }
+        let escaped = memchr(b'\\', raw).is_some();
+        if escaped {
+            unescape(self.data, open + 1, close, &mut self.scratch)?;
+        }
+        let text = if escaped { &self.scratch[..] } else { raw };
+        str::from_utf8(text).map_err(|e| Error::Utf8(e, position_at(self.data, open)))?;
+        Ok(escaped)
+    }
+
+    fn string(&self, open: usize, close: usize, escaped: bool) -> &str {
+        let text = if escaped { &self.scratch[..] } else { &self.data[open + 1..close] };
+        // Validated in `check_string`
+        unsafe { str::from_utf8_unchecked(text) }
+    }
+
+    fn scalar(&self, start: usize, end: usize) -> Result<Event<'a>> {
+        let data: &'a [u8] = self.data;
+        let text = &data[start..end];
+        Ok(match text {
+            b"true" => Event::Boolean(true),
+            b"false" => Event::Boolean(false),
+            b"null" => Event::Null,
+            [b'-', ..] | [b'0'..=b'9', ..] => {
+                if text.len() > self.limits.max_number {
+                    return Err(Error::NumberLimit(self.position(start)))
+                }
+                check_number(text).map_err(|e| Error::Number(e, self.position(start)))?;
+                // A checked number is ASCII
+                let text = unsafe { str::from_utf8_unchecked(text) };
+                if self.raw_numbers {
+                    return Ok(Event::RawNumber(text))
+                }
+                match number_lexeme(text, self.position(start))? {
+                    Lexeme::Int(value) => Event::Int(value),
+                    Lexeme::Uint(value) => Event::Uint(value),
+                    Lexeme::Number(value) => Event::Number(value),
+                    _ => unreachable!(),
+                }
+            }
+            _ => return Err(Error::Unknown(text.to_vec(), self.position(start))),
+        })
+    }
+
+    fn after_value(&mut self) {
+        self.expect = if self.stack.is_empty() { Expect::Done } else { Expect::CommaOrEnd };
+    }
+
+    fn open(&mut self, object: bool, at: usize) -> Result<Event<'static>> {
+        if self.stack.len() >= self.limits.max_depth {
+            return Err(Error::DepthLimit(self.position(at)))
+        }
+        self.stack.push(object);
+        self.expect = if object { Expect::KeyOrEnd } else { Expect::ValueOrEnd };
+        Ok(if object { Event::StartMap } else { Event::StartArray })
+    }
+
+    fn close(&mut self, object: bool, at: usize) -> Result<Event<'static>> {
+        if self.stack.pop() != Some(object) {
+            return Err(Error::Unmatched(self.position(at)))
+        }
+        self.after_value();
+        Ok(if object { Event::EndMap } else { Event::EndArray })
+    }
+
+    fn expected_end(&self) -> Expected {
+        match self.stack.last() {
+            Some(true) => Expected::END_MAP,
+            _ => Expected::END_ARRAY,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<Event<'_>>> {
+        if self.failed {
+            return None
+        }
+        let (at, token) = loop {
+            let (at, token) = match self.next_token() {
+                Some(token) => token,
+                None if self.expect == Expect::Done => return None,
+                None => {
+                    let error = Error::Incomplete(self.position(self.data.len()));
+                    return Some(Err(self.fail(error)))
+                }
+            };
+            match (self.expect, token) {
+                (Expect::Colon, Token::Byte(b':')) => self.expect = Expect::Value,
+                (Expect::CommaOrEnd, Token::Byte(b',')) => {
+                    self.expect = if self.stack.last() == Some(&true) { Expect::Key } else { Expect::Value };
+                }
+                _ => break (at, token),
+            }
+        };
+        if self.expect != Expect::Done && self.events >= self.limits.max_events {
+            let error = Error::EventLimit(self.position(at));
+            return Some(Err(self.fail(error)))
+        }
+        self.events += 1;
+        let position = self.position(at);
+        let result = match (self.expect, token) {
+            (Expect::Done, _) => Err(Error::AdditionalData(position)),
+            (_, Token::Unterminated) if self.expect != Expect::Colon && self.expect != Expect::CommaOrEnd => {
+                Err(Error::Unterminated(position))
+            }
+            (Expect::Key, Token::String(close)) | (Expect::KeyOrEnd, Token::String(close)) |
+            (Expect::Value, Token::String(close)) | (Expect::ValueOrEnd, Token::String(close)) => {
+                let key = matches!(self.expect, Expect::Key | Expect::KeyOrEnd);
+                let escaped = match self.check_string(at, close) {
+                    Ok(escaped) => escaped,
+                    Err(e) => return Some(Err(self.fail(e))),
+                };
+                if key {
+                    self.expect = Expect::Colon;
+                } else {
+                    self.after_value();
+                }
+                let text = self.string(at, close, escaped);
+                return Some(Ok(if key { Event::Key(text) } else { Event::String(text) }))
+            }
+            (Expect::KeyOrEnd, Token::Byte(b'}')) | (Expect::CommaOrEnd, Token::Byte(b'}')) => self.close(true, at),
+            (Expect::ValueOrEnd, Token::Byte(b']')) | (Expect::CommaOrEnd, Token::Byte(b']')) => self.close(false, at),
+            (Expect::Value, Token::Byte(b'{')) | (Expect::ValueOrEnd, Token::Byte(b'{')) => self.open(true, at),
+            (Expect::Value, Token::Byte(b'[')) | (Expect::ValueOrEnd, Token::Byte(b'[')) => self.open(false, at),
+            (Expect::Value, Token::Scalar(end)) | (Expect::ValueOrEnd, Token::Scalar(end)) => {
+                let result = self.scalar(at, end);
+                self.after_value();
+                result
+            }
+            (Expect::Colon, _) => Err(Error::Unexpected(Expected::COLON, position)),
+            (Expect::CommaOrEnd, _) => Err(Error::Unexpected(Expected::COMMA | self.expected_end(), position)),
+            (Expect::Key, _) => Err(Error::Unexpected(Expected::KEY, position)),
+            (Expect::KeyOrEnd, _) => Err(Error::Unexpected(Expected::KEY | Expected::END_MAP, position)),
+            (Expect::Value, _) => Err(Error::Unexpected(Expected::VALUE, position)),
+            (Expect::ValueOrEnd, _) => Err(Error::Unexpected(Expected::VALUE | Expected::END_ARRAY, position)),
+        };
+        Some(result.map_err(|e| self.fail(e)))
+    }
+}
+
+/// Owned events of a `FastParser`, for `Builder` and the rest of
+/// `EventIterator` adapters.
+pub struct FastEvents<'a> {
+    parser: FastParser<'a>,
+}
+
+impl<'a> Located for FastEvents<'a> {
+    fn location(&self) -> Position {
+        self.parser.position(self.parser.pos)
+    }
+}
+
+impl<'a> Iterator for FastEvents<'a> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parser.next().map(|result| result.map(OwnedEvent::from))
+    }
+}
+
+impl<'a> IntoIterator for FastParser<'a> {
+    type Item = Result<OwnedEvent>;
+    type IntoIter = FastEvents<'a>;
+
+    fn into_iter(self) -> FastEvents<'a> {
+        FastEvents { parser: self }
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+/// Default time to wait before reading again at the end of input.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Reads a file that is still being written, like `tail -f`: at the end of
+/// input it waits and reads again instead of reporting the end, so that a
+/// parser treats a value cut off at the end as one to be continued.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use ijson::follow::Follow;
+/// use ijson::parser::Parser;
+///
+/// let file = Follow::new(File::open("app.log.jsonl").unwrap());
+/// for event in Parser::new_multi(file).into_iter() {
+///     println!("{:?}", event.unwrap());
+/// }
+/// ```
+pub struct Follow<R: Read> {
+    reader: R,
+    interval: Duration,
+    timeout: Option<Duration>,
+}
+
+impl<R: Read> Follow<R> {
+    pub fn new(reader: R) -> Follow<R> {
+        Follow {
+            reader,
+            interval: POLL_INTERVAL,
+            timeout: None,
+        }
+    }
+
+    /// Sets the time to wait before reading again at the end of input.
+    pub fn set_interval(&mut self, value: Duration) {
+        self.interval = value;
+    }
+
+    /// Makes the end of input final once there has been nothing new to read
+    /// for `value`. By default it's never final.
+    pub fn set_timeout(&mut self, value: Option<Duration>) {
+        self.timeout = value;
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for Follow<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        let start = Instant::now();
+        loop {
+            match self.reader.read(buf)? {
+                0 if self.timeout.is_some_and(|timeout| start.elapsed() >= timeout) => return Ok(0),
+                0 => thread::sleep(self.interval),
+                size => return Ok(size),
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::Cursor;
+
+use rustc_serialize::json::Json;
+
+use crate::builder::Builder;
+use crate::errors::ErrorKind;
+use crate::lexer::Lexer;
+use crate::parser::{OwnedEvent, Parser};
+
+
+/// Buffer sizes a document is parsed with to have its lexemes split at
+/// every possible place.
+const BUFFER_SIZES: [usize; 5] = [1, 2, 3, 7, 64];
+
+/// Deepest nesting of generated documents.
+const MAX_DEPTH: usize = 6;
+
+/// Events of a whole document and the kind of the error it ended with.
+fn events<I: Iterator<Item=crate::errors::Result<OwnedEvent>>>(events: I) -> (Vec<OwnedEvent>, Option<ErrorKind>) {
+    let mut result = vec![];
+    for event in events {
+        match event {
+            Ok(event) => result.push(event),
+            Err(e) => return (result, Some(e.kind())),
+        }
+    }
+    (result, None)
+}
+
+/// Lexes arbitrary input to the end or to the first error.
+pub fn lexer(data: &[u8]) {
+    let mut lexer = Lexer::from_slice(data);
+    // An error may be reported more than once, but not forever
+    for _ in 0..data.len() + 2 {
+        match lexer.next() {
+            Some(Ok(_)) => (),
+            _ => return,
+        }
+    }
+    panic!("Lexer doesn't stop");
+}
+
+/// Parses arbitrary input with buffers of different sizes and with the
+/// fast parser, all of which have to agree.
+pub fn parser(data: &[u8]) {
+    let expected = events(Parser::from_slice(data).into_iter());
+    // Like a slice, input is taken as UTF-8 without looking for another
+    // encoding, but there's no byte order mark to skip
+    let text = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    for &size in BUFFER_SIZES.iter() {
+        let mut parser = Parser::with_capacity(Cursor::new(text), size);
+        parser.lexer_mut().set_transparent();
+        let result = events(parser.into_iter());
+        assert_eq!(result, expected, "buffer size {}", size);
+    }
+    let fast = events(Parser::fast(data).into_iter());
+    match expected.1 {
+        None => assert_eq!(fast, expected, "fast parser"),
+        Some(_) => assert!(fast.1.is_some(), "fast parser accepts broken input"),
+    }
+}
+
+/// Builds values out of arbitrary input, which, when it's valid, have to
+/// come back the same out of their serialization.
+pub fn items(data: &[u8]) {
+    let values: Vec<_> = Parser::from_slice(data).into_iter().items("").collect();
+    if let [Ok(value)] = &values[..] {
+        let text = value.to_string();
+        let mut again = Parser::from_slice(text.as_bytes()).into_iter().items("");
+        assert_eq!(again.next().unwrap().unwrap().to_string(), text);
+    }
+}
+
+/// Reads arbitrary input as a stream of BSON documents to the end or to
+/// the first error.
+#[cfg(feature = "bson")]
+pub fn bson(data: &[u8]) {
+    let reader = crate::bson::BsonReader::new_multi(data);
+    // Every event takes at least a byte
+    assert!(reader.take(data.len() + 2).count() <= data.len() + 1, "BSON reader doesn't stop");
+}
+
+struct Choices<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Choices<'a> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+
+    fn number(&mut self) -> i64 {
+        (0..4).fold(0i64, |n, _| n << 8 | self.byte() as i64) - (1 << 31)
+    }
+}
+
+/// Characters likely to trip string handling.
+const CHARACTERS: [char; 10] = ['a', '"', '\\', '/', '\n', '\u{1}', '\u{7f}', 'é', '\u{2028}', '😀'];
+
+fn generate(choices: &mut Choices<'_>, depth: usize) -> Json {
+    let choice = choices.byte();
+    match choice % 8 {
+        0 => Json::Null,
+        1 => Json::Boolean(choice >= 128),
+        2 => Json::I64(choices.number()),
+        3 => Json::F64(choices.number() as f64 / 1024.0 + 0.5),
+        4 => {
+            let len = choices.byte() % 16;
+            Json::String((0..len).map(|_| CHARACTERS[choices.byte() as usize % CHARACTERS.len()]).collect())
+        }
+        5 if depth < MAX_DEPTH => {
+            let len = choices.byte() % 5;
+            Json::Array((0..len).map(|_| generate(choices, depth + 1)).collect())
+        }
+        6 if depth < MAX_DEPTH => {
+            let len = choices.byte() % 5;
+            Json::Object((0..len).map(|i| (format!("k{}", i), generate(choices, depth + 1))).collect())
+        }
+        _ => Json::String(String::new()),
+    }
+}
+
+/// Bytes inserted into generated documents.
+const NOISE: &[u8] = b"{}[],:\"\\ \n0-.eE+tfnu\xff";
+
+/// Generates a valid document out of arbitrary input, which has to be
+/// parsed back as it was, then breaks it in a few places with the rest of
+/// the input and parses that as `parser` does.
+pub fn structured(data: &[u8]) {
+    let mut choices = Choices { data };
+    let text = generate(&mut choices, 0).to_string();
+    for &size in BUFFER_SIZES.iter() {
+        let value = Parser::with_capacity(Cursor::new(text.as_bytes()), size).into_iter().items("").next();
+        assert_eq!(value.unwrap().unwrap().to_string(), text, "buffer size {}", size);
+    }
+
+    let mut broken = text.into_bytes();
+    while !choices.data.is_empty() && !broken.is_empty() {
+        let op = choices.byte();
+        let at = (choices.byte() as usize) * broken.len() / 256;
+        let byte = NOISE[op as usize / 4 % NOISE.len()];
+        match op % 4 {
+            0 => {
+                broken.remove(at);
+            }
+            1 => broken.insert(at, byte),
+            2 => broken[at] = byte,
+            _ => broken.truncate(at),
+        }
+    }
+    parser(&broken);
+}
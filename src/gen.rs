@@ -0,0 +1,204 @@
+use std::io::Write;
+
+use crate::errors::Result;
+use crate::parser::OwnedEvent;
+use crate::writer::Writer;
+
+
+/// Characters of generated strings that have to be escaped.
+const ESCAPED: [char; 6] = ['"', '\\', '\n', '\t', '\u{1}', '\u{1f}'];
+
+/// Other characters of generated strings, of every UTF-8 length.
+const PLAIN: [char; 8] = ['a', 'q', 'z', ' ', '/', 'é', 'ж', '😀'];
+
+/// A pseudo-random number generator which, unlike the standard hasher,
+/// gives the same numbers for a seed on every platform and build.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.state >> 16
+    }
+
+    /// A number up to `limit` inclusive.
+    fn upto(&mut self, limit: usize) -> usize {
+        (self.next() % (limit as u64 + 1)) as usize
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() & 0xffff) as f64) < probability * 65536.0
+    }
+}
+
+struct Frame {
+    object: bool,
+    members: usize,
+    remaining: usize,
+    /// A key has been produced and its value is next.
+    pending_value: bool,
+}
+
+/// Produces a deterministic pseudo-random document of roughly the given
+/// size, as events or as text, for benchmarks and tests that need input
+/// of some shape without keeping large files around. The same seed and
+/// settings always give the same document.
+///
+/// ```
+/// use ijson::gen::Generator;
+/// use ijson::parser::Parser;
+///
+/// let mut generator = Generator::new(42);
+/// generator.set_size(10_000);
+/// let text = generator.bytes();
+/// let events: Vec<_> = Parser::from_slice(&text).into_iter().map(Result::unwrap).collect();
+/// let mut generator = Generator::new(42);
+/// generator.set_size(10_000);
+/// assert_eq!(generator.map(Result::unwrap).collect::<Vec<_>>(), events);
+/// ```
+pub struct Generator {
+    random: Random,
+    size: usize,
+    max_depth: usize,
+    max_items: usize,
+    max_string: usize,
+    escapes: f64,
+    /// Approximate length of the text produced so far.
+    produced: usize,
+    stack: Vec<Frame>,
+    started: bool,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Generator {
+        Generator {
+            random: Random { state: seed },
+            size: 1024,
+            max_depth: 8,
+            max_items: 8,
+            max_string: 32,
+            escapes: 0.05,
+            produced: 0,
+            stack: vec![],
+            started: false,
+        }
+    }
+
+    /// Sets the size of the document in bytes, 1 KiB by default. It's a
+    /// top-level array getting more elements until it's about that size,
+    /// nested containers closing early once it is.
+    pub fn set_size(&mut self, value: usize) {
+        self.size = value;
+    }
+
+    /// Sets the deepest nesting of containers, 8 by default.
+    pub fn set_max_depth(&mut self, value: usize) {
+        self.max_depth = value.max(1);
+    }
+
+    /// Sets the largest number of elements or members of nested
+    /// containers, 8 by default.
+    pub fn set_max_items(&mut self, value: usize) {
+        self.max_items = value;
+    }
+
+    /// Sets the longest string in characters, 32 by default.
+    pub fn set_max_string(&mut self, value: usize) {
+        self.max_string = value;
+    }
+
+    /// Sets the share of characters in strings that have to be escaped,
+    /// 0.05 by default.
+    pub fn set_escapes(&mut self, value: f64) {
+        self.escapes = value;
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.random.upto(self.max_string);
+        let result: String = (0..len).map(|_| {
+            if self.random.chance(self.escapes) {
+                ESCAPED[self.random.upto(ESCAPED.len() - 1)]
+            } else {
+                PLAIN[self.random.upto(PLAIN.len() - 1)]
+            }
+        }).collect();
+        self.produced += result.len() + 2;
+        result
+    }
+
+    fn value(&mut self) -> OwnedEvent {
+        let container = self.stack.len() < self.max_depth && self.random.chance(0.3);
+        let (event, len) = match self.random.upto(6) {
+            0 if container => {
+                let remaining = self.random.upto(self.max_items);
+                self.stack.push(Frame { object: false, members: 0, remaining, pending_value: false });
+                (OwnedEvent::StartArray, 1)
+            }
+            1 if container => {
+                let remaining = self.random.upto(self.max_items);
+                self.stack.push(Frame { object: true, members: 0, remaining, pending_value: false });
+                (OwnedEvent::StartMap, 1)
+            }
+            0 | 1 => (OwnedEvent::Null, 4),
+            2 => (OwnedEvent::Boolean(self.random.chance(0.5)), 5),
+            3 => (OwnedEvent::Int(self.random.next() as i64 - (1 << 47)), 15),
+            // Never a whole number, which would be read back as an integer
+            4 => (OwnedEvent::Number(((self.random.next() as i64 - (1 << 47)) as f64 + 0.25) / 4.0), 20),
+            _ => return OwnedEvent::String(self.string()),
+        };
+        self.produced += len;
+        event
+    }
+
+    /// Writes out the rest of the document.
+    pub fn write_to<W: Write>(self, out: W) -> Result<W> {
+        let mut writer = Writer::new(out);
+        for event in self {
+            writer.write_event(event?.as_event())?;
+        }
+        writer.finish()
+    }
+
+    /// The rest of the document as text.
+    pub fn bytes(self) -> Vec<u8> {
+        // Writing into memory doesn't fail
+        self.write_to(vec![]).unwrap()
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            self.stack.push(Frame { object: false, members: 0, remaining: usize::MAX, pending_value: false });
+            return Some(Ok(OwnedEvent::StartArray))
+        }
+        let produced = self.produced;
+        let size = self.size;
+        let frame = self.stack.last_mut()?;
+        if frame.pending_value {
+            frame.pending_value = false;
+            return Some(Ok(self.value()))
+        }
+        if frame.remaining == 0 || produced >= size {
+            let object = frame.object;
+            self.stack.pop();
+            self.produced += 1;
+            return Some(Ok(if object { OwnedEvent::EndMap } else { OwnedEvent::EndArray }))
+        }
+        frame.remaining -= 1;
+        self.produced += 1;
+        if frame.object {
+            frame.pending_value = true;
+            frame.members += 1;
+            let key = format!("field{}", frame.members);
+            self.produced += key.len() + 3;
+            return Some(Ok(OwnedEvent::Key(key)))
+        }
+        Some(Ok(self.value()))
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::Read;
+use std::ops::ControlFlow;
+
+use crate::errors::Result;
+use crate::lexer::ReadBuffer;
+use crate::parser::{Event, Parser};
+
+
+/// Receives events of a document from `drive` as callbacks. Every callback
+/// does nothing by default and can stop parsing by returning
+/// `ControlFlow::Break`.
+pub trait Handler {
+    fn start_map(&mut self) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn end_map(&mut self) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn start_array(&mut self) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn end_array(&mut self) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn key(&mut self, _key: &str) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn null(&mut self) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn boolean(&mut self, _value: bool) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn string(&mut self, _value: &str) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    /// Called instead of `string` for pieces of long strings if the parser
+    /// is set to split them, see `Parser::set_string_chunk_size`.
+    fn string_chunk(&mut self, _value: &str) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn string_end(&mut self) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    /// Called instead of `string` if the parser is set to produce strings
+    /// as bytes.
+    fn bytes(&mut self, _value: &[u8]) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn int(&mut self, _value: i64) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn uint(&mut self, _value: u64) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    fn number(&mut self, _value: f64) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    /// Called instead of the other number callbacks if the parser is set to
+    /// produce raw numbers.
+    fn raw_number(&mut self, _value: &str) -> ControlFlow<()> { ControlFlow::Continue(()) }
+    /// Called for comments if the parser is set to report them.
+    fn comment(&mut self, _text: &str) -> ControlFlow<()> { ControlFlow::Continue(()) }
+}
+
+/// Parses a document from `f` calling `handler` for every event. Returns
+/// `false` if the handler stopped parsing before the end.
+pub fn drive<T: Read, H: Handler>(f: T, handler: &mut H) -> Result<bool> {
+    drive_parser(&mut Parser::new(f), handler)
+}
+
+/// Same as `drive` for an already configured parser. A stopped parser can
+/// be driven again to continue from where it stopped.
+pub fn drive_parser<T: Read, B: ReadBuffer, H: Handler>(parser: &mut Parser<T, B>, handler: &mut H) -> Result<bool> {
+    while let Some(event) = parser.next() {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => return Err(parser.with_context(e)),
+        };
+        if dispatch(handler, event).is_break() {
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+/// Calls the callback of `handler` for `event`.
+pub(crate) fn dispatch<H: Handler>(handler: &mut H, event: Event) -> ControlFlow<()> {
+    match event {
+        Event::StartMap => handler.start_map(),
+        Event::EndMap => handler.end_map(),
+        Event::StartArray => handler.start_array(),
+        Event::EndArray => handler.end_array(),
+        Event::Key(k) => handler.key(k),
+        Event::Null => handler.null(),
+        Event::Boolean(v) => handler.boolean(v),
+        Event::String(v) => handler.string(v),
+        Event::StringChunk(v) => handler.string_chunk(v),
+        Event::StringEnd => handler.string_end(),
+        Event::Bytes(v) => handler.bytes(v),
+        Event::Int(v) => handler.int(v),
+        Event::Uint(v) => handler.uint(v),
+        Event::Number(v) => handler.number(v),
+        Event::RawNumber(v) => handler.raw_number(v),
+        Event::Comment(v) => handler.comment(v),
+    }
+}
@@ -0,0 +1,219 @@
+use std::convert::TryInto;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use crate::errors::{Error, Position, Result};
+use crate::parser::{OwnedEvent, Parser};
+use crate::path::{Path, Tracker};
+
+
+/// Start of a saved index.
+const MAGIC: &[u8; 8] = b"ijsonidx";
+const VERSION: u64 = 1;
+
+/// Bytes at the start and at the end of the input that identify it.
+const SAMPLE: usize = 64 * 1024;
+
+fn io_error(e: io::Error) -> Error {
+    Error::IO(e, Position::default())
+}
+
+fn invalid(message: &str) -> Error {
+    io_error(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// FNV-1a, which unlike the standard hasher stays the same across builds.
+fn hash(value: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(value, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+const HASH_START: u64 = 0xcbf29ce484222325;
+
+/// Passes input through, counting it and keeping its start and end.
+struct Sampler<R: Read> {
+    reader: R,
+    len: u64,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl<R: Read> Read for Sampler<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.reader.read(buf)?;
+        let data = &buf[..size];
+        self.len += size as u64;
+        let head = (SAMPLE - self.head.len()).min(size);
+        self.head.extend_from_slice(&data[..head]);
+        self.tail.extend_from_slice(data);
+        if self.tail.len() > 2 * SAMPLE {
+            self.tail.drain(..self.tail.len() - SAMPLE);
+        }
+        Ok(size)
+    }
+}
+
+impl<R: Read> Sampler<R> {
+    fn fingerprint(&self) -> u64 {
+        let tail = &self.tail[self.tail.len().saturating_sub(SAMPLE)..];
+        hash(hash(HASH_START, &self.head), tail)
+    }
+}
+
+/// Byte ranges of values at a prefix in a document, such as elements of
+/// a huge top-level array, for getting to any of them without parsing what
+/// comes before it. An index can be saved along with the size of the
+/// input and a hash of its start and end, to tell whether it still matches
+/// the input when it's loaded.
+///
+/// ```
+/// use std::io::Cursor;
+/// use ijson::builder::Builder;
+/// use ijson::index::Index;
+///
+/// let data = br#"{"docs": [{"id": 1}, {"id": 2}, {"id": 3}]}"#;
+/// let index = Index::build(&data[..], "docs.item").unwrap();
+/// assert_eq!(index.len(), 3);
+/// let parser = index.parser(Cursor::new(&data[..]), 2).unwrap().unwrap();
+/// let item = parser.into_iter().items("").next().unwrap().unwrap();
+/// assert_eq!(item.to_string(), r#"{"id":3}"#);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Index {
+    ranges: Vec<Range<u64>>,
+    source_len: u64,
+    fingerprint: u64,
+}
+
+impl Index {
+    /// Reads the document once, skipping values at `prefix` without
+    /// decoding them, see `Parser::skip_value`. Offsets count bytes of the
+    /// input as it is, including a UTF-8 byte order mark. UTF-16 input isn't
+    /// supported.
+    pub fn build<R: Read>(reader: R, prefix: &str) -> Result<Index> {
+        let mut reader = Sampler { reader, len: 0, head: vec![], tail: vec![] };
+        let mut start = vec![];
+        (&mut reader).take(3).read_to_end(&mut start).map_err(io_error)?;
+        // Offsets are counted past the byte order mark
+        let base = if start == b"\xEF\xBB\xBF" { start.len() as u64 } else { 0 };
+        let mut start = Cursor::new(start);
+        start.set_position(base);
+        let mut parser = Parser::new(start.chain(&mut reader));
+        parser.lexer_mut().set_transparent();
+
+        let reference = Path::parse(prefix);
+        let mut path = Tracker::default();
+        let mut ranges = vec![];
+        loop {
+            if path.at(&reference) {
+                if let Some((start, end)) = parser.value_range()? {
+                    ranges.push(base + start as u64..base + end as u64);
+                    path.complete();
+                    continue
+                }
+            }
+            let event = match parser.next() {
+                None => break,
+                Some(result) => OwnedEvent::from(result?),
+            };
+            path.enter(&event);
+            path.leave(&event);
+        }
+        drop(parser);
+        // The input is read to the end for the fingerprint
+        io::copy(&mut reader, &mut io::sink()).map_err(io_error)?;
+        Ok(Index { ranges, source_len: reader.len, fingerprint: reader.fingerprint() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Byte range of the value number `i`.
+    pub fn get(&self, i: usize) -> Option<Range<u64>> {
+        self.ranges.get(i).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=Range<u64>> + '_ {
+        self.ranges.iter().cloned()
+    }
+
+    /// Seeks `reader`, which has to be the indexed input, to the value
+    /// number `i` and returns a parser of just that value, or `None` if
+    /// there's no such value.
+    pub fn parser<R: Read + Seek>(&self, mut reader: R, i: usize) -> Result<Option<Parser<io::Take<R>>>> {
+        let range = match self.get(i) {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+        reader.seek(SeekFrom::Start(range.start)).map_err(io_error)?;
+        Ok(Some(Parser::new(reader.take(range.end - range.start))))
+    }
+
+    /// Checks whether the index was built from `reader`, comparing its
+    /// size and a hash of its first and last 64 KiB. Changes to the input
+    /// in between aren't noticed.
+    pub fn matches<R: Read + Seek>(&self, mut reader: R) -> Result<bool> {
+        let len = reader.seek(SeekFrom::End(0)).map_err(io_error)?;
+        if len != self.source_len {
+            return Ok(false)
+        }
+        let mut head = vec![];
+        reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        (&mut reader).take(SAMPLE as u64).read_to_end(&mut head).map_err(io_error)?;
+        let mut tail = vec![];
+        reader.seek(SeekFrom::Start(len.saturating_sub(SAMPLE as u64))).map_err(io_error)?;
+        reader.read_to_end(&mut tail).map_err(io_error)?;
+        Ok(hash(hash(HASH_START, &head), &tail) == self.fingerprint)
+    }
+
+    /// Saves the index: a header, the size and the hash of the input, the
+    /// number of values and offsets of their starts and ends, all as
+    /// little-endian 64-bit numbers, and a hash of all that.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        let mut data = MAGIC.to_vec();
+        let header = [VERSION, self.source_len, self.fingerprint, self.ranges.len() as u64];
+        let numbers = header.iter().copied().chain(self.ranges.iter().flat_map(|range| [range.start, range.end]));
+        for number in numbers {
+            data.extend_from_slice(&number.to_le_bytes());
+        }
+        let checksum = hash(HASH_START, &data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        out.write_all(&data).and_then(|_| out.flush()).map_err(io_error)
+    }
+
+    /// Loads an index saved by `write`, failing if it's damaged or if a
+    /// range is reversed or goes past the size of the input. Whether it
+    /// matches the input is up to `matches`.
+    pub fn read<R: Read>(mut reader: R) -> Result<Index> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data).map_err(io_error)?;
+        if data.len() < 48 || !data.starts_with(MAGIC) || !data.len().is_multiple_of(8) {
+            return Err(invalid("Not an index"))
+        }
+        let (data, checksum) = data.split_at(data.len() - 8);
+        if hash(HASH_START, data).to_le_bytes() != checksum {
+            return Err(invalid("Damaged index"))
+        }
+        let numbers: Vec<u64> = data[MAGIC.len()..].chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        if numbers[0] != VERSION {
+            return Err(invalid("Unsupported index version"))
+        }
+        if !numbers[4..].len().is_multiple_of(2) {
+            return Err(invalid("Damaged index"))
+        }
+        let ranges: Vec<_> = numbers[4..].chunks_exact(2).map(|pair| pair[0]..pair[1]).collect();
+        if numbers[3] != ranges.len() as u64 {
+            return Err(invalid("Damaged index"))
+        }
+        if ranges.iter().any(|range| range.start > range.end || range.end > numbers[1]) {
+            return Err(invalid("Index ranges out of the input"))
+        }
+        Ok(Index { ranges, source_len: numbers[1], fingerprint: numbers[2] })
+    }
+}
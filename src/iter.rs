@@ -0,0 +1,88 @@
+use std::iter::FusedIterator;
+
+use crate::errors::{Error, Result};
+
+
+/// Ends an iterator of results at its first error, for iterators that may
+/// go on after one, such as a parser with `OnError::Skip` or the adapters
+/// of `Builder` over it. The error is yielded and then `None` for good, or,
+/// with `set_silent(true)`, the iterator just ends and the error is kept
+/// for `take_error`.
+///
+/// ```
+/// use ijson::builder::Builder;
+/// use ijson::iter::Fused;
+/// use ijson::parser::{OnError, Parser};
+///
+/// let mut parser = Parser::new_multi(&b"1\n[2\n3\n"[..]);
+/// parser.set_on_error(OnError::Skip);
+/// let mut items = Fused::new(parser.into_iter().items(""));
+/// items.set_silent(true);
+/// let values: Vec<_> = items.by_ref().map(Result::unwrap).collect();
+/// assert_eq!(values.len(), 1);
+/// assert!(items.take_error().is_some());
+/// ```
+pub struct Fused<I> {
+    inner: I,
+    silent: bool,
+    done: bool,
+    error: Option<Error>,
+}
+
+impl<I> Fused<I> {
+    pub fn new(inner: I) -> Fused<I> {
+        Fused {
+            inner,
+            silent: false,
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Makes the iterator end at an error without yielding it.
+    pub fn set_silent(&mut self, value: bool) {
+        self.silent = value;
+    }
+
+    /// Whether the iterator has ended, at an error or at the end of the
+    /// inner one.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the error the iterator ended at, when it's silent.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I, T> Iterator for Fused<I> where I: Iterator<Item=Result<T>> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        match self.inner.next() {
+            Some(Ok(item)) => Some(Ok(item)),
+            Some(Err(e)) => {
+                self.done = true;
+                if self.silent {
+                    self.error = Some(e);
+                    return None
+                }
+                Some(Err(e))
+            }
+            None => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+impl<I, T> FusedIterator for Fused<I> where I: Iterator<Item=Result<T>> {}
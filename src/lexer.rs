@@ -1,23 +1,231 @@
-use std::{io, char, str};
+use std::{io, char, str, result};
+use std::io::{BufRead, BufReader, Read};
+use std::convert::TryFrom;
+use std::ops::Deref;
 
-use crate::errors::{Error, Result};
+use memchr::{memchr, memchr2, memchr_iter, memrchr};
 
+use crate::errors::{Error, Expected, NumberError, Position, Result, Snippet};
+use crate::decoder::Decoder;
 
-const BUFSIZE: usize = 4 * 1024;
+
+/// Default size of the read buffer.
+pub const BUFSIZE: usize = 4 * 1024;
+
+/// Bytes of input shown on each side of an error position.
+const SNIPPET_SIZE: usize = 24;
+
+/// How far into the input a lexer is, passed to the function set with
+/// `Lexer::set_progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes of input consumed so far, counted as `Position::offset`.
+    pub bytes: u64,
+    /// Length of the input, if the caller gave it.
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// Percentage of the input consumed, if its length is known.
+    pub fn percent(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(100.0),
+            Some(total) => Some((self.bytes as f64 * 100.0 / total as f64).min(100.0)),
+            None => None,
+        }
+    }
+}
+
+/// Counts of the work of a lexer, see `Parser::counters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LexerCounts {
+    pub refills: u64,
+    pub borrowed_strings: u64,
+    pub copied_strings: u64,
+}
+
+/// Function called with progress, and when to call it.
+struct ProgressHook {
+    function: Box<dyn FnMut(Progress) + Send>,
+    every: u64,
+    total: Option<u64>,
+    reported: u64,
+}
 
 
 #[inline(always)]
-fn is_whitespace(value: u8) -> bool {
-    match value {
-        9 | 10 | 13 | 32 => true,
-        _ => false,
+pub(crate) fn is_whitespace(value: u8) -> bool {
+    matches!(value, 9 | 10 | 13 | 32)
+}
+
+#[inline(always)]
+fn is_number_byte(value: u8) -> bool {
+    matches!(value, b'0'..=b'9' | b'+' | b'-' | b'.' | b'e' | b'E')
+}
+
+#[inline(always)]
+fn is_identifier_byte(value: u8) -> bool {
+    value.is_ascii_alphanumeric() || value == b'_' || value == b'$'
+}
+
+fn count_digits(value: &[u8]) -> usize {
+    value.iter().take_while(|b| b.is_ascii_digit()).count()
+}
+
+/// Checks the number syntax: an optional sign, integer and/or fractional
+/// parts and an optional exponent.
+fn is_number(value: &[u8]) -> bool {
+    let mut pos = 0;
+    if let Some(b'+') | Some(b'-') = value.first() {
+        pos += 1;
+    }
+    let mut digits = count_digits(&value[pos..]);
+    pos += digits;
+    if value.get(pos) == Some(&b'.') {
+        pos += 1;
+        let fraction = count_digits(&value[pos..]);
+        pos += fraction;
+        digits += fraction;
+    }
+    if digits == 0 {
+        return false
+    }
+    if let Some(b'e') | Some(b'E') = value.get(pos) {
+        pos += 1;
+        if let Some(b'+') | Some(b'-') = value.get(pos) {
+            pos += 1;
+        }
+        let exponent = count_digits(&value[pos..]);
+        if exponent == 0 {
+            return false
+        }
+        pos += exponent;
+    }
+    pos == value.len()
+}
+
+/// Checks the number syntax of JSON5, which adds `Infinity`, `NaN` and
+/// hexadecimal integers to lenient JSON numbers.
+fn is_json5_number(value: &[u8]) -> bool {
+    let unsigned = match value.first() {
+        Some(b'+') | Some(b'-') => &value[1..],
+        _ => value,
+    };
+    match unsigned {
+        b"Infinity" | b"NaN" => true,
+        [b'0', b'x', digits @ ..] | [b'0', b'X', digits @ ..] => {
+            !digits.is_empty() && digits.iter().all(u8::is_ascii_hexdigit)
+        }
+        _ => is_number(value),
     }
 }
 
+/// Converts JSON5 numbers that can't be parsed as JSON ones.
+fn json5_number(text: &str) -> Option<Lexeme<'static>> {
+    let (negative, unsigned) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    match unsigned {
+        "Infinity" if negative => Some(Lexeme::Number(f64::NEG_INFINITY)),
+        "Infinity" => Some(Lexeme::Number(f64::INFINITY)),
+        "NaN" => Some(Lexeme::Number(f64::NAN)),
+        _ if unsigned.starts_with("0x") || unsigned.starts_with("0X") => {
+            let value = u64::from_str_radix(&unsigned[2..], 16).ok()?;
+            Some(if negative {
+                i64::try_from(-(value as i128)).map_or(Lexeme::Number(-(value as f64)), Lexeme::Int)
+            } else {
+                i64::try_from(value).map_or(Lexeme::Uint(value), Lexeme::Int)
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Checks the number syntax according to RFC 8259: an optional minus, an
+/// integer part without leading zeros, an optional fraction and an optional
+/// exponent.
+pub(crate) fn check_number(value: &[u8]) -> result::Result<(), NumberError> {
+    let mut pos = 0;
+    match value.first() {
+        Some(b'+') => return Err(NumberError::PlusSign),
+        Some(b'-') => pos += 1,
+        _ => (),
+    }
+    let digits = count_digits(&value[pos..]);
+    if digits == 0 {
+        return Err(NumberError::NoInteger)
+    }
+    if digits > 1 && value[pos] == b'0' {
+        return Err(NumberError::LeadingZero)
+    }
+    pos += digits;
+    if value.get(pos) == Some(&b'.') {
+        pos += 1;
+        let fraction = count_digits(&value[pos..]);
+        if fraction == 0 {
+            return Err(NumberError::NoFraction)
+        }
+        pos += fraction;
+    }
+    if let Some(b'e') | Some(b'E') = value.get(pos) {
+        pos += 1;
+        if let Some(b'+') | Some(b'-') = value.get(pos) {
+            pos += 1;
+        }
+        let exponent = count_digits(&value[pos..]);
+        if exponent == 0 {
+            return Err(NumberError::NoExponent)
+        }
+        pos += exponent;
+    }
+    if pos < value.len() {
+        return Err(NumberError::TrailingData)
+    }
+    Ok(())
+}
+
+/// Converts the text of a checked number into an integer if it fits one,
+/// or a float otherwise.
+pub(crate) fn number_lexeme(text: &str, position: Position) -> Result<Lexeme<'static>> {
+    if let Ok(value) = text.parse() {
+        Ok(Lexeme::Int(value))
+    } else if let Ok(value) = text.parse() {
+        Ok(Lexeme::Uint(value))
+    } else {
+        text.parse().map(Lexeme::Number).map_err(|_| Error::Unknown(text.as_bytes().to_vec(), position))
+    }
+}
+
+/// Converts the text of a raw number the way the lexer does without
+/// `set_raw_numbers`, JSON5 hexadecimal integers included.
+pub(crate) fn raw_number_lexeme(text: &str) -> result::Result<Lexeme<'static>, NumberError> {
+    if let Some(lexeme) = json5_number(text) {
+        return Ok(lexeme)
+    }
+    // Text that doesn't parse can't be a valid JSON number either
+    number_lexeme(text, Position::default())
+        .map_err(|_| check_number(text.as_bytes()).err().unwrap_or(NumberError::TrailingData))
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Lexeme<'a> {
     String(&'a str),
+    /// A piece of a string longer than the chunk size, see
+    /// `Lexer::set_string_chunk_size`.
+    StringChunk(&'a str),
+    /// The end of a string split into chunks.
+    StringEnd,
+    /// Contents of a string not checked to be UTF-8, see
+    /// `Lexer::set_byte_strings`.
+    Bytes(&'a [u8]),
     Number(f64),
+    Int(i64),
+    Uint(u64),
+    RawNumber(&'a str),
+    /// An unquoted JSON5 object key.
+    Identifier(&'a str),
     Boolean(bool),
     Null,
     OBrace,
@@ -28,87 +236,558 @@ pub enum Lexeme<'a> {
     Colon,
 }
 
+/// What the lexer does with `//` and `/* */` comments between lexemes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comments {
+    /// Fail on them as on any unknown lexeme, as JSON has no comments.
+    Reject,
+    /// Skip them like whitespace.
+    Skip,
+    /// Make the parser produce `Event::Comment` for them.
+    Report,
+}
+
 enum Buffer {
     Within,
     Reset,
     Empty,
 }
 
-pub struct Lexer<T: io::Read> {
-    buf: [u8; BUFSIZE],
+/// Storage of the input being lexed: an owned buffer refilled from the
+/// reader, a slice holding the whole input (see `Lexer::from_slice`) or
+/// the buffer of a `BufReader` (see `Lexer::from_buf_reader`).
+pub trait ReadBuffer: Deref<Target=[u8]> {
+    /// Replaces the buffered input with the next piece, returning its size.
+    fn fill<R: Read>(&mut self, f: &mut R) -> io::Result<usize>;
+}
+
+impl ReadBuffer for Vec<u8> {
+    fn fill<R: Read>(&mut self, f: &mut R) -> io::Result<usize> {
+        f.read(self)
+    }
+}
+
+impl ReadBuffer for &[u8] {
+    fn fill<R: Read>(&mut self, _f: &mut R) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Input read directly out of the buffer of a `BufReader`.
+pub struct Buffered<R: Read> {
+    reader: BufReader<R>,
+    started: bool,
+    /// Bytes at the start which looked like a byte order mark but weren't,
+    /// given out before the buffer of `reader` when `held` is set.
+    prefix: Vec<u8>,
+    held: bool,
+}
+
+impl<R: Read> Buffered<R> {
+    pub fn into_inner(self) -> BufReader<R> {
+        self.reader
+    }
+}
+
+impl<R: Read> Deref for Buffered<R> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.held { &self.prefix } else { self.reader.buffer() }
+    }
+}
+
+impl<R: Read> ReadBuffer for Buffered<R> {
+    fn fill<T: Read>(&mut self, _f: &mut T) -> io::Result<usize> {
+        if self.held {
+            self.held = false;
+        } else {
+            let used = self.reader.buffer().len();
+            self.reader.consume(used);
+        }
+        if !self.started {
+            self.started = true;
+            // Skip a UTF-8 byte order mark, which may come in more than one
+            // read, or be longer than the buffer
+            const BOM: &[u8] = b"\xEF\xBB\xBF";
+            while self.prefix.len() < BOM.len() {
+                let data = self.reader.fill_buf()?;
+                let size = data.len().min(BOM.len() - self.prefix.len());
+                if size == 0 || data[..size] != BOM[self.prefix.len()..self.prefix.len() + size] {
+                    break
+                }
+                self.prefix.extend_from_slice(&data[..size]);
+                self.reader.consume(size);
+            }
+            if self.prefix.len() < BOM.len() && !self.prefix.is_empty() {
+                self.held = true;
+                return Ok(self.prefix.len())
+            }
+        }
+        self.reader.fill_buf().map(|data| data.len())
+    }
+}
+
+/// A file mapped into memory as a whole, see `Lexer::from_mmap`.
+#[cfg(feature = "mmap")]
+pub struct Mapped {
+    map: memmap2::Mmap,
+    start: usize,
+}
+
+#[cfg(feature = "mmap")]
+impl Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map[self.start..]
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ReadBuffer for Mapped {
+    fn fill<R: Read>(&mut self, _f: &mut R) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+pub struct Lexer<T: io::Read, B: ReadBuffer = Vec<u8>> {
+    buf: B,
     tmp: Vec<u8>,
     len: usize,
     pos: usize,
-    f: T,
+    f: Decoder<T>,
+    offset: usize,
+    line: usize,
+    line_start: usize,
+    start: usize,
+    raw_numbers: bool,
+    max_string: usize,
+    max_number: usize,
+    recording: Option<(Vec<u8>, usize)>,
+    empty_read_retries: usize,
+    lenient_numbers: bool,
+    control_characters: bool,
+    comments: Comments,
+    comment: String,
+    json5: bool,
+    non_finite: bool,
+    lossy_utf8: bool,
+    byte_strings: bool,
+    chunk_size: usize,
+    key_next: bool,
+    /// Set while producing chunks of a string, until `StringEnd`.
+    chunked: bool,
+    /// Quote of a chunked string whose end isn't consumed yet.
+    chunk_quote: Option<u8>,
+    /// Bytes at the start of `tmp` returned in the last chunk.
+    emitted: usize,
+    chunked_len: usize,
+    /// State of a chunked string as of `checkpoint`: `chunked`,
+    /// `chunk_quote` and the length of `tmp`.
+    saved_chunk: (bool, Option<u8>, usize),
+    progress: Option<ProgressHook>,
+    counts: LexerCounts,
+    repair: bool,
+    repaired: bool,
+    stop_at_newline: bool,
+    line_records: bool,
 }
 
 impl<T: io::Read> Lexer<T> {
 
     pub fn new(f: T) -> Lexer<T> {
+        Lexer::with_capacity(f, BUFSIZE)
+    }
+
+    /// Creates a lexer reading from `f` in chunks of `size` bytes.
+    pub fn with_capacity(f: T, size: usize) -> Lexer<T> {
+        Lexer::with_buffer(f, Vec::with_capacity(size))
+    }
+
+    /// Creates a lexer using `buf` as its read buffer, sized to the
+    /// buffer's capacity. The buffer can be reclaimed with `into_buffer`
+    /// and reused for another lexer.
+    pub fn with_buffer(f: T, mut buf: Vec<u8>) -> Lexer<T> {
+        let size = buf.capacity().max(1);
+        buf.clear();
+        buf.resize(size, 0);
+        Lexer::from_parts(Decoder::new(f), buf, 0)
+    }
+}
+
+impl<'a> Lexer<io::Empty, &'a [u8]> {
+
+    /// Creates a lexer over input that is in memory as a whole. Lexemes
+    /// borrow from it directly instead of being copied into a read buffer,
+    /// and strings without escapes are never copied at all. A UTF-8 byte
+    /// order mark is skipped, UTF-16 input isn't supported.
+    pub fn from_slice(data: &'a [u8]) -> Lexer<io::Empty, &'a [u8]> {
+        let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+        let mut f = Decoder::new(io::empty());
+        f.set_transparent();
+        Lexer::from_parts(f, data, data.len())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Lexer<io::Empty, Mapped> {
+
+    /// Creates a lexer over a file mapped into memory, which works like
+    /// `from_slice` without reading the file first. Mapping the file is up
+    /// to the caller, as it's only sound while nothing else changes the
+    /// file, which `memmap2::Mmap::map` leaves to be promised.
+    ///
+    /// ```no_run
+    /// use ijson::lexer::Lexer;
+    ///
+    /// let file = std::fs::File::open("data.json").unwrap();
+    /// // The file isn't changed while it's being read
+    /// let map = unsafe { memmap2::Mmap::map(&file).unwrap() };
+    /// let lexer = Lexer::from_mmap(map);
+    /// ```
+    pub fn from_mmap(map: memmap2::Mmap) -> Lexer<io::Empty, Mapped> {
+        let start = if map.starts_with(b"\xEF\xBB\xBF") { 3 } else { 0 };
+        let len = map.len() - start;
+        let mut f = Decoder::new(io::empty());
+        f.set_transparent();
+        Lexer::from_parts(f, Mapped { map, start }, len)
+    }
+}
+
+impl<R: Read> Lexer<io::Empty, Buffered<R>> {
+
+    /// Creates a lexer working right in the buffer of `reader`, without
+    /// copying input into a buffer of its own. The size of the pieces of
+    /// input is tuned by the capacity of `reader`. A UTF-8 byte order mark
+    /// is skipped, UTF-16 input isn't supported.
+    pub fn from_buf_reader(reader: BufReader<R>) -> Lexer<io::Empty, Buffered<R>> {
+        let mut f = Decoder::new(io::empty());
+        f.set_transparent();
+        Lexer::from_parts(f, Buffered { reader, started: false, prefix: vec![], held: false }, 0)
+    }
+
+    /// Returns the reader with the input it buffered but the lexer didn't
+    /// consume dropped from its buffer and returned separately, as
+    /// `into_inner` does for other lexers. What's left in the buffer of the
+    /// reader follows that input.
+    pub fn into_buf_reader(self) -> (BufReader<R>, Vec<u8>) {
+        let Buffered { mut reader, prefix, held, .. } = self.buf;
+        if held {
+            return (reader, prefix[self.pos..self.len].to_vec())
+        }
+        let rest = reader.buffer()[self.pos..self.len].to_vec();
+        reader.consume(self.len);
+        (reader, rest)
+    }
+}
+
+impl<T: io::Read, B: ReadBuffer> Lexer<T, B> {
+
+    /// Creates a lexer with `len` bytes of input already in `buf`.
+    fn from_parts(f: Decoder<T>, buf: B, len: usize) -> Lexer<T, B> {
         Lexer {
-            buf: [0; BUFSIZE],
-            tmp: Vec::with_capacity(BUFSIZE),
-            len: 0,
+            buf,
+            tmp: Vec::new(),
+            len,
             pos: 0,
-            f: f,
+            f,
+            offset: 0,
+            line: 1,
+            line_start: 0,
+            start: 0,
+            raw_numbers: false,
+            max_string: usize::MAX,
+            max_number: usize::MAX,
+            recording: None,
+            empty_read_retries: 0,
+            lenient_numbers: false,
+            control_characters: false,
+            comments: Comments::Reject,
+            comment: String::new(),
+            json5: false,
+            non_finite: false,
+            lossy_utf8: false,
+            byte_strings: false,
+            chunk_size: usize::MAX,
+            key_next: false,
+            chunked: false,
+            chunk_quote: None,
+            emitted: 0,
+            chunked_len: 0,
+            saved_chunk: (false, None, 0),
+            progress: None,
+            counts: LexerCounts::default(),
+            repair: false,
+            repaired: false,
+            stop_at_newline: false,
+            line_records: false,
+        }
+    }
+
+    /// Returns the read buffer for reuse, dropping the lexer.
+    pub fn into_buffer(self) -> B {
+        self.buf
+    }
+
+    /// Returns the reader along with the input read from it but not
+    /// consumed yet, for reading on past the end of a document. The input
+    /// comes as the lexer sees it, without a byte order mark and
+    /// transcoded from UTF-16.
+    pub fn into_inner(self) -> (T, Vec<u8>) {
+        let mut rest = self.buf[self.pos..self.len].to_vec();
+        let (f, pending) = self.f.into_parts();
+        rest.extend_from_slice(&pending);
+        (f, rest)
+    }
+
+    pub(crate) fn reader_mut(&mut self) -> &mut T {
+        &mut self.f.inner
+    }
+
+    /// Makes the lexer read input as is, without skipping a byte order mark
+    /// or transcoding UTF-16, so that offsets in the input match positions.
+    pub(crate) fn set_transparent(&mut self) {
+        self.f.set_transparent();
+    }
+
+    /// Returns the current position for `rewind`, also saving the state of
+    /// a chunked string.
+    pub(crate) fn checkpoint(&mut self) -> Position {
+        self.tmp.drain(..self.emitted);
+        self.emitted = 0;
+        self.saved_chunk = (self.chunked, self.chunk_quote, self.tmp.len());
+        self.position()
+    }
+
+    /// Drops buffered input and makes the lexer continue as if it was at
+    /// `position`, taken from `checkpoint`. The reader has to be rewound
+    /// accordingly.
+    pub(crate) fn rewind(&mut self, position: Position) {
+        let (chunked, quote, len) = self.saved_chunk;
+        self.chunked = chunked;
+        self.chunk_quote = quote;
+        self.tmp.truncate(len);
+        self.len = 0;
+        self.pos = 0;
+        self.offset = position.offset;
+        self.line = position.line;
+        self.line_start = position.offset + 1 - position.column;
+    }
+
+    /// Current position of the lexer in the source stream. Offsets count
+    /// bytes of UTF-8 after a byte order mark is skipped and UTF-16 input
+    /// is transcoded.
+    pub fn position(&self) -> Position {
+        let offset = self.offset + self.pos;
+        Position {
+            line: self.line,
+            column: offset - self.line_start + 1,
+            offset,
+        }
+    }
+
+    /// Position of the first byte of the most recently started lexeme.
+    pub fn lexeme_start(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.start - self.line_start + 1,
+            offset: self.start,
+        }
+    }
+
+    /// Buffered input around `offset` in the source stream, up to
+    /// `SNIPPET_SIZE` bytes on each side. The window is narrowed to whole
+    /// UTF-8 characters, so that the caret is placed by characters rather
+    /// than by replacements of their cut off bytes.
+    pub(crate) fn snippet(&self, offset: usize) -> Snippet {
+        let continuation = |i: usize| i < self.len && self.buf[i] & 0xc0 == 0x80;
+        let mut caret = offset.saturating_sub(self.offset).min(self.len);
+        while caret > 0 && continuation(caret) {
+            caret -= 1;
+        }
+        let mut start = caret.saturating_sub(SNIPPET_SIZE);
+        while start < caret && continuation(start) {
+            start += 1;
+        }
+        let mut end = (caret + SNIPPET_SIZE).min(self.len);
+        while end > caret && continuation(end) {
+            end -= 1;
+        }
+        Snippet {
+            bytes: self.buf[start..end].to_vec(),
+            caret: caret - start,
+            more_before: self.offset + start > 0,
+            more_after: end < self.len,
         }
     }
 
-    fn ensure_buffer(&mut self) -> io::Result<Buffer> {
+    fn ensure_buffer(&mut self) -> Result<Buffer> {
         if self.pos < self.len {
-            Ok(Buffer::Within)
-        } else {
-            self.f.read(&mut self.buf).and_then(|size| {
-                self.len = size;
-                self.pos = 0;
-                Ok(if size > 0 { Buffer::Reset } else { Buffer::Empty })
-            })
+            return Ok(Buffer::Within)
+        }
+        if let Some((ref mut recorded, ref mut start)) = self.recording {
+            recorded.extend_from_slice(&self.buf[*start..self.len]);
+            *start = 0;
+        }
+        self.offset += self.len;
+        self.len = 0;
+        self.pos = 0;
+        let mut retries = self.empty_read_retries;
+        loop {
+            match self.buf.fill(&mut self.f) {
+                Ok(0) if retries > 0 => retries -= 1,
+                Ok(size) => {
+                    self.len = size;
+                    if size > 0 {
+                        self.counts.refills += 1;
+                    }
+                    self.report_progress(size == 0);
+                    return Ok(if size > 0 { Buffer::Reset } else { Buffer::Empty })
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(Error::IO(e, self.position())),
+            }
         }
     }
 
-    fn hexdecode(&mut self) -> Result<char> {
+    fn hexdecode(&mut self, count: usize) -> Result<u32> {
         let mut value = 0;
-        for _ in 0..4 {
+        for _ in 0..count {
             if let Buffer::Empty = self.ensure_buffer()? {
-                return Err(Error::Escape(vec![]))
+                return Err(Error::Incomplete(self.position()))
             }
             match (self.buf[self.pos] as char).to_digit(16) {
-                None => return Err(Error::Escape(vec![])),
+                None => return Err(Error::Escape(vec![], self.position())),
                 Some(d) => value = value * 16 + d,
             }
             self.pos += 1;
         }
-        char::from_u32(value).map(Ok).unwrap_or(Err(Error::Escape(vec![])))
+        Ok(value)
+    }
+
+    fn check_byte(&mut self, expected: u8) -> Result<bool> {
+        Ok(match self.ensure_buffer()? {
+            Buffer::Empty => false,
+            _ if self.buf[self.pos] == expected => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        })
     }
 
-    fn parse_escape(&mut self) -> Result<char> {
+    /// Decodes a `\uXXXX` escape, combining a UTF-16 surrogate pair spread
+    /// over two consecutive escapes into a single character.
+    fn parse_unicode_escape(&mut self) -> Result<char> {
+        let position = self.position();
+        let high = self.hexdecode(4)?;
+        let value = match high {
+            0xD800..=0xDBFF => {
+                if !(self.check_byte(b'\\')? && self.check_byte(b'u')?) {
+                    return Err(Error::LoneSurrogate(high as u16, position))
+                }
+                let low = self.hexdecode(4)?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(Error::LoneSurrogate(high as u16, position))
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            0xDC00..=0xDFFF => return Err(Error::LoneSurrogate(high as u16, position)),
+            _ => high,
+        };
+        char::from_u32(value).ok_or_else(|| Error::Escape(vec![], position))
+    }
+
+    /// Decodes an escape, returning `None` for a JSON5 escaped line break
+    /// which doesn't produce a character.
+    fn parse_escape(&mut self) -> Result<Option<char>> {
         self.pos += 1; // swallow \
         if let Buffer::Empty = self.ensure_buffer()? {
-            return Err(Error::Escape(self.buf[self.pos - 1..].to_vec()))
+            return Err(Error::Incomplete(self.position()))
         }
         let escape = self.buf[self.pos];
         self.pos += 1; // move past the escape symbol
-        Ok(match escape {
-            b'u' => self.hexdecode()?,
+        Ok(Some(match escape {
+            b'u' => self.parse_unicode_escape()?,
             b'b' => '\x08',
             b'f' => '\x0c',
             b'n' => '\n',
             b'r' => '\r',
             b't' => '\t',
-            b @ b'"' | b @ b'\\' => b as char,
-            c => return Err(Error::Escape(vec![c])),
-        })
+            b @ b'"' | b @ b'\\' | b @ b'/' => b as char,
+            b'\'' if self.json5 => '\'',
+            b'v' if self.json5 => '\x0b',
+            b'0' if self.json5 => '\0',
+            b'x' if self.json5 => char::from(self.hexdecode(2)? as u8),
+            b'\n' | b'\r' if self.json5 => {
+                if escape == b'\r' {
+                    self.check_byte(b'\n')?;
+                }
+                self.line += 1;
+                self.line_start = self.offset + self.pos;
+                return Ok(None)
+            }
+            c => return Err(Error::Escape(vec![c], self.position())),
+        }))
     }
 
-    fn consume_string<'a>(&'a mut self) -> Result<&'a str> {
+    /// Whether an escape failed to decode only because the input ended
+    /// within it, which the repair mode takes as the end of the string.
+    fn cut_off(&mut self, error: &Error) -> Result<bool> {
+        if !self.repair || !matches!(error, Error::Incomplete(_) | Error::LoneSurrogate(..)) {
+            return Ok(false)
+        }
+        Ok(matches!(self.ensure_buffer()?, Buffer::Empty))
+    }
+
+    /// Fails on a control character in string contents between `start` and
+    /// the current position, unless they're allowed. In records taking
+    /// a line each, a line break is always an error, and the position is
+    /// left at it, so that skipping the record doesn't take the next one.
+    fn check_control_characters(&mut self, start: usize) -> Result<()> {
+        let contents = &self.buf[start..self.pos];
+        let newline = if self.line_records { memchr(b'\n', contents) } else { None };
+        let found = if self.control_characters { newline } else { contents.iter().position(|&b| b < 0x20) };
+        if let Some(i) = found {
+            let offset = self.offset + start + i;
+            let error = Error::ControlCharacter(self.buf[start + i], Position {
+                line: self.line,
+                column: offset - self.line_start + 1,
+                offset,
+            });
+            if let Some(newline) = newline {
+                self.pos = start + newline;
+            }
+            return Err(error)
+        }
+        Ok(())
+    }
+
+    fn consume_string(&mut self, quote: u8) -> Result<Lexeme<'_>> {
         let mut in_tmp = false;
+        let mut terminated = true;
         let mut start;
         let mut encode_buffer = [0; 5];
         self.pos += 1;
         loop {
             start = self.pos;
-            while self.pos < self.len && !(self.buf[self.pos] == b'"' || self.buf[self.pos] == b'\\') {
-                self.pos += 1;
+            self.pos = memchr2(quote, b'\\', &self.buf[self.pos..self.len]).map_or(self.len, |i| self.pos + i);
+            let size = (if in_tmp { self.tmp.len() } else { 0 }) + self.pos - start;
+            if size > self.max_string {
+                return Err(Error::StringLimit(self.lexeme_start()))
+            }
+            self.check_control_characters(start)?;
+            if size > self.chunk_size && !self.key_next {
+                if !in_tmp {
+                    self.tmp.clear();
+                }
+                self.tmp.extend_from_slice(&self.buf[start..self.pos]);
+                self.chunked = true;
+                self.chunk_quote = Some(quote);
+                self.chunked_len = 0;
+                self.counts.copied_strings += 1;
+                return self.consume_chunk(self.chunk_size)
             }
             if self.pos >= self.len || self.buf[self.pos] == b'\\' {
                 if !in_tmp {
@@ -118,8 +797,15 @@ impl<T: io::Read> Lexer<T> {
                 self.tmp.extend_from_slice(&self.buf[start..self.pos]);
             }
             match self.ensure_buffer()? {
-                Buffer::Empty => return Err(Error::Unterminated),
-                Buffer::Within if self.buf[self.pos] == b'"' => break,
+                // All of the string is in `tmp` at this point
+                Buffer::Empty if self.repair => {
+                    self.repaired = true;
+                    terminated = false;
+                    start = self.pos;
+                    break
+                }
+                Buffer::Empty => return Err(Error::Unterminated(self.position())),
+                Buffer::Within if self.buf[self.pos] == quote => break,
                 Buffer::Within => { // b'\'
                     // The ugly bit: parse_escape returns a char and we have
                     // to encode it into utf8 to push into self.tmp. This is extra
@@ -127,108 +813,674 @@ impl<T: io::Read> Lexer<T> {
                     // better for parse_escape to produce a unicode byte
                     // sequence directly, but I don't want to encode into utf-8
                     // manually (yet).
-                    let ch = self.parse_escape()?;
-                    let count = ch.encode_utf8(&mut encode_buffer).len();
-                    self.tmp.extend(&encode_buffer[..count]);
+                    match self.parse_escape() {
+                        Ok(Some(ch)) => {
+                            let count = ch.encode_utf8(&mut encode_buffer).len();
+                            self.tmp.extend(&encode_buffer[..count]);
+                        }
+                        Ok(None) => (),
+                        Err(ref e) if self.cut_off(e)? => {
+                            self.repaired = true;
+                            terminated = false;
+                            start = self.pos;
+                            break
+                        }
+                        Err(e) => return Err(e),
+                    }
                 }
                 _ => (),
             }
         }
-        let result = if in_tmp {
+        if in_tmp {
             self.tmp.extend_from_slice(&self.buf[start..self.pos]);
-            &self.tmp[..]
+        }
+        let position = self.position();
+        let end = self.pos;
+        if terminated {
+            self.pos += 1;
+        }
+        if in_tmp {
+            self.counts.copied_strings += 1;
         } else {
-            &self.buf[start..self.pos]
+            self.counts.borrowed_strings += 1;
+        }
+        if self.byte_strings && !self.key_next {
+            return Ok(Lexeme::Bytes(if in_tmp { &self.tmp[..] } else { &self.buf[start..end] }))
+        }
+        if !terminated {
+            self.drop_cut_character();
+        }
+        let result = if in_tmp { &self.tmp[..] } else { &self.buf[start..end] };
+        if let Err(e) = str::from_utf8(result) {
+            if !self.lossy_utf8 {
+                return Err(Error::Utf8(e, position))
+            }
+            self.tmp = String::from_utf8_lossy(result).into_owned().into_bytes();
+            // Each replacement character takes 3 bytes where a single
+            // invalid one was
+            if self.tmp.len() > self.max_string {
+                return Err(Error::StringLimit(self.lexeme_start()))
+            }
+            if !in_tmp {
+                self.counts.borrowed_strings -= 1;
+                self.counts.copied_strings += 1;
+            }
+            in_tmp = true;
+        }
+        let result = if in_tmp { &self.tmp[..] } else { &self.buf[start..end] };
+        // Validated above
+        Ok(Lexeme::String(unsafe { str::from_utf8_unchecked(result) }))
+    }
+
+    /// Drops the bytes of a character at the end of `tmp` cut off by the
+    /// end of input.
+    fn drop_cut_character(&mut self) {
+        if let Err(e) = str::from_utf8(&self.tmp) {
+            if e.error_len().is_none() {
+                self.tmp.truncate(e.valid_up_to());
+            }
+        }
+    }
+
+    /// Produces the next chunk of a chunked string of at most `chunk_size`
+    /// bytes, or `StringEnd` after the last one. Chunks are cut on
+    /// character boundaries.
+    fn consume_chunk(&mut self, chunk_size: usize) -> Result<Lexeme<'_>> {
+        let mut encode_buffer = [0; 4];
+        self.tmp.drain(..self.emitted);
+        self.emitted = 0;
+        while let Some(quote) = self.chunk_quote {
+            if self.tmp.len() >= chunk_size {
+                break
+            }
+            if let Buffer::Empty = self.ensure_buffer()? {
+                if !self.repair {
+                    return Err(Error::Unterminated(self.position()))
+                }
+                self.repaired = true;
+                self.chunk_quote = None;
+                self.drop_cut_character();
+                break
+            }
+            let start = self.pos;
+            let limit = self.len.min(start.saturating_add(chunk_size - self.tmp.len()));
+            self.pos = memchr2(quote, b'\\', &self.buf[start..limit]).map_or(limit, |i| start + i);
+            self.check_control_characters(start)?;
+            self.tmp.extend_from_slice(&self.buf[start..self.pos]);
+            if self.pos < limit {
+                if self.buf[self.pos] == quote {
+                    self.pos += 1;
+                    self.chunk_quote = None;
+                } else {
+                    match self.parse_escape() {
+                        Ok(Some(ch)) => self.tmp.extend_from_slice(ch.encode_utf8(&mut encode_buffer).as_bytes()),
+                        Ok(None) => (),
+                        Err(ref e) if self.cut_off(e)? => {
+                            self.repaired = true;
+                            self.chunk_quote = None;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        if self.tmp.is_empty() {
+            self.chunked = false;
+            return Ok(Lexeme::StringEnd)
+        }
+        let position = self.position();
+        let size = loop {
+            let size = self.tmp.len().min(chunk_size);
+            match str::from_utf8(&self.tmp[..size]) {
+                Ok(_) => break size,
+                // A character continuing in the next chunk
+                Err(e) if e.error_len().is_none() && e.valid_up_to() > 0 => break e.valid_up_to(),
+                Err(e) if !self.lossy_utf8 => return Err(Error::Utf8(e, position)),
+                Err(e) => {
+                    let end = e.valid_up_to() + e.error_len().unwrap_or(size - e.valid_up_to());
+                    self.tmp.splice(e.valid_up_to()..end, "\u{FFFD}".bytes());
+                }
+            }
         };
+        self.chunked_len += size;
+        if self.chunked_len > self.max_string {
+            return Err(Error::StringLimit(self.lexeme_start()))
+        }
+        self.emitted = size;
+        // Validated above
+        Ok(Lexeme::StringChunk(unsafe { str::from_utf8_unchecked(&self.tmp[..size]) }))
+    }
+
+    /// Consumes the rest of a chunked string, if the lexer is in one.
+    pub(crate) fn skip_chunks(&mut self) -> Result<()> {
+        while self.read_chunk()? {}
+        Ok(())
+    }
+
+    /// Checks whether a string starts at the next non-whitespace byte.
+    pub(crate) fn at_string(&mut self) -> Result<bool> {
+        Ok(match self.peek()? {
+            Some(b'"') => true,
+            Some(b'\'') => self.json5,
+            _ => false,
+        })
+    }
+
+    /// Starts producing the string at the current position in chunks, for
+    /// `read_chunk`.
+    pub(crate) fn start_chunks(&mut self) {
+        self.start = self.offset + self.pos;
+        self.chunk_quote = Some(self.buf[self.pos]);
         self.pos += 1;
-        Ok(str::from_utf8(result)?)
+        self.chunked = true;
+        self.tmp.clear();
+        self.emitted = 0;
+        self.chunked_len = 0;
+    }
+
+    /// Produces the next chunk of a chunked string of up to the size of the
+    /// read buffer but no more than `BUFSIZE`, available from `chunk`.
+    /// Returns `false` at the end of the string.
+    pub(crate) fn read_chunk(&mut self) -> Result<bool> {
+        if !self.chunked {
+            return Ok(false)
+        }
+        let size = self.buf.len().clamp(4, BUFSIZE);
+        Ok(matches!(self.consume_chunk(size)?, Lexeme::StringChunk(_)))
+    }
+
+    /// The last chunk produced by `read_chunk`.
+    pub(crate) fn chunk(&self) -> &[u8] {
+        &self.tmp[..self.emitted]
+    }
+
+    /// Shortens the last chunk to `size` bytes, leaving the rest to the
+    /// next one.
+    pub(crate) fn truncate_chunk(&mut self, size: usize) {
+        let size = size.min(self.emitted);
+        self.chunked_len -= self.emitted - size;
+        self.emitted = size;
+    }
+
+    /// Consumes an unquoted JSON5 identifier.
+    fn consume_identifier(&mut self) -> Result<&str> {
+        self.tmp.clear();
+        loop {
+            if let Buffer::Empty = self.ensure_buffer()? {
+                break
+            }
+            let start = self.pos;
+            while self.pos < self.len && is_identifier_byte(self.buf[self.pos]) {
+                self.pos += 1;
+            }
+            self.tmp.extend_from_slice(&self.buf[start..self.pos]);
+            if self.tmp.len() > self.max_string {
+                return Err(Error::StringLimit(self.lexeme_start()))
+            }
+            if self.pos < self.len {
+                break
+            }
+        }
+        let position = self.lexeme_start();
+        str::from_utf8(&self.tmp).map_err(|e| Error::Utf8(e, position))
     }
 
     fn check_word(&mut self, expected: &[u8]) -> Result<()> {
-        let mut iter = expected.iter();
-        while let Some(byte) = iter.next() {
+        for byte in expected {
             if let Buffer::Empty = self.ensure_buffer()? {
-                return Err(Error::Unknown(b"".to_vec()))
+                return Err(Error::Incomplete(self.position()))
             }
             if self.buf[self.pos] != *byte {
-                return Err(Error::Unknown(self.buf[self.pos..self.pos + 1].to_vec()))
+                return Err(Error::Unknown(self.buf[self.pos..self.pos + 1].to_vec(), self.position()))
             }
             self.pos += 1;
         }
         Ok(())
     }
 
-    fn consume_sign(&mut self) -> bool {
-        match self.buf[self.pos] {
-            b'-' => { self.pos += 1; false }
-            b'+' => { self.pos += 1; true }
-            _ => true,
+    fn consume_number(&mut self) -> Result<&str> {
+        self.tmp.clear();
+        loop {
+            if let Buffer::Empty = self.ensure_buffer()? {
+                break
+            }
+            let start = self.pos;
+            while self.pos < self.len && (is_number_byte(self.buf[self.pos]) || self.json5 && is_identifier_byte(self.buf[self.pos])) {
+                self.pos += 1;
+            }
+            self.tmp.extend_from_slice(&self.buf[start..self.pos]);
+            if self.tmp.len() > self.max_number {
+                return Err(Error::NumberLimit(self.lexeme_start()))
+            }
+            if self.pos < self.len {
+                break
+            }
+        }
+        if self.non_finite && self.tmp == b"-" && self.pos < self.len && self.buf[self.pos] == b'I' {
+            self.check_word(b"Infinity")?;
+            self.tmp.extend_from_slice(b"Infinity");
+            return Ok("-Infinity")
+        }
+        if self.json5 {
+            if !is_json5_number(&self.tmp) {
+                return Err(Error::Unknown(self.tmp.clone(), self.lexeme_start()))
+            }
+        } else if self.lenient_numbers {
+            if !is_number(&self.tmp) {
+                return Err(Error::Unknown(self.tmp.clone(), self.lexeme_start()))
+            }
+        } else if let Err(e) = check_number(&self.tmp) {
+            return Err(Error::Number(e, self.lexeme_start()))
+        }
+        let position = self.lexeme_start();
+        str::from_utf8(&self.tmp).map_err(|e| Error::Utf8(e, position))
+    }
+
+    /// Makes the lexer produce `Lexeme::RawNumber` with the untouched text
+    /// of numbers instead of converting them into `f64`.
+    pub fn set_raw_numbers(&mut self, value: bool) {
+        self.raw_numbers = value;
+    }
+
+    /// Makes the lexer accept numbers outside of the JSON grammar, such as
+    /// `+1`, `.5`, `01` or `1.`, instead of failing with `Error::Number`.
+    pub fn set_lenient_numbers(&mut self, value: bool) {
+        self.lenient_numbers = value;
+    }
+
+    /// Makes the lexer replace invalid UTF-8 in strings with U+FFFD
+    /// REPLACEMENT CHARACTER instead of failing with `Error::Utf8`.
+    pub fn set_lossy_utf8(&mut self, value: bool) {
+        self.lossy_utf8 = value;
+    }
+
+    /// Makes the lexer produce `Lexeme::Bytes` for strings other than object
+    /// keys, skipping UTF-8 validation of their contents. Escapes are
+    /// still decoded, and strings split into chunks still come as
+    /// `Lexeme::StringChunk`.
+    pub fn set_byte_strings(&mut self, value: bool) {
+        self.byte_strings = value;
+    }
+
+    /// Makes the lexer accept unescaped control characters (U+0000 to
+    /// U+001F) in strings instead of failing with `Error::ControlCharacter`.
+    pub fn set_control_characters(&mut self, value: bool) {
+        self.control_characters = value;
+    }
+
+    pub fn set_comments(&mut self, value: Comments) {
+        self.comments = value;
+    }
+
+    pub fn comments(&self) -> Comments {
+        self.comments
+    }
+
+    /// Makes the lexer end a string cut off by the end of input as if it
+    /// was terminated there, see `Parser::set_repair`.
+    pub fn set_repair(&mut self, value: bool) {
+        self.repair = value;
+    }
+
+    /// Whether a string had to be terminated, see `set_repair`.
+    pub fn repaired(&self) -> bool {
+        self.repaired
+    }
+
+    /// Makes the lexer accept `NaN`, `Infinity` and `-Infinity` as numbers,
+    /// as produced by Python's `json.dumps` and JavaScript's `JSON5`.
+    pub fn set_non_finite(&mut self, value: bool) {
+        self.non_finite = value;
+    }
+
+    /// Makes the lexer accept JSON5 lexemes, see `Dialect::Json5`.
+    pub(crate) fn set_json5(&mut self, value: bool) {
+        self.json5 = value;
+    }
+
+    /// Limits the length of strings in bytes, after decoding escapes.
+    pub fn set_max_string(&mut self, value: usize) {
+        self.max_string = value;
+    }
+
+    /// Makes strings longer than `value` bytes come as a series of
+    /// `Lexeme::StringChunk` of at most that size followed by
+    /// `Lexeme::StringEnd`, so that huge strings are never held in memory
+    /// whole. Object keys aren't split. As chunks end on character
+    /// boundaries, the size is at least 4.
+    pub fn set_string_chunk_size(&mut self, value: usize) {
+        self.chunk_size = value.max(4);
+    }
+
+    /// Bytes opening strings.
+    pub(crate) fn quotes(&self) -> &'static [u8] {
+        if self.json5 { b"\"'" } else { b"\"" }
+    }
+
+    /// Bytes of a string that surely come as a single lexeme without
+    /// hitting `max_string`, or `None` in the middle of a chunked string.
+    pub(crate) fn whole_string(&self) -> Option<usize> {
+        if self.chunked {
+            return None
         }
+        Some(self.max_string.min(self.chunk_size))
+    }
+
+    /// Tells the lexer whether the next string is an object key.
+    pub(crate) fn set_key_next(&mut self, value: bool) {
+        self.key_next = value;
+    }
+
+    /// Limits the length of numbers in bytes, including signs, the decimal
+    /// point and the exponent.
+    pub fn set_max_number(&mut self, value: usize) {
+        self.max_number = value;
     }
 
+    /// Skips a run of whitespace within the current buffer, keeping track
+    /// of line starts.
     #[inline(always)]
-    fn consume_int(&mut self, acc: &mut i64) -> Result<(usize)> {
-        let mut count = 0;
+    fn skip_whitespace(&mut self) {
+        let run = &self.buf[self.pos..self.len];
+        let size = if self.stop_at_newline {
+            run.iter().position(|&b| !is_whitespace(b) || b == b'\n')
+        } else {
+            run.iter().position(|&b| !is_whitespace(b))
+        }.unwrap_or(run.len());
+        let run = &run[..size];
+        if let Some(last) = memrchr(b'\n', run) {
+            self.line += memchr_iter(b'\n', run).count();
+            self.line_start = self.offset + self.pos + last + 1;
+        }
+        self.pos += size;
+    }
+
+    /// Makes the lexer take a line break for the end of input, to keep
+    /// a record of newline-delimited input from running into the next line.
+    pub(crate) fn set_stop_at_newline(&mut self, value: bool) {
+        self.stop_at_newline = value;
+    }
+
+    /// Tells the lexer that records take a line each, so a line break can't
+    /// be in a string.
+    pub(crate) fn set_line_records(&mut self, value: bool) {
+        self.line_records = value;
+    }
+
+    /// Skips input up to and including the next line break, dropping
+    /// a string being read in chunks.
+    pub(crate) fn skip_line(&mut self) -> Result<()> {
+        self.chunked = false;
+        self.chunk_quote = None;
+        self.tmp.clear();
+        self.emitted = 0;
         loop {
             if let Buffer::Empty = self.ensure_buffer()? {
-                break
+                return Ok(())
             }
-            match self.buf[self.pos] {
-                byte @ b'0'...b'9' => *acc = *acc * 10 + (byte - b'0') as i64,
-                _ => break,
+            if let Some(i) = memchr(b'\n', &self.buf[self.pos..self.len]) {
+                self.pos += i + 1;
+                self.line += 1;
+                self.line_start = self.offset + self.pos;
+                return Ok(())
             }
-            self.pos += 1;
-            count += 1;
+            self.pos = self.len;
         }
-        Ok(count)
     }
 
-    fn consume_number(&mut self) -> Result<f64> {
-        let sign = self.consume_sign();
-        let mut int = 0;
-        if self.consume_int(&mut int)? == 0 && (self.pos >= self.len || self.buf[self.pos] != b'.') {
-            return Err(Error::Unknown(vec![]))
+    /// Starts collecting consumed input from the current position on, the
+    /// collected bytes are returned by `stop_recording`.
+    pub(crate) fn start_recording(&mut self) {
+        self.recording = Some((vec![], self.pos));
+    }
+
+    pub(crate) fn stop_recording(&mut self) -> Vec<u8> {
+        match self.recording.take() {
+            Some((mut recorded, start)) => {
+                recorded.extend_from_slice(&self.buf[start..self.pos]);
+                recorded
+            }
+            None => vec![],
         }
-        let mut pow = 0;
-        if self.pos < self.len && self.buf[self.pos] == b'.' {
-            self.pos += 1;
-            pow -= self.consume_int(&mut int)? as i64;
+    }
+
+    /// Makes the lexer read again up to `value` times after a zero-length
+    /// read before taking it for the end of input, for readers returning
+    /// zero-length reads while not being at the end.
+    pub fn set_empty_read_retries(&mut self, value: usize) {
+        self.empty_read_retries = value;
+    }
+
+    /// Makes the lexer call `function` each time it has consumed another
+    /// `every` bytes of input, checked as it reads the next piece of input,
+    /// and once more at the end of input. `total` is the length of the
+    /// input, if known, to tell the percentage. Bytes are counted after
+    /// decompression or transcoding, so `total` has to be counted the same.
+    pub fn set_progress<F>(&mut self, every: u64, total: Option<u64>, function: F) where F: FnMut(Progress) + Send + 'static {
+        self.progress = Some(ProgressHook {
+            function: Box::new(function),
+            every: every.max(1),
+            total,
+            reported: 0,
+        });
+    }
+
+    pub(crate) fn counts(&self) -> LexerCounts {
+        self.counts
+    }
+
+    fn report_progress(&mut self, end: bool) {
+        let bytes = self.offset as u64;
+        if let Some(ref mut hook) = self.progress {
+            if bytes >= hook.reported + hook.every || end && bytes > hook.reported {
+                hook.reported = bytes;
+                (hook.function)(Progress { bytes, total: hook.total });
+            }
         }
-        if self.pos < self.len && (self.buf[self.pos] == b'E' || self.buf[self.pos] == b'e') {
+    }
+
+    /// Skips whitespace, and comments unless they're rejected, and returns
+    /// the next byte without consuming it.
+    pub fn peek(&mut self) -> Result<Option<u8>> {
+        loop {
+            match self.ensure_buffer()? {
+                Buffer::Empty => return Ok(None),
+                _ if self.stop_at_newline && self.buf[self.pos] == b'\n' => return Ok(None),
+                _ if is_whitespace(self.buf[self.pos]) => self.skip_whitespace(),
+                _ if self.buf[self.pos] == b'/' && self.comments != Comments::Reject => self.consume_comment(false)?,
+                _ => return Ok(Some(self.buf[self.pos])),
+            }
+        }
+    }
+
+    /// Skips whitespace and consumes a comment if it's next, returning
+    /// whether there was one. Its text is then available from `comment`.
+    pub(crate) fn next_comment(&mut self) -> Result<bool> {
+        loop {
+            match self.ensure_buffer()? {
+                Buffer::Empty => return Ok(false),
+                _ if self.stop_at_newline && self.buf[self.pos] == b'\n' => return Ok(false),
+                _ if is_whitespace(self.buf[self.pos]) => self.skip_whitespace(),
+                _ if self.buf[self.pos] == b'/' => break,
+                _ => return Ok(false),
+            }
+        }
+        let position = self.position();
+        self.consume_comment(true)?;
+        self.comment = String::from_utf8(self.tmp.clone()).map_err(|e| Error::Utf8(e.utf8_error(), position))?;
+        Ok(true)
+    }
+
+    /// Text of the last comment consumed by `next_comment`, without
+    /// delimiters.
+    pub(crate) fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Consumes a comment starting at the current position, leaving its
+    /// text in `tmp` if it's to be reported, in which case it's limited to
+    /// the maximum length of strings.
+    fn consume_comment(&mut self, report: bool) -> Result<()> {
+        let start = self.position();
+        self.pos += 1;
+        if let Buffer::Empty = self.ensure_buffer()? {
+            return Err(Error::Incomplete(self.position()))
+        }
+        let block = match self.buf[self.pos] {
+            b'/' => false,
+            b'*' => true,
+            byte => return Err(Error::Unknown(vec![b'/', byte], start)),
+        };
+        self.pos += 1;
+        self.tmp.clear();
+        let mut star = false;
+        loop {
+            if let Buffer::Empty = self.ensure_buffer()? {
+                return if block { Err(Error::Incomplete(self.position())) } else { Ok(()) }
+            }
+            let byte = self.buf[self.pos];
+            if byte == b'\n' {
+                if !block {
+                    return Ok(())
+                }
+                self.line += 1;
+                self.line_start = self.offset + self.pos + 1;
+            }
             self.pos += 1;
-            let sign = self.consume_sign();
-            let mut offset = 0;
-            if self.consume_int(&mut offset)? == 0 {
-                return Err(Error::Unknown(vec![]))
+            if block && star && byte == b'/' {
+                self.tmp.pop();
+                return Ok(())
             }
-            if !sign {
-                offset = -offset;
+            star = byte == b'*';
+            if report {
+                if self.tmp.len() >= self.max_string {
+                    return Err(Error::StringLimit(start))
+                }
+                self.tmp.push(byte);
             }
-            pow += offset;
         }
-        if !sign {
-            int = -int
+    }
+
+    /// Skips a string delimited by `quote`, which is `'` for JSON5.
+    fn skip_string(&mut self, quote: u8) -> Result<()> {
+        let position = self.position();
+        self.pos += 1;
+        loop {
+            if let Buffer::Empty = self.ensure_buffer()? {
+                return Err(Error::Unterminated(position))
+            }
+            match memchr2(quote, b'\\', &self.buf[self.pos..self.len]) {
+                None => self.pos = self.len,
+                Some(i) if self.buf[self.pos + i] == quote => {
+                    self.pos += i + 1;
+                    return Ok(())
+                }
+                Some(i) => {
+                    self.pos += i + 1;
+                    if let Buffer::Empty = self.ensure_buffer()? {
+                        return Err(Error::Unterminated(position))
+                    }
+                    self.pos += 1;
+                }
+            }
         }
+    }
 
-        Ok(if pow == 0 {
-            int as f64
-        } else  if pow < 0 {
-            int as f64 / (10.0f64).powi(-pow as i32)
-        } else {
-            int as f64 * (10.0f64).powi(pow as i32)
-        })
+    /// Skips the next value without decoding it. Skipped strings are not
+    /// checked for valid escapes and UTF-8, and containers only for matching
+    /// brackets and for nesting no deeper than `max_depth`.
+    pub fn skip_value(&mut self, max_depth: usize) -> Result<()> {
+        let position = self.position();
+        match self.peek()? {
+            None => Err(Error::Incomplete(position)),
+            Some(b'"') => self.skip_string(b'"'),
+            Some(b'\'') if self.json5 => self.skip_string(b'\''),
+            Some(b'{') | Some(b'[') if max_depth == 0 => Err(Error::DepthLimit(position)),
+            Some(b'{') => {
+                self.pos += 1;
+                self.skip_container(b"}", max_depth)
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                self.skip_container(b"]", max_depth)
+            }
+            Some(b'}') | Some(b']') | Some(b',') | Some(b':') => Err(Error::Unexpected(Expected::VALUE, self.position())),
+            Some(_) => match self.next() {
+                None => Err(Error::Incomplete(position)),
+                Some(result) => result.map(|_| ()),
+            },
+        }
     }
 
-    pub fn next<'a>(&'a mut self) -> Option<Result<Lexeme<'a>>> {
-        while match itry!(self.ensure_buffer()) {
-            Buffer::Empty => return None,
-            _ => is_whitespace(self.buf[self.pos]),
-        } {
-            self.pos += 1;
+    /// Skips input until currently open containers are closed, with the
+    /// same lack of validation as `skip_value`. `open` holds the closing
+    /// brackets of the containers, `}` or `]`, from the outermost one.
+    /// Containers in it count towards `max_depth` too.
+    pub fn skip_container(&mut self, open: &[u8], max_depth: usize) -> Result<()> {
+        let mut closers = open.to_vec();
+        while let Some(&closer) = closers.last() {
+            let position = self.position();
+            match self.peek()? {
+                None => return Err(Error::Incomplete(position)),
+                Some(b'"') => self.skip_string(b'"')?,
+                Some(b'\'') if self.json5 => self.skip_string(b'\'')?,
+                Some(b'{') | Some(b'[') if closers.len() >= max_depth => return Err(Error::DepthLimit(position)),
+                Some(b'{') => {
+                    closers.push(b'}');
+                    self.pos += 1;
+                }
+                Some(b'[') => {
+                    closers.push(b']');
+                    self.pos += 1;
+                }
+                Some(byte @ b'}') | Some(byte @ b']') if byte != closer => {
+                    let expected = if closer == b'}' { Expected::END_MAP } else { Expected::END_ARRAY };
+                    return Err(Error::Unexpected(expected, position))
+                }
+                Some(b'}') | Some(b']') => {
+                    closers.pop();
+                    self.pos += 1;
+                }
+                Some(_) => self.pos += 1,
+            }
         }
+        Ok(())
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<Lexeme<'_>>> {
+        self.next_with_position().map(|r| r.map(|(_, lexeme)| lexeme))
+    }
+
+    /// Same as `next` but also returns the position where the lexeme starts.
+    pub fn next_with_position(&mut self) -> Option<Result<(Position, Lexeme<'_>)>> {
+        if self.chunked {
+            let start = self.lexeme_start();
+            let size = self.chunk_size;
+            return Some(self.consume_chunk(size).map(|lexeme| (start, lexeme)))
+        }
+        itry!(self.peek())?;
+        self.start = self.offset + self.pos;
+        let start = self.lexeme_start();
 
-        Some(Ok(match self.buf[self.pos] {
-            b'"' => Lexeme::String(itry!(self.consume_string())),
+        Some(Ok((start, match self.buf[self.pos] {
+            b'"' => itry!(self.consume_string(b'"')),
+            b'\'' if self.json5 => itry!(self.consume_string(b'\'')),
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$' if self.json5 => {
+                let raw = self.raw_numbers;
+                match itry!(self.consume_identifier()) {
+                    "true" => Lexeme::Boolean(true),
+                    "false" => Lexeme::Boolean(false),
+                    "null" => Lexeme::Null,
+                    text @ "Infinity" | text @ "NaN" if raw => Lexeme::RawNumber(text),
+                    text => json5_number(text).unwrap_or(Lexeme::Identifier(text)),
+                }
+            }
+            b'N' | b'I' if self.non_finite => {
+                let (word, value) = if self.buf[self.pos] == b'N' { ("NaN", f64::NAN) } else { ("Infinity", f64::INFINITY) };
+                itry!(self.check_word(word.as_bytes()));
+                if self.raw_numbers { Lexeme::RawNumber(word) } else { Lexeme::Number(value) }
+            }
             b't' => {
                 itry!(self.check_word(b"true"));
                 Lexeme::Boolean(true)
@@ -241,8 +1493,17 @@ impl<T: io::Read> Lexer<T> {
                 itry!(self.check_word(b"null"));
                 Lexeme::Null
             }
-            b'+' | b'-' | b'.' | b'0' ... b'9' => {
-                Lexeme::Number(itry!(self.consume_number()))
+            b'+' | b'-' | b'.' | b'0'..=b'9' => {
+                let raw = self.raw_numbers;
+                let json5 = self.json5;
+                let text = itry!(self.consume_number());
+                if raw {
+                    Lexeme::RawNumber(text)
+                } else if let Some(lexeme) = json5.then(|| json5_number(text)).flatten() {
+                    lexeme
+                } else {
+                    itry!(number_lexeme(text, start))
+                }
             }
             byte => {
                 self.pos += 1;
@@ -253,9 +1514,9 @@ impl<T: io::Read> Lexer<T> {
                     b']' => Lexeme::CBracket,
                     b',' => Lexeme::Comma,
                     b':' => Lexeme::Colon,
-                    _ => return Some(Err(Error::Unknown(vec![byte]))),
+                    _ => return Some(Err(Error::Unknown(vec![byte], start))),
                 }
             }
-        }))
+        })))
     }
 }
@@ -1,9 +1,52 @@
 extern crate rustc_serialize;
 
 #[macro_use] mod errors;
+mod decoder;
 pub mod lexer;
 pub mod parser;
-//pub mod builder;
+pub mod fast;
+pub mod builder;
+pub mod ordered;
+pub mod compare;
+pub mod csv;
+pub mod seq;
+pub mod follow;
+pub mod parallel;
+pub mod index;
+pub mod iter;
+pub mod path;
+pub mod writer;
+pub mod emit;
+pub mod push;
+pub mod handler;
+#[cfg(feature = "async")]
+pub mod stream;
+#[cfg(feature = "serde")]
+pub mod value;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "serde")]
+pub mod ser;
+#[cfg(any(feature = "cbor", feature = "msgpack", feature = "bson"))]
+mod binary;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "bson")]
+pub mod bson;
+#[cfg(any(test, feature = "fuzz"))]
+#[doc(hidden)]
+pub mod fuzz;
+#[cfg(any(test, feature = "gen"))]
+pub mod gen;
 
-// #[cfg(test)]
-// mod test;
+pub use crate::errors::{Error, ErrorKind, Expected, NumberError, Position, Snippet};
+pub use crate::handler::{Handler, drive};
+#[cfg(feature = "serde")]
+pub use crate::de::{Deserializer, from_reader};
+#[cfg(feature = "serde")]
+pub use crate::ser::{Serializer, to_events};
+
+#[cfg(test)]
+mod test;
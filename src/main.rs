@@ -0,0 +1,900 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::process;
+
+use ijson::builder::{Builder, EventIterator};
+#[cfg(feature = "cbor")]
+use ijson::cbor::{CborReader, CborWriter};
+#[cfg(feature = "msgpack")]
+use ijson::msgpack::{MsgpackReader, MsgpackWriter};
+#[cfg(feature = "bson")]
+use ijson::bson::BsonReader;
+use ijson::csv::Export;
+use ijson::follow::Follow;
+use ijson::ordered::OrderedJson;
+use ijson::path::{Path, Segment};
+use ijson::lexer::BUFSIZE;
+use ijson::parser::{Limits, OwnedEvent, Parser};
+use ijson::writer::{Format, Minify, Writer};
+use ijson::{Error, Position};
+
+
+const USAGE: &str = "\
+Usage: ijson [OPTIONS] COMMAND [FILE]
+
+Reads FILE or, if it's missing or `-`, standard input. Files ending with
+`.gz` or `.zst` are decompressed when built with the `gzip` or `zstd`
+features.
+
+Commands:
+  validate [FILE...]
+                    check that each input is valid JSON, reporting the first
+                    error of each invalid one
+  extract [--prefix] PREFIX
+                    print values at PREFIX, such as `docs.item.meta`, one per
+                    line, strings as raw text and other values as JSON
+  split [--prefix PREFIX] [--lines N --output PATTERN]
+                    print values at PREFIX, `item` by default, as compact
+                    JSON one per line, or write them into files of N lines
+                    named by PATTERN with `{}` replaced by a file number
+  head [--prefix PREFIX] [-n N]
+                    print the first N values at PREFIX, 10 by default, as
+                    compact JSON one per line without reading further
+  csv [--prefix PREFIX] [--columns A,B,...] [--ignore-extra-keys]
+                    print objects at PREFIX, `item` by default, as CSV rows
+                    with a header, in the given columns or in those found in
+                    the first 100 rows; other keys in later rows fail after
+                    the rows before them are printed, unless they're ignored
+  grep [--prefix PREFIX] [--where PATH=VALUE...] [--contains TEXT] [--paths]
+                    print values at PREFIX, `item` by default, as compact JSON
+                    where each PATH inside, written like PREFIX, has VALUE, as
+                    a string, a number or JSON, and some string contains TEXT;
+                    with `--paths`, print only the matching paths and values
+                    prefixed with the number of the value
+  pretty [--indent N] [--sort-keys]
+                    print the input indented by N spaces, 2 by default; with
+                    `--sort-keys`, with object members sorted by their keys
+  minify            print the input without whitespace
+  to-cbor           print the input as CBOR, when built with the `cbor`
+                    feature
+  to-msgpack        print the input as MessagePack, when built with the
+                    `msgpack` feature; each top-level value is kept in memory
+                    until it's complete, as its size goes before it
+  stats             print counts of events, the maximum nesting depth, the
+                    longest string and the number of values at each path
+
+Options:
+  --multi           accept multiple top-level values, such as JSON lines or
+                    concatenated JSON
+  --follow          keep reading FILE as it grows, like `tail -f`, instead
+                    of ending at its end
+  --cbor            read the input as CBOR rather than JSON, or a CBOR
+                    sequence with `--multi`, when built with the `cbor`
+                    feature
+  --msgpack         read the input as MessagePack, when built with the
+                    `msgpack` feature
+  --bson            read the input as a BSON document, or a stream of them
+                    such as a `mongodump` file with `--multi`, when built
+                    with the `bson` feature
+  --progress        show how much of the input is read on standard error
+  --repair          complete input cut off in the middle of a document
+                    instead of failing
+  --max-depth N     fail on containers nested deeper than N, 128 by default
+  --max-string N    fail on strings longer than N bytes
+  --max-number N    fail on numbers longer than N bytes
+  --max-events N    fail on inputs of more than N events
+";
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Options common to all commands.
+#[derive(Default)]
+struct Options {
+    multi: bool,
+    follow: bool,
+    progress: bool,
+    repair: bool,
+    encoding: Encoding,
+    limits: Limits,
+}
+
+/// How the input is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+    Msgpack,
+    Bson,
+}
+
+impl Encoding {
+    /// The feature the binary has to be built with to support it.
+    fn feature(self) -> &'static str {
+        match self {
+            Encoding::Json => "",
+            Encoding::Cbor => "cbor",
+            Encoding::Msgpack => "msgpack",
+            Encoding::Bson => "bson",
+        }
+    }
+}
+
+impl Options {
+    /// Splits options off `args`, returning `None` for wrong ones.
+    fn parse(args: Vec<String>) -> Option<(Options, Vec<String>)> {
+        let mut options = Options::default();
+        let mut rest = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let limit = match arg.as_str() {
+                "--multi" => {
+                    options.multi = true;
+                    continue
+                }
+                "--follow" => {
+                    options.follow = true;
+                    continue
+                }
+                "--progress" => {
+                    options.progress = true;
+                    continue
+                }
+                "--repair" => {
+                    options.repair = true;
+                    continue
+                }
+                "--cbor" => {
+                    options.encoding = Encoding::Cbor;
+                    continue
+                }
+                "--msgpack" => {
+                    options.encoding = Encoding::Msgpack;
+                    continue
+                }
+                "--bson" => {
+                    options.encoding = Encoding::Bson;
+                    continue
+                }
+                "--max-depth" => &mut options.limits.max_depth,
+                "--max-string" => &mut options.limits.max_string,
+                "--max-number" => &mut options.limits.max_number,
+                "--max-events" => &mut options.limits.max_events,
+                _ => {
+                    rest.push(arg);
+                    continue
+                }
+            };
+            *limit = args.next()?.parse().ok()?;
+        }
+        Some((options, rest))
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::IO(e, Position::default())
+}
+
+/// Wraps a file into a decoder if its name says it's compressed with
+/// a format that the binary is built with.
+#[cfg_attr(not(any(feature = "gzip", feature = "zstd")), allow(unused_variables))]
+fn decompressed<R: Read + 'static>(path: &str, file: R) -> Result<Box<dyn Read>> {
+    #[cfg(feature = "gzip")]
+    {
+        if path.ends_with(".gz") {
+            return Ok(Box::new(flate2::read::MultiGzDecoder::new(file)))
+        }
+    }
+    #[cfg(feature = "zstd")]
+    {
+        if path.ends_with(".zst") {
+            return Ok(Box::new(zstd::stream::read::Decoder::new(file).map_err(io_error)?))
+        }
+    }
+    Ok(Box::new(file))
+}
+
+/// Bytes of input between progress reports.
+const PROGRESS_BYTES: u64 = 1024 * 1024;
+
+/// Length of a file as the parser sees it, unless it's compressed or
+/// growing.
+fn input_len(path: &str, options: &Options) -> Option<u64> {
+    let compressed = cfg!(feature = "gzip") && path.ends_with(".gz") || cfg!(feature = "zstd") && path.ends_with(".zst");
+    if options.follow || compressed {
+        return None
+    }
+    File::open(path).and_then(|f| f.metadata()).ok().filter(|m| m.is_file()).map(|m| m.len())
+}
+
+/// Events of the input, read by the parser for JSON, which some commands
+/// use directly, or by a reader of a binary encoding.
+enum Input {
+    Json(Box<Parser<Box<dyn Read>>>),
+    #[cfg_attr(not(any(feature = "cbor", feature = "msgpack", feature = "bson")), allow(dead_code))]
+    Binary(Box<dyn Iterator<Item = Result<OwnedEvent>>>),
+}
+
+impl Input {
+    fn events(self) -> Box<dyn Iterator<Item = Result<OwnedEvent>>> {
+        match self {
+            Input::Json(parser) => Box::new(parser.into_iter()),
+            Input::Binary(events) => events,
+        }
+    }
+}
+
+fn unsupported(encoding: Encoding) -> Error {
+    let feature = encoding.feature();
+    Error::Custom(format!("Built without the `{}` feature", feature), Position::default())
+}
+
+fn open(file: Option<&str>, options: &Options) -> Result<Input> {
+    let reader: Box<dyn Read> = match file {
+        None | Some("-") => Box::new(io::stdin()),
+        Some(path) if options.follow => decompressed(path, Follow::new(File::open(path).map_err(io_error)?))?,
+        Some(path) => decompressed(path, File::open(path).map_err(io_error)?)?,
+    };
+    match options.encoding {
+        Encoding::Json => (),
+        #[cfg(feature = "cbor")]
+        Encoding::Cbor => {
+            let mut reader = if options.multi { CborReader::new_multi(reader) } else { CborReader::new(reader) };
+            reader.set_limits(options.limits);
+            return Ok(Input::Binary(Box::new(reader)))
+        }
+        #[cfg(feature = "msgpack")]
+        Encoding::Msgpack => {
+            let mut reader = if options.multi { MsgpackReader::new_multi(reader) } else { MsgpackReader::new(reader) };
+            reader.set_limits(options.limits);
+            return Ok(Input::Binary(Box::new(reader)))
+        }
+        #[cfg(feature = "bson")]
+        Encoding::Bson => {
+            let mut reader = if options.multi { BsonReader::new_multi(reader) } else { BsonReader::new(reader) };
+            reader.set_limits(options.limits);
+            return Ok(Input::Binary(Box::new(reader)))
+        }
+        #[allow(unreachable_patterns)]
+        encoding => return Err(unsupported(encoding)),
+    }
+    let mut parser = if options.multi { Parser::new_multi(reader) } else { Parser::new(reader) };
+    parser.set_limits(options.limits);
+    // Numbers and long strings are passed on as they are
+    parser.set_raw_numbers(true);
+    parser.set_string_chunk_size(BUFSIZE);
+    parser.set_error_context(true);
+    parser.set_repair(options.repair);
+    if options.progress {
+        let total = file.filter(|&path| path != "-").and_then(|path| input_len(path, options));
+        parser.set_progress(PROGRESS_BYTES, total, |progress| match progress.percent() {
+            Some(percent) => eprint!("\r{:.0}%", percent),
+            None => eprint!("\r{} MiB", progress.bytes / PROGRESS_BYTES),
+        });
+    }
+    Ok(Input::Json(Box::new(parser)))
+}
+
+fn output() -> BufWriter<io::StdoutLock<'static>> {
+    BufWriter::new(io::stdout().lock())
+}
+
+/// Writes out all events, each top-level value on its own line.
+fn write_events<E: EventIterator, W: Write>(events: E, writer: Writer<W>) -> Result<()> {
+    let mut tee = events.tee(writer);
+    let mut empty = true;
+    for event in &mut tee {
+        event?;
+        empty = false;
+    }
+    if empty {
+        return Ok(())
+    }
+    let mut out = tee.finish()?;
+    out.write_all(b"\n").and_then(|_| out.flush()).map_err(io_error)
+}
+
+/// Passes writes through, leaving flushing to `end_line`.
+struct Unflushed<W: Write>(W);
+
+impl<W: Write> Write for Unflushed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Ends a line of output, flushing it with `--follow` so that values show
+/// up as soon as they're parsed. Otherwise the output is only flushed when
+/// its buffer is full.
+fn end_line<W: Write>(out: &mut W, follow: bool) -> Result<()> {
+    out.write_all(b"\n").map_err(io_error)?;
+    if follow {
+        out.flush().map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Writes out a single value starting with `event` as compact JSON, without
+/// flushing the output.
+fn write_value<E: EventIterator, W: Write>(events: &mut E, event: OwnedEvent, out: W) -> Result<()> {
+    let mut writer = Writer::new(Unflushed(out));
+    let mut depth = 0usize;
+    let mut event = event;
+    loop {
+        writer.write_event(event.as_event())?;
+        match event {
+            OwnedEvent::StartMap | OwnedEvent::StartArray => depth += 1,
+            OwnedEvent::EndMap | OwnedEvent::EndArray => depth -= 1,
+            _ => (),
+        }
+        // Pieces of a long string come up to `StringEnd`
+        if depth == 0 && !matches!(event, OwnedEvent::StringChunk(_)) {
+            return writer.finish().map(|_| ())
+        }
+        event = events.next().unwrap_or_else(|| Err(Error::Incomplete(Position::default())))?;
+    }
+}
+
+/// Writes out values at a prefix, each on its own line. Strings go as they
+/// are, without quotes and escapes.
+fn extract<E: EventIterator>(events: E, prefix: &str, follow: bool) -> Result<()> {
+    let mut out = output();
+    let mut events = events.prefix(prefix);
+    while let Some(event) = events.next() {
+        match event? {
+            OwnedEvent::Comment(_) => continue,
+            OwnedEvent::String(s) => out.write_all(s.as_bytes()).map_err(io_error)?,
+            OwnedEvent::Bytes(b) => out.write_all(&b).map_err(io_error)?,
+            OwnedEvent::StringChunk(s) => {
+                out.write_all(s.as_bytes()).map_err(io_error)?;
+                loop {
+                    match events.next() {
+                        Some(Ok(OwnedEvent::StringChunk(s))) => out.write_all(s.as_bytes()).map_err(io_error)?,
+                        Some(Ok(OwnedEvent::StringEnd)) => break,
+                        Some(result) => {
+                            result?;
+                        }
+                        None => return Err(Error::Incomplete(Position::default())),
+                    }
+                }
+            }
+            event => write_value(&mut events, event, &mut out)?,
+        }
+        end_line(&mut out, follow)?;
+    }
+    out.flush().map_err(io_error)
+}
+
+/// Where `split` writes its lines: standard output or files of `lines`
+/// lines each, named by `pattern`.
+struct Shards {
+    pattern: Option<String>,
+    lines: usize,
+    count: usize,
+    out: Box<dyn Write>,
+}
+
+impl Shards {
+    fn new(pattern: Option<String>, lines: usize) -> Shards {
+        Shards {
+            pattern,
+            lines,
+            count: 0,
+            out: Box::new(output()),
+        }
+    }
+
+    /// Returns the output for the next line, starting a new file when the
+    /// current one is full.
+    fn next(&mut self) -> Result<&mut Box<dyn Write>> {
+        if let Some(ref pattern) = self.pattern {
+            if self.count.is_multiple_of(self.lines) {
+                self.out.flush().map_err(io_error)?;
+                let name = pattern.replace("{}", &format!("{:05}", self.count / self.lines));
+                self.out = Box::new(BufWriter::new(File::create(name).map_err(io_error)?));
+            }
+        }
+        self.count += 1;
+        Ok(&mut self.out)
+    }
+}
+
+/// Writes the first `count` values at a prefix as lines of compact JSON,
+/// leaving the rest of the input unread.
+fn head<E: EventIterator>(events: E, prefix: &str, count: usize, follow: bool) -> Result<()> {
+    let mut out = output();
+    let mut events = events.prefix(prefix);
+    for _ in 0..count {
+        let event = match events.find(|r| !matches!(r, Ok(OwnedEvent::Comment(_)))) {
+            Some(event) => event?,
+            None => break,
+        };
+        write_value(&mut events, event, &mut out)?;
+        end_line(&mut out, follow)?;
+    }
+    out.flush().map_err(io_error)
+}
+
+/// What `grep` looks for in values.
+struct Query {
+    conditions: Vec<(Path, String)>,
+    contains: Option<String>,
+    paths: bool,
+}
+
+/// Collects values at a path, which may select several of them with `item`
+/// and `*`, along with their dotted paths.
+fn lookup<'a>(value: &'a OrderedJson, segments: &[Segment], path: String, found: &mut Vec<(String, &'a OrderedJson)>) {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return found.push((path, value)),
+    };
+    let join = |step: &str| if path.is_empty() { step.to_string() } else { format!("{}.{}", path, step) };
+    match (segment, value) {
+        (Segment::Key(key), OrderedJson::Object(_)) | (Segment::Token(key), OrderedJson::Object(_)) => {
+            if let Some(value) = value.find(key) {
+                lookup(value, rest, join(key), found);
+            }
+        }
+        (Segment::Index(i), OrderedJson::Array(values)) => {
+            if let Some(value) = values.get(*i) {
+                lookup(value, rest, join(&i.to_string()), found);
+            }
+        }
+        (Segment::Token(token), OrderedJson::Array(values)) if token != "item" => {
+            // Tokens other than `item` are indices
+            if let Some(value) = token.parse().ok().and_then(|i: usize| values.get(i)) {
+                lookup(value, rest, join(token), found);
+            }
+        }
+        (Segment::AnyItem, OrderedJson::Array(values)) |
+        (Segment::Token(_), OrderedJson::Array(values)) |
+        (Segment::Wildcard, OrderedJson::Array(values)) => {
+            for (i, value) in values.iter().enumerate() {
+                lookup(value, rest, join(&i.to_string()), found);
+            }
+        }
+        (Segment::Wildcard, OrderedJson::Object(members)) => {
+            for (key, value) in members {
+                lookup(value, rest, join(key), found);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Whether a value is `expected`: a string as it is, a number equal to it
+/// as a number, and anything else as compact JSON.
+fn is(value: &OrderedJson, expected: &str) -> bool {
+    let number = || expected.parse::<f64>().ok();
+    match *value {
+        OrderedJson::String(ref s) => s == expected,
+        OrderedJson::I64(n) => expected.parse::<i64>().map_or_else(|_| number() == Some(n as f64), |e| e == n),
+        OrderedJson::U64(n) => expected.parse::<u64>().map_or_else(|_| number() == Some(n as f64), |e| e == n),
+        OrderedJson::F64(n) => number() == Some(n),
+        ref value => value.to_string() == expected,
+    }
+}
+
+/// Collects strings containing `text` with their dotted paths.
+fn find_text<'a>(value: &'a OrderedJson, path: &str, text: &str, found: &mut Vec<(String, &'a OrderedJson)>) {
+    let join = |step: &str| if path.is_empty() { step.to_string() } else { format!("{}.{}", path, step) };
+    match value {
+        OrderedJson::String(s) if s.contains(text) => found.push((path.to_string(), value)),
+        OrderedJson::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                find_text(value, &join(&i.to_string()), text, found);
+            }
+        }
+        OrderedJson::Object(members) => {
+            for (key, value) in members {
+                find_text(value, &join(key), text, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Writes values at a prefix matching a query, or their matching parts.
+fn grep<E: EventIterator>(events: E, prefix: &str, query: &Query, follow: bool) -> Result<()> {
+    let mut out = output();
+    for (index, item) in events.ordered_items(prefix).enumerate() {
+        let item = item?;
+        let mut found = vec![];
+        let matched = query.conditions.iter().all(|(path, expected)| {
+            let mut values = vec![];
+            lookup(&item, path.segments(), String::new(), &mut values);
+            let count = found.len();
+            found.extend(values.into_iter().filter(|(_, value)| is(value, expected)));
+            found.len() > count
+        });
+        if !matched {
+            continue
+        }
+        if let Some(ref text) = query.contains {
+            let count = found.len();
+            find_text(&item, "", text, &mut found);
+            if found.len() == count {
+                continue
+            }
+        }
+        let written = if query.paths {
+            found.iter().try_for_each(|(path, value)| writeln!(out, "{}.{}={}", index, path, value))
+        } else {
+            writeln!(out, "{}", item)
+        };
+        written.map_err(io_error)?;
+        if follow {
+            out.flush().map_err(io_error)?;
+        }
+    }
+    out.flush().map_err(io_error)
+}
+
+/// Writes values at a prefix as lines of compact JSON.
+fn split<E: EventIterator>(events: E, prefix: &str, mut shards: Shards, follow: bool) -> Result<()> {
+    let mut events = events.prefix(prefix);
+    while let Some(event) = events.next() {
+        let event = event?;
+        if let OwnedEvent::Comment(_) = event {
+            continue
+        }
+        let out = shards.next()?;
+        write_value(&mut events, event, &mut *out)?;
+        end_line(out, follow)?;
+    }
+    shards.out.flush().map_err(io_error)
+}
+
+fn validate(input: Input) -> Result<()> {
+    match input {
+        Input::Json(mut parser) => {
+            while let Some(result) = parser.next() {
+                if let Err(e) = result {
+                    return Err(parser.with_context(e))
+                }
+            }
+            Ok(())
+        }
+        Input::Binary(mut events) => events.try_for_each(|result| result.map(|_| ())),
+    }
+}
+
+fn minify(parser: Parser<Box<dyn Read>>) -> Result<()> {
+    let mut out = output();
+    let size = io::copy(&mut Minify::from_parser(parser), &mut out).map_err(|e| {
+        // Parsing errors come wrapped in I/O errors
+        match e.into_inner().map(|inner| inner.downcast::<Error>()) {
+            Some(Ok(error)) => *error,
+            Some(Err(inner)) => io_error(io::Error::other(inner)),
+            None => io_error(io::Error::other("read failed")),
+        }
+    })?;
+    if size > 0 {
+        out.write_all(b"\n").map_err(io_error)?;
+    }
+    out.flush().map_err(io_error)
+}
+
+/// Counts of what `stats` finds in the input.
+#[derive(Default)]
+struct Stats {
+    objects: usize,
+    arrays: usize,
+    keys: usize,
+    strings: usize,
+    numbers: usize,
+    booleans: usize,
+    nulls: usize,
+    top_level_elements: usize,
+    max_depth: usize,
+    longest_string: usize,
+    /// Number of values at each path.
+    paths: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "objects: {}", self.objects)?;
+        writeln!(out, "arrays: {}", self.arrays)?;
+        writeln!(out, "keys: {}", self.keys)?;
+        writeln!(out, "strings: {}", self.strings)?;
+        writeln!(out, "numbers: {}", self.numbers)?;
+        writeln!(out, "booleans: {}", self.booleans)?;
+        writeln!(out, "nulls: {}", self.nulls)?;
+        writeln!(out, "top-level array elements: {}", self.top_level_elements)?;
+        writeln!(out, "max depth: {}", self.max_depth)?;
+        writeln!(out, "longest string: {} bytes", self.longest_string)?;
+        writeln!(out, "paths:")?;
+        for (path, count) in &self.paths {
+            writeln!(out, "  {}: {}", path, count)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(feature = "cbor")]
+fn to_cbor<E: EventIterator>(events: E) -> Result<()> {
+    let mut writer = CborWriter::new(output());
+    let mut empty = true;
+    for event in events {
+        writer.write_event(event?.as_event())?;
+        empty = false;
+    }
+    if !empty {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "cbor"))]
+fn to_cbor<E: EventIterator>(_events: E) -> Result<()> {
+    Err(unsupported(Encoding::Cbor))
+}
+
+#[cfg(feature = "msgpack")]
+fn to_msgpack<E: EventIterator>(events: E) -> Result<()> {
+    let mut writer = MsgpackWriter::new(output());
+    let mut empty = true;
+    for event in events {
+        writer.write_event(event?.as_event())?;
+        empty = false;
+    }
+    if !empty {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "msgpack"))]
+fn to_msgpack<E: EventIterator>(_events: E) -> Result<()> {
+    Err(unsupported(Encoding::Msgpack))
+}
+
+fn stats<E: EventIterator>(events: E) -> Result<()> {
+    let mut stats = Stats::default();
+    let (mut depth, mut string, mut root_array) = (0, 0, false);
+    for result in events.parse() {
+        let (path, event) = result?;
+        let level = depth;
+        if level == 0 {
+            root_array = event == OwnedEvent::StartArray;
+        }
+        let value = match event {
+            OwnedEvent::StartMap | OwnedEvent::StartArray => {
+                if event == OwnedEvent::StartMap { stats.objects += 1 } else { stats.arrays += 1 }
+                depth += 1;
+                stats.max_depth = stats.max_depth.max(depth);
+                true
+            }
+            OwnedEvent::EndMap | OwnedEvent::EndArray => {
+                depth -= 1;
+                false
+            }
+            OwnedEvent::Key(_) => {
+                stats.keys += 1;
+                false
+            }
+            OwnedEvent::String(ref s) => {
+                stats.strings += 1;
+                stats.longest_string = stats.longest_string.max(s.len());
+                true
+            }
+            OwnedEvent::Bytes(ref b) => {
+                stats.strings += 1;
+                stats.longest_string = stats.longest_string.max(b.len());
+                true
+            }
+            // A long string is counted at its end, with all of its chunks
+            OwnedEvent::StringChunk(ref s) => {
+                string += s.len();
+                false
+            }
+            OwnedEvent::StringEnd => {
+                stats.strings += 1;
+                stats.longest_string = stats.longest_string.max(mem::replace(&mut string, 0));
+                true
+            }
+            OwnedEvent::Number(_) | OwnedEvent::Int(_) | OwnedEvent::Uint(_) | OwnedEvent::RawNumber(_) => {
+                stats.numbers += 1;
+                true
+            }
+            OwnedEvent::Boolean(_) => {
+                stats.booleans += 1;
+                true
+            }
+            OwnedEvent::Null => {
+                stats.nulls += 1;
+                true
+            }
+            OwnedEvent::Comment(_) => false,
+        };
+        if !value {
+            continue
+        }
+        if root_array && level == 1 {
+            stats.top_level_elements += 1;
+        }
+        if !path.is_empty() {
+            *stats.paths.entry(path).or_insert(0) += 1;
+        }
+    }
+    stats.write(output()).map_err(io_error)
+}
+
+/// Prints an error, prefixed with the name of the file if there is one.
+fn report(file: Option<&str>, e: Error) {
+    eprintln!("{}", e.render(file.filter(|&file| file != "-").unwrap_or("<stdin>")));
+}
+
+/// Validates each file in turn, going on after invalid ones.
+fn validate_all(files: &[String], options: &Options) -> bool {
+    if files.is_empty() {
+        return match open(None, options).and_then(validate) {
+            Ok(()) => true,
+            Err(e) => {
+                report(None, e);
+                false
+            }
+        }
+    }
+    let mut valid = true;
+    for file in files {
+        if let Err(e) = open(Some(file), options).and_then(validate) {
+            report(Some(file), e);
+            valid = false;
+        }
+    }
+    valid
+}
+
+/// Takes `name` and its value out of `args`, returning `None` for a
+/// missing value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<Option<String>> {
+    let index = match args.iter().position(|a| a == name) {
+        Some(index) => index,
+        None => return Some(None),
+    };
+    if index + 1 == args.len() {
+        return None
+    }
+    args.remove(index);
+    Some(Some(args.remove(index)))
+}
+
+/// Takes a flag out of `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|a| a != name);
+    args.len() != len
+}
+
+/// Runs a command, returning whether it succeeded or `None` for wrong
+/// arguments.
+fn run(args: &[String], options: &Options) -> Option<bool> {
+    let (command, rest) = args.split_first()?;
+    if command == "validate" {
+        return Some(validate_all(rest, options))
+    }
+    let mut rest = rest.to_vec();
+    let prefix = take_option(&mut rest, "--prefix")?;
+    let indent = match take_option(&mut rest, "--indent")? {
+        Some(_) if command != "pretty" => return None,
+        Some(n) => n.parse().ok()?,
+        None => 2,
+    };
+    let sort_keys = take_flag(&mut rest, "--sort-keys");
+    if sort_keys && command != "pretty" {
+        return None
+    }
+    let count = match take_option(&mut rest, "-n")? {
+        Some(_) if command != "head" => return None,
+        Some(n) => n.parse().ok()?,
+        None => 10,
+    };
+    let columns = match take_option(&mut rest, "--columns")? {
+        Some(_) if command != "csv" => return None,
+        columns => columns,
+    };
+    let ignore_extra_keys = take_flag(&mut rest, "--ignore-extra-keys");
+    if ignore_extra_keys && command != "csv" {
+        return None
+    }
+    let mut query = Query { conditions: vec![], contains: None, paths: false };
+    if command == "grep" {
+        while let Some(condition) = take_option(&mut rest, "--where")? {
+            let (path, value) = condition.split_once('=')?;
+            query.conditions.push((Path::parse(path), value.to_string()));
+        }
+        query.contains = take_option(&mut rest, "--contains")?;
+        query.paths = take_flag(&mut rest, "--paths");
+    }
+    let (prefix, shards) = match command.as_str() {
+        "extract" if prefix.is_none() && !rest.is_empty() => (rest.remove(0), None),
+        "extract" => (prefix?, None),
+        "head" | "csv" | "grep" => (prefix.unwrap_or_else(|| "item".to_string()), None),
+        "split" => {
+            let lines = take_option(&mut rest, "--lines")?;
+            let pattern = take_option(&mut rest, "--output")?;
+            let lines = match (lines, &pattern) {
+                (Some(lines), Some(_)) => lines.parse().ok().filter(|&n| n > 0)?,
+                (None, None) => 1,
+                _ => return None,
+            };
+            (prefix.unwrap_or_else(|| "item".to_string()), Some(Shards::new(pattern, lines)))
+        }
+        _ if prefix.is_some() => return None,
+        _ => (String::new(), None),
+    };
+    let file = match rest.as_slice() {
+        [] => None,
+        [file] => Some(file.as_str()),
+        _ => return None,
+    };
+    let parser = match command.as_str() {
+        "extract" | "head" | "split" | "csv" | "grep" | "pretty" | "minify" | "to-cbor" | "to-msgpack" | "stats" => open(file, options),
+        _ => return None,
+    };
+    let result = parser.and_then(|input| match command.as_str() {
+        "extract" => extract(input.events(), &prefix, options.follow),
+        "head" => head(input.events(), &prefix, count, options.follow),
+        "csv" => {
+            let export = match columns {
+                Some(columns) => Export::new().columns(columns.split(',')),
+                None => Export::new(),
+            };
+            export.ignore_extra_keys(ignore_extra_keys).write(input.events(), &prefix, output()).map(|_| ())
+        }
+        "grep" => grep(input.events(), &prefix, &query, options.follow),
+        "split" => split(input.events(), &prefix, shards.unwrap(), options.follow),
+        "pretty" => {
+            let mut writer = Writer::with_format(output(), Format::Indented(" ".repeat(indent)));
+            writer.set_sort_keys(sort_keys);
+            write_events(input.events(), writer)
+        }
+        "minify" => match input {
+            Input::Json(parser) => minify(*parser),
+            Input::Binary(events) => write_events(events, Writer::new(output())),
+        },
+        "to-cbor" => to_cbor(input.events()),
+        "to-msgpack" => to_msgpack(input.events()),
+        _ => stats(input.events()),
+    });
+    match result {
+        Ok(()) => Some(true),
+        Err(e) => {
+            report(file, e);
+            Some(false)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        return
+    }
+    let succeeded = Options::parse(args).and_then(|(options, args)| {
+        let succeeded = run(&args, &options);
+        if options.progress {
+            eprintln!();
+        }
+        succeeded
+    });
+    match succeeded {
+        None => {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+        Some(false) => process::exit(1),
+        Some(true) => (),
+    }
+}
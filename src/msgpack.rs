@@ -0,0 +1,478 @@
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use crate::binary::{base64url, io_error, position, timestamp, Checker, Input};
+use crate::builder::Located;
+use crate::errors::{Error, Position, Result};
+use crate::parser::{Event, Limits, OwnedEvent};
+
+
+/// Extension type of timestamps.
+const TIMESTAMP: i8 = -1;
+
+struct Frame {
+    map: bool,
+    /// Elements or members left.
+    remaining: u64,
+    /// The next item of a map is a key.
+    key: bool,
+}
+
+/// Reads MessagePack producing the same events as `Parser` does for JSON,
+/// so prefixes, items and the rest work on it as well. Being read as it
+/// comes, input of any size takes little memory. Values are converted to
+/// what JSON has:
+///
+/// - Binary data and extension types become base64url strings without
+///   padding, except for timestamps which become numbers of seconds since
+///   the epoch, as `Event::RawNumber`.
+/// - Map keys which are integers become their decimal text, other keys
+///   besides strings are an error.
+///
+/// ```
+/// use ijson::builder::Builder;
+/// use ijson::msgpack::MsgpackReader;
+///
+/// // {"a": [1, -2.5], "b": bin 00ff}
+/// let data = b"\x82\xa1a\x92\x01\xca\xc0\x20\x00\x00\xa1b\xc4\x02\x00\xff";
+/// let value = MsgpackReader::new(&data[..]).items("").next().unwrap().unwrap();
+/// assert_eq!(value.to_string(), r#"{"a":[1,-2.5],"b":"AP8"}"#);
+/// ```
+pub struct MsgpackReader<R: Read> {
+    input: Input<R>,
+    stack: Vec<Frame>,
+    multi: bool,
+    limits: Limits,
+    events: usize,
+    /// A top-level value has been read.
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read> MsgpackReader<R> {
+    /// Reads a single top-level value, failing with `Error::AdditionalData`
+    /// on anything after it.
+    pub fn new(input: R) -> MsgpackReader<R> {
+        MsgpackReader {
+            input: Input::new(input),
+            stack: vec![],
+            multi: false,
+            limits: Limits::default(),
+            events: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Reads any number of top-level values one after another, the way
+    /// MessagePack is streamed.
+    pub fn new_multi(input: R) -> MsgpackReader<R> {
+        let mut reader = MsgpackReader::new(input);
+        reader.multi = true;
+        reader
+    }
+
+    /// Sets limits on the input, same as `Parser::set_limits`. Numbers have
+    /// no text, so `max_number` doesn't apply.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Reads a big-endian length of `size` bytes.
+    fn length(&mut self, size: usize) -> Result<u64> {
+        Ok(match size {
+            1 => u8::from_be_bytes(self.input.exact()?) as u64,
+            2 => u16::from_be_bytes(self.input.exact()?) as u64,
+            _ => u32::from_be_bytes(self.input.exact()?) as u64,
+        })
+    }
+
+    fn open(&mut self, map: bool, len: u64, start: usize) -> Result<OwnedEvent> {
+        if self.stack.len() >= self.limits.max_depth {
+            return Err(Error::DepthLimit(position(start)))
+        }
+        self.stack.push(Frame { map, remaining: len, key: map });
+        Ok(if map { OwnedEvent::StartMap } else { OwnedEvent::StartArray })
+    }
+
+    fn extension(&mut self, len: u64, start: usize) -> Result<OwnedEvent> {
+        let kind = i8::from_be_bytes(self.input.exact()?);
+        let data = self.input.bytes(len, self.limits.max_string, start)?;
+        let time = match (kind, data.len()) {
+            (TIMESTAMP, 4) => Some((u32::from_be_bytes(data[..].try_into().unwrap()) as i64, 0)),
+            (TIMESTAMP, 8) => {
+                let value = u64::from_be_bytes(data[..].try_into().unwrap());
+                Some(((value & 0x3_ffff_ffff) as i64, (value >> 34) as u32))
+            }
+            (TIMESTAMP, 12) => {
+                let nanoseconds = u32::from_be_bytes(data[..4].try_into().unwrap());
+                Some((i64::from_be_bytes(data[4..].try_into().unwrap()), nanoseconds))
+            }
+            _ => None,
+        };
+        Ok(match time {
+            Some((seconds, nanoseconds)) => OwnedEvent::RawNumber(timestamp(seconds, nanoseconds)),
+            None => OwnedEvent::String(base64url(&data)),
+        })
+    }
+
+    fn read_event(&mut self) -> Result<Option<OwnedEvent>> {
+        // Containers end without a byte of their own
+        if let Some(&Frame { remaining: 0, key, map }) = self.stack.last() {
+            if key || !map {
+                self.stack.pop();
+                return Ok(Some(if map { OwnedEvent::EndMap } else { OwnedEvent::EndArray }))
+            }
+        }
+        let start = self.input.offset;
+        let byte = match self.input.byte()? {
+            Some(byte) => byte,
+            None if self.stack.is_empty() && (self.multi || self.started) => return Ok(None),
+            None => return Err(Error::Incomplete(position(self.input.offset))),
+        };
+        if self.stack.is_empty() {
+            if self.started && !self.multi {
+                return Err(Error::AdditionalData(position(start)))
+            }
+            self.started = true;
+        }
+        let key = match self.stack.last_mut() {
+            Some(frame) if frame.map => {
+                if !frame.key {
+                    frame.remaining -= 1;
+                }
+                frame.key = !frame.key;
+                !frame.key
+            }
+            Some(frame) => {
+                frame.remaining -= 1;
+                false
+            }
+            None => false,
+        };
+
+        let limit = self.limits.max_string;
+        let event = match byte {
+            0x00..=0x7f => OwnedEvent::Int(byte as i64),
+            0x80..=0x8f => self.open(true, (byte & 0x0f) as u64, start)?,
+            0x90..=0x9f => self.open(false, (byte & 0x0f) as u64, start)?,
+            0xa0..=0xbf => OwnedEvent::String(self.input.text((byte & 0x1f) as u64, limit, start)?),
+            0xc0 => OwnedEvent::Null,
+            0xc2 => OwnedEvent::Boolean(false),
+            0xc3 => OwnedEvent::Boolean(true),
+            0xc4..=0xc6 => {
+                let len = self.length(1 << (byte - 0xc4))?;
+                OwnedEvent::String(base64url(&self.input.bytes(len, limit, start)?))
+            }
+            0xc7..=0xc9 => {
+                let len = self.length(1 << (byte - 0xc7))?;
+                self.extension(len, start)?
+            }
+            0xca => OwnedEvent::Number(f32::from_be_bytes(self.input.exact()?) as f64),
+            0xcb => OwnedEvent::Number(f64::from_be_bytes(self.input.exact()?)),
+            0xcc..=0xce => OwnedEvent::Int(self.length(1 << (byte - 0xcc))? as i64),
+            0xcf => {
+                let value = u64::from_be_bytes(self.input.exact()?);
+                match i64::try_from(value) {
+                    Ok(value) => OwnedEvent::Int(value),
+                    Err(_) => OwnedEvent::Uint(value),
+                }
+            }
+            0xd0 => OwnedEvent::Int(i8::from_be_bytes(self.input.exact()?) as i64),
+            0xd1 => OwnedEvent::Int(i16::from_be_bytes(self.input.exact()?) as i64),
+            0xd2 => OwnedEvent::Int(i32::from_be_bytes(self.input.exact()?) as i64),
+            0xd3 => OwnedEvent::Int(i64::from_be_bytes(self.input.exact()?)),
+            0xd4..=0xd8 => self.extension(1 << (byte - 0xd4), start)?,
+            0xd9..=0xdb => {
+                let len = self.length(1 << (byte - 0xd9))?;
+                OwnedEvent::String(self.input.text(len, limit, start)?)
+            }
+            0xdc | 0xdd => {
+                let len = self.length(if byte == 0xdc { 2 } else { 4 })?;
+                self.open(false, len, start)?
+            }
+            0xde | 0xdf => {
+                let len = self.length(if byte == 0xde { 2 } else { 4 })?;
+                self.open(true, len, start)?
+            }
+            0xe0..=0xff => OwnedEvent::Int(byte as i8 as i64),
+            0xc1 => return Err(Error::Unknown(vec![byte], position(start))),
+        };
+        if !key {
+            return Ok(Some(event))
+        }
+        match event {
+            OwnedEvent::String(s) | OwnedEvent::RawNumber(s) => Ok(Some(OwnedEvent::Key(s))),
+            OwnedEvent::Int(n) => Ok(Some(OwnedEvent::Key(n.to_string()))),
+            OwnedEvent::Uint(n) => Ok(Some(OwnedEvent::Key(n.to_string()))),
+            _ => Err(Error::Custom("Map keys have to be strings or integers".to_string(), position(start))),
+        }
+    }
+}
+
+impl<R: Read> Located for MsgpackReader<R> {
+    fn location(&self) -> Position {
+        position(self.input.offset)
+    }
+}
+
+impl<R: Read> Iterator for MsgpackReader<R> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let result = self.read_event().transpose();
+        match result {
+            Some(Ok(_)) => {
+                self.events += 1;
+                if self.events > self.limits.max_events {
+                    self.done = true;
+                    return Some(Err(Error::EventLimit(position(self.input.offset))))
+                }
+            }
+            _ => self.done = true,
+        }
+        result
+    }
+}
+
+struct Container {
+    map: bool,
+    /// Elements or members written.
+    count: u64,
+    content: Vec<u8>,
+    /// Where the header is, when it's written before the contents.
+    header: Option<u64>,
+}
+
+/// Writes events as MessagePack, checking that they form valid documents
+/// the same way `Writer` does. Floats which are the same as single
+/// precision are written as such, and `Event::Bytes` becomes binary data.
+/// Several top-level values are written one after another.
+///
+/// MessagePack needs the size of a container before its contents, so each
+/// top-level value is kept in memory until it's complete. A stream of many
+/// of them, such as JSON lines, takes only as much memory as the largest
+/// one. Output which can seek, such as a file, doesn't need that, see
+/// `MsgpackWriter::seekable`.
+///
+/// ```
+/// use ijson::msgpack::MsgpackWriter;
+/// use ijson::parser::Parser;
+///
+/// let mut writer = MsgpackWriter::new(vec![]);
+/// for event in Parser::from_slice(&br#"{"a": [1, -2.5]}"#[..]) {
+///     writer.write_event(event.unwrap().as_event()).unwrap();
+/// }
+/// assert_eq!(writer.finish().unwrap(), b"\x81\xa1a\x92\x01\xca\xc0\x20\x00\x00");
+/// ```
+pub struct MsgpackWriter<W: Write> {
+    out: W,
+    checker: Checker,
+    stack: Vec<Container>,
+    /// Chunks of the string being written.
+    string: String,
+    /// Seeks in the output, if it can.
+    seek: Option<fn(&mut W, SeekFrom) -> io::Result<u64>>,
+}
+
+fn too_long() -> Error {
+    Error::Custom("Too long for MessagePack".to_string(), Position::default())
+}
+
+impl<W: Write> MsgpackWriter<W> {
+    pub fn new(out: W) -> MsgpackWriter<W> {
+        MsgpackWriter {
+            out,
+            checker: Checker::new(),
+            stack: vec![],
+            string: String::new(),
+            seek: None,
+        }
+    }
+
+    /// Where the value goes: the innermost container or the output.
+    fn output(&mut self) -> &mut dyn Write {
+        match self.stack.last_mut() {
+            Some(container) if self.seek.is_none() => &mut container.content,
+            _ => &mut self.out,
+        }
+    }
+
+    /// Writes the header of a container with room for its size, returning
+    /// where it is.
+    fn start(&mut self, seek: fn(&mut W, SeekFrom) -> io::Result<u64>, map: bool) -> io::Result<u64> {
+        let header = seek(&mut self.out, SeekFrom::Current(0))?;
+        self.out.write_all(&[if map { 0xdf } else { 0xdd }, 0, 0, 0, 0])?;
+        Ok(header)
+    }
+
+    /// Writes the size of a container into its header.
+    fn patch(&mut self, seek: fn(&mut W, SeekFrom) -> io::Result<u64>, header: u64, len: u32) -> io::Result<()> {
+        let end = seek(&mut self.out, SeekFrom::Current(0))?;
+        seek(&mut self.out, SeekFrom::Start(header + 1))?;
+        self.out.write_all(&len.to_be_bytes())?;
+        seek(&mut self.out, SeekFrom::Start(end)).map(|_| ())
+    }
+
+    /// Writes the type byte and the length of a string or a container:
+    /// `fixed` combined with the length if it's below the given limit, or
+    /// one of `sized` for lengths of 8, 16 and 32 bits, 0 where there's no
+    /// such type.
+    fn header(&mut self, len: usize, fixed: Option<(u8, usize)>, sized: [u8; 3]) -> Result<()> {
+        let out = self.output();
+        match (len, fixed) {
+            (len, Some((fixed, limit))) if len < limit => out.write_all(&[fixed | len as u8]),
+            (0..=0xff, _) if sized[0] != 0 => out.write_all(&[sized[0], len as u8]),
+            (0..=0xffff, _) => out.write_all(&[sized[1]]).and_then(|_| out.write_all(&(len as u16).to_be_bytes())),
+            (0..=0xffff_ffff, _) => out.write_all(&[sized[2]]).and_then(|_| out.write_all(&(len as u32).to_be_bytes())),
+            _ => return Err(too_long()),
+        }.map_err(io_error)
+    }
+
+    fn string(&mut self, value: &str) -> Result<()> {
+        self.header(value.len(), Some((0xa0, 32)), [0xd9, 0xda, 0xdb])?;
+        self.output().write_all(value.as_bytes()).map_err(io_error)
+    }
+
+    fn integer(&mut self, value: i128) -> io::Result<()> {
+        let out = self.output();
+        match value {
+            -32..=0x7f => out.write_all(&[value as i8 as u8]),
+            0x80..=0xff => out.write_all(&[0xcc, value as u8]),
+            0x100..=0xffff => {
+                out.write_all(&[0xcd])?;
+                out.write_all(&(value as u16).to_be_bytes())
+            }
+            0x10000..=0xffff_ffff => {
+                out.write_all(&[0xce])?;
+                out.write_all(&(value as u32).to_be_bytes())
+            }
+            0x1_0000_0000.. => {
+                out.write_all(&[0xcf])?;
+                out.write_all(&(value as u64).to_be_bytes())
+            }
+            -0x80..=-33 => out.write_all(&[0xd0, value as i8 as u8]),
+            -0x8000..=-0x81 => {
+                out.write_all(&[0xd1])?;
+                out.write_all(&(value as i16).to_be_bytes())
+            }
+            -0x8000_0000..=-0x8001 => {
+                out.write_all(&[0xd2])?;
+                out.write_all(&(value as i32).to_be_bytes())
+            }
+            _ => {
+                out.write_all(&[0xd3])?;
+                out.write_all(&(value as i64).to_be_bytes())
+            }
+        }
+    }
+
+    fn number(&mut self, value: f64) -> io::Result<()> {
+        let out = self.output();
+        if value as f32 as f64 == value || value.is_nan() {
+            out.write_all(&[0xca])?;
+            out.write_all(&(value as f32).to_be_bytes())
+        } else {
+            out.write_all(&[0xcb])?;
+            out.write_all(&value.to_be_bytes())
+        }
+    }
+
+    /// Writes out a single event, failing if it is not valid in the current
+    /// position of the document.
+    pub fn write_event(&mut self, event: Event) -> Result<()> {
+        self.checker.check(&event)?;
+        match event {
+            Event::Comment(_) => return Ok(()),
+            Event::Key(key) => {
+                if let Some(container) = self.stack.last_mut() {
+                    container.count += 1;
+                }
+                return self.string(key)
+            }
+            Event::StringChunk(s) => {
+                self.string.push_str(s);
+                return Ok(())
+            }
+            Event::StartArray | Event::StartMap => {
+                let map = event == Event::StartMap;
+                let header = match self.seek {
+                    Some(seek) => Some(self.start(seek, map).map_err(io_error)?),
+                    None => None,
+                };
+                self.stack.push(Container { map, count: 0, content: vec![], header });
+                return Ok(())
+            }
+            Event::EndArray | Event::EndMap => {
+                let container = self.stack.pop().unwrap_or(Container { map: false, count: 0, content: vec![], header: None });
+                match (self.seek, container.header) {
+                    (Some(seek), Some(header)) => {
+                        let len = u32::try_from(container.count).map_err(|_| too_long())?;
+                        self.patch(seek, header, len).map_err(io_error)?;
+                    }
+                    _ => {
+                        let len = usize::try_from(container.count).map_err(|_| too_long())?;
+                        if container.map {
+                            self.header(len, Some((0x80, 16)), [0, 0xde, 0xdf])?;
+                        } else {
+                            self.header(len, Some((0x90, 16)), [0, 0xdc, 0xdd])?;
+                        }
+                        self.output().write_all(&container.content).map_err(io_error)?;
+                    }
+                }
+            }
+            Event::StringEnd => {
+                let value = mem::take(&mut self.string);
+                self.string(&value)?;
+            }
+            Event::String(s) => self.string(s)?,
+            Event::Bytes(b) => {
+                self.header(b.len(), None, [0xc4, 0xc5, 0xc6])?;
+                self.output().write_all(b).map_err(io_error)?;
+            }
+            Event::Null => self.output().write_all(&[0xc0]).map_err(io_error)?,
+            Event::Boolean(b) => self.output().write_all(&[if b { 0xc3 } else { 0xc2 }]).map_err(io_error)?,
+            Event::Number(n) => self.number(n).map_err(io_error)?,
+            Event::Int(n) => self.integer(n as i128).map_err(io_error)?,
+            Event::Uint(n) => self.integer(n as i128).map_err(io_error)?,
+            Event::RawNumber(s) => match s.parse::<i128>() {
+                Ok(n) if (i64::MIN as i128..=u64::MAX as i128).contains(&n) => self.integer(n).map_err(io_error)?,
+                _ => match s.parse::<f64>() {
+                    Ok(n) => self.number(n).map_err(io_error)?,
+                    Err(_) => return Err(Error::Custom(format!("Can't write number {} in MessagePack", s), Position::default())),
+                },
+            },
+        }
+        // A complete value is one more element of an array
+        if let Some(container) = self.stack.last_mut() {
+            if !container.map {
+                container.count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that complete documents have been written, flushes the output
+    /// and returns it.
+    pub fn finish(mut self) -> Result<W> {
+        self.checker.finish()?;
+        self.out.flush().map_err(io_error)?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write + Seek> MsgpackWriter<W> {
+    /// Creates a writer which keeps nothing in memory: containers are
+    /// written with 32-bit headers as they start, and their sizes are filled
+    /// in as they end. Small containers take 4 bytes more than they would
+    /// otherwise.
+    pub fn seekable(out: W) -> MsgpackWriter<W> {
+        let mut writer = MsgpackWriter::new(out);
+        writer.seek = Some(W::seek);
+        writer
+    }
+}
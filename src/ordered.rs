@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+use rustc_serialize::json::Json;
+
+use crate::builder::Node;
+use crate::errors::Result;
+use crate::parser::Event;
+use crate::writer::Writer;
+
+
+/// A JSON value like `Json` but keeping members of objects in the order of
+/// the document, built by `Builder::ordered_items`. Displays as compact
+/// JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderedJson {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    String(String),
+    Boolean(bool),
+    Array(Vec<OrderedJson>),
+    Object(Vec<(String, OrderedJson)>),
+    Null,
+}
+
+impl OrderedJson {
+    /// Looks up a member of an object.
+    pub fn find(&self, key: &str) -> Option<&OrderedJson> {
+        match self {
+            OrderedJson::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Writes the value as events. Non-finite numbers are written as `null`,
+    /// the same as `Json` does.
+    pub fn write<W: Write>(&self, writer: &mut Writer<W>) -> Result<()> {
+        match self {
+            OrderedJson::I64(v) => writer.write_event(Event::Int(*v)),
+            OrderedJson::U64(v) => writer.write_event(Event::Uint(*v)),
+            OrderedJson::F64(v) if v.is_finite() => writer.write_event(Event::Number(*v)),
+            OrderedJson::F64(_) | OrderedJson::Null => writer.write_event(Event::Null),
+            OrderedJson::String(v) => writer.write_event(Event::String(v)),
+            OrderedJson::Boolean(v) => writer.write_event(Event::Boolean(*v)),
+            OrderedJson::Array(values) => {
+                writer.write_event(Event::StartArray)?;
+                for value in values {
+                    value.write(writer)?;
+                }
+                writer.write_event(Event::EndArray)
+            }
+            OrderedJson::Object(members) => {
+                writer.write_event(Event::StartMap)?;
+                for (key, value) in members {
+                    writer.write_event(Event::Key(key))?;
+                    value.write(writer)?;
+                }
+                writer.write_event(Event::EndMap)
+            }
+        }
+    }
+}
+
+impl fmt::Display for OrderedJson {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut writer = Writer::new(vec![]);
+        self.write(&mut writer).map_err(|_| fmt::Error)?;
+        let out = writer.finish().map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+/// Members of an object come in the order of keys.
+impl From<Json> for OrderedJson {
+    fn from(value: Json) -> Self {
+        match value {
+            Json::I64(v) => OrderedJson::I64(v),
+            Json::U64(v) => OrderedJson::U64(v),
+            Json::F64(v) => OrderedJson::F64(v),
+            Json::String(v) => OrderedJson::String(v),
+            Json::Boolean(v) => OrderedJson::Boolean(v),
+            Json::Array(values) => OrderedJson::Array(values.into_iter().map(OrderedJson::from).collect()),
+            Json::Object(object) => OrderedJson::Object(object.into_iter().map(|(k, v)| (k, v.into())).collect()),
+            Json::Null => OrderedJson::Null,
+        }
+    }
+}
+
+/// Loses the order of object members, and all but the last of repeated keys.
+impl From<OrderedJson> for Json {
+    fn from(value: OrderedJson) -> Self {
+        match value {
+            OrderedJson::I64(v) => Json::I64(v),
+            OrderedJson::U64(v) => Json::U64(v),
+            OrderedJson::F64(v) => Json::F64(v),
+            OrderedJson::String(v) => Json::String(v),
+            OrderedJson::Boolean(v) => Json::Boolean(v),
+            OrderedJson::Array(values) => Json::Array(values.into_iter().map(Json::from).collect()),
+            OrderedJson::Object(members) => Json::Object(members.into_iter().map(|(k, v)| (k, v.into())).collect()),
+            OrderedJson::Null => Json::Null,
+        }
+    }
+}
+
+/// An object of `OrderedJson` under construction, with an index of keys to
+/// find repeated ones.
+#[derive(Default)]
+pub struct OrderedObject {
+    members: Vec<(String, OrderedJson)>,
+    index: HashMap<String, usize>,
+}
+
+impl Node for OrderedJson {
+    type Object = OrderedObject;
+
+    fn array(values: Vec<OrderedJson>) -> OrderedJson {
+        OrderedJson::Array(values)
+    }
+
+    fn object(object: OrderedObject) -> OrderedJson {
+        OrderedJson::Object(object.members)
+    }
+
+    fn member<'a>(object: &'a mut OrderedObject, key: &str) -> Option<&'a mut OrderedJson> {
+        let index = *object.index.get(key)?;
+        Some(&mut object.members[index].1)
+    }
+
+    fn insert(object: &mut OrderedObject, key: String, value: OrderedJson) {
+        object.index.insert(key.clone(), object.members.len());
+        object.members.push((key, value));
+    }
+
+    fn elements(&mut self) -> Option<&mut Vec<OrderedJson>> {
+        match self {
+            OrderedJson::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+use rustc_serialize::json::Json;
+
+use crate::builder::Builder;
+use crate::errors::{Error, Position, Result};
+use crate::parser::Parser;
+
+
+/// Lines of input in a batch given to a thread at once.
+pub const BATCH_LINES: usize = 1000;
+
+/// A batch of lines: its number, the number of its first line and the
+/// text of its lines.
+type Job = (usize, usize, Vec<u8>);
+
+/// The function processing a line, shared by threads.
+type LineFunction<T> = Arc<dyn Fn(&[u8]) -> Result<T> + Send + Sync>;
+
+/// Results of a batch, or the panic of the function processing it.
+type Done<T> = (usize, thread::Result<Vec<(usize, Result<T>)>>);
+
+/// Processes lines of input, such as newline-delimited JSON, on several
+/// threads. Input is read on the calling thread in batches of lines, and
+/// each non-empty line is handed to a function on one of the threads.
+/// Results come with the numbers of their lines, starting with 1, in the
+/// order of input or, with `set_ordered(false)`, in the order batches
+/// complete.
+///
+/// ```
+/// use ijson::parallel::ParallelLines;
+///
+/// let data = b"{\"a\": 1}\n{\"a\": 2}\n\n[3\n";
+/// let results: Vec<_> = ParallelLines::json(&data[..]).collect();
+/// assert_eq!(results.len(), 3);
+/// assert_eq!(results[1].0, 2);
+/// assert!(results[2].1.is_err());
+/// ```
+pub struct ParallelLines<R: BufRead, T: Send + 'static> {
+    reader: R,
+    function: LineFunction<T>,
+    threads: usize,
+    ordered: bool,
+    batch_lines: usize,
+    max_line: usize,
+    workers: Vec<JoinHandle<()>>,
+    jobs: Option<Sender<Job>>,
+    done: Option<Receiver<Done<T>>>,
+    /// Batches sent and not received yet.
+    in_flight: usize,
+    /// Batches received ahead of their turn in the ordered mode, which
+    /// count against the batches in flight until they're taken.
+    pending: BTreeMap<usize, Vec<(usize, Result<T>)>>,
+    sent: usize,
+    next_batch: usize,
+    line: usize,
+    /// Reading input has ended, with an error to report if it failed.
+    end: Option<Option<Error>>,
+    current: vec::IntoIter<(usize, Result<T>)>,
+}
+
+impl<R: BufRead> ParallelLines<R, Json> {
+    /// Builds a `Json` value out of each line.
+    pub fn json(reader: R) -> Self {
+        ParallelLines::new(reader, |line| {
+            let mut items = Parser::from_slice(line).into_iter().items("");
+            let value = items.next().unwrap_or_else(|| Err(Error::Incomplete(Position::default())))?;
+            match items.next() {
+                Some(Err(e)) => Err(e),
+                _ => Ok(value),
+            }
+        })
+    }
+}
+
+impl<R: BufRead, T: Send + 'static> ParallelLines<R, T> {
+    /// Calls `function` for each line, without its line break. Errors it
+    /// returns have positions counted from the start of the line.
+    pub fn new<F>(reader: R, function: F) -> Self where F: Fn(&[u8]) -> Result<T> + Send + Sync + 'static {
+        ParallelLines {
+            reader,
+            function: Arc::new(function),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            ordered: true,
+            batch_lines: BATCH_LINES,
+            max_line: usize::MAX,
+            workers: vec![],
+            jobs: None,
+            done: None,
+            in_flight: 0,
+            pending: BTreeMap::new(),
+            sent: 0,
+            next_batch: 0,
+            line: 0,
+            end: None,
+            current: vec![].into_iter(),
+        }
+    }
+
+    /// Sets the number of threads, the number of CPUs by default. Has no
+    /// effect once results are taken.
+    pub fn set_threads(&mut self, value: usize) {
+        self.threads = value.max(1);
+    }
+
+    /// Makes results come in the order batches complete rather than in the
+    /// order of input, which keeps threads busy when lines take uneven time.
+    pub fn set_ordered(&mut self, value: bool) {
+        self.ordered = value;
+    }
+
+    /// Sets the number of lines in a batch, `BATCH_LINES` by default.
+    pub fn set_batch_lines(&mut self, value: usize) {
+        self.batch_lines = value.max(1);
+    }
+
+    /// Sets the maximum length of a line in bytes, unlimited by default.
+    /// A longer line ends the input with `Error::StringLimit`, as lines are
+    /// kept in memory whole.
+    pub fn set_max_line(&mut self, value: usize) {
+        self.max_line = value;
+    }
+
+    fn start(&mut self) {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (results, done) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..self.threads {
+            let queue = Arc::clone(&queue);
+            let results: Sender<Done<T>> = results.clone();
+            let function = Arc::clone(&self.function);
+            self.workers.push(thread::spawn(move || loop {
+                let job = queue.lock().map(|queue| queue.recv());
+                let (index, first_line, data) = match job {
+                    Ok(Ok(job)) => job,
+                    _ => return,
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    data.split(|&b| b == b'\n').enumerate()
+                        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+                        .map(|(i, line)| (first_line + i, function(line)))
+                        .collect()
+                }));
+                if results.send((index, result)).is_err() {
+                    return
+                }
+            }));
+        }
+        self.jobs = Some(jobs);
+        self.done = Some(done);
+    }
+
+    /// Reads a line into `data` like `read_until`, failing on a line longer
+    /// than `max_line`.
+    fn read_line(&mut self, data: &mut Vec<u8>, line: usize) -> Result<usize> {
+        let start = data.len();
+        let mut size = 0;
+        loop {
+            let buf = self.reader.fill_buf().map_err(|e| Error::IO(e, Position::default()))?;
+            let (take, found) = match buf.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            if size + take - found as usize > self.max_line {
+                data.truncate(start);
+                return Err(Error::StringLimit(Position { line, ..Position::default() }))
+            }
+            data.extend_from_slice(&buf[..take]);
+            self.reader.consume(take);
+            size += take;
+            if found || take == 0 {
+                return Ok(size)
+            }
+        }
+    }
+
+    /// Reads and sends batches until there are enough in flight or waiting
+    /// for their turn.
+    fn fill(&mut self) {
+        while self.end.is_none() && self.in_flight + self.pending.len() < self.threads * 2 {
+            let mut data = vec![];
+            let mut lines = 0;
+            while lines < self.batch_lines {
+                match self.read_line(&mut data, self.line + lines + 1) {
+                    Ok(0) => {
+                        self.end = Some(None);
+                        break
+                    }
+                    Ok(_) => lines += 1,
+                    Err(e) => {
+                        self.end = Some(Some(e));
+                        break
+                    }
+                }
+            }
+            if data.ends_with(b"\n") {
+                data.pop();
+            }
+            if lines == 0 {
+                break
+            }
+            if let Some(ref jobs) = self.jobs {
+                let _ = jobs.send((self.sent, self.line + 1, data));
+            }
+            self.sent += 1;
+            self.in_flight += 1;
+            self.line += lines;
+        }
+    }
+}
+
+impl<R: BufRead, T: Send + 'static> Iterator for ParallelLines<R, T> {
+    type Item = (usize, Result<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.jobs.is_none() {
+            self.start();
+        }
+        loop {
+            if let Some(item) = self.current.next() {
+                return Some(item)
+            }
+            if let Some(batch) = self.pending.remove(&self.next_batch) {
+                self.next_batch += 1;
+                self.current = batch.into_iter();
+                continue
+            }
+            self.fill();
+            if self.in_flight == 0 {
+                let error = self.end.as_mut().and_then(Option::take)?;
+                return Some((self.line + 1, Err(error)))
+            }
+            let (index, result) = self.done.as_ref()?.recv().ok()?;
+            self.in_flight -= 1;
+            let batch = result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+            if self.ordered {
+                self.pending.insert(index, batch);
+            } else {
+                self.current = batch.into_iter();
+            }
+        }
+    }
+}
+
+impl<R: BufRead, T: Send + 'static> Drop for ParallelLines<R, T> {
+    fn drop(&mut self) {
+        // Workers end once the queue is closed
+        self.jobs = None;
+        self.done = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -1,31 +1,124 @@
-use std::io::Read;
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{self, BufReader, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::lexer::{Lexer, Lexeme};
-use crate::errors::{Error, Result};
+use crate::lexer::{Lexer, Lexeme, Comments, ReadBuffer, Buffered, Progress, BUFSIZE};
+use crate::builder::Located;
+use crate::fast::FastParser;
+use crate::errors::{Error, Expected, Position, Result};
+use crate::path::{Path, Step, Tracker, dotted_steps};
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[derive(PartialEq)]
 pub enum Event<'a> {
     Null,
     Boolean(bool),
     String(&'a str),
+    /// A piece of a long string, see `Parser::set_string_chunk_size`.
+    StringChunk(&'a str),
+    /// The end of a string that came in chunks.
+    StringEnd,
+    /// A string value not checked to be UTF-8, see
+    /// `Parser::set_byte_strings`.
+    Bytes(&'a [u8]),
     Key(&'a str),
     Number(f64),
+    Int(i64),
+    Uint(u64),
+    RawNumber(&'a str),
     StartArray,
     EndArray,
     StartMap,
     EndMap,
+    /// Text of a comment without delimiters, see `Comments::Report`.
+    Comment(&'a str),
+}
+
+/// An owned counterpart of `Event` that doesn't borrow from the parser and
+/// thus can be collected, sent across threads and so on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedEvent {
+    Null,
+    Boolean(bool),
+    String(String),
+    StringChunk(String),
+    StringEnd,
+    Bytes(Vec<u8>),
+    Key(String),
+    Number(f64),
+    Int(i64),
+    Uint(u64),
+    RawNumber(String),
+    StartArray,
+    EndArray,
+    StartMap,
+    EndMap,
+    Comment(String),
+}
+
+impl<'a> From<Event<'a>> for OwnedEvent {
+    fn from(event: Event<'a>) -> Self {
+        match event {
+            Event::Null => OwnedEvent::Null,
+            Event::Boolean(b) => OwnedEvent::Boolean(b),
+            Event::String(s) => OwnedEvent::String(s.to_owned()),
+            Event::StringChunk(s) => OwnedEvent::StringChunk(s.to_owned()),
+            Event::StringEnd => OwnedEvent::StringEnd,
+            Event::Bytes(b) => OwnedEvent::Bytes(b.to_owned()),
+            Event::Key(s) => OwnedEvent::Key(s.to_owned()),
+            Event::Number(n) => OwnedEvent::Number(n),
+            Event::Int(n) => OwnedEvent::Int(n),
+            Event::Uint(n) => OwnedEvent::Uint(n),
+            Event::RawNumber(s) => OwnedEvent::RawNumber(s.to_owned()),
+            Event::StartArray => OwnedEvent::StartArray,
+            Event::EndArray => OwnedEvent::EndArray,
+            Event::StartMap => OwnedEvent::StartMap,
+            Event::EndMap => OwnedEvent::EndMap,
+            Event::Comment(s) => OwnedEvent::Comment(s.to_owned()),
+        }
+    }
+}
+
+impl OwnedEvent {
+    pub fn as_event(&self) -> Event<'_> {
+        match *self {
+            OwnedEvent::Null => Event::Null,
+            OwnedEvent::Boolean(b) => Event::Boolean(b),
+            OwnedEvent::String(ref s) => Event::String(s),
+            OwnedEvent::StringChunk(ref s) => Event::StringChunk(s),
+            OwnedEvent::StringEnd => Event::StringEnd,
+            OwnedEvent::Bytes(ref b) => Event::Bytes(b),
+            OwnedEvent::Key(ref s) => Event::Key(s),
+            OwnedEvent::Number(n) => Event::Number(n),
+            OwnedEvent::Int(n) => Event::Int(n),
+            OwnedEvent::Uint(n) => Event::Uint(n),
+            OwnedEvent::RawNumber(ref s) => Event::RawNumber(s),
+            OwnedEvent::StartArray => Event::StartArray,
+            OwnedEvent::EndArray => Event::EndArray,
+            OwnedEvent::StartMap => Event::StartMap,
+            OwnedEvent::EndMap => Event::EndMap,
+            OwnedEvent::Comment(ref s) => Event::Comment(s),
+        }
+    }
 }
 
 #[derive(Debug)]
 enum State {
+    Failed,
     Closed,
     Value,
     ArrayOpen,
     ObjectOpen,
     Colon,
     Comma,
+    Key,
+    Element,
+    /// Between chunks of a string.
+    String,
 }
 
 #[derive(PartialEq)]
@@ -34,47 +127,322 @@ enum Container {
     Array,
 }
 
+/// The flavour of JSON accepted by the parser, see `Parser::set_dialect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// JSON as per RFC 8259.
+    Json,
+    /// JSON5, adding to JSON unquoted keys, single-quoted strings, more
+    /// escapes, hexadecimal numbers, `Infinity`, `NaN`, numbers with a
+    /// leading plus or point, trailing commas and comments.
+    #[cfg(feature = "json5")]
+    Json5,
+}
+
+/// What a parser of multiple values does after an error in the input, see
+/// `Parser::set_on_error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// End parsing, the default.
+    Stop,
+    /// Skip the rest of the line with the error and go on with the next
+    /// value, for newline-delimited input where one bad record shouldn't
+    /// stop the rest.
+    Skip,
+}
+
+/// Default limit of container nesting, see `Parser::set_max_depth`.
+pub const MAX_DEPTH: usize = 128;
+
+/// Bounds on resources an input document can make the parser consume. Each
+/// exceeded limit is reported with its own error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Maximum nesting of containers, `Error::DepthLimit`.
+    pub max_depth: usize,
+    /// Maximum length of a string, or of a reported comment, in bytes,
+    /// `Error::StringLimit`.
+    pub max_string: usize,
+    /// Maximum length of a number in bytes, `Error::NumberLimit`.
+    pub max_number: usize,
+    /// Maximum total number of events, `Error::EventLimit`.
+    pub max_events: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: MAX_DEPTH,
+            max_string: usize::MAX,
+            max_number: usize::MAX,
+            max_events: usize::MAX,
+        }
+    }
+}
+
+/// Numbers of events of each kind produced by a parser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventCounts {
+    pub null: u64,
+    pub boolean: u64,
+    /// Strings and byte strings, including chunked ones, counted once.
+    pub string: u64,
+    pub string_chunk: u64,
+    pub key: u64,
+    pub number: u64,
+    pub start_array: u64,
+    pub end_array: u64,
+    pub start_map: u64,
+    pub end_map: u64,
+    pub comment: u64,
+}
+
+impl EventCounts {
+    fn count(&mut self, event: &Event<'_>) {
+        let counter = match *event {
+            Event::Null => &mut self.null,
+            Event::Boolean(_) => &mut self.boolean,
+            Event::String(_) | Event::Bytes(_) | Event::StringEnd => &mut self.string,
+            Event::StringChunk(_) => &mut self.string_chunk,
+            Event::Key(_) => &mut self.key,
+            Event::Number(_) | Event::Int(_) | Event::Uint(_) | Event::RawNumber(_) => &mut self.number,
+            Event::StartArray => &mut self.start_array,
+            Event::EndArray => &mut self.end_array,
+            Event::StartMap => &mut self.start_map,
+            Event::EndMap => &mut self.end_map,
+            Event::Comment(_) => &mut self.comment,
+        };
+        *counter += 1;
+    }
+}
+
+/// Counts of the work a parser has done so far, see `Parser::counters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Bytes of input consumed, as counted by `Position::offset`.
+    pub bytes: u64,
+    pub events: EventCounts,
+    /// Reads filling the read buffer with more input.
+    pub refills: u64,
+    /// Strings and keys returned right from the read buffer.
+    pub borrowed_strings: u64,
+    /// Strings and keys that had to be copied, because of escapes or
+    /// because they were split between reads, which a larger read buffer
+    /// can help with. Chunked strings count here too.
+    pub copied_strings: u64,
+}
+
+impl Counters {
+    /// Counts of the work done since `earlier` counters of the same parser
+    /// were taken.
+    pub fn since(&self, earlier: &Counters) -> Counters {
+        let (e, b) = (&self.events, &earlier.events);
+        Counters {
+            bytes: self.bytes - earlier.bytes,
+            events: EventCounts {
+                null: e.null - b.null,
+                boolean: e.boolean - b.boolean,
+                string: e.string - b.string,
+                string_chunk: e.string_chunk - b.string_chunk,
+                key: e.key - b.key,
+                number: e.number - b.number,
+                start_array: e.start_array - b.start_array,
+                end_array: e.end_array - b.end_array,
+                start_map: e.start_map - b.start_map,
+                end_map: e.end_map - b.end_map,
+                comment: e.comment - b.comment,
+            },
+            refills: self.refills - earlier.refills,
+            borrowed_strings: self.borrowed_strings - earlier.borrowed_strings,
+            copied_strings: self.copied_strings - earlier.copied_strings,
+        }
+    }
+
+    /// Adds the counts to counters of the `metrics` crate: `ijson_bytes`,
+    /// `ijson_refills`, `ijson_strings` labeled with `storage` and
+    /// `ijson_events` labeled with `kind`. To report a parser still
+    /// running, pass the counts `since` the previous report.
+    #[cfg(feature = "metrics")]
+    pub fn record(&self) {
+        use metrics::counter;
+
+        counter!("ijson_bytes").increment(self.bytes);
+        counter!("ijson_refills").increment(self.refills);
+        counter!("ijson_strings", "storage" => "borrowed").increment(self.borrowed_strings);
+        counter!("ijson_strings", "storage" => "copied").increment(self.copied_strings);
+        let e = &self.events;
+        for &(kind, value) in &[
+            ("null", e.null),
+            ("boolean", e.boolean),
+            ("string", e.string),
+            ("string_chunk", e.string_chunk),
+            ("key", e.key),
+            ("number", e.number),
+            ("start_array", e.start_array),
+            ("end_array", e.end_array),
+            ("start_map", e.start_map),
+            ("end_map", e.end_map),
+            ("comment", e.comment),
+        ] {
+            counter!("ijson_events", "kind" => kind).increment(value);
+        }
+    }
+}
+
+/// An open container checked for duplicate keys, see
+/// `Parser::set_reject_duplicate_keys`.
+enum Scope {
+    /// Hashes of keys seen in an object and its current key, for the path
+    /// of a duplicate.
+    Object(HashSet<u64>, String),
+    /// The number of values started in an array.
+    Array(usize),
+}
+
 struct ParserState {
     state: State,
     stack: Vec<Container>,
+    max_depth: usize,
+    trailing_commas: bool,
+    /// Duplicate key checks of each open container, `None` for containers
+    /// opened with the checks off.
+    scopes: Vec<Option<Scope>>,
+    reject_duplicate_keys: bool,
+    hasher: RandomState,
+    /// Set on an error to skip past with `OnError::Skip`.
+    resync: bool,
 }
 
 impl ParserState {
 
     #[inline(always)]
-    fn process_value<'a>(&mut self, lexeme: Lexeme<'a>) -> Result<Event<'a>> {
-        match &lexeme {
-            &Lexeme::OBracket => self.stack.push(Container::Array),
-            &Lexeme::OBrace => self.stack.push(Container::Object),
+    fn process_value<'a>(&mut self, lexeme: Lexeme<'a>, position: Position) -> Result<Event<'a>> {
+        if let Lexeme::Identifier(_) = lexeme {
+            return Err(Error::Unexpected(Expected::VALUE, position))
+        }
+        if let Lexeme::OBracket | Lexeme::OBrace = lexeme {
+            if self.stack.len() >= self.max_depth {
+                return Err(Error::DepthLimit(position))
+            }
+        }
+        self.count_value();
+        match lexeme {
+            Lexeme::OBracket => {
+                self.stack.push(Container::Array);
+                self.scopes.push(self.reject_duplicate_keys.then(|| Scope::Array(0)));
+            }
+            Lexeme::OBrace => {
+                self.stack.push(Container::Object);
+                self.scopes.push(self.reject_duplicate_keys.then(|| Scope::Object(HashSet::new(), String::new())));
+            }
             _ => (),
         };
         let result = match lexeme {
             Lexeme::OBracket => Event::StartArray,
             Lexeme::OBrace => Event::StartMap,
             Lexeme::String(s) => Event::String(s),
+            Lexeme::StringChunk(s) => Event::StringChunk(s),
+            Lexeme::Bytes(b) => Event::Bytes(b),
             Lexeme::Number(n) => Event::Number(n),
+            Lexeme::Int(n) => Event::Int(n),
+            Lexeme::Uint(n) => Event::Uint(n),
+            Lexeme::RawNumber(s) => Event::RawNumber(s),
             Lexeme::Null => Event::Null,
             Lexeme::Boolean(b) => Event::Boolean(b),
             _ => unreachable!(),
         };
 
+        self.state = match lexeme {
+            Lexeme::OBracket => State::ArrayOpen,
+            Lexeme::OBrace => State::ObjectOpen,
+            Lexeme::StringChunk(_) => State::String,
+            _ if self.stack.is_empty() => State::Closed,
+            _ => State::Comma,
+        };
+
+        Ok(result)
+    }
+
+    /// Counts a value starting in an array, for paths of duplicate keys.
+    fn count_value(&mut self) {
+        if let Some(Some(Scope::Array(values))) = self.scopes.last_mut() {
+            *values += 1;
+        }
+    }
+
+    /// The dotted path of the current value, if all containers around it
+    /// are checked for duplicate keys.
+    fn path(&self) -> Option<String> {
+        let steps = self.scopes.iter().map(|scope| match scope.as_ref()? {
+            Scope::Object(_, key) => Some(Step::Key(key.clone())),
+            Scope::Array(values) => Some(Step::Index(values.saturating_sub(1))),
+        }).collect::<Option<Vec<_>>>()?;
+        Some(dotted_steps(&steps))
+    }
+
+    /// The end of the innermost open container.
+    fn expected_end(&self) -> Expected {
+        match self.stack.last() {
+            Some(Container::Object) => Expected::END_MAP,
+            Some(Container::Array) => Expected::END_ARRAY,
+            None => Expected::default(),
+        }
+    }
+
+    /// What can follow a comma in the innermost open container.
+    fn expected_after_comma(&self) -> Expected {
+        let item = match self.stack.last() {
+            Some(Container::Object) => Expected::KEY,
+            _ => Expected::VALUE,
+        };
+        item.with(self.expected_end(), self.trailing_commas)
+    }
+
+    /// Stops parsing after `error`. Errors in the input, unlike failing
+    /// reads, limits on the whole input and cancellation, can be skipped.
+    fn fail(&mut self, error: &Error) {
+        fn skippable(error: &Error) -> bool {
+            match *error {
+                Error::IO(..) | Error::EventLimit(_) | Error::Cancelled(_) => false,
+                Error::Context(ref e, _) => skippable(e),
+                _ => true,
+            }
+        }
+        self.state = State::Failed;
+        self.resync = skippable(error);
+    }
+
+    /// Forgets the document an error was found in, to go on with the next.
+    fn reset(&mut self) {
+        self.stack.clear();
+        self.scopes.clear();
+        self.state = State::Closed;
+        self.resync = false;
+    }
+
+    /// Makes up the next event completing a document cut off by the end of
+    /// input, see `Parser::set_repair`.
+    fn repair<'a>(&mut self, position: Position) -> Result<Event<'a>> {
+        match (&self.state, self.stack.last()) {
+            (State::Colon, _) | (State::Value, _) => self.process_value(Lexeme::Null, position),
+            (_, Some(Container::Object)) => self.process_closing(Container::Object, position),
+            _ => self.process_closing(Container::Array, position),
+        }
+    }
+
+    fn after_value(&mut self) {
         self.state = if self.stack.is_empty() {
             State::Closed
-        } else if lexeme == Lexeme::OBracket {
-            State::ArrayOpen
-        } else if lexeme == Lexeme::OBrace {
-            State::ObjectOpen
         } else {
             State::Comma
         };
-
-        Ok(result)
     }
 
     #[inline(always)]
-    fn process_closing<'a>(&mut self, expected: Container) -> Result<Event<'a>> {
+    fn process_closing<'a>(&mut self, expected: Container, position: Position) -> Result<Event<'a>> {
         match self.stack.pop() {
             Some(ref value) if *value == expected => {
+                self.scopes.pop();
                 self.state = if self.stack.is_empty() {
                     State::Closed
                 } else {
@@ -85,100 +453,1213 @@ impl ParserState {
                     Container::Object => Event::EndMap,
                 })
             }
-            _ => Err(Error::Unmatched),
+            _ => Err(Error::Unmatched(position)),
         }
     }
 
     #[inline(always)]
-    fn process_key<'a>(&mut self, lexeme: Lexeme<'a>) -> Result<Event<'a>> {
-        self.state = State::Colon;
-        match lexeme {
-            Lexeme::String(s) => Ok(Event::Key(s)),
-            _ => Err(Error::Unexpected),
+    fn process_key<'a>(&mut self, key: &'a str, position: Position) -> Result<Event<'a>> {
+        if let Some(Some(Scope::Object(keys, current))) = self.scopes.last_mut() {
+            current.clear();
+            current.push_str(key);
+            if !keys.insert(self.hasher.hash_one(key)) {
+                let e = Error::DuplicateKey(key.to_owned(), position);
+                return Err(match self.path() {
+                    Some(path) => e.with_path(path),
+                    None => e,
+                })
+            }
         }
+        self.state = State::Colon;
+        Ok(Event::Key(key))
     }
 
 }
 
-pub struct Parser<T: Read> {
-    lexer: Lexer<T>,
+pub struct Parser<T: Read, B: ReadBuffer = Vec<u8>> {
+    lexer: Lexer<T, B>,
     state: ParserState,
+    multiple: bool,
+    single_value: bool,
+    events: usize,
+    max_events: usize,
+    error_context: bool,
+    cancel: Option<Arc<AtomicBool>>,
+    event_counts: EventCounts,
+    repair: bool,
+    repaired: bool,
+    on_error: OnError,
 }
 
-impl<T: Read> Lexer<T> {
+impl<T: Read, B: ReadBuffer> Lexer<T, B> {
     #[inline]
-    pub fn consume(&mut self) -> Result<Lexeme> {
-        self.next().unwrap_or(Err(Error::MoreLexemes))
+    pub fn consume(&mut self) -> Result<(Position, Lexeme<'_>)> {
+        let position = self.position();
+        self.next_with_position().unwrap_or(Err(Error::Incomplete(position)))
     }
 }
 
 impl<T: Read> Parser<T> {
 
     pub fn new(f: T) -> Parser<T> {
+        Parser::from_lexer(Lexer::new(f))
+    }
+
+    /// Creates a parser reading from `f` in chunks of `size` bytes.
+    pub fn with_capacity(f: T, size: usize) -> Parser<T> {
+        Parser::from_lexer(Lexer::with_capacity(f, size))
+    }
+
+    /// Creates a parser using `buf` as its read buffer, see
+    /// `Lexer::with_buffer`.
+    pub fn with_buffer(f: T, buf: Vec<u8>) -> Parser<T> {
+        Parser::from_lexer(Lexer::with_buffer(f, buf))
+    }
+
+    /// Creates a parser accepting any number of top-level values, such as
+    /// newline-delimited JSON. Values may also come back to back with
+    /// nothing between them, `{"a":1}{"b":2}[3]`, as some loggers write
+    /// them, except for numbers, which need whitespace to tell where they
+    /// end. Each document is a value at prefix `""`.
+    pub fn new_multi(f: T) -> Parser<T> {
+        let mut parser = Parser::new(f);
+        parser.set_multiple();
+        parser
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<T: Read> Parser<flate2::read::MultiGzDecoder<T>> {
+
+    /// Creates a parser of gzip-compressed input, decompressing it on the
+    /// fly. Input of several gzip members, such as concatenated `.gz`
+    /// files, is read through all of them. Other options are available by
+    /// passing a decoder to `ParserBuilder::build`.
+    pub fn new_gzip(f: T) -> Self {
+        Parser::new(flate2::read::MultiGzDecoder::new(f))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<T: Read> Parser<zstd::stream::read::Decoder<'static, BufReader<T>>> {
+
+    /// Creates a parser of zstd-compressed input, decompressing it on the
+    /// fly. Input of several frames is read through all of them.
+    pub fn new_zstd(f: T) -> Result<Self> {
+        zstd::stream::read::Decoder::new(f).map(Parser::new).map_err(|e| Error::IO(e, Position::default()))
+    }
+}
+
+impl Parser<io::Empty> {
+
+    /// Starts configuring a parser with all options at once:
+    ///
+    /// ```
+    /// use ijson::parser::Parser;
+    ///
+    /// let data = b"[1, /* two */ 2]";
+    /// let parser = Parser::builder().max_depth(64).allow_comments(true).build(&data[..]);
+    /// assert_eq!(parser.into_iter().count(), 4);
+    /// ```
+    pub fn builder() -> ParserBuilder {
+        ParserBuilder::default()
+    }
+}
+
+impl<'a> Parser<io::Empty, &'a [u8]> {
+
+    /// Creates a parser over input that is in memory as a whole, see
+    /// `Lexer::from_slice`.
+    pub fn from_slice(data: &'a [u8]) -> Parser<io::Empty, &'a [u8]> {
+        Parser::from_lexer(Lexer::from_slice(data))
+    }
+
+    /// Creates a two-stage parser over a single document in memory, which
+    /// is several times faster on large input, see `FastParser`.
+    pub fn fast(data: &'a [u8]) -> FastParser<'a> {
+        FastParser::new(data)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Parser<io::Empty, crate::lexer::Mapped> {
+
+    /// Creates a parser over a file mapped into memory, see
+    /// `Lexer::from_mmap`. Strings without escapes are borrowed right out
+    /// of the map.
+    pub fn from_mmap(map: memmap2::Mmap) -> Parser<io::Empty, crate::lexer::Mapped> {
+        Parser::from_lexer(Lexer::from_mmap(map))
+    }
+}
+
+impl<R: Read> Parser<io::Empty, Buffered<R>> {
+
+    /// Creates a parser working right in the buffer of `reader`, see
+    /// `Lexer::from_buf_reader`.
+    pub fn from_buf_reader(reader: BufReader<R>) -> Parser<io::Empty, Buffered<R>> {
+        Parser::from_lexer(Lexer::from_buf_reader(reader))
+    }
+
+    /// Same as `into_inner` but returns the `BufReader`, see
+    /// `Lexer::into_buf_reader`.
+    pub fn into_buf_reader(self) -> (BufReader<R>, Vec<u8>) {
+        self.lexer.into_buf_reader()
+    }
+}
+
+impl<T: Read, B: ReadBuffer> Parser<T, B> {
+
+    /// Returns the read buffer for reuse, dropping the parser.
+    pub fn into_buffer(self) -> B {
+        self.lexer.into_buffer()
+    }
+
+    /// Returns the reader along with the input already buffered but not
+    /// parsed, so that a stream can be read on after the last event taken
+    /// from the parser. Parsers over a slice have nothing to return as the
+    /// reader, see `Lexer::into_inner`, and ones over a `BufReader` return it
+    /// with `into_buf_reader`.
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use ijson::parser::Parser;
+    ///
+    /// let data = br#"{"size": 5}hello"#;
+    /// let mut parser = Parser::new(&data[..]);
+    /// while parser.next().unwrap().unwrap() != ijson::parser::Event::EndMap {}
+    /// let (mut reader, mut rest) = parser.into_inner();
+    /// reader.read_to_end(&mut rest).unwrap();
+    /// assert_eq!(rest, b"hello");
+    /// ```
+    pub fn into_inner(self) -> (T, Vec<u8>) {
+        self.lexer.into_inner()
+    }
+
+    pub(crate) fn lexer_mut(&mut self) -> &mut Lexer<T, B> {
+        &mut self.lexer
+    }
+
+    fn set_multiple(&mut self) {
+        self.state.state = State::Closed;
+        self.multiple = true;
+    }
+
+    fn from_lexer(lexer: Lexer<T, B>) -> Parser<T, B> {
         Parser {
-            lexer: Lexer::new(f),
+            lexer,
             state: ParserState {
                 stack: vec![],
                 state: State::Value,
+                max_depth: MAX_DEPTH,
+                trailing_commas: false,
+                scopes: vec![],
+                reject_duplicate_keys: false,
+                hasher: RandomState::new(),
+                resync: false,
             },
+            multiple: false,
+            single_value: false,
+            events: 0,
+            max_events: usize::MAX,
+            error_context: false,
+            cancel: None,
+            event_counts: EventCounts::default(),
+            repair: false,
+            repaired: false,
+            on_error: OnError::Stop,
         }
     }
 
-    pub fn next<'a>(&'a mut self) -> Option<Result<Event<'a>>> {
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.state.max_depth = limits.max_depth;
+        self.max_events = limits.max_events;
+        self.lexer.set_max_string(limits.max_string);
+        self.lexer.set_max_number(limits.max_number);
+    }
+
+    /// Limits nesting of containers, deeper documents fail with
+    /// `Error::DepthLimit`.
+    pub fn set_max_depth(&mut self, value: usize) {
+        self.state.max_depth = value;
+    }
+
+    /// Skips the value that would come next without producing its events:
+    /// a top-level value, a value after a key or the next array element.
+    /// Returns `false` if there's no such value, e.g. at the end of an
+    /// array. See `Lexer::skip_value` for what is validated. The skipped
+    /// value counts as a single event against `Limits::max_events`, and
+    /// its containers against the maximum depth as if they were parsed.
+    pub fn skip_value(&mut self) -> Result<bool> {
+        self.try_skip_value(false).map(|raw| raw.is_some()).map_err(|e| self.fail(e))
+    }
+
+    /// Same as `skip_value` but returns the original text of the skipped
+    /// value, or `None` if there's no value to skip.
+    pub fn raw_value(&mut self) -> Result<Option<Vec<u8>>> {
+        self.try_skip_value(true).map_err(|e| self.fail(e))
+    }
+
+    /// Same as `skip_value` but returns the offsets where the skipped value
+    /// starts and ends, or `None` if there's no value to skip.
+    pub(crate) fn value_range(&mut self) -> Result<Option<(usize, usize)>> {
+        let result = self.value_ahead().and_then(|ahead| {
+            if !ahead {
+                return Ok(None)
+            }
+            self.count_skipped()?;
+            self.lexer.peek()?;
+            let start = self.lexer.position().offset;
+            self.state.count_value();
+            self.lexer.skip_value(self.depth_left())?;
+            self.state.after_value();
+            Ok(Some((start, self.lexer.position().offset)))
+        });
+        result.map_err(|e| self.fail(e))
+    }
+
+    /// Moves the parser up to the value that would come next, consuming a
+    /// colon or a comma before it. Returns `false` if there's no such value.
+    fn value_ahead(&mut self) -> Result<bool> {
+        let position = self.lexer.position();
+        match self.state.state {
+            State::Failed | State::ObjectOpen | State::Key | State::String => Ok(false),
+            State::Closed if !self.multiple => Ok(false),
+            State::Closed => Ok(self.lexer.peek()?.is_some()),
+            State::Value => Ok(true),
+            State::ArrayOpen | State::Element => Ok(self.lexer.peek()? != Some(b']')),
+            State::Colon => {
+                match self.lexer.consume()? {
+                    (_, Lexeme::Colon) => {
+                        self.state.state = State::Value;
+                        Ok(true)
+                    }
+                    (position, _) => Err(Error::Unexpected(Expected::COLON, position)),
+                }
+            }
+            State::Comma => {
+                if self.state.stack.last() == Some(&Container::Object) {
+                    return Ok(false)
+                }
+                match self.lexer.peek()? {
+                    Some(b']') => return Ok(false),
+                    Some(b',') => self.lexer.consume().map(|_| ())?,
+                    Some(_) => return Err(Error::Unexpected(Expected::COMMA | Expected::END_ARRAY, self.lexer.position())),
+                    None => return Err(Error::Incomplete(position)),
+                }
+                self.state.state = State::Element;
+                // A closing bracket after a trailing comma is left to `next`
+                Ok(!(self.state.trailing_commas && self.lexer.peek()? == Some(b']')))
+            }
+        }
+    }
+
+    /// Counts a value skipped without producing its events as a single
+    /// event against `Limits::max_events`.
+    fn count_skipped(&mut self) -> Result<()> {
+        if self.events >= self.max_events {
+            return Err(Error::EventLimit(self.lexer.position()))
+        }
+        self.events += 1;
+        Ok(())
+    }
+
+    /// How much deeper than the current value skipped containers can nest.
+    fn depth_left(&self) -> usize {
+        self.state.max_depth.saturating_sub(self.state.stack.len())
+    }
+
+    fn try_skip_value(&mut self, record: bool) -> Result<Option<Vec<u8>>> {
+        if !self.value_ahead()? {
+            return Ok(None)
+        }
+        self.count_skipped()?;
+        self.state.count_value();
+        if !record {
+            self.lexer.skip_value(self.depth_left())?;
+            self.state.after_value();
+            return Ok(Some(vec![]))
+        }
+        self.lexer.peek()?;
+        self.lexer.start_recording();
+        let result = self.lexer.skip_value(self.depth_left());
+        let raw = self.lexer.stop_recording();
+        result?;
+        self.state.after_value();
+        Ok(Some(raw))
+    }
+
+    /// Returns a reader over the decoded contents of the string value that
+    /// would come next (see `skip_value`), or `None` if the next value isn't
+    /// a string. The string is decoded as it's read and is never held in
+    /// memory whole. Events continue after the string once the reader
+    /// reaches its end. If the reader is dropped before that, the rest of
+    /// the string comes as `Event::StringChunk` and `Event::StringEnd`.
+    pub fn read_string(&mut self) -> Result<Option<StringReader<'_, T, B>>> {
+        let ahead = self.value_ahead().and_then(|ahead| Ok(ahead && self.lexer.at_string()?));
+        if !ahead.map_err(|e| self.fail(e))? {
+            return Ok(None)
+        }
+        // The string counts as a single event, as if it came whole
+        if self.events >= self.max_events {
+            return Err(self.fail(Error::EventLimit(self.lexer.position())))
+        }
+        self.events += 1;
+        self.event_counts.string += 1;
+        self.state.count_value();
+        self.lexer.start_chunks();
+        self.state.state = State::String;
+        Ok(Some(StringReader { parser: self, pos: 0 }))
+    }
+
+    /// Reads the next piece of a string for `StringReader`.
+    fn read_chunk(&mut self) -> Result<()> {
+        match self.lexer.read_chunk() {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.state.after_value();
+                Ok(())
+            }
+            Err(e) if e.is_would_block() => Err(e),
+            Err(e) => Err(self.fail(e)),
+        }
+    }
+
+    /// Skips the rest of the innermost open container, including its
+    /// closing bracket, without producing events.
+    pub fn skip_container(&mut self) -> Result<()> {
+        if let State::String = self.state.state {
+            self.lexer.skip_chunks().map_err(|e| self.fail(e))?;
+            self.state.after_value();
+        }
+        if self.state.stack.is_empty() {
+            return Ok(())
+        }
+        let closer: &[u8] = match self.state.stack.last() {
+            Some(Container::Object) => b"}",
+            _ => b"]",
+        };
+        // The container being skipped is already counted in the depth
+        let result = self.count_skipped().and_then(|_| self.lexer.skip_container(closer, self.depth_left() + 1));
+        result.map_err(|e| self.fail(e))?;
+        self.state.stack.pop();
+        self.state.scopes.pop();
+        self.state.after_value();
+        Ok(())
+    }
+
+    /// Yields the original text of values at a prefix (see
+    /// `Builder::prefix`) without decoding them, for passing them on
+    /// verbatim. The values are validated only as much as by `skip_value`,
+    /// and so are values that can't contain the prefix, which are skipped
+    /// without producing events.
+    pub fn raw_items(self, prefix: &str) -> RawItems<T, B> {
+        RawItems {
+            reference: Path::parse(prefix),
+            path: Tracker::default(),
+            parser: self,
+        }
+    }
+
+    /// Makes errors carry the input around their position as
+    /// `Error::Context`, see `Error::diagnostic`. Errors returned by `next`
+    /// don't, as the input can't be accessed while an event might borrow
+    /// it, and can be passed through `with_context` instead.
+    pub fn set_error_context(&mut self, value: bool) {
+        self.error_context = value;
+    }
+
+    /// Adds the input around the position of an error returned by the
+    /// parser, if enabled by `set_error_context`.
+    pub fn with_context(&self, error: Error) -> Error {
+        match error {
+            Error::Context(..) => error,
+            _ if !self.error_context || error.is_would_block() => error,
+            _ => {
+                let snippet = self.lexer.snippet(error.position().offset);
+                Error::Context(Box::new(error), snippet)
+            }
+        }
+    }
+
+    /// Stops the parser after an error.
+    fn fail(&mut self, error: Error) -> Error {
+        self.state.fail(&error);
+        self.with_context(error)
+    }
+
+    /// See `Lexer::set_lenient_numbers`.
+    pub fn set_lenient_numbers(&mut self, value: bool) {
+        self.lexer.set_lenient_numbers(value);
+    }
+
+    /// See `Lexer::set_control_characters`.
+    pub fn set_control_characters(&mut self, value: bool) {
+        self.lexer.set_control_characters(value);
+    }
+
+    /// Makes the parser produce `Event::Bytes` instead of `Event::String`
+    /// without checking that strings are valid UTF-8, for consumers that
+    /// only pass them on. See `Lexer::set_byte_strings`.
+    pub fn set_byte_strings(&mut self, value: bool) {
+        self.lexer.set_byte_strings(value);
+    }
+
+    /// See `Lexer::set_lossy_utf8`.
+    pub fn set_lossy_utf8(&mut self, value: bool) {
+        self.lexer.set_lossy_utf8(value);
+    }
+
+    /// See `Lexer::set_empty_read_retries`.
+    pub fn set_empty_read_retries(&mut self, value: usize) {
+        self.lexer.set_empty_read_retries(value);
+    }
+
+    /// Switches the parser to another dialect, which also sets the options
+    /// the dialect implies: lenient numbers, trailing commas and skipping
+    /// comments for JSON5. These can be changed afterwards, for example
+    /// to report comments.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        let json5 = dialect != Dialect::Json;
+        self.lexer.set_json5(json5);
+        self.lexer.set_lenient_numbers(json5);
+        self.lexer.set_comments(if json5 { Comments::Skip } else { Comments::Reject });
+        self.state.trailing_commas = json5;
+    }
+
+    /// Turns off everything the parser can accept beyond RFC 8259, so that
+    /// it accepts exactly the documents the RFC allows: the JSON dialect
+    /// with no comments, trailing commas, lenient or non-finite numbers,
+    /// control characters in strings, invalid UTF-8 or repairing cut off
+    /// input. Lone surrogates are rejected, as they are by default. Checked
+    /// against the `y_` and `n_` cases of JSONTestSuite.
+    pub fn set_strict(&mut self) {
+        self.set_dialect(Dialect::Json);
+        self.set_control_characters(false);
+        self.set_non_finite(false);
+        self.set_lossy_utf8(false);
+        self.set_byte_strings(false);
+        self.set_repair(false);
+    }
+
+    /// Makes the parser accept a comma after the last element of an array
+    /// or object, as in `[1, 2,]`.
+    pub fn set_trailing_commas(&mut self, value: bool) {
+        self.state.trailing_commas = value;
+    }
+
+    /// See `Lexer::set_non_finite`.
+    pub fn set_non_finite(&mut self, value: bool) {
+        self.lexer.set_non_finite(value);
+    }
+
+    /// Makes the parser fail with `Error::DuplicateKey` on a key repeated
+    /// in an object, with the dotted path of the repeated member as
+    /// `Error::path`. Keys aren't kept, only their 64-bit hashes, so memory
+    /// grows by a few bytes per key of open objects. The hashes are seeded
+    /// randomly, yet two different keys can still hash the same and be
+    /// taken for a duplicate: the chance of it is about n² / 2⁶⁵ for an
+    /// object of n keys, or one in 37 million for a million keys. Applies
+    /// to containers opened after the call, the path is left out if any
+    /// container around the key was opened before.
+    pub fn set_reject_duplicate_keys(&mut self, value: bool) {
+        self.state.reject_duplicate_keys = value;
+    }
+
+    /// Makes the parser end after the first complete top-level value
+    /// instead of failing with `Error::AdditionalData` on anything but
+    /// whitespace after it. The rest of the input is left unread: the value
+    /// ends at `position` and `into_inner` returns what follows it. Doesn't
+    /// apply to parsers of multiple values.
+    pub fn set_single_value(&mut self, value: bool) {
+        self.single_value = value;
+    }
+
+    /// Counts of the input consumed, events produced and so on, for tuning
+    /// the size of the read buffer or spotting unusual inputs.
+    pub fn counters(&self) -> Counters {
+        let lexer = self.lexer.counts();
+        Counters {
+            bytes: self.lexer.position().offset as u64,
+            events: self.event_counts,
+            refills: lexer.refills,
+            borrowed_strings: lexer.borrowed_strings,
+            copied_strings: lexer.copied_strings,
+        }
+    }
+
+    /// Position in the input right after the last event, which is where
+    /// a top-level value ends once it's complete.
+    pub fn position(&self) -> Position {
+        self.lexer.position()
+    }
+
+    /// Makes the parser fail with `Error::Cancelled` once `flag` is set,
+    /// from another thread for example. The flag is checked before each
+    /// event, so a long string value or a value skipped with `skip_value`
+    /// is read through before the parser stops.
+    pub fn set_cancel_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.cancel = Some(flag);
+    }
+
+    /// Makes the parser complete a document cut off by the end of input, as
+    /// left by a crashed producer, instead of failing with
+    /// `Error::Incomplete`: a string is ended where the input ends, a key
+    /// gets a `null` value and open containers are closed. A number, literal
+    /// or escape cut in the middle, or a trailing comma, is dropped. Whether
+    /// anything was repaired is told by `repaired`.
+    pub fn set_repair(&mut self, value: bool) {
+        self.repair = value;
+        self.lexer.set_repair(value);
+    }
+
+    /// Sets what a parser of multiple values, see `new_multi`, does after
+    /// an error in the input. With `OnError::Skip`, values are taken to be
+    /// separated by line breaks as in JSON lines, and a value still open at
+    /// the end of a line is reported as `Error::Incomplete`. After an error
+    /// is returned, the next call goes on from the next line. Failing
+    /// reads, `Error::EventLimit` and `Error::Cancelled` still end parsing.
+    pub fn set_on_error(&mut self, value: OnError) {
+        self.on_error = value;
+    }
+
+    /// Whether events were made up to complete a cut off document, see
+    /// `set_repair`.
+    pub fn repaired(&self) -> bool {
+        self.repaired || self.lexer.repaired()
+    }
+
+    /// See `Lexer::set_progress`.
+    pub fn set_progress<F>(&mut self, every: u64, total: Option<u64>, function: F) where F: FnMut(Progress) + Send + 'static {
+        self.lexer.set_progress(every, total, function);
+    }
+
+    /// Sets what to do with comments in the input, see `Comments`.
+    pub fn set_comments(&mut self, value: Comments) {
+        self.lexer.set_comments(value);
+    }
+
+    fn next_chunk(&mut self) -> Result<Event<'_>> {
+        let position = self.lexer.position();
+        let event = match self.lexer.consume() {
+            Ok((_, Lexeme::StringChunk(s))) => Ok(Event::StringChunk(s)),
+            Ok((_, Lexeme::StringEnd)) => {
+                self.state.after_value();
+                Ok(Event::StringEnd)
+            }
+            Ok((position, _)) => Err(Error::Unexpected(Expected::default(), position)),
+            Err(e) => Err(e),
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                if !e.is_would_block() {
+                    self.state.fail(&e);
+                }
+                return Err(e)
+            }
+        };
+        if self.events >= self.max_events {
+            self.state.state = State::Failed;
+            return Err(Error::EventLimit(position))
+        }
+        self.events += 1;
+        self.event_counts.count(&event);
+        Ok(event)
+    }
+
+    fn comment_event(&mut self) -> Result<Event<'_>> {
+        if self.events >= self.max_events {
+            self.state.state = State::Failed;
+            return Err(Error::EventLimit(self.lexer.position()))
+        }
+        self.events += 1;
+        self.event_counts.comment += 1;
+        Ok(Event::Comment(self.lexer.comment()))
+    }
+
+    /// Makes the parser produce string values longer than `value` bytes as
+    /// a series of `Event::StringChunk` followed by `Event::StringEnd`,
+    /// see `Lexer::set_string_chunk_size`.
+    pub fn set_string_chunk_size(&mut self, value: usize) {
+        self.lexer.set_string_chunk_size(value);
+    }
+
+    /// Makes the parser produce `Event::RawNumber` with the original text of
+    /// numbers, so they can be converted without loss of precision.
+    pub fn set_raw_numbers(&mut self, value: bool) {
+        self.lexer.set_raw_numbers(value);
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<Event<'_>>> {
+        // Consumes the next lexeme, failing the parser on error
+        macro_rules! consume {
+            () => {
+                match self.lexer.consume() {
+                    Ok(v) => v,
+                    // Fields are used one by one as the lexer stays borrowed
+                    Err(e) if self.repair && e.is_incomplete() && !self.state.stack.is_empty() => {
+                        self.repaired = true;
+                        let event = if self.events < self.max_events {
+                            self.state.repair(e.position())
+                        } else {
+                            Err(Error::EventLimit(e.position()))
+                        };
+                        match event {
+                            Ok(ref event) => {
+                                self.events += 1;
+                                self.event_counts.count(event);
+                            }
+                            Err(_) => self.state.state = State::Failed,
+                        }
+                        return Some(event)
+                    }
+                    Err(e) => {
+                        if !e.is_would_block() {
+                            self.state.fail(&e);
+                        }
+                        return Some(Err(e))
+                    }
+                }
+            }
+        }
+
+        // Reports a comment if it's next, moving the parser to `$state` for
+        // the rest of the current event
+        macro_rules! comment {
+            ($($state: expr)?) => {
+                if self.lexer.comments() == Comments::Report {
+                    match self.lexer.next_comment() {
+                        Ok(false) => (),
+                        Ok(true) => {
+                            $(self.state.state = $state;)?
+                            return Some(self.comment_event())
+                        }
+                        Err(e) => {
+                            if !e.is_would_block() {
+                                self.state.fail(&e);
+                            }
+                            return Some(Err(e))
+                        }
+                    }
+                }
+            }
+        }
+
+        if let State::Failed = self.state.state {
+            if !(self.state.resync && self.multiple && self.on_error == OnError::Skip) {
+                return None
+            }
+            if let Err(e) = self.lexer.skip_line() {
+                self.state.resync = false;
+                return Some(Err(e))
+            }
+            self.state.reset();
+        }
+        if self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+            self.state.state = State::Failed;
+            return Some(Err(Error::Cancelled(self.lexer.position())))
+        }
+        if let State::String = self.state.state {
+            return Some(self.next_chunk())
+        }
+        if let State::Closed = self.state.state {
+            if self.single_value && !self.multiple {
+                return None
+            }
+        }
+        if self.multiple && self.on_error == OnError::Skip {
+            let closed = matches!(self.state.state, State::Closed);
+            self.lexer.set_stop_at_newline(!closed);
+            self.lexer.set_line_records(true);
+        }
+        comment!();
+        let key_next = match self.state.state {
+            State::ObjectOpen | State::Key => true,
+            State::Comma => self.state.stack.last() == Some(&Container::Object),
+            _ => false,
+        };
+        self.lexer.set_key_next(key_next);
+        let position = self.lexer.position();
         let event = match self.state.state {
+            State::Failed => return None,
+            State::String => unreachable!(),
+            State::Closed if self.multiple => {
+                match self.lexer.next_with_position() {
+                    None => return None,
+                    Some(Err(e)) => Err(e),
+                    Some(Ok((position, lexeme))) => match lexeme {
+                        Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace | Lexeme::CBracket => Err(Error::Unexpected(Expected::VALUE, position)),
+                        _ => self.state.process_value(lexeme, position),
+                    }
+                }
+            }
             State::Closed => {
-                match self.lexer.next() {
+                match self.lexer.next_with_position() {
+                    Some(Err(e)) if e.is_would_block() => Err(e),
                     Some(Err(Error::IO(..))) | None => return None,
-                    Some(..) => Err(Error::AdditionalData),
+                    Some(Err(e)) => Err(Error::AdditionalData(e.position())),
+                    Some(Ok((position, _))) => Err(Error::AdditionalData(position)),
                 }
             }
             State::Value => {
-                let lexeme = itry!(self.lexer.consume());
+                let (position, lexeme) = consume!();
                 match lexeme {
-                    Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace | Lexeme::CBracket => Err(Error::Unexpected),
-                    _ => self.state.process_value(lexeme),
+                    Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace | Lexeme::CBracket => Err(Error::Unexpected(Expected::VALUE, position)),
+                    _ => self.state.process_value(lexeme, position),
                 }
             }
             State::ArrayOpen => {
-                let lexeme = itry!(self.lexer.consume());
+                let (position, lexeme) = consume!();
                 match lexeme {
-                    Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace => Err(Error::Unexpected),
-                    Lexeme::CBracket => self.state.process_closing(Container::Array),
-                    _ => self.state.process_value(lexeme),
+                    Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace => Err(Error::Unexpected(Expected::VALUE | Expected::END_ARRAY, position)),
+                    Lexeme::CBracket => self.state.process_closing(Container::Array, position),
+                    _ => self.state.process_value(lexeme, position),
                 }
             }
             State::ObjectOpen => {
-                let lexeme = itry!(self.lexer.consume());
-                match lexeme {
-                    Lexeme::CBrace => self.state.process_closing(Container::Object),
-                    Lexeme::String(_) => self.state.process_key(lexeme),
-                    _ => Err(Error::Unexpected)
+                match consume!() {
+                    (position, Lexeme::CBrace) => self.state.process_closing(Container::Object, position),
+                    (position, Lexeme::String(s)) | (position, Lexeme::Identifier(s)) => self.state.process_key(s, position),
+                    (position, _) => Err(Error::Unexpected(Expected::KEY | Expected::END_MAP, position)),
                 }
             }
             State::Colon => {
-                match itry!(self.lexer.consume()) {
-                    Lexeme::Colon => {
-                        let lexeme = itry!(self.lexer.consume());
-                        self.state.process_value(lexeme)
+                match consume!() {
+                    (_, Lexeme::Colon) => {
+                        comment!(State::Value);
+                        let (position, lexeme) = consume!();
+                        match lexeme {
+                            Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace | Lexeme::CBracket => Err(Error::Unexpected(Expected::VALUE, position)),
+                            _ => self.state.process_value(lexeme, position),
+                        }
                     }
-                    _ => Err(Error::Unexpected),
+                    (position, _) => Err(Error::Unexpected(Expected::COLON, position)),
+                }
+            }
+            State::Key => {
+                match consume!() {
+                    (position, Lexeme::String(s)) | (position, Lexeme::Identifier(s)) => self.state.process_key(s, position),
+                    (position, Lexeme::CBrace) if self.state.trailing_commas => self.state.process_closing(Container::Object, position),
+                    (position, _) => Err(Error::Unexpected(Expected::KEY.with(Expected::END_MAP, self.state.trailing_commas), position)),
+                }
+            }
+            State::Element => {
+                let (position, lexeme) = consume!();
+                match lexeme {
+                    Lexeme::CBracket if self.state.trailing_commas => self.state.process_closing(Container::Array, position),
+                    Lexeme::Comma | Lexeme::Colon | Lexeme::CBrace | Lexeme::CBracket => Err(Error::Unexpected(Expected::VALUE.with(Expected::END_ARRAY, self.state.trailing_commas), position)),
+                    _ => self.state.process_value(lexeme, position),
                 }
             }
             State::Comma => {
-                match itry!(self.lexer.consume()) {
-                    Lexeme::Comma => {
-                        let lexeme = itry!(self.lexer.consume());
-                        match *self.state.stack.last().unwrap() {
-                            Container::Array => self.state.process_value(lexeme),
-                            Container::Object => self.state.process_key(lexeme),
+                match consume!() {
+                    (_, Lexeme::Comma) => {
+                        comment!(if self.state.stack.last() == Some(&Container::Object) { State::Key } else { State::Element });
+                        let (position, lexeme) = consume!();
+                        match (self.state.stack.last(), lexeme) {
+                            (Some(Container::Object), Lexeme::String(s)) |
+                            (Some(Container::Object), Lexeme::Identifier(s)) => self.state.process_key(s, position),
+                            (Some(Container::Object), Lexeme::CBrace) if self.state.trailing_commas => {
+                                self.state.process_closing(Container::Object, position)
+                            }
+                            (Some(Container::Array), Lexeme::CBracket) if self.state.trailing_commas => {
+                                self.state.process_closing(Container::Array, position)
+                            }
+                            (Some(Container::Array), Lexeme::Comma) |
+                            (Some(Container::Array), Lexeme::Colon) |
+                            (Some(Container::Array), Lexeme::CBrace) |
+                            (Some(Container::Array), Lexeme::CBracket) |
+                            (Some(Container::Object), _) |
+                            (None, _) => Err(Error::Unexpected(self.state.expected_after_comma(), position)),
+                            (Some(Container::Array), lexeme) => self.state.process_value(lexeme, position),
                         }
                     }
-                    Lexeme::CBracket => self.state.process_closing(Container::Array),
-                    Lexeme::CBrace => self.state.process_closing(Container::Object),
-                    _ => Err(Error::Unexpected),
+                    (position, Lexeme::CBracket) => self.state.process_closing(Container::Array, position),
+                    (position, Lexeme::CBrace) => self.state.process_closing(Container::Object, position),
+                    (position, _) => Err(Error::Unexpected(Expected::COMMA | self.state.expected_end(), position)),
                 }
             }
         };
+        // A reader signalling `WouldBlock` leaves the state intact, so that a
+        // caller able to supply the same input again can retry the call
+        // (see `push::PushParser`).
+        let event = match event {
+            Ok(_) if self.events >= self.max_events => Err(Error::EventLimit(position)),
+            Ok(ref e) => {
+                self.events += 1;
+                self.event_counts.count(e);
+                event
+            }
+            event => event,
+        };
+        match event {
+            Err(ref e) if e.is_would_block() => (),
+            Err(ref e) => self.state.fail(e),
+            Ok(_) => (),
+        }
         Some(event)
     }
 }
+
+/// Configuration of a parser, created by `Parser::builder`. Options not
+/// set keep the defaults of a parser created with `Parser::new`.
+#[derive(Debug, Clone)]
+pub struct ParserBuilder {
+    capacity: usize,
+    multiple: bool,
+    single_value: bool,
+    limits: Limits,
+    dialect: Dialect,
+    // Options implied by the dialect unless set explicitly
+    comments: Option<Comments>,
+    trailing_commas: Option<bool>,
+    lenient_numbers: Option<bool>,
+    control_characters: bool,
+    non_finite: bool,
+    raw_numbers: bool,
+    lossy_utf8: bool,
+    byte_strings: bool,
+    string_chunk_size: usize,
+    empty_read_retries: usize,
+    error_context: bool,
+    reject_duplicate_keys: bool,
+    cancel: Option<Arc<AtomicBool>>,
+    repair: bool,
+    on_error: OnError,
+}
+
+impl Default for ParserBuilder {
+    fn default() -> Self {
+        ParserBuilder {
+            capacity: BUFSIZE,
+            multiple: false,
+            single_value: false,
+            limits: Limits::default(),
+            dialect: Dialect::Json,
+            comments: None,
+            trailing_commas: None,
+            lenient_numbers: None,
+            control_characters: false,
+            non_finite: false,
+            raw_numbers: false,
+            lossy_utf8: false,
+            byte_strings: false,
+            string_chunk_size: usize::MAX,
+            empty_read_retries: 0,
+            error_context: false,
+            reject_duplicate_keys: false,
+            cancel: None,
+            repair: false,
+            on_error: OnError::Stop,
+        }
+    }
+}
+
+impl ParserBuilder {
+
+    /// Size of the read buffer, see `Parser::with_capacity`. Not used by
+    /// `build_slice` and `build_buf_reader`.
+    pub fn capacity(mut self, value: usize) -> Self {
+        self.capacity = value;
+        self
+    }
+
+    /// Accept any number of top-level values, see `Parser::new_multi`.
+    pub fn multiple(mut self, value: bool) -> Self {
+        self.multiple = value;
+        self
+    }
+
+    /// End after the first value, see `Parser::set_single_value`.
+    pub fn single_value(mut self, value: bool) -> Self {
+        self.single_value = value;
+        self
+    }
+
+    pub fn limits(mut self, value: Limits) -> Self {
+        self.limits = value;
+        self
+    }
+
+    pub fn max_depth(mut self, value: usize) -> Self {
+        self.limits.max_depth = value;
+        self
+    }
+
+    pub fn max_string(mut self, value: usize) -> Self {
+        self.limits.max_string = value;
+        self
+    }
+
+    pub fn max_number(mut self, value: usize) -> Self {
+        self.limits.max_number = value;
+        self
+    }
+
+    pub fn max_events(mut self, value: usize) -> Self {
+        self.limits.max_events = value;
+        self
+    }
+
+    /// See `Parser::set_dialect`. Options the dialect implies can be
+    /// overridden regardless of the order of calls.
+    pub fn dialect(mut self, value: Dialect) -> Self {
+        self.dialect = value;
+        self
+    }
+
+    pub fn comments(mut self, value: Comments) -> Self {
+        self.comments = Some(value);
+        self
+    }
+
+    /// Skip comments if `value` is true, reject them otherwise.
+    pub fn allow_comments(self, value: bool) -> Self {
+        self.comments(if value { Comments::Skip } else { Comments::Reject })
+    }
+
+    pub fn trailing_commas(mut self, value: bool) -> Self {
+        self.trailing_commas = Some(value);
+        self
+    }
+
+    pub fn lenient_numbers(mut self, value: bool) -> Self {
+        self.lenient_numbers = Some(value);
+        self
+    }
+
+    pub fn control_characters(mut self, value: bool) -> Self {
+        self.control_characters = value;
+        self
+    }
+
+    pub fn non_finite(mut self, value: bool) -> Self {
+        self.non_finite = value;
+        self
+    }
+
+    pub fn raw_numbers(mut self, value: bool) -> Self {
+        self.raw_numbers = value;
+        self
+    }
+
+    pub fn lossy_utf8(mut self, value: bool) -> Self {
+        self.lossy_utf8 = value;
+        self
+    }
+
+    pub fn byte_strings(mut self, value: bool) -> Self {
+        self.byte_strings = value;
+        self
+    }
+
+    pub fn string_chunk_size(mut self, value: usize) -> Self {
+        self.string_chunk_size = value;
+        self
+    }
+
+    pub fn empty_read_retries(mut self, value: usize) -> Self {
+        self.empty_read_retries = value;
+        self
+    }
+
+    pub fn error_context(mut self, value: bool) -> Self {
+        self.error_context = value;
+        self
+    }
+
+    pub fn reject_duplicate_keys(mut self, value: bool) -> Self {
+        self.reject_duplicate_keys = value;
+        self
+    }
+
+    pub fn repair(mut self, value: bool) -> Self {
+        self.repair = value;
+        self
+    }
+
+    /// See `Parser::set_strict`. Options set after this call apply on top.
+    pub fn strict(mut self) -> Self {
+        self.dialect = Dialect::Json;
+        self.comments = Some(Comments::Reject);
+        self.trailing_commas = Some(false);
+        self.lenient_numbers = Some(false);
+        self.control_characters = false;
+        self.non_finite = false;
+        self.lossy_utf8 = false;
+        self.byte_strings = false;
+        self.repair = false;
+        self
+    }
+
+    pub fn on_error(mut self, value: OnError) -> Self {
+        self.on_error = value;
+        self
+    }
+
+    /// See `Parser::set_cancel_flag`. Parsers built share the flag.
+    pub fn cancel_flag(mut self, value: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(value);
+        self
+    }
+
+    /// Creates a parser reading from `f`.
+    pub fn build<T: Read>(&self, f: T) -> Parser<T> {
+        self.configure(Parser::with_capacity(f, self.capacity))
+    }
+
+    /// Creates a parser over input in memory, see `Parser::from_slice`.
+    pub fn build_slice<'a>(&self, data: &'a [u8]) -> Parser<io::Empty, &'a [u8]> {
+        self.configure(Parser::from_slice(data))
+    }
+
+    /// Creates a parser working in the buffer of `reader`, see
+    /// `Parser::from_buf_reader`.
+    pub fn build_buf_reader<R: Read>(&self, reader: BufReader<R>) -> Parser<io::Empty, Buffered<R>> {
+        self.configure(Parser::from_buf_reader(reader))
+    }
+
+    fn configure<T: Read, B: ReadBuffer>(&self, mut parser: Parser<T, B>) -> Parser<T, B> {
+        if self.multiple {
+            parser.set_multiple();
+        }
+        parser.set_single_value(self.single_value);
+        parser.set_limits(self.limits);
+        parser.set_dialect(self.dialect);
+        if let Some(value) = self.comments {
+            parser.set_comments(value);
+        }
+        if let Some(value) = self.trailing_commas {
+            parser.set_trailing_commas(value);
+        }
+        if let Some(value) = self.lenient_numbers {
+            parser.set_lenient_numbers(value);
+        }
+        parser.set_control_characters(self.control_characters);
+        parser.set_non_finite(self.non_finite);
+        parser.set_raw_numbers(self.raw_numbers);
+        parser.set_lossy_utf8(self.lossy_utf8);
+        parser.set_byte_strings(self.byte_strings);
+        parser.set_string_chunk_size(self.string_chunk_size);
+        parser.set_empty_read_retries(self.empty_read_retries);
+        parser.set_error_context(self.error_context);
+        parser.set_reject_duplicate_keys(self.reject_duplicate_keys);
+        parser.set_repair(self.repair);
+        parser.set_on_error(self.on_error);
+        if let Some(ref flag) = self.cancel {
+            parser.set_cancel_flag(Arc::clone(flag));
+        }
+        parser
+    }
+}
+
+/// Iterator over owned events produced by `Parser::into_iter`.
+pub struct Events<T: Read, B: ReadBuffer = Vec<u8>> {
+    parser: Parser<T, B>,
+}
+
+impl<T: Read, B: ReadBuffer> Located for Events<T, B> {
+    fn location(&self) -> Position {
+        self.parser.position()
+    }
+}
+
+impl<T: Read, B: ReadBuffer> Iterator for Events<T, B> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parser.next()?.map(OwnedEvent::from) {
+            Err(e) => Some(Err(self.parser.with_context(e))),
+            event => Some(event),
+        }
+    }
+}
+
+/// Reader over the contents of a string value returned by
+/// `Parser::read_string`.
+pub struct StringReader<'a, T: Read, B: ReadBuffer = Vec<u8>> {
+    parser: &'a mut Parser<T, B>,
+    pos: usize,
+}
+
+impl<'a, T: Read, B: ReadBuffer> Read for StringReader<'a, T, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let chunk = self.parser.lexer.chunk();
+            if self.pos < chunk.len() {
+                let size = buf.len().min(chunk.len() - self.pos);
+                buf[..size].copy_from_slice(&chunk[self.pos..self.pos + size]);
+                self.pos += size;
+                return Ok(size)
+            }
+            if let State::String = self.parser.state.state {
+                self.pos = 0;
+                match self.parser.read_chunk() {
+                    Ok(()) => (),
+                    Err(Error::IO(e, _)) => return Err(e),
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                }
+            } else {
+                return Ok(0)
+            }
+        }
+    }
+}
+
+impl<'a, T: Read, B: ReadBuffer> Drop for StringReader<'a, T, B> {
+    fn drop(&mut self) {
+        // The unread part of the current piece goes to the next event
+        self.parser.lexer.truncate_chunk(self.pos);
+    }
+}
+
+/// Iterator over raw values produced by `Parser::raw_items`.
+pub struct RawItems<T: Read, B: ReadBuffer = Vec<u8>> {
+    reference: Path,
+    path: Tracker,
+    parser: Parser<T, B>,
+}
+
+impl<T: Read, B: ReadBuffer> Iterator for RawItems<T, B> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.path.at(&self.reference) {
+                match self.parser.raw_value() {
+                    Ok(Some(raw)) => {
+                        self.path.complete();
+                        return Some(Ok(raw))
+                    }
+                    Ok(None) => (),
+                    Err(e) => return Some(Err(e)),
+                }
+            } else if !self.path.leads_to(&self.reference) {
+                match self.parser.skip_value() {
+                    Ok(true) => {
+                        self.path.complete();
+                        continue
+                    }
+                    Ok(false) => (),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            let event = match self.parser.next()?.map(OwnedEvent::from) {
+                Ok(event) => event,
+                Err(e) => return Some(Err(self.parser.with_context(e))),
+            };
+            self.path.enter(&event);
+            self.path.leave(&event);
+        }
+    }
+}
+
+impl<T: Read, B: ReadBuffer> IntoIterator for Parser<T, B> {
+    type Item = Result<OwnedEvent>;
+    type IntoIter = Events<T, B>;
+
+    fn into_iter(self) -> Events<T, B> {
+        Events { parser: self }
+    }
+}
@@ -0,0 +1,295 @@
+use std::iter::FromIterator;
+
+use crate::parser::OwnedEvent;
+
+
+/// A step from a container to one of its values in the document.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// A single element of a prefix selecting values in the document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// An object member with this key.
+    Key(String),
+    /// An array element at this position.
+    Index(usize),
+    /// Any array element.
+    AnyItem,
+    /// Any object member or array element, `*` in dotted prefixes.
+    Wildcard,
+    /// A number or `item` in a prefix string, which can't tell arrays from
+    /// objects: it selects an object member with this key as well as what
+    /// `Index` or `AnyItem` would. JSON Pointer treats numeric tokens the
+    /// same way.
+    Token(String),
+}
+
+impl Segment {
+    fn matches(&self, step: &Step) -> bool {
+        match (self, step) {
+            (Segment::Wildcard, _) => true,
+            (Segment::AnyItem, Step::Index(_)) => true,
+            (Segment::Key(r), Step::Key(k)) | (Segment::Token(r), Step::Key(k)) => r == k,
+            (Segment::Index(r), Step::Index(i)) => r == i,
+            (Segment::Token(r), Step::Index(i)) => r == "item" || parse_index(r) == Some(*i),
+            (Segment::AnyItem, Step::Key(_)) | (Segment::Index(_), Step::Key(_)) | (Segment::Key(_), Step::Index(_)) => false,
+        }
+    }
+}
+
+/// A prefix built out of segments, for selecting values without
+/// formatting and escaping a prefix string:
+///
+/// ```
+/// use ijson::builder::Builder;
+/// use ijson::parser::Parser;
+/// use ijson::path::Path;
+///
+/// let path = Path::new().key("a.b").key("item").index(3);
+/// let values: Vec<_> = Parser::new(&br#"{"a.b": {"item": [0, 1, 2, 3]}}"#[..])
+///     .into_iter()
+///     .items(path)
+///     .map(|r| r.unwrap().to_string())
+///     .collect();
+/// assert_eq!(values, vec!["3"]);
+/// ```
+///
+/// Any place taking a prefix also takes a string, which is parsed with
+/// `Path::parse`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Path {
+    segments: Vec<Segment>,
+}
+
+impl Path {
+    /// An empty path selecting the whole document.
+    pub fn new() -> Path {
+        Path::default()
+    }
+
+    /// Parses a dotted prefix like `docs.item.meta` or, if it starts with a
+    /// slash, an RFC 6901 JSON Pointer like `/docs/0/meta`. Numbers and
+    /// `item` become `Segment::Token`s, as a string doesn't say whether they
+    /// are array elements or keys.
+    pub fn parse(prefix: &str) -> Path {
+        if prefix.starts_with('/') {
+            return parse_pointer(prefix)
+        }
+        split_dotted(prefix).into_iter().map(|(segment, escaped)| match &segment[..] {
+            _ if escaped => Segment::Key(segment),
+            "*" => Segment::Wildcard,
+            "item" => Segment::Token(segment),
+            _ => match parse_index(&segment) {
+                Some(_) => Segment::Token(segment),
+                None => Segment::Key(segment),
+            },
+        }).collect()
+    }
+
+    pub fn push(&mut self, segment: Segment) {
+        self.segments.push(segment);
+    }
+
+    pub fn key(mut self, key: &str) -> Path {
+        self.push(Segment::Key(key.to_string()));
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Path {
+        self.push(Segment::Index(index));
+        self
+    }
+
+    pub fn item(mut self) -> Path {
+        self.push(Segment::AnyItem);
+        self
+    }
+
+    pub fn wildcard(mut self) -> Path {
+        self.push(Segment::Wildcard);
+        self
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Checks whether `steps` are at or under this path.
+    pub(crate) fn matches(&self, steps: &[Step]) -> bool {
+        steps.len() >= self.segments.len() &&
+            self.segments.iter().zip(steps).all(|(r, p)| r.matches(p))
+    }
+}
+
+impl From<Vec<Segment>> for Path {
+    fn from(segments: Vec<Segment>) -> Path {
+        Path { segments }
+    }
+}
+
+impl<'a> From<&'a str> for Path {
+    fn from(prefix: &'a str) -> Path {
+        Path::parse(prefix)
+    }
+}
+
+impl<'a> From<&'a String> for Path {
+    fn from(prefix: &'a String) -> Path {
+        Path::parse(prefix)
+    }
+}
+
+impl From<String> for Path {
+    fn from(prefix: String) -> Path {
+        Path::parse(&prefix)
+    }
+}
+
+impl FromIterator<Segment> for Path {
+    fn from_iter<I: IntoIterator<Item=Segment>>(iter: I) -> Path {
+        Path { segments: iter.into_iter().collect() }
+    }
+}
+
+/// Splits a dotted prefix on dots not escaped with a backslash, marking
+/// segments that had any escapes in them so that `\item`, `\*` or `\0`
+/// are taken as literal keys.
+fn split_dotted(prefix: &str) -> Vec<(String, bool)> {
+    let mut result = vec![];
+    let mut segment = String::new();
+    let mut escaped = false;
+    let mut chars = prefix.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                segment.extend(chars.next());
+                escaped = true;
+            }
+            '.' => result.push((std::mem::take(&mut segment), std::mem::replace(&mut escaped, false))),
+            _ => segment.push(c),
+        }
+    }
+    if !segment.is_empty() || escaped {
+        result.push((segment, escaped));
+    }
+    result
+}
+
+fn parse_pointer(pointer: &str) -> Path {
+    pointer.split('/').skip(1).map(|token| match parse_index(token) {
+        Some(_) => Segment::Token(token.to_string()),
+        None => Segment::Key(token.replace("~1", "/").replace("~0", "~")),
+    }).collect()
+}
+
+/// Parses an array index, which can't have leading zeros.
+fn parse_index(token: &str) -> Option<usize> {
+    let is_index = !token.is_empty() && token.bytes().all(|b| b.is_ascii_digit()) &&
+        (token == "0" || !token.starts_with('0'));
+    if is_index { token.parse().ok() } else { None }
+}
+
+/// Dotted representation of steps with array indices as numbers, escaping
+/// keys so that `Path::parse` reads it back.
+pub(crate) fn dotted_steps(steps: &[Step]) -> String {
+    let segments: Vec<_> = steps.iter().map(|step| match step {
+        Step::Index(i) => i.to_string(),
+        Step::Key(k) => {
+            let mut segment = String::with_capacity(k.len());
+            if k == "*" || k == "item" || parse_index(k).is_some() {
+                segment.push('\\');
+            }
+            for c in k.chars() {
+                if c == '.' || c == '\\' {
+                    segment.push('\\');
+                }
+                segment.push(c);
+            }
+            segment
+        }
+    }).collect();
+    segments.join(".")
+}
+
+/// Keeps track of the path of the current event in the document.
+#[derive(Default)]
+pub(crate) struct Tracker {
+    pub steps: Vec<Step>,
+    /// Buffers of keys gone from the path, reused for the next ones so
+    /// that keys aren't allocated over and over.
+    spare: Vec<String>,
+}
+
+impl Tracker {
+    /// Updates the path to be the one of the event
+    pub fn enter(&mut self, event: &OwnedEvent) {
+        match *event {
+            OwnedEvent::Key(_) | OwnedEvent::EndMap | OwnedEvent::EndArray => {
+                match self.steps.pop() {
+                    Some(Step::Key(key)) if key.capacity() > 0 => self.spare.push(key),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Updates the path to be the one of the events following the event
+    pub fn leave(&mut self, event: &OwnedEvent) {
+        match *event {
+            OwnedEvent::Key(ref value) => {
+                let mut key = self.spare.pop().unwrap_or_default();
+                key.clear();
+                key.push_str(value);
+                self.steps.push(Step::Key(key));
+            }
+            OwnedEvent::StartMap => self.steps.push(Step::Key(String::new())),
+            OwnedEvent::StartArray => self.steps.push(Step::Index(0)),
+            OwnedEvent::Comment(_) | OwnedEvent::StringChunk(_) => (),
+            _ => self.complete(),
+        }
+    }
+
+    /// Updates the path after a complete value, which moves an enclosing
+    /// array to the next element
+    pub fn complete(&mut self) {
+        if let Some(Step::Index(i)) = self.steps.last_mut() {
+            *i += 1;
+        }
+    }
+
+    /// Checks whether the next value in the document is at `reference`
+    /// exactly, rather than under it.
+    pub fn at(&self, reference: &Path) -> bool {
+        self.steps.len() == reference.segments.len() && reference.matches(&self.steps)
+    }
+
+    /// Checks whether the next value in the document can contain values at
+    /// `reference`, being an ancestor of it.
+    pub fn leads_to(&self, reference: &Path) -> bool {
+        self.steps.len() < reference.segments.len() &&
+            reference.segments.iter().zip(&self.steps).all(|(r, p)| r.matches(p))
+    }
+
+    /// Path of the current event for error messages, with array indices as
+    /// numbers. An object before its first key has the path of the object.
+    pub fn error_path(&self) -> String {
+        match self.steps.split_last() {
+            Some((Step::Key(key), steps)) if key.is_empty() => dotted_steps(steps),
+            _ => dotted_steps(&self.steps),
+        }
+    }
+
+    /// Dotted representation of the path with `item` for array elements.
+    pub fn dotted(&self) -> String {
+        let segments: Vec<_> = self.steps.iter().map(|step| match step {
+            Step::Key(k) => &k[..],
+            Step::Index(_) => "item",
+        }).collect();
+        segments.join(".")
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::{self, Read};
+
+use crate::errors::{Position, Result};
+use crate::lexer::is_whitespace;
+use crate::parser::{Counters, Event, Parser};
+
+
+/// Input fed into a `PushParser`. Keeps everything starting from the
+/// beginning of the current event so it can be re-read after running out of
+/// data in the middle of a lexeme.
+struct Chunks {
+    data: Vec<u8>,
+    pos: usize,
+    mark: usize,
+    finished: bool,
+}
+
+impl Chunks {
+    fn set_mark(&mut self, mark: usize) {
+        self.mark = mark;
+        // Compacting is amortized over enough consumed input
+        if self.mark > 4096 && self.mark * 2 > self.data.len() {
+            self.data.drain(..self.mark);
+            self.pos -= self.mark;
+            self.mark = 0;
+        }
+    }
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.data.len() && !self.finished {
+            return Err(io::ErrorKind::WouldBlock.into())
+        }
+        let size = buf.len().min(self.data.len() - self.pos);
+        buf[..size].copy_from_slice(&self.data[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+/// What's known of the input fed after the start of an event which is
+/// still incomplete, scanned once as it comes. The event is parsed again
+/// only when new input may complete it, otherwise each chunk of a long
+/// string would make the parser read all of it again.
+#[derive(Clone, Copy)]
+enum Pending {
+    /// Whitespace and separators.
+    Between,
+    /// Contents of a string, with their length and the state of an escape:
+    /// `Some(0)` after a backslash and `Some(n)` with `n` hex digits of
+    /// `\u` to go.
+    String { quote: u8, length: usize, escape: Option<u8> },
+    /// Anything else, which may complete the event or be an error.
+    Other,
+}
+
+pub enum Step<'a> {
+    Event(Event<'a>),
+    NeedMoreData,
+}
+
+/// A parser that receives its input in chunks through `feed` instead of
+/// pulling it from a reader.
+pub struct PushParser {
+    parser: Parser<Chunks>,
+    checkpoint: Position,
+    base: usize,
+    starved: bool,
+    pending: Pending,
+    /// Bytes of input scanned into `pending`.
+    scanned: usize,
+}
+
+impl Default for PushParser {
+    fn default() -> Self {
+        PushParser::new()
+    }
+}
+
+impl PushParser {
+
+    pub fn new() -> PushParser {
+        PushParser::from_parser(Parser::new)
+    }
+
+    /// Creates a push parser accepting multiple top-level values, see
+    /// `Parser::new_multi`.
+    pub fn new_multi() -> PushParser {
+        PushParser::from_parser(Parser::new_multi)
+    }
+
+    fn from_parser(new: fn(Chunks) -> Parser<Chunks>) -> PushParser {
+        let chunks = Chunks {
+            data: vec![],
+            pos: 0,
+            mark: 0,
+            finished: false,
+        };
+        // Rewinding relies on offsets in the fed bytes, so input is taken
+        // as UTF-8 as is
+        let mut parser = new(chunks);
+        parser.lexer_mut().set_transparent();
+        PushParser {
+            parser,
+            checkpoint: Position::default(),
+            base: 0,
+            starved: false,
+            pending: Pending::Between,
+            scanned: 0,
+        }
+    }
+
+    /// Appends a chunk of input.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.parser.lexer_mut().reader_mut().data.extend_from_slice(data);
+    }
+
+    /// Signals the end of input, after which the parser reports incomplete
+    /// documents as errors instead of asking for more data.
+    pub fn finish(&mut self) {
+        self.parser.lexer_mut().reader_mut().finished = true;
+    }
+
+    /// Counts of the input consumed and events produced, see
+    /// `Parser::counters`.
+    pub fn counters(&self) -> Counters {
+        self.parser.counters()
+    }
+
+    /// Scans the input fed since the last call, telling if it may complete
+    /// the pending event.
+    fn may_complete(&mut self) -> bool {
+        let lexer = self.parser.lexer_mut();
+        let whole_string = lexer.whole_string();
+        let quotes = lexer.quotes();
+        let chunks = lexer.reader_mut();
+        if chunks.finished {
+            return true
+        }
+        let fed = &chunks.data[self.scanned..];
+        self.scanned = chunks.data.len();
+        if fed.is_empty() {
+            return false
+        }
+        for &byte in fed {
+            self.pending = match self.pending {
+                Pending::Between if is_whitespace(byte) || byte == b',' || byte == b':' => Pending::Between,
+                Pending::Between if quotes.contains(&byte) && whole_string.is_some() => {
+                    Pending::String { quote: byte, length: 0, escape: None }
+                }
+                Pending::String { length, .. } if whole_string.is_none_or(|limit| length >= limit) => Pending::Other,
+                // Errors and the end of the string are up to the parser
+                Pending::String { .. } if byte < 0x20 => Pending::Other,
+                Pending::String { quote, length, escape } => match escape {
+                    None if byte == quote => Pending::Other,
+                    None if byte == b'\\' => Pending::String { quote, length, escape: Some(0) },
+                    None => Pending::String { quote, length: length + 1, escape: None },
+                    Some(0) if byte == b'u' => Pending::String { quote, length, escape: Some(4) },
+                    Some(0) if b"\"\\/bfnrt".contains(&byte) => Pending::String { quote, length: length + 1, escape: None },
+                    Some(n) if n > 0 && byte.is_ascii_hexdigit() => {
+                        Pending::String { quote, length: length + 1, escape: Some(n - 1).filter(|&n| n > 0) }
+                    }
+                    _ => Pending::Other,
+                },
+                _ => Pending::Other,
+            };
+            if let Pending::Other = self.pending {
+                return true
+            }
+        }
+        false
+    }
+
+    /// Returns the next event or `Step::NeedMoreData` if the fed input
+    /// doesn't contain a complete one. Returns `None` after the input is
+    /// finished and fully parsed.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<Step<'_>>> {
+        if self.starved && !self.may_complete() {
+            return Some(Ok(Step::NeedMoreData))
+        }
+        let lexer = self.parser.lexer_mut();
+        if self.starved {
+            self.starved = false;
+            lexer.rewind(self.checkpoint);
+            let chunks = lexer.reader_mut();
+            chunks.pos = chunks.mark;
+        } else {
+            self.checkpoint = lexer.checkpoint();
+            let base = self.base;
+            let chunks = lexer.reader_mut();
+            let before = chunks.data.len();
+            chunks.set_mark(self.checkpoint.offset - base);
+            self.base += before - chunks.data.len();
+            self.pending = Pending::Between;
+            self.scanned = chunks.mark;
+        }
+        match self.parser.next() {
+            Some(Err(ref e)) if e.is_would_block() => {
+                self.starved = true;
+                Some(Ok(Step::NeedMoreData))
+            }
+            result => result.map(|r| r.map(Step::Event)),
+        }
+    }
+}
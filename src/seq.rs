@@ -0,0 +1,187 @@
+use std::io::{self, BufRead, Read, Write};
+
+use memchr::memchr;
+use rustc_serialize::json::Json;
+
+use crate::builder::{Builder, EventIterator};
+use crate::errors::{Error, Position, Result};
+use crate::parser::Parser;
+use crate::writer::Writer;
+
+
+/// Record separator, which starts every document of a JSON text sequence.
+pub const RS: u8 = 0x1E;
+
+fn io_error(e: io::Error) -> Error {
+    Error::IO(e, Position::default())
+}
+
+/// Reads documents of a JSON text sequence, `application/json-seq` as per
+/// RFC 7464, where each document starts with RS and ends with LF. A broken
+/// document is reported as an error, and reading goes on with the next
+/// one. Positions in errors count from the start of the document.
+///
+/// ```
+/// use ijson::seq::SeqReader;
+///
+/// let data = b"\x1e{\"a\": 1}\n\x1e[2, \n\x1e3\n";
+/// let results: Vec<_> = SeqReader::new(&data[..]).collect();
+/// assert_eq!(results[0].as_ref().unwrap().to_string(), r#"{"a":1}"#);
+/// assert!(results[1].is_err());
+/// assert_eq!(results[2].as_ref().unwrap().to_string(), "3");
+/// ```
+pub struct SeqReader<R: BufRead> {
+    reader: R,
+    max_document: usize,
+}
+
+impl<R: BufRead> SeqReader<R> {
+    pub fn new(reader: R) -> SeqReader<R> {
+        SeqReader {
+            reader,
+            max_document: usize::MAX,
+        }
+    }
+
+    /// Sets the maximum length of a document built by the iterator in
+    /// bytes, unlimited by default. Documents are kept in memory whole, and
+    /// a longer one is reported as `Error::StringLimit` and skipped.
+    pub fn set_max_document(&mut self, value: usize) {
+        self.max_document = value;
+    }
+
+    /// Skips to the start of the next document, returning a reader of its
+    /// text up to the next RS or the end of input, to parse it as a stream.
+    /// Whatever of a document is left unread is skipped by the next call.
+    pub fn next_document(&mut self) -> io::Result<Option<Document<'_, R>>> {
+        loop {
+            let (skip, found) = {
+                let buf = self.reader.fill_buf()?;
+                if buf.is_empty() {
+                    return Ok(None)
+                }
+                match memchr(RS, buf) {
+                    Some(i) => (i + 1, true),
+                    None => (buf.len(), false),
+                }
+            };
+            self.reader.consume(skip);
+            if found {
+                return Ok(Some(Document { reader: &mut self.reader, last: None, done: false }))
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Text of a single document of a JSON text sequence, see
+/// `SeqReader::next_document`.
+pub struct Document<'a, R: BufRead> {
+    reader: &'a mut R,
+    last: Option<u8>,
+    done: bool,
+}
+
+impl<'a, R: BufRead> Document<'a, R> {
+    /// Whether the text read so far ends with whitespace. A document with
+    /// a top-level number, `true`, `false` or `null` not followed by
+    /// whitespace may have been cut off, and RFC 7464 requires dropping it.
+    pub fn ends_with_whitespace(&self) -> bool {
+        matches!(self.last, Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n'))
+    }
+}
+
+impl<'a, R: BufRead> Read for Document<'a, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.done || out.is_empty() {
+            return Ok(0)
+        }
+        let buf = self.reader.fill_buf()?;
+        let size = memchr(RS, buf).unwrap_or(buf.len()).min(out.len());
+        if size == 0 {
+            self.done = true;
+            return Ok(0)
+        }
+        out[..size].copy_from_slice(&buf[..size]);
+        self.last = Some(buf[size - 1]);
+        self.reader.consume(size);
+        Ok(size)
+    }
+}
+
+impl<R: BufRead> Iterator for SeqReader<R> {
+    type Item = Result<Json>;
+
+    /// Builds the next document, skipping empty ones.
+    fn next(&mut self) -> Option<Self::Item> {
+        let limit = self.max_document;
+        loop {
+            let mut text = vec![];
+            let truncated = {
+                let mut document = match self.next_document() {
+                    Ok(Some(document)) => document,
+                    Ok(None) => return None,
+                    Err(e) => return Some(Err(io_error(e))),
+                };
+                // One byte past the limit tells a longer document
+                if let Err(e) = document.by_ref().take((limit as u64).saturating_add(1)).read_to_end(&mut text) {
+                    return Some(Err(io_error(e)))
+                }
+                if text.len() > limit {
+                    return Some(Err(Error::StringLimit(Position::default())))
+                }
+                !document.ends_with_whitespace()
+            };
+            if text.iter().all(u8::is_ascii_whitespace) {
+                continue
+            }
+            let mut items = Parser::from_slice(&text).into_iter().items("");
+            let value = match items.next() {
+                Some(Ok(value)) => value,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return Some(Err(Error::Incomplete(Position::default()))),
+            };
+            if let Some(Err(e)) = items.next() {
+                return Some(Err(e))
+            }
+            let scalar = !matches!(value, Json::Array(_) | Json::Object(_) | Json::String(_));
+            if scalar && truncated {
+                return Some(Err(Error::Incomplete(Position { line: 1, column: text.len() + 1, offset: text.len() })))
+            }
+            return Some(Ok(value))
+        }
+    }
+}
+
+/// Writes documents of a JSON text sequence, each between RS and LF.
+pub struct SeqWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> SeqWriter<W> {
+    pub fn new(out: W) -> SeqWriter<W> {
+        SeqWriter { out }
+    }
+
+    /// Writes out the single value that `events` make up as a document.
+    /// The document is put together in memory first, so that nothing of it
+    /// is written if the events fail or don't make up a value.
+    pub fn write_document<E: EventIterator>(&mut self, events: E) -> Result<()> {
+        let mut writer = Writer::new(vec![RS]);
+        for event in events {
+            writer.write_event(event?.as_event())?;
+        }
+        let mut record = writer.finish()?;
+        record.push(b'\n');
+        self.out.write_all(&record).map_err(io_error)
+    }
+
+    /// Flushes the output and returns it.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.out.flush().map_err(io_error)?;
+        Ok(self.out)
+    }
+}
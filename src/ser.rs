@@ -0,0 +1,542 @@
+use std::io::Write;
+use std::mem;
+use std::ops::ControlFlow;
+use std::panic;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+use serde::ser::{self, Serialize};
+
+use crate::errors::{Error, Position, Result};
+use crate::handler::{dispatch, Handler};
+use crate::parser::{Event, OwnedEvent};
+use crate::writer::Writer;
+
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string(), Position::default())
+    }
+}
+
+/// A serde `Serializer` turning a value into events passed to a function,
+/// the inverse of `Deserializer`, for putting Rust data into the same
+/// pipelines as parsed input. The shape is the one of `serde_json`: enums
+/// are externally tagged, bytes are arrays of numbers and keys of maps have
+/// to be strings, numbers, booleans or characters. Integers that don't fit
+/// 64 bits become `Event::RawNumber`.
+pub struct Serializer<F: FnMut(Event) -> Result<()>> {
+    sink: F,
+}
+
+impl<F: FnMut(Event) -> Result<()>> Serializer<F> {
+    pub fn new(sink: F) -> Self {
+        Serializer { sink }
+    }
+
+    fn emit(&mut self, event: Event) -> Result<()> {
+        (self.sink)(event)
+    }
+}
+
+/// Events passed at once from the thread of `to_events`.
+const BATCH: usize = 64;
+
+/// Events of `value`, followed by an error if it can't be serialized. The
+/// value is serialized on its own thread, which waits for the events to be
+/// taken once a couple of batches are pending, so only those are held in
+/// memory. Dropping the iterator stops the serialization.
+pub fn to_events<T: Serialize + Send + 'static>(value: T) -> Events {
+    let (sender, receiver) = mpsc::sync_channel(2);
+    let thread = thread::spawn(move || {
+        let mut batch = Vec::with_capacity(BATCH);
+        let result = value.serialize(&mut Serializer::new(|event| {
+            batch.push(OwnedEvent::from(event));
+            if batch.len() == BATCH {
+                let full = mem::replace(&mut batch, Vec::with_capacity(BATCH));
+                sender.send(Ok(full)).map_err(|_| Error::Cancelled(Position::default()))?;
+            }
+            Ok(())
+        }));
+        // Sending fails only when nobody is reading any more
+        let _ = sender.send(Ok(batch));
+        if let Err(e) = result {
+            let _ = sender.send(Err(e));
+        }
+    });
+    Events {
+        receiver,
+        batch: vec![].into_iter(),
+        thread: Some(thread),
+    }
+}
+
+/// Events of a value serialized on another thread, see `to_events`.
+pub struct Events {
+    receiver: Receiver<Result<Vec<OwnedEvent>>>,
+    batch: std::vec::IntoIter<OwnedEvent>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Iterator for Events {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.batch.next() {
+                return Some(Ok(event))
+            }
+            match self.receiver.recv() {
+                Ok(Ok(batch)) => self.batch = batch.into_iter(),
+                Ok(Err(e)) => return Some(Err(e)),
+                Err(_) => {
+                    // A panic in `Serialize` goes on to the reading thread
+                    if let Some(Err(payload)) = self.thread.take().map(JoinHandle::join) {
+                        panic::resume_unwind(payload)
+                    }
+                    return None
+                }
+            }
+        }
+    }
+}
+
+/// Writes `value` out as a document.
+pub fn to_writer<T: Serialize + ?Sized, W: Write>(value: &T, writer: &mut Writer<W>) -> Result<()> {
+    value.serialize(&mut Serializer::new(|event| writer.write_event(event)))
+}
+
+/// Calls `handler` for every event of `value`, the same as `drive` does
+/// for a parsed document. Returns `false` if the handler stopped early.
+pub fn to_handler<T: Serialize + ?Sized, H: Handler>(value: &T, handler: &mut H) -> Result<bool> {
+    let mut stopped = false;
+    let result = value.serialize(&mut Serializer::new(|event| match dispatch(handler, event) {
+        ControlFlow::Continue(()) => Ok(()),
+        ControlFlow::Break(()) => {
+            stopped = true;
+            Err(Error::Cancelled(Position::default()))
+        }
+    }));
+    match result {
+        Err(_) if stopped => Ok(false),
+        result => result.map(|_| true),
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::Serializer for &'a mut Serializer<F> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, F>;
+    type SerializeTuple = Compound<'a, F>;
+    type SerializeTupleStruct = Compound<'a, F>;
+    type SerializeTupleVariant = Compound<'a, F>;
+    type SerializeMap = Compound<'a, F>;
+    type SerializeStruct = Compound<'a, F>;
+    type SerializeStructVariant = Compound<'a, F>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.emit(Event::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.emit(Event::Int(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        match v {
+            _ if v as i64 as i128 == v => self.emit(Event::Int(v as i64)),
+            _ if v as u64 as i128 == v => self.emit(Event::Uint(v as u64)),
+            _ => self.emit(Event::RawNumber(&v.to_string())),
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.serialize_i128(v as i128)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        match v {
+            _ if v <= i128::MAX as u128 => self.serialize_i128(v as i128),
+            _ => self.emit(Event::RawNumber(&v.to_string())),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        // Widening would show the error of `f32`, such as 0.1 becoming
+        // 0.10000000149011612, so the shortest text of `v` is taken instead
+        self.serialize_f64(v.to_string().parse().unwrap())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.emit(Event::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.emit(Event::String(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.emit(Event::String(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.emit(Event::StartArray)?;
+        for &byte in v {
+            self.emit(Event::Int(byte as i64))?;
+        }
+        self.emit(Event::EndArray)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.emit(Event::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.emit(Event::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.emit(Event::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<()> {
+        self.emit(Event::String(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T) -> Result<()> {
+        self.emit(Event::StartMap)?;
+        self.emit(Event::Key(variant))?;
+        value.serialize(&mut *self)?;
+        self.emit(Event::EndMap)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a, F>> {
+        self.emit(Event::StartArray)?;
+        Ok(Compound { ser: self, variant: false })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, F>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, F>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<Compound<'a, F>> {
+        self.emit(Event::StartMap)?;
+        self.emit(Event::Key(variant))?;
+        self.emit(Event::StartArray)?;
+        Ok(Compound { ser: self, variant: true })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a, F>> {
+        self.emit(Event::StartMap)?;
+        Ok(Compound { ser: self, variant: false })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, F>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<Compound<'a, F>> {
+        self.emit(Event::StartMap)?;
+        self.emit(Event::Key(variant))?;
+        self.emit(Event::StartMap)?;
+        Ok(Compound { ser: self, variant: true })
+    }
+}
+
+/// Serializes the contents of an array or an object, see `Serializer`.
+pub struct Compound<'a, F: FnMut(Event) -> Result<()>> {
+    ser: &'a mut Serializer<F>,
+    /// The container is the value of an enum variant, inside an object
+    /// closed along with it.
+    variant: bool,
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> Compound<'a, F> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn member<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.ser.emit(Event::Key(key))?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn close(self, event: Event) -> Result<()> {
+        self.ser.emit(event)?;
+        if self.variant {
+            self.ser.emit(Event::EndMap)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeSeq for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndArray)
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeTuple for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndArray)
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeTupleStruct for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndArray)
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeTupleVariant for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndArray)
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeMap for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let key = key.serialize(KeySerializer)?;
+        self.ser.emit(Event::Key(&key))
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndMap)
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeStruct for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.member(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndMap)
+    }
+}
+
+impl<'a, F: FnMut(Event) -> Result<()>> ser::SerializeStructVariant for Compound<'a, F> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.member(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close(Event::EndMap)
+    }
+}
+
+fn key_error() -> Error {
+    ser::Error::custom("key must be a string, a number, a boolean or a character")
+}
+
+/// Turns keys of maps into strings.
+struct KeySerializer;
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<String, Error>;
+    type SerializeTuple = ser::Impossible<String, Error>;
+    type SerializeTupleStruct = ser::Impossible<String, Error>;
+    type SerializeTupleVariant = ser::Impossible<String, Error>;
+    type SerializeMap = ser::Impossible<String, Error>;
+    type SerializeStruct = ser::Impossible<String, Error>;
+    type SerializeStructVariant = ser::Impossible<String, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit(self) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<String> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_error())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant> {
+        Err(key_error())
+    }
+}
@@ -0,0 +1,86 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::errors::{Error, Position, Result};
+use crate::lexer::BUFSIZE;
+use crate::parser::OwnedEvent;
+use crate::push::{PushParser, Step};
+
+
+/// Parses input from a tokio `AsyncRead`, producing events as a `Stream`.
+pub struct AsyncParser<R: AsyncRead + Unpin> {
+    reader: R,
+    parser: PushParser,
+    buf: Vec<u8>,
+    starved: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncParser<R> {
+
+    pub fn new(reader: R) -> AsyncParser<R> {
+        AsyncParser::from_parser(reader, PushParser::new())
+    }
+
+    /// Creates a parser accepting multiple top-level values, see
+    /// `Parser::new_multi`.
+    pub fn new_multi(reader: R) -> AsyncParser<R> {
+        AsyncParser::from_parser(reader, PushParser::new_multi())
+    }
+
+    fn from_parser(reader: R, parser: PushParser) -> AsyncParser<R> {
+        AsyncParser {
+            reader,
+            parser,
+            buf: vec![0; BUFSIZE],
+            starved: false,
+        }
+    }
+
+    /// Returns the next event, same as `StreamExt::next` would.
+    pub async fn next(&mut self) -> Option<Result<OwnedEvent>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut buf = ReadBuf::new(&mut self.buf);
+        match Pin::new(&mut self.reader).poll_read(cx, &mut buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => Poll::Ready(Err(Error::IO(e, Position::default()))),
+            Poll::Ready(Ok(())) => {
+                if buf.filled().is_empty() {
+                    self.parser.finish();
+                } else {
+                    self.parser.feed(buf.filled());
+                }
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncParser<R> {
+    type Item = Result<OwnedEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            // Avoid re-parsing the pending input while the reader isn't ready
+            if this.starved {
+                match this.poll_fill(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                    Poll::Ready(Ok(())) => this.starved = false,
+                }
+            }
+            match this.parser.next() {
+                None => return Poll::Ready(None),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Some(Ok(Step::Event(event))) => return Poll::Ready(Some(Ok(event.into()))),
+                Some(Ok(Step::NeedMoreData)) => this.starved = true,
+            }
+        }
+    }
+}
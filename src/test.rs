@@ -1,11 +1,24 @@
 use std::fs::File;
 use std::io::Cursor;
+use std::mem::discriminant;
+use std::ops::ControlFlow;
 use std::result::Result;
-use std::error::Error as _Error;
 
-use ::errors::Error;
-use ::parser::{Parser, Event};
-use ::builder::{Builder, decode};
+use rustc_serialize::{Decodable, Decoder};
+use rustc_serialize::json::Json;
+
+use crate::errors::{Error, ErrorKind, Expected, NumberError, Position};
+use crate::lexer::Comments;
+use crate::parser::{Parser, Limits, OnError, Event as BorrowedEvent, OwnedEvent as Event};
+use crate::builder::{Builder, decode};
+use crate::writer::{Writer, Format, Minify};
+use crate::push::{PushParser, Step};
+use crate::path::{Path, Segment};
+use crate::handler::{Handler, drive};
+use crate::seq::{SeqReader, SeqWriter};
+use crate::gen::Generator;
+use crate::compare::Compare;
+use crate::emit::Emit;
 
 
 fn reference_events() -> Vec<Event> {
@@ -21,13 +34,13 @@ fn reference_events() -> Vec<Event> {
                 Event::Key("true".to_string()),
                 Event::Boolean(true),
                 Event::Key("integer".to_string()),
-                Event::Number(0f64),
+                Event::Int(0),
                 Event::Key("double".to_string()),
                 Event::Number(0.5f64),
                 Event::Key("exponent".to_string()),
                 Event::Number(100f64),
                 Event::Key("long".to_string()),
-                Event::Number(10000000000f64),
+                Event::Int(10000000000),
                 Event::Key("string".to_string()),
                 Event::String("строка - тест".to_string()),
             Event::EndMap,
@@ -35,7 +48,7 @@ fn reference_events() -> Vec<Event> {
                 Event::Key("meta".to_string()),
                 Event::StartArray,
                     Event::StartArray,
-                        Event::Number(1f64),
+                        Event::Int(1),
                     Event::EndArray,
                     Event::StartMap,
                     Event::EndMap,
@@ -61,43 +74,608 @@ fn reference_events() -> Vec<Event> {
 #[test]
 fn parser() {
     let f = File::open("test.json").unwrap();
-    let events: Vec<_> = Parser::new(f).map(Result::unwrap).collect();
+    let events: Vec<_> = Parser::new(f).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, reference_events());
+}
+
+#[test]
+fn buffer_sizes() {
+    for size in &[1, 2, 3, 7, 64, 256 * 1024] {
+        let f = File::open("test.json").unwrap();
+        let events: Vec<_> = Parser::with_capacity(f, *size).into_iter().map(Result::unwrap).collect();
+        assert_eq!(events, reference_events(), "buffer size {}", size);
+    }
+
+    let mut buffer = Vec::with_capacity(16);
+    for _ in 0..2 {
+        let f = File::open("test.json").unwrap();
+        let mut parser = Parser::with_buffer(f, buffer);
+        while let Some(event) = parser.next() {
+            event.unwrap();
+        }
+        buffer = parser.into_buffer();
+        assert_eq!(buffer.len(), 16);
+    }
+}
+
+#[test]
+fn flaky_reader() {
+    // Returns data a byte at a time, interleaved with interruptions and
+    // zero-length reads
+    struct Flaky {
+        data: Vec<u8>,
+        pos: usize,
+        calls: usize,
+    }
+
+    impl std::io::Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            match self.calls % 3 {
+                0 => Err(std::io::ErrorKind::Interrupted.into()),
+                1 => Ok(0),
+                _ if self.pos < self.data.len() => {
+                    buf[0] = self.data[self.pos];
+                    self.pos += 1;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    let data = br#"{"key": [1, "value", null]}"#.to_vec();
+    let mut parser = Parser::new(Flaky { data: data.clone(), pos: 0, calls: 0 });
+    parser.set_empty_read_retries(1);
+    let result: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    let expected: Vec<_> = Parser::new(&data[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn push_parser() {
+    let data = std::fs::read("test.json").unwrap();
+    for size in &[1, 2, 5, 100, data.len()] {
+        let mut parser = PushParser::new();
+        let mut events = vec![];
+        let mut chunks = data.chunks(*size);
+        loop {
+            match parser.next() {
+                None => break,
+                Some(Ok(Step::Event(event))) => events.push(Event::from(event)),
+                Some(Ok(Step::NeedMoreData)) => match chunks.next() {
+                    Some(chunk) => parser.feed(chunk),
+                    None => parser.finish(),
+                },
+                Some(Err(e)) => panic!("{}", e),
+            }
+        }
+        assert_eq!(events, reference_events(), "chunk size {}", size);
+    }
+
+    let data = format!("[{}]", (0..5000).map(|i| i.to_string()).collect::<Vec<_>>().join(",\n"));
+    let mut parser = PushParser::new();
+    let mut chunks = data.as_bytes().chunks(7);
+    let mut sum = 0;
+    while let Some(step) = parser.next() {
+        match step.unwrap() {
+            Step::Event(BorrowedEvent::Int(i)) => sum += i,
+            Step::Event(_) => (),
+            Step::NeedMoreData => match chunks.next() {
+                Some(chunk) => parser.feed(chunk),
+                None => parser.finish(),
+            },
+        }
+    }
+    assert_eq!(sum, (0..5000i64).sum::<i64>());
+
+    // A long string fed in small chunks isn't read again for each one
+    let data = format!(r#"["{}\n\u00e9", {{"a": 1}}]"#, "x".repeat(200_000));
+    let mut parser = PushParser::new();
+    let mut chunks = data.as_bytes().chunks(10);
+    let mut events = vec![];
+    while let Some(step) = parser.next() {
+        match step.unwrap() {
+            Step::Event(event) => events.push(Event::from(event)),
+            Step::NeedMoreData => match chunks.next() {
+                Some(chunk) => parser.feed(chunk),
+                None => parser.finish(),
+            },
+        }
+    }
+    assert_eq!(events[1], Event::String(format!("{}\né", "x".repeat(200_000))));
+    assert_eq!(events.len(), 7);
+    assert!(parser.counters().refills < 100, "{}", parser.counters().refills);
+
+    let mut parser = PushParser::new();
+    parser.feed(b"[1, ");
+    parser.finish();
+    let mut result = None;
+    while let Some(step) = parser.next() {
+        if let Err(e) = step {
+            result = Some(e);
+        }
+    }
+    assert!(matches!(result, Some(Error::Incomplete(..))));
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_parser() {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, ReadBuf};
+    use crate::stream::AsyncParser;
+
+    // Returns a few bytes at a time, alternating with `Pending`
+    struct Slow(Vec<u8>, usize, bool);
+
+    impl AsyncRead for Slow {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            self.2 = !self.2;
+            if self.2 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending
+            }
+            let end = (self.1 + 3).min(self.0.len());
+            buf.put_slice(&self.0[self.1..end]);
+            self.1 = end;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    let mut parser = AsyncParser::new(Slow(std::fs::read("test.json").unwrap(), 0, false));
+    let mut events = vec![];
+    while let Some(event) = parser.next().await {
+        events.push(event.unwrap());
+    }
     assert_eq!(events, reference_events());
 }
 
+#[test]
+fn skipping() {
+    let data = br#"{"a": [1, {"b": "]}\"\\"}, [[]]], "c": "\xff", "d": [true, 2]}"#;
+    let mut parser = Parser::with_capacity(&data[..], 3);
+    let mut events = vec![];
+    while let Some(event) = parser.next() {
+        let event = Event::from(event.unwrap());
+        let key = if let Event::Key(ref k) = event { Some(k.clone()) } else { None };
+        events.push(event);
+        match key.as_deref() {
+            Some("a") | Some("c") => assert!(parser.skip_value().unwrap()),
+            Some("d") => {
+                parser.next().unwrap().unwrap();
+                assert!(parser.skip_value().unwrap());
+                parser.skip_container().unwrap();
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(events, vec![
+        Event::StartMap,
+        Event::Key("a".to_string()),
+        Event::Key("c".to_string()),
+        Event::Key("d".to_string()),
+        Event::EndMap,
+    ]);
+
+    let mut parser = Parser::new(&b"[1, 2]"[..]);
+    parser.next().unwrap().unwrap();
+    assert!(parser.skip_value().unwrap());
+    assert!(parser.skip_value().unwrap());
+    assert!(!parser.skip_value().unwrap());
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::EndArray);
+    assert!(parser.next().is_none());
+
+    let mut parser = Parser::new(&b"[1, {"[..]);
+    parser.next().unwrap().unwrap();
+    assert!(parser.skip_value().unwrap());
+    assert!(matches!(parser.skip_value(), Err(Error::Incomplete(..))));
+
+    // Brackets have to match, including the one of the open container
+    for data in [&b"[[1, {]}]"[..], b"[{\"a\": [}]", b"{\"a\": 1]", b"[1, 2}"] {
+        let mut parser = Parser::new(data);
+        parser.next().unwrap().unwrap();
+        assert!(matches!(parser.skip_container(), Err(Error::Unexpected(..))), "{:?}", data);
+    }
+    let mut parser = Parser::new(&b"[[1, \"]\"], {\"a\": [{}]}]"[..]);
+    parser.next().unwrap().unwrap();
+    parser.skip_container().unwrap();
+    assert!(parser.next().is_none());
+}
+
 #[test]
 fn prefixes() {
     let f = File::open("test.json").unwrap();
-    let full: Vec<_> = Parser::new(f).map(Result::unwrap).collect();
+    let full: Vec<_> = Parser::new(f).into_iter().map(Result::unwrap).collect();
     let f = File::open("test.json").unwrap();
-    let result: Vec<_> = Parser::new(f).prefix("").map(Result::unwrap).collect();
+    let result: Vec<_> = Parser::new(f).into_iter().prefix("").map(Result::unwrap).collect();
     assert_eq!(result, full);
 
     let f = File::open("test.json").unwrap();
-    let result: Vec<_> = Parser::new(f).prefix("docs.item.meta.item").map(Result::unwrap).collect();
+    let result: Vec<_> = Parser::new(f).into_iter().prefix("docs.item.meta.item").map(Result::unwrap).collect();
     assert_eq!(result, vec![
         Event::StartArray,
-        Event::Number(1f64),
+        Event::Int(1),
         Event::EndArray,
         Event::StartMap,
         Event::EndMap,
     ]);
 }
 
+#[test]
+fn multi_prefix() {
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter()
+        .multi_prefix(vec!["docs.item.meta.item", "docs.item.string", "docs.item.meta.key"])
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(result, vec![
+        (1, Event::String("строка - тест".to_string())),
+        (0, Event::StartArray),
+        (0, Event::Int(1)),
+        (0, Event::EndArray),
+        (0, Event::StartMap),
+        (0, Event::EndMap),
+        (2, Event::String("value".to_string())),
+    ]);
+
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().multi_prefix(vec!["item.name", "*.name"]).map(Result::unwrap).collect();
+    assert_eq!(result.len(), 4);
+}
+
+#[test]
+fn project() {
+    let project = |data: &[u8], prefixes: &[&str]| {
+        let mut writer = Writer::new(vec![]);
+        for event in Parser::new(data).into_iter().project(prefixes.iter().cloned()) {
+            writer.write_event(event.unwrap().as_event()).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    };
+    let data = br#"{"a": 1, "b": {"c": [1, {"d": 2}], "x": 3}, "e": [{"f": 1, "g": 2}, {"g": 3}], "h": null}"#;
+    assert_eq!(project(data, &["a", "b.c"]), r#"{"a":1,"b":{"c":[1,{"d":2}]}}"#);
+    assert_eq!(project(data, &["e.item.f"]), r#"{"e":[{"f":1}]}"#);
+    assert_eq!(project(data, &["b.c.1.d", "h"]), r#"{"b":{"c":[{"d":2}]},"h":null}"#);
+    assert_eq!(project(data, &["missing"]), "{}");
+    assert_eq!(project(data, &[""]), project(data, &["*"]));
+}
+
+#[test]
+fn redact() {
+    use crate::builder::Mask;
+
+    let redact = |data: &[u8], prefixes: &[&str], mask: Mask| {
+        let mut parser = Parser::new(data);
+        parser.set_string_chunk_size(4);
+        let mut writer = Writer::new(vec![]);
+        for event in parser.into_iter().redact(prefixes.iter().cloned(), mask) {
+            writer.write_event(event.unwrap().as_event()).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    };
+    let data = br#"[{"name": "John Smith", "card": {"no": 1234}, "id": 1}, {"name": "Jane", "id": 2}]"#;
+    assert_eq!(
+        redact(data, &["item.name", "item.card"], Mask::Null),
+        r#"[{"name":null,"card":null,"id":1},{"name":null,"id":2}]"#,
+    );
+    assert_eq!(
+        redact(data, &["1.name"], Mask::Placeholder("***".to_string())),
+        r#"[{"name":"John Smith","card":{"no":1234},"id":1},{"name":"***","id":2}]"#,
+    );
+}
+
+#[cfg(feature = "hash")]
+#[test]
+fn redact_hash() {
+    use crate::builder::Mask;
+
+    let data = br#"[{"a": [1, "xyzzy"], "b": "xyzzy"}, {"a": [1, "xyzzy"], "b": "other"}]"#;
+    let hashed: Vec<Json> = Parser::new(&data[..]).into_iter()
+        .redact(["item.a", "item.b"], Mask::Hash(b"key".to_vec()))
+        .items("item").map(Result::unwrap).collect();
+    assert_eq!(hashed[0]["a"], hashed[1]["a"]);
+    assert_ne!(hashed[0]["b"], hashed[1]["b"]);
+    // HMAC-SHA256 of `"xyzzy"`, which doesn't change between builds
+    assert_eq!(hashed[0]["b"].as_string().unwrap(), "b46faa455d4ca79d2e33dc8b906777d02944a589439c3f8b9d4d45bbd914f2e1");
+    let salted: Vec<Json> = Parser::new(&data[..]).into_iter()
+        .redact(["item.a"], Mask::Hash(b"other key".to_vec()))
+        .items("item").map(Result::unwrap).collect();
+    assert_ne!(hashed[0]["a"], salted[0]["a"]);
+}
+
+#[test]
+fn rename_keys() {
+    use std::collections::HashMap;
+
+    let data = br#"{"first_name": "John", "address": {"zip_code": 1, "city": "X"}, "tags": ["a_b"]}"#;
+    let camel_case = |key: &str| {
+        if !key.contains('_') {
+            return None
+        }
+        let mut parts = key.split('_');
+        let first = parts.next().unwrap_or("").to_string();
+        Some(parts.fold(first, |mut result, part| {
+            let mut chars = part.chars();
+            result.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+            result.push_str(chars.as_str());
+            result
+        }))
+    };
+    let json = Parser::new(&data[..]).into_iter().rename_keys(camel_case).items("").next().unwrap().unwrap();
+    assert_eq!(json.to_string(), r#"{"address":{"city":"X","zipCode":1},"firstName":"John","tags":["a_b"]}"#);
+
+    let map: HashMap<&str, String> = vec![("city", "town".to_string())].into_iter().collect();
+    let keys: Vec<_> = Parser::new(&data[..]).into_iter().rename_keys(|k| map.get(k).cloned())
+        .filter_map(|e| match e.unwrap() { Event::Key(k) => Some(k), _ => None }).collect();
+    assert_eq!(keys, vec!["first_name", "address", "zip_code", "town", "tags"]);
+}
+
+#[test]
+fn flat_items() {
+    let data = br#"[{"id": 1, "address": {"city": "X", "zip": null}, "tags": ["a", "b"], "a.b": true, "0": {}}, {"id": 2}]"#;
+    let mut parser = Parser::new(&data[..]);
+    parser.set_string_chunk_size(4);
+    let pairs: Vec<_> = parser.into_iter().flat_items("item").map(Result::unwrap).collect();
+    assert_eq!(pairs, vec![
+        ("id".to_string(), Event::Int(1)),
+        ("address.city".to_string(), Event::String("X".to_string())),
+        ("address.zip".to_string(), Event::Null),
+        ("tags.0".to_string(), Event::String("a".to_string())),
+        ("tags.1".to_string(), Event::String("b".to_string())),
+        ("a\\.b".to_string(), Event::Boolean(true)),
+        ("id".to_string(), Event::Int(2)),
+    ]);
+    let events: Vec<_> = Parser::new(&data[..]).into_iter().prefix("0.a\\.b").map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::Boolean(true)]);
+
+    let pairs: Vec<_> = Parser::new(&b"5"[..]).into_iter().flat_items("").map(Result::unwrap).collect();
+    assert_eq!(pairs, vec![("".to_string(), Event::Int(5))]);
+}
+
+#[test]
+fn unflatten() {
+    use crate::builder::Unflatten;
+
+    let data = r#"{"id":1,"address":{"city":"X","zip":null},"tags":["a",["b"]],"a.b":true,"c":[{"d":1},{"d":2}]}"#;
+    let pairs = Parser::new(data.as_bytes()).into_iter().flat_items("");
+    let mut tee = Unflatten::new(pairs).tee(Writer::new(vec![]));
+    assert!(tee.all(|e| e.is_ok()));
+    assert_eq!(tee.finish().unwrap(), data.as_bytes());
+
+    let data = br#"{"a.b": 1, "a.c.0": 2, "a.c.1": 3, "\\0": 4} {} {"": 5}"#;
+    let mut tee = Parser::new_multi(&data[..]).into_iter().unflatten().tee(Writer::new(vec![]));
+    assert!(tee.all(|e| e.is_ok()));
+    let output = tee.finish().unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "{\"a\":{\"b\":1,\"c\":[2,3]},\"0\":4}\n{}\n5");
+
+    let result: Result<Vec<_>, _> = Parser::new(&br#"{"a": {"b": 1}}"#[..]).into_iter().unflatten().collect();
+    assert!(matches!(result, Err(Error::Custom(..))));
+
+    // Paths overlapping with the previous one
+    for data in [r#"{"a": 1, "a.b": 2}"#, r#"{"a.b": 1, "a": 2}"#, r#"{"a.b": 1, "a.0": 2}"#, r#"{"a.b": 1, "a.b": 2}"#] {
+        let result: Result<Vec<_>, _> = Parser::new(data.as_bytes()).into_iter().unflatten().collect();
+        assert!(matches!(result, Err(Error::Custom(ref m, _)) if m.contains("overlaps")), "{}", data);
+    }
+    let pairs = vec![Ok(("0".to_string(), Event::Int(1))), Ok(("0.0".to_string(), Event::Int(2)))];
+    assert!(Unflatten::new(pairs.into_iter()).any(|e| e.is_err()));
+}
+
+#[test]
+fn csv_export() {
+    use crate::csv::Export;
+
+    let data = br#"[{"name": "A \"B\"", "n": 1.5, "tags": ["x"]}, {"n": null, "ok": true}, {"name": "line\nbreak"}]"#;
+    let export = |export: Export| {
+        let mut out = vec![];
+        let rows = export.write(Parser::new(&data[..]).into_iter(), "item", &mut out);
+        (rows, String::from_utf8(out).unwrap())
+    };
+    let (rows, out) = export(Export::new());
+    assert_eq!(rows.unwrap(), 3);
+    assert_eq!(out, "name,n,tags,ok\n\"A \"\"B\"\"\",1.5,\"[\"\"x\"\"]\",\n,,,true\n\"line\nbreak\",,,\n");
+
+    let (_, out) = export(Export::new().delimiter(b'\t').null("NULL").columns(["ok", "n"]));
+    assert_eq!(out, "ok\tn\nNULL\t1.5\ntrue\tNULL\nNULL\tNULL\n");
+
+    let (rows, _) = export(Export::new().infer_rows(1));
+    assert!(matches!(rows, Err(Error::Custom(..))));
+    let (rows, out) = export(Export::new().infer_rows(1).ignore_extra_keys(true));
+    assert_eq!(rows.unwrap(), 3);
+    assert_eq!(out, "name,n,tags\n\"A \"\"B\"\"\",1.5,\"[\"\"x\"\"]\"\n,,\n\"line\nbreak\",,\n");
+
+    // Nothing at all for no rows
+    let mut out = vec![];
+    assert_eq!(Export::new().write(Parser::new(&b"[]"[..]).into_iter(), "item", &mut out).unwrap(), 0);
+    assert!(out.is_empty());
+
+    // Numbers keep their text
+    let mut parser = Parser::new(&br#"[{"n": 1.50, "a": [1.0e2, 2]}]"#[..]);
+    parser.set_raw_numbers(true);
+    let mut out = vec![];
+    Export::new().write(parser.into_iter(), "item", &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "n,a\n1.50,\"[1.0e2,2]\"\n");
+}
+
+#[test]
+fn paths() {
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().parse().take(9).map(Result::unwrap).collect();
+    let result: Vec<_> = result.iter().map(|(p, e)| (&p[..], e.clone())).collect();
+    assert_eq!(result, vec![
+        ("", Event::StartArray),
+        ("item", Event::StartMap),
+        ("item", Event::Key("name".to_string())),
+        ("item.name", Event::String("John".to_string())),
+        ("item", Event::Key("friends".to_string())),
+        ("item.friends", Event::StartArray),
+        ("item.friends.item", Event::String("Mary".to_string())),
+        ("item.friends.item", Event::String("Michael".to_string())),
+        ("item.friends", Event::EndArray),
+    ]);
+}
+
+#[test]
+fn wildcards() {
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("docs.*.meta").map(Result::unwrap).collect();
+    assert_eq!(result.len(), 3);
+
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("*.name").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::String("John".to_string()), Json::String("Steve".to_string())]);
+
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("docs.item.*").map(Result::unwrap).collect();
+    assert_eq!(result.len(), 11);
+}
+
+#[test]
+fn array_indices() {
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("1.friends.0").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::String("Diane".to_string())]);
+
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("docs.3.meta").map(Result::unwrap).collect();
+    assert_eq!(result.len(), 1);
+
+    let data = br#"{"2": "key", "list": [0, 1, "index"]}"#;
+    let result: Vec<_> = Parser::new(Cursor::new(&data[..])).into_iter().items("2").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::String("key".to_string())]);
+    let result: Vec<_> = Parser::new(Cursor::new(&data[..])).into_iter().items("list.2").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::String("index".to_string())]);
+    let data = br#"{"02": "padded"}"#;
+    assert_eq!(Parser::new(Cursor::new(&data[..])).into_iter().items("2").count(), 0);
+}
+
+#[test]
+fn escaped_prefixes() {
+    let data = br#"{"a.b": 1, "item": 2, "*": 3, "0": 4, "a\\b": 5, "a": {"b": 6}}"#;
+    let select = |prefix| -> Vec<_> {
+        Parser::new(Cursor::new(&data[..])).into_iter().items(prefix).map(Result::unwrap).collect()
+    };
+    assert_eq!(select(r"a\.b"), vec![Json::I64(1)]);
+    assert_eq!(select(r"\item"), vec![Json::I64(2)]);
+    assert_eq!(select(r"\*"), vec![Json::I64(3)]);
+    assert_eq!(select(r"\0"), vec![Json::I64(4)]);
+    assert_eq!(select(r"a\\b"), vec![Json::I64(5)]);
+    assert_eq!(select("a.b"), vec![Json::I64(6)]);
+}
+
+#[test]
+fn typed_paths() {
+    let token = |t: &str| Segment::Token(t.to_string());
+    let path = Path::from(vec![Segment::Key("docs".to_string()), token("item"), Segment::Key("meta".to_string()), token("0")]);
+    assert_eq!(Path::parse("docs.item.meta.0"), path);
+    assert_eq!(Path::parse("/a.b/item"), Path::from(vec![Segment::Key("a.b".to_string()), Segment::Key("item".to_string())]));
+    assert_eq!(Path::parse("/a/0"), Path::from(vec![Segment::Key("a".to_string()), token("0")]));
+
+    // Typed segments tell array elements from keys, unlike prefix strings
+    let data = br#"{"item": 1, "0": 2, "a": [3]}"#;
+    let select = |path: Path| -> Vec<_> {
+        Parser::new(&data[..]).into_iter().items(path).map(Result::unwrap).collect()
+    };
+    assert_eq!(select(Path::new().item()), vec![]);
+    assert_eq!(select(Path::new().index(0)), vec![]);
+    assert_eq!(select(Path::parse("item")), vec![Json::I64(1)]);
+    assert_eq!(select(Path::parse("0")), vec![Json::I64(2)]);
+    assert_eq!(select(Path::new().key("a").item()), vec![Json::I64(3)]);
+    assert_eq!(select(Path::new().key("a").index(0)), vec![Json::I64(3)]);
+
+    // Every prefix argument takes a path
+    let events: Vec<_> = Parser::new(&data[..]).into_iter()
+        .project(vec![Path::new().key("a").index(0)])
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(events, vec![Event::StartMap, Event::Key("a".to_string()), Event::StartArray, Event::Int(3), Event::EndArray, Event::EndMap]);
+    let count = Parser::new(&data[..]).into_iter().multi_prefix(vec![Path::new().key("item"), Path::parse("a")]).count();
+    assert_eq!(count, 4);
+
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter()
+        .prefix_path(Path::new().wildcard().key("friends").index(1))
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(result, vec![Event::String("Michael".to_string()), Event::String("Jack".to_string())]);
+}
+
+#[test]
+fn json_pointers() {
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("/1/name").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::String("Steve".to_string())]);
+
+    let f = File::open("people.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().items("/0/friends/1").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::String("Michael".to_string())]);
+
+    let data = br#"{"a/b": {"c~d": 1}, "e.f": 2}"#;
+    let result: Vec<_> = Parser::new(Cursor::new(&data[..])).into_iter().items("/a~1b/c~0d").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::I64(1)]);
+    let result: Vec<_> = Parser::new(Cursor::new(&data[..])).into_iter().items("/e.f").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::I64(2)]);
+}
+
+#[test]
+fn raw_items() {
+    let data = br#"{"docs": [{"a": [1, 2]}, "x\"]", 3.50 ], "other": {"docs": 1}}"#;
+    let result: Vec<_> = Parser::with_capacity(Cursor::new(&data[..]), 4).raw_items("docs.item").map(Result::unwrap).collect();
+    assert_eq!(result, vec![br#"{"a": [1, 2]}"#.to_vec(), br#""x\"]""#.to_vec(), b"3.50".to_vec()]);
+
+    let result: Vec<_> = Parser::new(Cursor::new(&data[..])).raw_items("*.docs").map(Result::unwrap).collect();
+    assert_eq!(result, vec![b"1".to_vec()]);
+
+    let result: Vec<_> = Parser::new_multi(Cursor::new(&b" [1]\n{} "[..])).raw_items("").map(Result::unwrap).collect();
+    assert_eq!(result, vec![b"[1]".to_vec(), b"{}".to_vec()]);
+
+    // Values outside the prefix are skipped without decoding
+    let data = b"{\"skip\": [\"\xff\", {\"a\": 0}], \"a\": [1, 2]}";
+    let result: Vec<_> = Parser::new(&data[..]).raw_items("a.item").map(Result::unwrap).collect();
+    assert_eq!(result, vec![b"1".to_vec(), b"2".to_vec()]);
+    assert!(Parser::new(&data[..]).into_iter().any(|e| e.is_err()));
+}
+
+#[test]
+fn kvitems() {
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().kvitems("docs.item").map(Result::unwrap).collect();
+    let keys: Vec<_> = result.iter().map(|(k, _)| &k[..]).collect();
+    assert_eq!(keys, vec![
+        "null", "boolean", "true", "integer", "double", "exponent", "long", "string",
+        "meta", "meta", "meta",
+    ]);
+    assert_eq!(result[8].1, Json::Array(vec![Json::Array(vec![Json::I64(1)]), Json::Object(Default::default())]));
+
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().kvitems("docs").map(Result::unwrap).collect();
+    assert!(result.is_empty());
+}
+
 #[test]
 fn items() {
     let f = File::open("test.json").unwrap();
-    let result: Vec<_> = Parser::new(f).items("").map(Result::unwrap).collect();
+    let result: Vec<_> = Parser::new(f).into_iter().items("").map(Result::unwrap).collect();
     assert_eq!(result.len(), 1);
 
-    #[derive(RustcDecodable, Debug, PartialEq)]
+    #[derive(Debug, PartialEq)]
     struct Person {
         name: String,
         friends: Vec<String>,
     }
 
+    impl Decodable for Person {
+        fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+            d.read_struct("Person", 2, |d| Ok(Person {
+                name: d.read_struct_field("name", 0, Decodable::decode)?,
+                friends: d.read_struct_field("friends", 1, Decodable::decode)?,
+            }))
+        }
+    }
+
     let f = File::open("people.json").unwrap();
-    let json = Parser::new(f).items("item").next().unwrap().unwrap();
+    let json = Parser::new(f).into_iter().items("item").next().unwrap().unwrap();
     let result: Person = decode(json).unwrap();
     let reference = Person {
         name: "John".to_string(),
@@ -106,53 +684,2485 @@ fn items() {
     assert_eq!(result, reference);
 }
 
-fn test_error(data: &[u8], error: Error) {
-    let r = Parser::new(Cursor::new(data.to_vec())).last().unwrap();
-    assert!(r.is_err(), "Not an error: {:?}", r.ok().unwrap());
-    let rerror = r.err().unwrap();
-    if rerror.description() != error.description() {
-        panic!("Not <{:?}> at data: {:?}. Got {:?} instead.", error, data, rerror);
+#[test]
+fn early_termination() {
+    // Fails on any read past the beginning of the data, so only what the
+    // first items need may be read
+    struct Truncated<'a>(&'a [u8]);
+
+    impl<'a> std::io::Read for Truncated<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::Error::other("read past the first items"))
+            }
+            let size = self.0.len().min(buf.len());
+            buf[..size].copy_from_slice(&self.0[..size]);
+            self.0 = &self.0[size..];
+            Ok(size)
+        }
     }
+
+    let data = br#"[{"a": 1}, {"a": 2}, "#;
+    let parser = Parser::with_capacity(Truncated(&data[..]), 4);
+    let result: Vec<_> = parser.into_iter().items("item").take(2).map(|j| j.unwrap().to_string()).collect();
+    assert_eq!(result, vec![r#"{"a":1}"#, r#"{"a":2}"#]);
+
+    let parser = Parser::with_capacity(Truncated(&data[..]), 4);
+    assert!(parser.into_iter().items("item").nth(2).unwrap().is_err());
 }
 
 #[test]
-fn unterminated_string() {
-    test_error(br#"{"key": "value"#, Error::Unterminated);
+fn into_inner() {
+    use std::io::Read;
+
+    let data = b"\xEF\xBB\xBF{\"size\": 11}hello world";
+    for &size in &[1, 3, 8, 64] {
+        let mut parser = Parser::with_capacity(&data[..], size);
+        while parser.next().unwrap().unwrap() != BorrowedEvent::EndMap {}
+        let (mut reader, mut rest) = parser.into_inner();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"hello world", "buffer size {}", size);
+    }
 }
 
 #[test]
-fn additional_data() {
-    test_error(br#"{"key": "value"} stuff"#, Error::AdditionalData);
+fn single_value() {
+    use std::io::Read;
+
+    let data = b"{\"a\": [1]}\r\nbinary\x00";
+    let mut parser = Parser::builder().single_value(true).capacity(4).build(&data[..]);
+    let mut count = 0;
+    while let Some(event) = parser.next() {
+        event.unwrap();
+        count += 1;
+    }
+    assert_eq!(count, 6);
+    assert_eq!(parser.position().offset, 10);
+    let (mut reader, mut rest) = parser.into_inner();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"\r\nbinary\x00");
+
+    let mut parser = Parser::from_slice(b"123 456");
+    parser.set_single_value(true);
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Int(123));
+    assert!(parser.next().is_none());
+    assert_eq!(parser.position().offset, 3);
+
+    let result: Vec<_> = Parser::from_slice(b"123 456").into_iter().collect();
+    assert!(matches!(result.last(), Some(Err(Error::AdditionalData(_)))));
 }
 
+#[cfg(feature = "serde")]
 #[test]
-fn incomplete() {
-    let data: Vec<&'static [u8]> = vec![
-        b"",
-        b"[",
-        b"[1",
-        b"[1,",
-        b"{",
-        br#"{"key""#,
-        br#"{"key":"#,
-        br#"{"key": "value""#,
-        br#"{"key": "value","#,
-    ];
-    for d in data.iter() {
-        test_error(d, Error::MoreLexemes);
+fn serde_items() {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Person {
+        name: String,
+        friends: Vec<String>,
+    }
+
+    let f = File::open("people.json").unwrap();
+    let result: Vec<Person> = Parser::new(f).into_iter().items_as("item").map(Result::unwrap).collect();
+    assert_eq!(result, vec![
+        Person { name: "John".to_string(), friends: vec!["Mary".to_string(), "Michael".to_string()] },
+        Person { name: "Steve".to_string(), friends: vec!["Diane".to_string(), "Jack".to_string()] },
+    ]);
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Point {
+        x: i64,
+    }
+
+    let mut parser = Parser::new(r#"[{"x": 1, "tags": ["a", {"b": null}]}, /* c */ {"x": 2}, {"x": "3"}]"#.as_bytes());
+    parser.set_comments(Comments::Report);
+    let mut items = parser.into_iter().items_as::<Point>("item");
+    assert_eq!(items.next().unwrap().unwrap(), Point { x: 1 });
+    assert_eq!(items.next().unwrap().unwrap(), Point { x: 2 });
+    assert!(items.next().unwrap().is_err());
+
+    // A value failing to deserialize is skipped as a whole
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Id {
+        id: i64,
+    }
+    let data = br#"[{"id": "bad", "x": {"id": 5}}, {"id": 2}, [{"id": 3}], {"id": 4}]"#;
+    let results: Vec<_> = Parser::new(&data[..]).into_iter().items_as::<Id>("item").map(|r| r.map_err(|e| e.position().offset)).collect();
+    assert_eq!(results, vec![Err(13), Ok(Id { id: 2 }), Err(45), Ok(Id { id: 4 })]);
+
+    let mut values = Parser::new(&br#"{"a": 1, "a": 2}"#[..]).into_iter().values("");
+    values.set_duplicate_keys(crate::builder::DuplicateKeys::Collect);
+    assert_eq!(values.next().unwrap().unwrap(), json!({"a": [1, 2]}));
+
+    let f = File::open("test.json").unwrap();
+    let result: Vec<_> = Parser::new(f).into_iter().values("docs.item.meta").map(Result::unwrap).collect();
+    assert_eq!(result, vec![json!([[1], {}]), json!({"key": "value"}), json!(null)]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_deserializer() {
+    use std::collections::BTreeMap;
+    use serde::Deserialize;
+    use crate::de::{Deserializer, from_reader};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(untagged)]
+    enum Meta {
+        List(Vec<serde_json::Value>),
+        Map(BTreeMap<String, String>),
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Doc {
+        #[serde(default)]
+        integer: Option<u32>,
+        double: Option<f64>,
+        string: Option<String>,
+        meta: Option<Meta>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Docs {
+        docs: Vec<Doc>,
+    }
+
+    let f = File::open("test.json").unwrap();
+    let result: Docs = from_reader(f).unwrap();
+    assert_eq!(result.docs.len(), 4);
+    assert_eq!(result.docs[0].integer, Some(0));
+    assert_eq!(result.docs[0].double, Some(0.5));
+    assert_eq!(result.docs[0].string.as_deref(), Some("строка - тест"));
+    assert_eq!(result.docs[2].meta, Some(Meta::Map(vec![("key".to_string(), "value".to_string())].into_iter().collect())));
+    assert_eq!(result.docs[3].meta, None);
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Point,
+        Circle { radius: f64 },
     }
+
+    let data = br#"["Point", {"Circle": {"radius": 2}}]"#;
+    let shapes = Vec::<Shape>::deserialize(Deserializer::new(&data[..])).unwrap();
+    assert_eq!(shapes, vec![Shape::Point, Shape::Circle { radius: 2.0 }]);
+
+    assert!(from_reader::<_, Vec<u32>>(&b"[1] 2"[..]).is_err());
+
+    // Errors point at the event where they're found
+    let error = from_reader::<_, Vec<u32>>(&b"[1,\n 2, \"x\"]"[..]).unwrap_err();
+    assert_eq!(error.position(), Position { line: 2, column: 8, offset: 11 });
+    let error = from_reader::<_, Vec<Shape>>(&br#"[{"Circle": {}}]"#[..]).unwrap_err();
+    assert_eq!((error.kind(), error.position().offset), (ErrorKind::Custom, 14));
 }
 
 #[test]
-fn bad_escape() {
-    let data: Vec<&'static [u8]> = vec![
-        br#""\w""#,
-        br#""\u""#,
-        br#""\u0""#,
-        br#""\uXXXX""#,
-        br#""\uD800""#,
-    ];
-    for d in data.iter() {
-        test_error(d, Error::Escape(vec![]));
+fn comments() {
+    let data = b"// head\n{\"a\" /* 1 */ : /* 2 */ [1, // 3\n 2 /* 4\n */], /* 5 */ \"b\": null} // tail";
+    let r = Parser::new(&data[..]).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::Unknown(..))));
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_comments(Comments::Skip);
+    let result: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    let expected: Vec<_> = Parser::new(&br#"{"a": [1, 2], "b": null}"#[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(result, expected);
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_comments(Comments::Report);
+    let result: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    let comment = |s: &str| Event::Comment(s.to_string());
+    assert_eq!(result, vec![
+        comment(" head"),
+        Event::StartMap,
+        Event::Key("a".to_string()),
+        comment(" 1 "),
+        comment(" 2 "),
+        Event::StartArray,
+        Event::Int(1),
+        comment(" 3"),
+        Event::Int(2),
+        comment(" 4\n "),
+        Event::EndArray,
+        comment(" 5 "),
+        Event::Key("b".to_string()),
+        Event::Null,
+        Event::EndMap,
+        comment(" tail"),
+    ]);
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_comments(Comments::Report);
+    let result: Vec<_> = parser.into_iter().items("a").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::Array(vec![Json::I64(1), Json::I64(2)])]);
+
+    let mut parser = Parser::new(&b"[1 /* unterminated"[..]);
+    parser.set_comments(Comments::Skip);
+    assert!(parser.into_iter().last().unwrap().err().unwrap().is_incomplete());
+
+    // Reported comments are limited like strings, skipped ones aren't kept
+    let data = b"[1, /* a long comment **/ 2] // and another";
+    let limits = Limits { max_string: 16, ..Limits::default() };
+    let mut parser = Parser::new(&data[..]);
+    parser.set_limits(limits);
+    parser.set_comments(Comments::Skip);
+    assert_eq!(parser.into_iter().map(Result::unwrap).count(), 4);
+    let mut parser = Parser::new(&data[..]);
+    parser.set_limits(limits);
+    parser.set_comments(Comments::Report);
+    let error = parser.into_iter().find_map(Result::err).unwrap();
+    assert_eq!((error.kind(), error.position().offset), (ErrorKind::StringLimit, 4));
+    let mut parser = Parser::new(&data[..]);
+    parser.set_comments(Comments::Report);
+    assert_eq!(parser.into_iter().nth(2).unwrap().unwrap(), comment(" a long comment *"));
+}
+
+#[test]
+fn trailing_commas() {
+    let data = br#"{"a": [1, 2,], "b": {"c": null,},}"#;
+    let r = Parser::new(&data[..]).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::Unexpected(..))));
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_trailing_commas(true);
+    let result: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    let expected: Vec<_> = Parser::new(&br#"{"a": [1, 2], "b": {"c": null}}"#[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(result, expected);
+
+    let mut parser = Parser::new(&b"[1, /* c */ ]"[..]);
+    parser.set_trailing_commas(true);
+    parser.set_comments(Comments::Report);
+    assert_eq!(parser.into_iter().count(), 4);
+
+    let mut parser = Parser::new(&b"[1,]"[..]);
+    parser.set_trailing_commas(true);
+    assert!(matches!(parser.next(), Some(Ok(BorrowedEvent::StartArray))));
+    assert!(parser.skip_value().unwrap());
+    assert!(!parser.skip_value().unwrap());
+    assert!(matches!(parser.next(), Some(Ok(BorrowedEvent::EndArray))));
+
+    for data in [&b"[,]"[..], b"{,}", b"[1,,]"] {
+        let mut parser = Parser::new(data);
+        parser.set_trailing_commas(true);
+        assert!(parser.into_iter().last().unwrap().is_err(), "{:?}", data);
+    }
+}
+
+#[cfg(feature = "json5")]
+#[test]
+fn json5() {
+    use crate::parser::Dialect;
+
+    let data = br#"// config
+{
+  unquoted: 'single "quoted"',
+  $id_2: 0x1F,
+  neg: -0XFF,
+  big: 0xFFFFFFFFFFFFFFFF,
+  inf: [Infinity, -Infinity, +1, .5, 2.,],
+  escapes: "\x41\v\0\'\
+next",
+  /* trailing */
+}"#;
+    let mut parser = Parser::new(&data[..]);
+    parser.set_dialect(Dialect::Json5);
+    let result: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert_eq!(result, vec![
+        Event::StartMap,
+        Event::Key("unquoted".to_string()),
+        Event::String("single \"quoted\"".to_string()),
+        Event::Key("$id_2".to_string()),
+        Event::Int(31),
+        Event::Key("neg".to_string()),
+        Event::Int(-255),
+        Event::Key("big".to_string()),
+        Event::Uint(u64::MAX),
+        Event::Key("inf".to_string()),
+        Event::StartArray,
+        Event::Number(f64::INFINITY),
+        Event::Number(f64::NEG_INFINITY),
+        Event::Int(1),
+        Event::Number(0.5),
+        Event::Number(2.0),
+        Event::EndArray,
+        Event::Key("escapes".to_string()),
+        Event::String("A\x0b\0'next".to_string()),
+        Event::EndMap,
+    ]);
+
+    // Raw numbers build into the same values as parsed ones
+    let build = |raw| {
+        let parser = Parser::builder().dialect(Dialect::Json5).raw_numbers(raw).build(&data[..]);
+        parser.into_iter().items("").next().unwrap().unwrap().to_string()
+    };
+    assert_eq!(build(true), build(false));
+
+    let mut parser = Parser::new(&b"['a\\\r\nb']"[..]);
+    parser.set_dialect(Dialect::Json5);
+    assert_eq!(parser.into_iter().nth(1).unwrap().unwrap(), Event::String("ab".to_string()));
+
+    // Skipped values can have single-quoted strings with brackets in them
+    let mut parser = Parser::new(&br#"[{'a': 'x]\'}'}, 2]"#[..]);
+    parser.set_dialect(Dialect::Json5);
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::StartArray);
+    assert!(parser.skip_value().unwrap());
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Int(2));
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::EndArray);
+    let mut parser = Parser::new(&b"['x]', 1]"[..]);
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::StartArray);
+    assert!(parser.skip_value().is_err());
+
+    let mut parser = Parser::new(&b"[NaN]"[..]);
+    parser.set_dialect(Dialect::Json5);
+    assert!(matches!(parser.into_iter().nth(1), Some(Ok(Event::Number(n))) if n.is_nan()));
+
+    for data in [&b"[unquoted]"[..], b"{'a' 1}", b"[0x]", b"[Infinit]"] {
+        let mut parser = Parser::new(data);
+        parser.set_dialect(Dialect::Json5);
+        assert!(parser.into_iter().last().unwrap().is_err(), "{:?}", data);
     }
+    let r = Parser::new(&b"{unquoted: 1}"[..]).into_iter().last().unwrap();
+    assert!(r.is_err());
+}
+
+#[test]
+fn multiple_values() {
+    let data = b"{\"a\": 1}\n[2, 3]\n\"four\"\n";
+    let result: Vec<_> = Parser::new_multi(&data[..]).into_iter().items("").map(Result::unwrap).collect();
+    assert_eq!(result.len(), 3);
+    assert_eq!(result[2], Json::String("four".to_string()));
+
+    assert_eq!(Parser::new_multi(&b" \n "[..]).into_iter().count(), 0);
+
+    // Concatenated without separators
+    let data = br#"{"a":1}{"b":2}[3]"four"null 5 6"#;
+    let result: Vec<_> = Parser::new_multi(&data[..]).into_iter().items("").map(|j| j.unwrap().to_string()).collect();
+    assert_eq!(result, vec![r#"{"a":1}"#, r#"{"b":2}"#, "[3]", r#""four""#, "null", "5", "6"]);
+
+    let r = Parser::new_multi(&b"1\n2\n]"[..]).into_iter().last().unwrap();
+    assert_eq!(r.err().unwrap().position(), Position { line: 3, column: 1, offset: 4 });
+}
+
+#[test]
+fn json_seq() {
+    // Junk before the first RS, an empty document, a broken one, a number
+    // cut off without its LF
+    let data = b"junk\x1e{\"a\": [1, 2]}\n\x1e\x1e\n\x1e{\"b\": \n\x1e\"c\"\n\x1e123";
+    let results: Vec<_> = SeqReader::new(&data[..]).collect();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_ref().unwrap().to_string(), r#"{"a":[1,2]}"#);
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap(), &Json::String("c".to_string()));
+    assert!(matches!(results[3], Err(Error::Incomplete(_))));
+
+    // Documents streamed through a parser
+    let mut reader = SeqReader::new(std::io::BufReader::with_capacity(2, &data[..]));
+    let mut counts = vec![];
+    while let Some(document) = reader.next_document().unwrap() {
+        counts.push(Parser::new(document).into_iter().take_while(Result::is_ok).count());
+    }
+    assert_eq!(counts, vec![7, 0, 0, 2, 1, 1]);
+
+    let mut writer = SeqWriter::new(vec![]);
+    writer.write_document(Parser::new(&b"{\"a\": [1, 2]}"[..]).into_iter()).unwrap();
+    writer.write_document(Parser::new(&b"3"[..]).into_iter()).unwrap();
+    assert!(writer.write_document(Parser::new(&b"[1"[..]).into_iter()).is_err());
+    assert!(writer.write_document(Parser::new(&b"[1, x]"[..]).into_iter()).is_err());
+    writer.write_document(Parser::new(&b"4"[..]).into_iter()).unwrap();
+    let out = writer.into_inner().unwrap();
+    assert_eq!(out, b"\x1e{\"a\":[1,2]}\n\x1e3\n\x1e4\n");
+
+    // Long documents are skipped
+    let mut reader = SeqReader::new(&b"\x1e[1, 2, 3]\n\x1e[4]\n\x1e[1, 2, 3, 4]"[..]);
+    reader.set_max_document(8);
+    let results: Vec<_> = reader.collect();
+    assert_eq!(results.len(), 3);
+    assert!(matches!(results[0], Err(Error::StringLimit(_))));
+    assert_eq!(results[1].as_ref().unwrap().to_string(), "[4]");
+    assert!(matches!(results[2], Err(Error::StringLimit(_))));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip() {
+    use std::io::Write;
+    use flate2::{Compression, write::GzEncoder};
+
+    // Two members, the second one starting in the middle of the document
+    let mut data = vec![];
+    for part in &[&b"{\"a\": [1, "[..], &b"2]}"[..]] {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(part).unwrap();
+        data.extend(encoder.finish().unwrap());
+    }
+    let result: Vec<_> = Parser::new_gzip(&data[..]).into_iter().items("a.item").map(Result::unwrap).collect();
+    assert_eq!(result.len(), 2);
+
+    assert!(Parser::new_gzip(&b"{}"[..]).into_iter().next().unwrap().is_err());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd() {
+    let mut data = zstd::encode_all(&b"[1, "[..], 0).unwrap();
+    data.extend(zstd::encode_all(&b"2]"[..], 0).unwrap());
+    let result: Vec<_> = Parser::new_zstd(&data[..]).unwrap().into_iter().items("item").map(Result::unwrap).collect();
+    assert_eq!(result.len(), 2);
+}
+
+#[test]
+fn follow() {
+    use std::time::Duration;
+    use crate::follow::Follow;
+
+    // Pieces of a log being written, with nothing new in between
+    struct Growing(Vec<&'static [u8]>);
+
+    impl std::io::Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.first().copied() {
+                None => Ok(0),
+                Some(piece) => {
+                    buf[..piece.len()].copy_from_slice(piece);
+                    self.0.remove(0);
+                    Ok(piece.len())
+                }
+            }
+        }
+    }
+
+    let pieces: Vec<&[u8]> = vec![b"{\"a\": 12", b"", b"", b"3}\n[\"b", b"", b"\"]\n"];
+    let mut reader = Follow::new(Growing(pieces));
+    reader.set_interval(Duration::from_millis(1));
+    reader.set_timeout(Some(Duration::from_millis(20)));
+    let result: Vec<_> = Parser::new_multi(reader).into_iter().items("").map(|j| j.unwrap().to_string()).collect();
+    assert_eq!(result, vec![r#"{"a":123}"#, r#"["b"]"#]);
+}
+
+#[test]
+fn parallel_lines() {
+    use crate::parallel::ParallelLines;
+
+    let mut data = String::new();
+    for i in 0..1000 {
+        data.push_str(&format!("{{\"i\": {}}}\n", i));
+        if i % 100 == 0 {
+            data.push_str("\n{\"broken\"\n");
+        }
+    }
+    let mut lines = ParallelLines::json(data.as_bytes());
+    lines.set_threads(4);
+    lines.set_batch_lines(7);
+    let results: Vec<_> = lines.collect();
+    assert_eq!(results.len(), 1010);
+    let errors: Vec<_> = results.iter().filter(|(_, r)| r.is_err()).map(|(line, _)| *line).collect();
+    assert_eq!(errors[..2], [3, 105]);
+    let values: Vec<_> = results.into_iter().filter_map(|(_, r)| r.ok()).map(|j| j["i"].as_u64().unwrap()).collect();
+    assert_eq!(values, (0..1000).collect::<Vec<_>>());
+
+    let mut lines = ParallelLines::new(data.as_bytes(), |line| Ok(line.len()));
+    lines.set_ordered(false);
+    lines.set_batch_lines(3);
+    let mut numbers: Vec<_> = lines.map(|(line, _)| line).collect();
+    numbers.sort_unstable();
+    assert_eq!(numbers.len(), 1010);
+    numbers.dedup();
+    assert_eq!(numbers.len(), 1010);
+
+    // Dropped before all the results are taken
+    assert!(ParallelLines::json(data.as_bytes()).next().unwrap().1.is_ok());
+
+    // Batches done ahead of a slow one don't make it read on
+    let read = std::rc::Rc::new(std::cell::Cell::new(0));
+    struct Counted(std::rc::Rc<std::cell::Cell<usize>>, usize);
+    impl std::io::Read for Counted {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let size = buf.len().min(self.1 - self.0.get());
+            for (i, b) in buf[..size].iter_mut().enumerate() {
+                *b = match self.0.get() + i {
+                    0 => b'0',
+                    offset if offset % 2 == 0 => b'1',
+                    _ => b'\n',
+                };
+            }
+            self.0.set(self.0.get() + size);
+            Ok(size)
+        }
+    }
+    let reader = std::io::BufReader::with_capacity(1, Counted(read.clone(), 20_000));
+    let mut lines = ParallelLines::new(reader, |line| {
+        if line == b"0" {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        }
+        Ok(line.len())
+    });
+    lines.set_threads(2);
+    lines.set_batch_lines(1);
+    assert!(matches!(lines.next(), Some((1, Ok(1)))));
+    assert!(read.get() <= 2 * 6, "read {} bytes", read.get());
+
+    let data = "1\n22\n333\n4\n";
+    let mut lines = ParallelLines::new(data.as_bytes(), |line| Ok(line.len()));
+    lines.set_max_line(2);
+    lines.set_batch_lines(2);
+    let results: Vec<_> = lines.collect();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[1].1.as_ref().unwrap(), &2);
+    assert!(matches!(results[2], (3, Err(Error::StringLimit(Position { line: 3, .. })))));
+}
+
+#[test]
+fn index() {
+    use crate::index::Index;
+
+    let data = b"\xEF\xBB\xBF{\"docs\": [{\"id\": 1}, \"two\", [3],\n 4], \"more\": [5]}";
+    let index = Index::build(&data[..], "docs.item").unwrap();
+    let raw: Vec<_> = index.iter().map(|r| &data[r.start as usize..r.end as usize]).collect();
+    assert_eq!(raw, vec![&b"{\"id\": 1}"[..], b"\"two\"", b"[3]", b"4"]);
+
+    for (i, raw) in raw.iter().enumerate() {
+        let parser = index.parser(Cursor::new(&data[..]), i).unwrap().unwrap();
+        let value = parser.into_iter().items("").next().unwrap().unwrap();
+        assert_eq!(value.to_string().replace(':', ": "), String::from_utf8_lossy(raw));
+    }
+    assert!(index.parser(Cursor::new(&data[..]), 4).unwrap().is_none());
+
+    let mut saved = vec![];
+    index.write(&mut saved).unwrap();
+    assert_eq!(Index::read(&saved[..]).unwrap(), index);
+    assert!(index.matches(Cursor::new(&data[..])).unwrap());
+    let mut changed = data.to_vec();
+    changed[15] = b'2';
+    assert!(!index.matches(Cursor::new(&changed[..])).unwrap());
+    assert!(!index.matches(Cursor::new(&data[..data.len() - 1])).unwrap());
+    // Damaged data with a checksum matching it
+    let with_checksum = |data: &[u8]| {
+        let checksum = data.iter().fold(0xcbf29ce484222325u64, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+        [data, &checksum.to_le_bytes()].concat()
+    };
+    let odd = with_checksum(&saved[..saved.len() - 16]);
+    assert!(matches!(Index::read(&odd[..]), Err(Error::IO(e, _)) if e.kind() == std::io::ErrorKind::InvalidData));
+    // A range ending before its start, and one past the end of the input
+    let mut reversed = saved[..saved.len() - 8].to_vec();
+    reversed[40..48].copy_from_slice(&100u64.to_le_bytes());
+    assert!(matches!(Index::read(&with_checksum(&reversed)[..]), Err(Error::IO(e, _)) if e.kind() == std::io::ErrorKind::InvalidData));
+    let mut past_end = saved[..saved.len() - 8].to_vec();
+    let end = past_end.len();
+    past_end[end - 8..].copy_from_slice(&(data.len() as u64 + 1).to_le_bytes());
+    assert!(matches!(Index::read(&with_checksum(&past_end)[..]), Err(Error::IO(e, _)) if e.kind() == std::io::ErrorKind::InvalidData));
+    saved[20] ^= 1;
+    assert!(matches!(Index::read(&saved[..]), Err(Error::IO(e, _)) if e.kind() == std::io::ErrorKind::InvalidData));
+    assert!(Index::read(&b"[1, 2]"[..]).is_err());
+
+    assert!(Index::build(&b"[1, 2"[..], "item").is_err());
+}
+
+#[test]
+fn progress() {
+    use std::sync::{Arc, Mutex};
+    use crate::lexer::Progress;
+
+    let data = format!("[{}]", vec!["12345"; 100].join(", "));
+    let reports = Arc::new(Mutex::new(vec![]));
+    let mut parser = Parser::with_capacity(data.as_bytes(), 16);
+    let shared = Arc::clone(&reports);
+    parser.set_progress(100, Some(data.len() as u64), move |progress| shared.lock().unwrap().push(progress));
+    assert_eq!(parser.into_iter().count(), 102);
+
+    let reports = reports.lock().unwrap();
+    assert!(reports.len() >= data.len() / 100);
+    assert!(reports.windows(2).all(|w| w[1].bytes >= w[0].bytes + 100 || w[1].bytes == data.len() as u64));
+    assert_eq!(reports.last(), Some(&Progress { bytes: data.len() as u64, total: Some(data.len() as u64) }));
+    assert_eq!(reports.last().unwrap().percent(), Some(100.0));
+    assert_eq!(Progress { bytes: 5, total: None }.percent(), None);
+}
+
+#[test]
+fn cancel() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let flag = Arc::new(AtomicBool::new(false));
+    let mut parser = Parser::new(&b"[1, 2, 3]"[..]);
+    parser.set_cancel_flag(Arc::clone(&flag));
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::StartArray);
+    assert!(parser.next().unwrap().is_ok());
+    flag.store(true, Ordering::Relaxed);
+    assert!(matches!(parser.next(), Some(Err(Error::Cancelled(_)))));
+    assert!(parser.next().is_none());
+
+    let parser = Parser::builder().cancel_flag(flag).build(&b"[1]"[..]);
+    assert!(matches!(parser.into_iter().next(), Some(Err(Error::Cancelled(_)))));
+}
+
+#[test]
+fn counters() {
+    let data = br#"{"a": [1, "two", "th\u0072ee", null, true], "b": "a long string split between reads"}"#;
+    let mut parser = Parser::with_capacity(&data[..], 64);
+    let mut middle = None;
+    let mut count = 0;
+    while let Some(event) = parser.next() {
+        event.unwrap();
+        count += 1;
+        if count == 4 {
+            middle = Some(parser.counters());
+        }
+    }
+    let counters = parser.counters();
+    assert_eq!(counters.bytes, data.len() as u64);
+    assert_eq!(counters.refills, 2);
+    assert_eq!(counters.borrowed_strings, 3);
+    assert_eq!(counters.copied_strings, 2);
+    let events = counters.events;
+    assert_eq!((events.start_map, events.end_map, events.start_array, events.end_array), (1, 1, 1, 1));
+    assert_eq!((events.key, events.string, events.number, events.null, events.boolean), (2, 3, 1, 1, 1));
+
+    let later = counters.since(&middle.unwrap());
+    assert_eq!(later.events.key, 1);
+    assert_eq!(later.events.number, 0);
+}
+
+#[test]
+fn repair() {
+    fn repaired(data: &[u8]) -> (String, bool) {
+        let mut parser = Parser::with_capacity(data, 4);
+        parser.set_repair(true);
+        let mut out = vec![];
+        let mut writer = Writer::new(&mut out);
+        while let Some(event) = parser.next() {
+            writer.write_event(event.unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+        (String::from_utf8(out).unwrap(), parser.repaired())
+    }
+
+    assert_eq!(repaired(b"[1, 2]"), ("[1,2]".to_string(), false));
+    assert_eq!(repaired(b"[1, [2, {\"a\": 3,"), ("[1,[2,{\"a\":3}]]".to_string(), true));
+    assert_eq!(repaired(b"{\"a\": {\"b\""), ("{\"a\":{\"b\":null}}".to_string(), true));
+    assert_eq!(repaired(b"{\"a\": [\"long stri"), ("{\"a\":[\"long stri\"]}".to_string(), true));
+    assert_eq!(repaired("[\"caf\u{e9}".as_bytes()[..6].as_ref()), ("[\"caf\"]".to_string(), true));
+    assert_eq!(repaired(b"[true, fal"), ("[true]".to_string(), true));
+    assert_eq!(repaired(b"[\"ab\\"), ("[\"ab\"]".to_string(), true));
+    assert_eq!(repaired(b"[\"ab\\u00"), ("[\"ab\"]".to_string(), true));
+    assert_eq!(repaired(b"{\"a\": \"ab\\ud83d\\"), ("{\"a\":\"ab\"}".to_string(), true));
+
+    let mut parser = Parser::with_capacity(&b"[\"long string in chunks"[..], 4);
+    parser.set_repair(true);
+    parser.set_string_chunk_size(5);
+    let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert_eq!(events.last(), Some(&Event::EndArray));
+    assert!(events.contains(&Event::StringEnd));
+    let mut parser = Parser::with_capacity(&b"[\"abcdefg\\u00"[..], 4);
+    parser.set_repair(true);
+    parser.set_string_chunk_size(5);
+    let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert_eq!(events[1..4], [Event::StringChunk("abcde".to_string()), Event::StringChunk("fg".to_string()), Event::StringEnd]);
+    let mut parser = Parser::new(&b"[\"\\ud83d\"]"[..]);
+    parser.set_repair(true);
+    assert!(matches!(parser.into_iter().last(), Some(Err(Error::LoneSurrogate(..)))));
+
+    let parser = Parser::new(&b"[1, 2"[..]);
+    assert!(parser.into_iter().last().unwrap().is_err());
+}
+
+#[test]
+fn skip_errors() {
+    let data = b"{\"a\": 1}\n{\"a\": 2\n[1, tru\n{\"a\": ]}\n\n\"five\"\n[6,\n7] 8\n9";
+    let mut parser = Parser::new_multi(&data[..]);
+    parser.set_on_error(OnError::Skip);
+    let results: Vec<_> = parser.into_iter().items("").map(|r| r.map(|v| v.to_string()).map_err(|e| e.position().line)).collect();
+    assert_eq!(results, vec![
+        Ok(r#"{"a":1}"#.to_string()),
+        Err(2),
+        Err(3),
+        Err(4),
+        Ok(r#""five""#.to_string()),
+        Err(7),
+        Ok("7".to_string()),
+        Err(8),
+        Ok("9".to_string()),
+    ]);
+
+    // A string running into the next line doesn't take it along
+    for data in [&b"{\"a\": \"x\n{\"b\": 1}\n{\"c\": 2}\n"[..], b"[\"\x01 \n{\"b\": 1}\n{\"c\": 2}", b"\"x\n{\"b\": 1}\n{\"c\": 2}"].iter() {
+        let mut parser = Parser::new_multi(&data[..]);
+        parser.set_on_error(OnError::Skip);
+        let results: Vec<_> = parser.into_iter().items("").map(|r| r.map(|v| v.to_string()).map_err(|e| e.kind())).collect();
+        assert_eq!(results, vec![
+            Err(ErrorKind::ControlCharacter),
+            Ok(r#"{"b":1}"#.to_string()),
+            Ok(r#"{"c":2}"#.to_string()),
+        ]);
+    }
+    let mut parser = Parser::new_multi(&b"\"x\n\"\n1"[..]);
+    parser.set_on_error(OnError::Skip);
+    parser.set_control_characters(true);
+    let results: Vec<_> = parser.into_iter().items("").map(|r| r.map(|v| v.to_string()).map_err(|e| e.kind())).collect();
+    assert_eq!(results, vec![Err(ErrorKind::ControlCharacter), Err(ErrorKind::ControlCharacter), Ok("1".to_string())]);
+
+    let mut parser = Parser::builder().multiple(true).on_error(OnError::Skip).build(&b"[1\n2"[..]);
+    parser.set_cancel_flag(std::sync::Arc::new(true.into()));
+    assert!(parser.next().unwrap().is_err());
+    assert!(parser.next().is_none());
+}
+
+#[test]
+fn fused() {
+    use crate::iter::Fused;
+
+    let results = vec![Ok(1), Err(Error::Unexpected(Default::default(), Position::default())), Ok(2)];
+    let mut fused = Fused::new(results.into_iter());
+    assert_eq!(fused.next().unwrap().unwrap(), 1);
+    assert!(fused.next().unwrap().is_err());
+    assert!(fused.is_done());
+    assert!(fused.next().is_none());
+    assert!(fused.take_error().is_none());
+
+    let results = vec![Ok(1), Err(Error::Unexpected(Default::default(), Position::default())), Ok(2)];
+    let mut fused = Fused::new(results.into_iter());
+    fused.set_silent(true);
+    assert_eq!(fused.by_ref().map(Result::unwrap).collect::<Vec<_>>(), vec![1]);
+    assert!(matches!(fused.take_error(), Some(Error::Unexpected(..))));
+
+    // Prefix and Items end for good at the first error of a parser
+    let mut events = Parser::new(&b"[1, x, 3]"[..]).into_iter().prefix("item");
+    assert_eq!(events.next().unwrap().unwrap(), Event::Int(1));
+    assert!(events.next().unwrap().is_err());
+    assert!(events.next().is_none());
+    assert!(events.next().is_none());
+    let mut items = Parser::new(&b"[1, x, 3]"[..]).into_iter().items("item");
+    assert_eq!(items.next().unwrap().unwrap(), Json::I64(1));
+    assert!(items.next().unwrap().is_err());
+    assert!(items.next().is_none());
+    assert!(items.next().is_none());
+
+    // Once they've ended they don't go on with the events after it
+    let events = vec![Event::Int(1), Event::EndArray, Event::Int(2)];
+    let mut items = crate::builder::Items::new(events.into_iter().map(Ok));
+    assert_eq!(items.next().unwrap().unwrap(), Json::I64(1));
+    assert!(items.next().is_none());
+    assert!(items.next().is_none());
+}
+
+#[test]
+fn error_data() {
+    fn error(data: &[u8]) -> Error {
+        let mut parser = Parser::new(data);
+        parser.set_error_context(true);
+        let error = parser.into_iter().find_map(Result::err).unwrap();
+        assert_eq!(error.kind(), match error { Error::Context(ref e, _) => e.kind(), _ => unreachable!() });
+        error
+    }
+
+    let e = error(b"[1 2]");
+    assert_eq!(e.kind(), ErrorKind::Unexpected);
+    assert_eq!(e.expected(), Some(Expected::COMMA | Expected::END_ARRAY));
+    assert_eq!(e.expected().unwrap().to_string(), "`,` or `]`");
+    assert_eq!(error(b"{\"a\" 1}").expected(), Some(Expected::COLON));
+    assert_eq!(error(b"{\"a\": 1, ]").expected(), Some(Expected::KEY));
+    assert_eq!((Expected::VALUE | Expected::KEY | Expected::END_MAP).to_string(), "a value, a key or `}`");
+
+    let e = error(b"[nul]");
+    assert_eq!(e.kind(), ErrorKind::Unknown);
+    assert_eq!(e.expected(), None);
+    assert!(e.offending_bytes().is_some());
+    assert_eq!(error(b"[1.]").kind(), ErrorKind::Number(NumberError::NoFraction));
+    assert_eq!(error(b"[\"\x01\"]").offending_bytes(), Some(&[1][..]));
+    assert_eq!(Error::Unknown(b"nul".to_vec(), Position::default()).to_string(), "Unknown lexeme: 'nul' at line 1, column 1");
+
+    // Prefixes add the path of the value
+    let error = Parser::new(&br#"{"docs": [{"id": 1}, {"id" 2}]}"#[..]).into_iter().items("docs.item").find_map(Result::err).unwrap();
+    assert_eq!(error.kind(), ErrorKind::Unexpected);
+    assert_eq!(error.path(), Some("docs.1.id"));
+    assert_eq!(error.to_string(), "Unexpected lexeme, expected `:` at line 1, column 28 in docs.1.id");
+    let error = Parser::new(&b"[[1, 2], {]"[..]).into_iter().prefix("item").find_map(Result::err).unwrap();
+    assert_eq!(error.path(), Some("1"));
+}
+
+#[test]
+fn render() {
+    let mut parser = Parser::new(&b"{\"key\": \"value\",\n  \"other\" 1 }"[..]);
+    parser.set_error_context(true);
+    let error = parser.into_iter().find_map(Result::err).unwrap();
+    assert_eq!(error.render("data.json"), [
+        "data.json:2:11: Unexpected lexeme, expected `:`",
+        "    …ey\": \"value\",   \"other\" 1 }",
+        "                             ^",
+    ].join("\n"));
+    assert_eq!(Error::Incomplete(Position { line: 2, column: 3, offset: 5 }).render("-"), "-:2:3: Incomplete document");
+}
+
+#[test]
+fn fast_parser() {
+    let data = std::fs::read("test.json").unwrap();
+    let events: Vec<_> = Parser::fast(&data).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, reference_events());
+
+    // Escapes and strings crossing blocks of the index
+    let mut data = String::from("[");
+    for i in 0..200 {
+        let pad = " ".repeat(i % 67);
+        data.push_str(&format!(r#"{}{{"k\\{}": "a\"{}\\", "u": "\ud83d\ude00\u00e9", "n": [-1.5e3, {}, true]}},"#, pad, i, r"\\".repeat(i % 5), i));
+    }
+    data.push_str("null]");
+    let expected: Vec<_> = Parser::from_slice(data.as_bytes()).into_iter().map(Result::unwrap).collect();
+    let events: Vec<_> = Parser::fast(data.as_bytes()).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, expected);
+
+    for data in [
+        &b"[1, 2"[..], b"[1 2]", b"{\"a\" 1}", b"{\"a\": 1,}", b"[1}", b"1 2", b"[\"a]",
+        b"[01]", b"[\"\\x\"]", b"[\"\\ud800\"]", b"[\"\x01\"]", b"[\"\xff\"]", b"{1: 2}", b"",
+    ].iter() {
+        let expected = Parser::from_slice(data).into_iter().find_map(Result::err).unwrap();
+        let error = Parser::fast(data).into_iter().find_map(Result::err).unwrap();
+        assert_eq!(error.kind(), expected.kind(), "{:?}", String::from_utf8_lossy(data));
+        assert_eq!(error.expected(), expected.expected());
+    }
+    assert!(matches!(Parser::fast(b"[nul]").into_iter().last(), Some(Err(Error::Unknown(..)))));
+
+    // Positions are found without counting lines from the start for every
+    // event, which would take minutes here
+    let mut data = String::from("[\n");
+    for i in 0..100_000 {
+        data.push_str(&format!("  {{\"id\": {}, \"name\": \"x\"}},\n", i));
+    }
+    data.push_str("  [1}]");
+    let expected = Parser::from_slice(data.as_bytes()).into_iter().find_map(Result::err).unwrap();
+    let error = Parser::fast(data.as_bytes()).into_iter().find_map(Result::err).unwrap();
+    assert_eq!(error.position(), expected.position());
+    assert_eq!(error.position().line, 100_002);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap() {
+    let map = || unsafe { memmap2::Mmap::map(&File::open("test.json").unwrap()).unwrap() };
+    let events: Vec<_> = Parser::from_mmap(map()).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, reference_events());
+
+    let mut parser = Parser::from_mmap(map());
+    parser.next();
+    parser.next();
+    assert_eq!(parser.counters().borrowed_strings, 1);
+}
+
+#[test]
+fn path_key_buffers() {
+    // Keys of the path are reused across members of different lengths
+    let data = br#"{"a": {"long key": 1, "k": {"x": 2}, "longer key": [3]}, "b": 4}"#;
+    let values: Vec<_> = Parser::from_slice(&data[..]).into_iter().multi_prefix(vec!["a.k.x", "a.longer key.item", "b"])
+        .map(|r| r.unwrap().1).collect();
+    assert_eq!(values, vec![Event::Int(2), Event::Int(3), Event::Int(4)]);
+}
+
+#[test]
+fn fuzz_regressions() {
+    let targets = [
+        ("lexer", crate::fuzz::lexer as fn(&[u8])),
+        ("parser", crate::fuzz::parser),
+        ("items", crate::fuzz::items),
+        ("structured", crate::fuzz::structured),
+    ];
+    #[cfg(feature = "bson")]
+    let targets = [&targets[..], &[("bson", crate::fuzz::bson as fn(&[u8]))]].concat();
+    for (name, target) in targets.iter() {
+        for entry in std::fs::read_dir(format!("fuzz/regressions/{}", name)).unwrap() {
+            let path = entry.unwrap().path();
+            let data = std::fs::read(&path).unwrap();
+            if std::panic::catch_unwind(|| target(&data)).is_err() {
+                panic!("{} fails on {}", name, path.display());
+            }
+        }
+    }
+
+    // A quick run of the generator on pseudo-random input
+    let mut seed = 1u64;
+    for len in 0..500 {
+        let data: Vec<u8> = (0..len % 97).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 56) as u8
+        }).collect();
+        crate::fuzz::structured(&data);
+        crate::fuzz::parser(&data);
+        crate::fuzz::items(&data);
+        crate::fuzz::lexer(&data);
+    }
+}
+
+/// Cases of JSONTestSuite by Nicolas Seriot: `y_` ones have to be accepted
+/// and `n_` ones rejected.
+const CONFORMANCE: &[(bool, &[u8])] = &[
+    (true, b"[[]   ]"), (true, b"[\"\"]"), (true, b"[null, 1, \"1\", {}]"), (true, b" [1]"),
+    (true, b"[1\n]"), (true, b"[123e65]"), (true, b"[0e+1]"), (true, b"[0e1]"), (true, b"[-0]"),
+    (true, b"[-0.000000000000000000000000000000000000000000000000000000000000000000000000000000001]"),
+    (true, b"[1E22]"), (true, b"[1E-2]"), (true, b"[1E+2]"), (true, b"[123.456e78]"), (true, b"[1e-2]"),
+    (true, b"[100000000000000000000]"), (true, b"[-9223372036854775809]"), (true, b"[1.5e999]"),
+    (true, b"{\"a\":\"b\",\"a\":\"c\"}"), (true, b"{\"\":0}"), (true, b"{\"foo\\u0000bar\": 42}"),
+    (true, b"[\"\\\"\\\\\\/\\b\\f\\n\\r\\t\"]"), (true, b"[\"\\/\"]"), (true, b"[\"\\u0000\"]"),
+    (true, b"[\"\\uD801\\udc37\"]"), (true, b"[\"\\ud83d\\ude39\\ud83d\\udc8d\"]"), (true, b"[\"\\uDBFF\\uDFFF\"]"),
+    (true, b"[\"\\uFFFF\"]"), (true, b"[\"\\u0060\\u012a\\u12AB\"]"), (true, b"[\"\xe2\x80\xa8\"]"),
+    (true, b"[\"\x7f\"]"), (true, b"[\"\xf4\x8f\xbf\xbf\"]"), (true, b"[\"a\\u0000b\"]"),
+    (true, b"false"), (true, b"42"), (true, b"-0.1"), (true, b"null"), (true, b"\"asd\""), (true, b" [] "),
+    (true, b"\xef\xbb\xbf{}"), (true, b"{\"a\":[]}\n"), (true, b"[1,null,null,null,2]"),
+    (false, b"[1 true]"), (false, b"[\"\": 1]"), (false, b"[\"\"],"), (false, b"[,1]"), (false, b"[1,,2]"),
+    (false, b"[\"x\"]]"), (false, b"[\"\",]"), (false, b"[\"x\""), (false, b"[3[4]]"), (false, b"[1:2]"),
+    (false, b"[-]"), (false, b"[1,]"), (false, b"[*]"), (false, b"[\"\\va\"\\f]"), (false, b"[++1234]"),
+    (false, b"[+1]"), (false, b"[+Inf]"), (false, b"[-01]"), (false, b"[-1.0.]"), (false, b"[-2.]"),
+    (false, b"[-NaN]"), (false, b"[.-1]"), (false, b"[.2e-3]"), (false, b"[0.1.2]"), (false, b"[0.3e+]"),
+    (false, b"[0.e1]"), (false, b"[0E]"), (false, b"[1.0e-]"), (false, b"[1 000.0]"), (false, b"[1eE2]"),
+    (false, b"[2.e3]"), (false, b"[Inf]"), (false, b"[NaN]"), (false, b"[Infinity]"), (false, b"[0x1]"),
+    (false, b"[012]"), (false, b"[-012]"), (false, b"[1.]"), (false, b"[.123]"), (false, b"[1ea]"),
+    (false, b"[-123.123foo]"), (false, b"[0\xc3\xa9]"), (false, b"[\xef\xbc\x91]"), (false, b"[- 1]"),
+    (false, b"{\"x\", null}"), (false, b"{\"x\"::\"b\"}"), (false, b"{\"a\":\"a\" 123}"), (false, b"{key: 'value'}"),
+    (false, b"{:\"b\"}"), (false, b"{\"a\" \"b\"}"), (false, b"{1:1}"), (false, b"{null:null}"), (false, b"{'a':0}"),
+    (false, b"{\"id\":0,}"), (false, b"{\"a\":\"b\"}/**/"), (false, b"{\"a\":\"b\"}//"), (false, b"{\"a\":\"b\"}#"),
+    (false, b"{\"a\":\"b\",,\"c\":\"d\"}"), (false, b"{\"a\":"), (false, b"{\"a\""), (false, b"{"),
+    (false, b"[\"\\uD800\\\"]"), (false, b"[\"\\uD800\\u\"]"), (false, b"[\"\\uD800\\u1\"]"),
+    (false, b"[\"\\uD800\\u1x\"]"), (false, b"[\"\\x00\"]"), (false, b"[\"\\\\\\\"]"), (false, b"[\"\\\t\"]"),
+    (false, b"[\"\\\xf0\x9f\x8c\x80\"]"), (false, b"[\"\\\"]"), (false, b"[\"\\u00A\"]"), (false, b"[\"\\uD834\\uDd\"]"),
+    (false, b"[\"\\a\"]"), (false, b"[\"\\uqqqq\"]"), (false, b"[\"\\UA66D\"]"), (false, b"[\"a\x00\"]"),
+    (false, b"[\"a\x1f\"]"), (false, b"[\"new\nline\"]"), (false, b"[\"\t\"]"), (false, b"['single quote']"),
+    (false, b"abc"), (false, b"\"a"), (false, b"[tru]"), (false, b"[nul]"), (false, b"[True]"), (false, b"[nulll]"),
+    (false, b"[truth]"), (false, b"<.>"), (false, b"[1]x"), (false, b"[1]]"), (false, b"1]"), (false, b"[][]"),
+    (false, b"]"), (false, b"["), (false, b""), (false, b" "), (false, b"\xef\xbb\xbf"), (false, b"[1]\x00"),
+    (false, b"[\x0c]"), (false, b"[\xe2\x80\x8b]"), (false, b"2@"), (false, b"}"), (false, b"{}}"), (false, b"[{]"),
+    (false, b"[\"\xff\"]"), (false, b"[\"\xed\xa0\x80\"]"), (false, b"[\"\xc0\xaf\"]"), (false, b"\"\\u00"),
+    (false, b"[\"\\uDFAA\"]"), (false, b"[\"\\uD888\\u1234\"]"), (false, b"[\"\\uDd1e\\uD834\"]"), (false, b"[\"\\uD800\"]"),
+];
+
+#[test]
+fn conformance() {
+    for &(valid, data) in CONFORMANCE.iter() {
+        let parser = Parser::builder().strict().build(Cursor::new(data));
+        let accepted = parser.into_iter().all(|r| r.is_ok());
+        assert_eq!(accepted, valid, "{:?}", String::from_utf8_lossy(data));
+    }
+
+    let mut parser = Parser::from_slice(b"[1, 2,]");
+    parser.set_trailing_commas(true);
+    parser.set_strict();
+    assert!(parser.into_iter().any(|r| r.is_err()));
+
+    // The whole suite, from test_parsing/ of a checkout of
+    // https://github.com/nst/JSONTestSuite
+    let dir = match std::env::var("JSON_TEST_SUITE") {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let mut failed = vec![];
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let valid = match &name[..2] {
+            "y_" => true,
+            "n_" => false,
+            _ => continue,
+        };
+        let data = std::fs::read(&path).unwrap();
+        let parser = Parser::builder().strict().build(Cursor::new(data));
+        if parser.into_iter().all(|r| r.is_ok()) != valid {
+            failed.push(name);
+        }
+    }
+    assert!(failed.is_empty(), "{:?}", failed);
+}
+
+/// Returns input in pieces of the given sizes in turn, to have tokens
+/// straddle the boundaries of reads.
+struct Pieces {
+    data: Vec<u8>,
+    pos: usize,
+    sizes: Vec<usize>,
+    read: usize,
+}
+
+impl std::io::Read for Pieces {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.sizes[self.read % self.sizes.len()].min(buf.len()).min(self.data.len() - self.pos);
+        self.read += 1;
+        buf[..size].copy_from_slice(&self.data[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+/// Events of the whole input and the error it ends with, as text to
+/// compare positions as well.
+fn outcome<I: Iterator<Item=Result<Event, Error>>>(events: I) -> (Vec<Event>, Option<String>) {
+    let mut result = vec![];
+    for event in events {
+        match event {
+            Ok(event) => result.push(event),
+            Err(e) => return (result, Some(format!("{:?}", e))),
+        }
+    }
+    (result, None)
+}
+
+#[test]
+fn buffer_boundaries() {
+    let mut fixtures = vec![std::fs::read("test.json").unwrap(), std::fs::read("people.json").unwrap()];
+    fixtures.extend(CONFORMANCE.iter().map(|(_, data)| data.to_vec()));
+    fixtures.extend([
+        &br#"[-12.5e+3, 1e2, 0, -0.0, 18446744073709551615, true, false, null]"#[..],
+        r#"{"é😀\n\"": "тест тест 😀"}"#.as_bytes(),
+        b"[1.5e, tru, 2]", b"[\"\\ud83d\\u\"]", b"[\"caf\xc3\"]", b"[12", b"[\"abc",
+    ].iter().map(|data| data.to_vec()));
+    fixtures.push(Generator::new(1).bytes());
+
+    let mut seed = 7u64;
+    let mut random = move |limit: usize| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % limit + 1
+    };
+    for data in fixtures {
+        let expected = outcome(Parser::new(Cursor::new(&data)).into_iter());
+        let mut splits: Vec<Vec<usize>> = (1..=8).map(|size| vec![size]).collect();
+        splits.extend((0..8).map(|_| (0..16).map(|_| random(9)).collect()));
+        for sizes in splits {
+            for &capacity in [3, 4, 5, 64].iter() {
+                let reader = Pieces { data: data.clone(), pos: 0, sizes: sizes.clone(), read: 0 };
+                let result = outcome(Parser::with_capacity(reader, capacity).into_iter());
+                assert_eq!(result, expected, "{:?} in pieces of {:?} into {}", String::from_utf8_lossy(&data), sizes, capacity);
+            }
+        }
+    }
+}
+
+#[test]
+fn generator() {
+    let document = |seed| {
+        let mut generator = Generator::new(seed);
+        generator.set_size(100_000);
+        generator.set_max_depth(3);
+        generator.bytes()
+    };
+    let text = document(1);
+    assert_eq!(text, document(1));
+    assert_ne!(text, document(2));
+    assert!(text.len() > 90_000 && text.len() < 110_000, "{}", text.len());
+
+    let mut depth = 0;
+    let mut max_depth = 0;
+    for event in Parser::from_slice(&text).into_iter() {
+        match event.unwrap() {
+            Event::StartArray | Event::StartMap => depth += 1,
+            Event::EndArray | Event::EndMap => depth -= 1,
+            _ => (),
+        }
+        max_depth = max_depth.max(depth);
+    }
+    assert_eq!(max_depth, 3);
+
+    let mut generator = Generator::new(3);
+    generator.set_escapes(0.0);
+    assert!(!generator.bytes().contains(&b'\\'));
+    let mut generator = Generator::new(3);
+    generator.set_escapes(1.0);
+    generator.set_max_string(4);
+    assert!(generator.bytes().contains(&b'\\'));
+}
+
+#[test]
+fn compare() {
+    let events = |data: &'static str| Parser::from_slice(data.as_bytes()).into_iter();
+    let mut compare = Compare::new();
+    let left = r#"{"a": [1, "x", {"b": 1.0}], "c": null}"#;
+    assert_eq!(compare.events(events(left), events(left)).unwrap(), None);
+    // Numbers by value, strings in chunks
+    let mut parser = Parser::from_slice(br#"{"a": [1.0, "x", {"b": 1}], "c": null}"#);
+    parser.set_string_chunk_size(1);
+    assert_eq!(compare.events(events(left), parser.into_iter()).unwrap(), None);
+
+    let reordered = r#"{"c": null, "a": [1, "x", {"b": 1}]}"#;
+    let difference = compare.events(events(left), events(reordered)).unwrap().unwrap();
+    assert_eq!(difference.path, "");
+    compare.set_ignore_key_order(true);
+    assert_eq!(compare.events(events(left), events(reordered)).unwrap(), None);
+
+    let difference = compare.events(events(left), events(r#"{"a": [1, "x", {"b": 2}], "c": null}"#)).unwrap().unwrap();
+    assert_eq!((&difference.path[..], difference.left.as_deref(), difference.right.as_deref()), ("a.2.b", Some("1.0"), Some("2")));
+    let difference = compare.events(events(left), events(r#"{"a": [1, "x"], "c": null, "d": 0}"#)).unwrap().unwrap();
+    assert_eq!((&difference.path[..], difference.right), ("a.2", None));
+    let difference = compare.events(events(left), events(r#"{"a": [1, "x", {"b": 1}], "c": null, "d": 0}"#)).unwrap().unwrap();
+    assert_eq!((&difference.path[..], difference.left.as_deref(), difference.right.as_deref()), ("d", None, Some("0")));
+    assert_eq!(difference.to_string(), "Document 0 differs at d: nothing vs 0");
+
+    let multi = |data: &'static str| Parser::new_multi(data.as_bytes()).into_iter();
+    let difference = compare.events(multi("1 2 3"), multi("1 2")).unwrap().unwrap();
+    assert_eq!((difference.document, difference.right), (2, None));
+    assert!(compare.events(events("[1"), events("[1]")).is_err());
+
+    let value = Json::from_str(left).unwrap();
+    assert_eq!(compare.value(events(left), &value).unwrap(), None);
+
+    // Deep documents don't take the call stack
+    let deep = |last: &str| {
+        let data = format!("{}{}{}", "[".repeat(8000), last, "]".repeat(8000)).into_bytes();
+        Parser::builder().max_depth(usize::MAX).build(Cursor::new(data)).into_iter()
+    };
+    let difference = compare.events(deep("1"), deep("2")).unwrap().unwrap();
+    assert_eq!(difference.path, vec!["0"; 8000].join("."));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_serializer() {
+    use std::collections::BTreeMap;
+    use serde::{Deserialize, Serialize};
+    use crate::de::Deserializer;
+    use crate::ser::{to_events, to_handler, to_writer};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    enum Shape {
+        Point,
+        Circle { radius: f64 },
+        Line(i32, i32),
+        Named(String),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Doc {
+        id: u64,
+        name: Option<String>,
+        shapes: Vec<Shape>,
+        tags: BTreeMap<u32, bool>,
+        big: i128,
+    }
+
+    let doc = Doc {
+        id: u64::MAX,
+        name: None,
+        shapes: vec![Shape::Point, Shape::Circle { radius: 0.5 }, Shape::Line(1, -2), Shape::Named("é\"".into())],
+        tags: vec![(1, true), (20, false)].into_iter().collect(),
+        big: -(1 << 70),
+    };
+    let mut writer = Writer::new(vec![]);
+    to_writer(&doc, &mut writer).unwrap();
+    let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+    let mut expected = serde_json::to_string(&doc.shapes).unwrap();
+    expected = format!(r#"{{"id":18446744073709551615,"name":null,"shapes":{},"tags":{{"1":true,"20":false}},"big":-1180591620717411303424}}"#, expected);
+    assert_eq!(text, expected);
+
+    let shapes = Vec::<Shape>::deserialize(&mut Deserializer::from_events(to_events(doc.shapes.clone()))).unwrap();
+    assert_eq!(shapes, doc.shapes);
+    let items: Vec<_> = to_events(doc.clone()).items("shapes.item").map(Result::unwrap).collect();
+    assert_eq!(items[3], Json::from_str(r#"{"Named": "é\""}"#).unwrap());
+
+    let key = BTreeMap::from([(vec![1], 1)]);
+    assert!(to_events(key).last().unwrap().is_err());
+
+    // Events come as the value is serialized, so it doesn't have to end
+    struct Endless;
+    impl Serialize for Endless {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeSeq;
+            let mut seq = serializer.serialize_seq(None)?;
+            loop {
+                seq.serialize_element(&1)?;
+            }
+        }
+    }
+    let events: Vec<_> = to_events(Endless).take(1000).map(Result::unwrap).collect();
+    assert_eq!((&events[0], &events[999]), (&Event::StartArray, &Event::Int(1)));
+
+    let mut writer = Writer::new(vec![]);
+    to_writer(&[0.1f32, 1e30, f32::MAX], &mut writer).unwrap();
+    assert_eq!(writer.finish().unwrap(), b"[0.1,1e30,3.4028235e38]".to_vec());
+
+    struct Keys(usize);
+    impl Handler for Keys {
+        fn key(&mut self, _key: &str) -> ControlFlow<()> {
+            self.0 += 1;
+            if self.0 == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        }
+    }
+    assert!(!to_handler(&doc, &mut Keys(0)).unwrap());
+    assert!(to_handler(&doc.shapes[..2], &mut Keys(0)).unwrap());
+}
+
+#[test]
+fn emit() {
+    let events = Emit::map()
+        .key("a").number(1u8)
+        .key("b").start_array().number(u64::MAX).number(0.5).boolean(true).end()
+        .key("c").start_map().end()
+        .end()
+        .events().unwrap();
+    assert_eq!(events, vec![
+        Event::StartMap,
+        Event::Key("a".into()), Event::Int(1),
+        Event::Key("b".into()), Event::StartArray, Event::Uint(u64::MAX), Event::Number(0.5), Event::Boolean(true), Event::EndArray,
+        Event::Key("c".into()), Event::StartMap, Event::EndMap,
+        Event::EndMap,
+    ]);
+    assert_eq!(Emit::new().string("x").events().unwrap(), vec![Event::String("x".into())]);
+
+    // A value where a key goes, which also stops the events after it
+    let wrong = Emit::map().number(1).key("a").null().end();
+    assert_eq!(wrong.events().unwrap_err().kind(), ErrorKind::UnexpectedEvent);
+    assert_eq!(Emit::map().key("a").end().events().unwrap_err().kind(), ErrorKind::UnexpectedEvent);
+    assert!(Emit::array().number(f64::NAN).events().is_err());
+    assert_eq!(Emit::array().null().events().unwrap_err().kind(), ErrorKind::Incomplete);
+    assert_eq!(Emit::new().events().unwrap_err().kind(), ErrorKind::Incomplete);
+
+    let results: Vec<_> = Emit::array().null().into_iter().collect();
+    assert_eq!(results.len(), 3);
+    assert!(results[2].is_err());
+}
+
+#[test]
+fn canonical() {
+    let canonical = |data: &[u8]| -> Result<String, Error> {
+        let mut writer = Writer::canonical(vec![]);
+        for event in Parser::from_slice(data) {
+            writer.write_event(event?.as_event())?;
+        }
+        Ok(String::from_utf8(writer.finish()?).unwrap())
+    };
+
+    // Examples from RFC 8785
+    let data = br#"{
+        "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+        "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+        "literals": [null, true, false]
+    }"#;
+    assert_eq!(canonical(data).unwrap(), r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#);
+    let data = br#"{"\u20ac": 1, "\r": 2, "\ufb33": 3, "1": 4, "\ud83d\ude00": 5, "\u0080": 6, "\u00f6": 7}"#;
+    assert_eq!(canonical(data).unwrap(), "{\"\\r\":2,\"1\":4,\"\u{80}\":6,\"ö\":7,\"€\":1,\"😀\":5,\"\u{fb33}\":3}");
+    let numbers = [
+        (0.0, "0"), (-0.0, "0"), (5e-324, "5e-324"), (-5e-324, "-5e-324"),
+        (f64::MAX, "1.7976931348623157e+308"), (9007199254740992.0, "9007199254740992"),
+        (295147905179352830000.0, "295147905179352830000"), (9.999999999999997e22, "9.999999999999997e+22"),
+        (1e23, "1e+23"), (1e21, "1e+21"), (1e-6, "0.000001"), (9.999999999999997e-7, "9.999999999999997e-7"),
+        (333333333.3333333, "333333333.3333333"), (-1.5, "-1.5"), (100.0, "100"),
+    ];
+    for &(number, expected) in numbers.iter() {
+        let mut writer = Writer::canonical(vec![]);
+        writer.write_event(BorrowedEvent::Number(number)).unwrap();
+        assert_eq!(writer.finish().unwrap(), expected.as_bytes(), "{}", number);
+    }
+
+    // Nested objects are sorted on their own, arrays keep their order
+    let data = br#"[{"b": {"z": [3, {"y": 1, "x": 2}], "a": null}, "a": "x"}, {"c": 1.0}]"#;
+    assert_eq!(canonical(data).unwrap(), r#"[{"a":"x","b":{"a":null,"z":[3,{"x":2,"y":1}]}},{"c":1}]"#);
+    let mut parser = Parser::from_slice(&br#"{"b": "long string", "a": 1}"#[..]);
+    parser.set_string_chunk_size(4);
+    let mut writer = Writer::canonical(vec![]);
+    for event in parser {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), br#"{"a":1,"b":"long string"}"#.to_vec());
+
+    let mut writer = Writer::canonical(vec![]);
+    writer.set_non_finite(true);
+    assert!(writer.write_event(BorrowedEvent::Number(f64::NAN)).is_err());
+    assert!(Writer::canonical(vec![]).write_event(BorrowedEvent::Bytes(b"\xff")).is_err());
+    assert!(Writer::canonical(vec![]).write_event(BorrowedEvent::RawNumber("0x10")).is_err());
+}
+
+#[test]
+fn sort_keys() {
+    let write = |data: &[u8], format: Format| {
+        let mut writer = Writer::with_format(vec![], format);
+        writer.set_sort_keys(true);
+        for event in Parser::from_slice(data) {
+            writer.write_event(event.unwrap().as_event()).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    };
+    let data = r#"[{"b": [{"d": 1, "c": 2}], "a": 1.50, "a": "again"}, {}, {"😀": 1, "דּ": 2}]"#.as_bytes();
+    assert_eq!(write(data, Format::Compact), r#"[{"a":1.5,"a":"again","b":[{"c":2,"d":1}]},{},{"דּ":2,"😀":1}]"#);
+    assert_eq!(write(data, Format::ArrayLines), "[\n{\"a\":1.5,\"a\":\"again\",\"b\":[\n{\"c\":2,\"d\":1}\n]},\n{},\n{\"דּ\":2,\"😀\":1}\n]");
+    assert_eq!(write(br#"{"z": {}, "y": {"x": []}}"#, Format::Indented(" ".to_string())), "{\n \"y\": {\n  \"x\": []\n },\n \"z\": {}\n}");
+
+    let mut writer = Writer::new(vec![]);
+    writer.set_sort_keys(true);
+    let mut tee = Parser::new_multi(&br#"{"b": 1, "a": 2} {"d": 3, "c": 4}"#[..]).into_iter().tee(writer);
+    assert_eq!((&mut tee).count(), 12);
+    assert_eq!(tee.finish().unwrap(), b"{\"a\":2,\"b\":1}\n{\"c\":4,\"d\":3}".to_vec());
+
+    // Arrays outside of objects are written out as they go
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let out = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let mut writer = Writer::new(Shared(out.clone()));
+    writer.set_sort_keys(true);
+    let mut written = vec![];
+    for event in Parser::from_slice(br#"[[1, {"b": [2], "a": 3}], 4]"#) {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+        written.push(out.borrow().len());
+    }
+    assert_eq!(written, vec![1, 2, 3, 5, 5, 5, 5, 5, 5, 5, 19, 20, 22, 23]);
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor() {
+    use crate::cbor::{CborReader, CborWriter};
+
+    let read = |data: &[u8]| CborReader::new(data).collect::<Result<Vec<_>, _>>();
+    let value = |data: &[u8]| CborReader::new(data).items("").next().unwrap().unwrap().to_string();
+
+    // Examples from RFC 8949
+    assert_eq!(read(b"\x1b\xff\xff\xff\xff\xff\xff\xff\xff").unwrap(), vec![Event::Uint(u64::MAX)]);
+    assert_eq!(read(b"\x3b\xff\xff\xff\xff\xff\xff\xff\xff").unwrap(), vec![Event::RawNumber("-18446744073709551616".into())]);
+    assert_eq!(read(b"\xc2\x49\x01\x00\x00\x00\x00\x00\x00\x00\x00").unwrap(), vec![Event::RawNumber("18446744073709551616".into())]);
+    assert_eq!(read(b"\xc3\x49\x01\x00\x00\x00\x00\x00\x00\x00\x00").unwrap(), vec![Event::RawNumber("-18446744073709551617".into())]);
+    assert_eq!(read(b"\x39\x03\xe7").unwrap(), vec![Event::Int(-1000)]);
+    assert_eq!(read(b"\xf9\x00\x01").unwrap(), vec![Event::Number(5.960464477539063e-8)]);
+    assert_eq!(read(b"\xf9\x7c\x00").unwrap(), vec![Event::Number(f64::INFINITY)]);
+    assert_eq!(read(b"\xfb\x3f\xf1\x99\x99\x99\x99\x99\x9a").unwrap(), vec![Event::Number(1.1)]);
+    assert_eq!(read(b"\xf7").unwrap(), vec![Event::Null]);
+    assert_eq!(value(b"\xc1\x1a\x51\x4b\x67\xb0"), "1363896240");
+    assert_eq!(value(b"\x5f\x42\x01\x02\x43\x03\x04\x05\xff"), r#""AQIDBAU""#);
+    assert_eq!(read(b"\x7f\x65strea\x64ming\xff").unwrap(), vec![
+        Event::StringChunk("".into()), Event::StringChunk("strea".into()), Event::StringChunk("ming".into()), Event::StringEnd,
+    ]);
+    assert_eq!(value(b"\xbf\x61a\x01\x61b\x9f\x02\x03\xff\xff"), r#"{"a":1,"b":[2,3]}"#);
+    assert_eq!(value(b"\x82\x61a\xbf\x7f\x61b\x61c\xff\xf5\xff"), r#"["a",{"bc":true}]"#);
+    assert_eq!(value(b"\xa2\x01\x02\x20\x04"), r#"{"-1":4,"1":2}"#);
+    assert_eq!(value(b"\x83\x80\xa0\x60"), r#"[[],{},""]"#);
+
+    let kind = |data: &[u8]| read(data).unwrap_err().kind();
+    assert_eq!(kind(b""), ErrorKind::Incomplete);
+    assert_eq!(kind(b"\x82\x01"), ErrorKind::Incomplete);
+    assert_eq!(kind(b"\x63ab"), ErrorKind::Incomplete);
+    assert_eq!(kind(b"\x01\x02"), ErrorKind::AdditionalData);
+    assert_eq!(kind(b"\x82\x01\xff"), ErrorKind::Unmatched);
+    assert_eq!(kind(b"\xbf\x01\xff"), ErrorKind::Unmatched);
+    assert_eq!(kind(b"\x1c"), ErrorKind::Unknown);
+    assert_eq!(kind(b"\x62\xc3\x28"), ErrorKind::Utf8);
+    assert_eq!(kind(b"\xa1\x80\x01"), ErrorKind::Custom);
+    assert_eq!(CborReader::new_multi(&b"\x01\x82\x02\x03"[..]).count(), 5);
+    let mut reader = CborReader::new(&b"\x81\x81\x81\x01"[..]);
+    reader.set_limits(Limits { max_depth: 2, ..Limits::default() });
+    assert_eq!(reader.last().unwrap().unwrap_err().kind(), ErrorKind::DepthLimit);
+    let mut reader = CborReader::new(&b"\x7a\xff\xff\xff\xff"[..]);
+    reader.set_limits(Limits { max_string: 1000, ..Limits::default() });
+    assert_eq!(reader.last().unwrap().unwrap_err().kind(), ErrorKind::StringLimit);
+
+    // JSON to CBOR and back
+    let data = std::fs::read("test.json").unwrap();
+    let mut parser = Parser::new(&data[..]);
+    parser.set_string_chunk_size(8);
+    let mut writer = CborWriter::new(vec![]);
+    for event in parser {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    let encoded = writer.finish().unwrap();
+    let events: Vec<_> = CborReader::new(&encoded[..]).items("").map(Result::unwrap).collect();
+    let expected: Vec<_> = Parser::new(&data[..]).into_iter().items("").map(Result::unwrap).collect();
+    assert_eq!(events, expected);
+
+    let mut writer = CborWriter::new(vec![]);
+    for event in Parser::new_multi(&b"1 -18446744073709551616 1.5 \"\""[..]) {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    writer.write_event(BorrowedEvent::RawNumber("-18446744073709551616")).unwrap();
+    assert_eq!(writer.finish().unwrap(), b"\x01\xfa\xdf\x80\x00\x00\xfa\x3f\xc0\x00\x00\x60\x3b\xff\xff\xff\xff\xff\xff\xff\xff");
+    let mut writer = CborWriter::new(vec![]);
+    writer.write_event(BorrowedEvent::StartMap).unwrap();
+    assert!(writer.write_event(BorrowedEvent::Null).is_err());
+    assert!(CborWriter::new(vec![]).finish().is_err());
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack() {
+    use crate::msgpack::{MsgpackReader, MsgpackWriter};
+
+    let read = |data: &[u8]| MsgpackReader::new(data).collect::<Result<Vec<_>, _>>();
+    let value = |data: &[u8]| MsgpackReader::new(data).items("").next().unwrap().unwrap().to_string();
+
+    assert_eq!(read(b"\xcf\xff\xff\xff\xff\xff\xff\xff\xff").unwrap(), vec![Event::Uint(u64::MAX)]);
+    assert_eq!(read(b"\xd3\x80\x00\x00\x00\x00\x00\x00\x00").unwrap(), vec![Event::Int(i64::MIN)]);
+    assert_eq!(read(b"\xe0").unwrap(), vec![Event::Int(-32)]);
+    assert_eq!(read(b"\xcd\x01\x00").unwrap(), vec![Event::Int(256)]);
+    assert_eq!(read(b"\xcb\x3f\xf1\x99\x99\x99\x99\x99\x9a").unwrap(), vec![Event::Number(1.1)]);
+    assert_eq!(value(b"\x93\xc0\xc2\xc3"), "[null,false,true]");
+    assert_eq!(value(b"\x82\x01\xa1x\xd0\xff\x90"), r#"{"-1":[],"1":"x"}"#);
+    assert_eq!(value(b"\xdc\x00\x02\xd9\x02\xc3\xa9\xc5\x00\x03\x01\x02\x03"), r#"["é","AQID"]"#);
+    assert_eq!(value(b"\xde\x00\x01\xa0\x80"), r#"{"":{}}"#);
+    // Timestamps in each of their forms, and some other extension type
+    assert_eq!(read(b"\xd6\xff\x65\x53\xf1\x00").unwrap(), vec![Event::RawNumber("1700000000".into())]);
+    assert_eq!(read(b"\xd7\xff\x77\x35\x94\x00\x65\x53\xf1\x00").unwrap(), vec![Event::RawNumber("1700000000.5".into())]);
+    assert_eq!(read(b"\xc7\x0c\xff\x00\x00\x00\x01\xff\xff\xff\xff\xff\xff\xff\xff").unwrap(), vec![Event::RawNumber("-0.999999999".into())]);
+    assert_eq!(read(b"\xd4\x05\xfb").unwrap(), vec![Event::String("-w".into())]);
+
+    let kind = |data: &[u8]| read(data).unwrap_err().kind();
+    assert_eq!(kind(b""), ErrorKind::Incomplete);
+    assert_eq!(kind(b"\x92\x01"), ErrorKind::Incomplete);
+    assert_eq!(kind(b"\xdb\xff\xff\xff\xff"), ErrorKind::Incomplete);
+    assert_eq!(kind(b"\x01\x02"), ErrorKind::AdditionalData);
+    assert_eq!(kind(b"\xc1"), ErrorKind::Unknown);
+    assert_eq!(kind(b"\xa2\xc3\x28"), ErrorKind::Utf8);
+    assert_eq!(kind(b"\x81\x90\x01"), ErrorKind::Custom);
+    assert_eq!(MsgpackReader::new_multi(&b"\x01\x92\x02\x03"[..]).count(), 5);
+    let mut reader = MsgpackReader::new(&b"\x91\x91\x91\x01"[..]);
+    reader.set_limits(Limits { max_depth: 2, ..Limits::default() });
+    assert_eq!(reader.last().unwrap().unwrap_err().kind(), ErrorKind::DepthLimit);
+
+    // JSON to MessagePack and back
+    let data = std::fs::read("test.json").unwrap();
+    let mut parser = Parser::new(&data[..]);
+    parser.set_string_chunk_size(8);
+    let mut writer = MsgpackWriter::new(vec![]);
+    for event in parser {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    let encoded = writer.finish().unwrap();
+    let events: Vec<_> = MsgpackReader::new(&encoded[..]).items("").map(Result::unwrap).collect();
+    let expected: Vec<_> = Parser::new(&data[..]).into_iter().items("").map(Result::unwrap).collect();
+    assert_eq!(events, expected);
+
+    // Containers in seekable output get their sizes as they end
+    let mut writer = MsgpackWriter::seekable(Cursor::new(vec![]));
+    for event in Parser::new(&data[..]) {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    let encoded = writer.finish().unwrap().into_inner();
+    let events: Vec<_> = MsgpackReader::new(&encoded[..]).items("").map(Result::unwrap).collect();
+    assert_eq!(events, expected);
+    let mut writer = MsgpackWriter::seekable(Cursor::new(vec![]));
+    for event in Parser::new_multi(&b"{\"a\": [1, []]} 2"[..]) {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    let expected = b"\xdf\x00\x00\x00\x01\xa1a\xdd\x00\x00\x00\x02\x01\xdd\x00\x00\x00\x00\x02";
+    assert_eq!(writer.finish().unwrap().into_inner(), expected);
+
+    let mut writer = MsgpackWriter::new(vec![]);
+    let long = "x".repeat(300);
+    let numbers = format!("[-33, 128, 65536, -129, 1.5, 1.1, \"{}\"] 4294967296 -18446744073709551616", long);
+    for event in Parser::new_multi(numbers.as_bytes()) {
+        writer.write_event(event.unwrap().as_event()).unwrap();
+    }
+    let mut expected = b"\x97\xd0\xdf\xcc\x80\xce\x00\x01\x00\x00\xd1\xff\x7f\xca\x3f\xc0\x00\x00\xcb\x3f\xf1\x99\x99\x99\x99\x99\x9a\xda\x01\x2c".to_vec();
+    expected.extend(long.as_bytes());
+    expected.extend(b"\xcf\x00\x00\x00\x01\x00\x00\x00\x00\xca\xdf\x80\x00\x00");
+    assert_eq!(writer.finish().unwrap(), expected);
+    let mut writer = MsgpackWriter::new(vec![]);
+    writer.write_event(BorrowedEvent::StartMap).unwrap();
+    assert!(writer.write_event(BorrowedEvent::Null).is_err());
+    assert!(MsgpackWriter::new(vec![]).finish().is_err());
+}
+
+#[cfg(feature = "bson")]
+#[test]
+fn bson() {
+    use crate::bson::BsonReader;
+
+    fn document(elements: &[u8]) -> Vec<u8> {
+        let mut result = ((elements.len() + 5) as i32).to_le_bytes().to_vec();
+        result.extend(elements);
+        result.push(0);
+        result
+    }
+    fn element(kind: u8, key: &str, value: &[u8]) -> Vec<u8> {
+        let mut result = vec![kind];
+        result.extend(key.as_bytes());
+        result.push(0);
+        result.extend(value);
+        result
+    }
+    fn string(s: &str) -> Vec<u8> {
+        let mut result = ((s.len() + 1) as i32).to_le_bytes().to_vec();
+        result.extend(s.as_bytes());
+        result.push(0);
+        result
+    }
+    let decimal = |significand: u128, exponent: i64| (((exponent + 6176) as u128) << 113 | significand).to_le_bytes();
+    let value = |data: &[u8]| BsonReader::new(data).items("").next().unwrap().unwrap().to_string();
+
+    let array = document(&[element(0x10, "0", &7i32.to_le_bytes()), element(0x0a, "1", b""), element(0x12, "2", &(-1i64).to_le_bytes())].concat());
+    let data = document(&[
+        element(0x01, "double", &1.5f64.to_le_bytes()),
+        element(0x02, "string", &string("é")),
+        element(0x03, "document", &document(&element(0x08, "t", b"\x01"))),
+        element(0x04, "array", &array),
+        element(0x05, "binary", b"\x03\x00\x00\x00\x00\x01\x02\x03"),
+        element(0x07, "id", &[0xab; 12]),
+        element(0x09, "date", &(-1500i64).to_le_bytes()),
+        element(0x0b, "regex", b"^a\0i\0"),
+        element(0x0f, "code", &[&20i32.to_le_bytes()[..], &string("f()"), &document(&element(0x0a, "x", b""))].concat()),
+        element(0x11, "timestamp", &(1u64 << 32 | 2).to_le_bytes()),
+        element(0x13, "decimal", &decimal(15, -1)),
+        element(0xff, "min", b""),
+    ].concat());
+    assert_eq!(value(&data), concat!(
+        r#"{"array":[7,null,-1],"binary":"AQID","code":"f()","date":-1.5,"decimal":1.5,"document":{"t":true},"#,
+        r#""double":1.5,"id":"abababababababababababab","min":null,"regex":"/^a/i","string":"é","timestamp":4294967298}"#,
+    ));
+    let decimals = |significand, exponent| match BsonReader::new(&document(&element(0x13, "", &decimal(significand, exponent)))[..]).nth(2) {
+        Some(Ok(Event::RawNumber(s))) => s,
+        event => panic!("{:?}", event),
+    };
+    assert_eq!(decimals(0, 0), "0");
+    assert_eq!(decimals(1, 3), "1E+3");
+    assert_eq!(decimals(1234, -6), "0.001234");
+    assert_eq!(decimals(1, -7), "1E-7");
+    assert_eq!(decimals(12345, -2), "123.45");
+    let nan = document(&element(0x13, "", &(0x7cu128 << 120).to_le_bytes()));
+    assert!(matches!(BsonReader::new(&nan[..]).nth(2), Some(Ok(Event::Number(n))) if n.is_nan()));
+
+    let empty = document(b"");
+    assert_eq!(BsonReader::new_multi(&[&data[..], &empty, &empty].concat()[..]).items("").count(), 3);
+    assert_eq!(BsonReader::new_multi(&b""[..]).count(), 0);
+
+    let kind = |data: &[u8]| BsonReader::new(data).find_map(Result::err).unwrap().kind();
+    assert_eq!(kind(b""), ErrorKind::Incomplete);
+    assert_eq!(kind(&data[..data.len() - 1]), ErrorKind::Incomplete);
+    assert_eq!(kind(&[&empty[..], &empty].concat()), ErrorKind::AdditionalData);
+    assert_eq!(kind(&document(&element(0x14, "", b""))), ErrorKind::Unknown);
+    assert_eq!(kind(&document(&element(0x08, "", b"\x02"))), ErrorKind::Unknown);
+    assert_eq!(kind(&document(&element(0x02, "", b"\x02\x00\x00\x00\xc3\x28\x00"))), ErrorKind::Utf8);
+    assert_eq!(kind(&document(&element(0x02, "", b"\x02\x00\x00\x00ab"))), ErrorKind::Unterminated);
+    assert_eq!(kind(b"\x04\x00\x00\x00\x00"), ErrorKind::Custom);
+    // Lengths not matching the contents
+    assert_eq!(kind(&document(&element(0x03, "", b"\x06\x00\x00\x00\x00"))), ErrorKind::Custom);
+    assert_eq!(kind(&document(&element(0x03, "", b"\x05\x00\x00\x00\x0a\x00\x00"))), ErrorKind::Custom);
+    assert_eq!(kind(&document(&element(0x02, "", &(-1i32).to_le_bytes()))), ErrorKind::Custom);
+    let scope = [&10i32.to_le_bytes()[..], &string("x"), &0i32.to_le_bytes()].concat();
+    assert_eq!(kind(&document(&element(0x0f, "", &scope))), ErrorKind::Custom);
+    let mut reader = BsonReader::new(&data[..]);
+    reader.set_limits(Limits { max_depth: 1, ..Limits::default() });
+    assert_eq!(reader.find_map(Result::err).unwrap().kind(), ErrorKind::DepthLimit);
+    let mut reader = BsonReader::new(&data[..]);
+    reader.set_limits(Limits { max_string: 5, ..Limits::default() });
+    assert_eq!(reader.find_map(Result::err).unwrap().kind(), ErrorKind::StringLimit);
+}
+
+#[test]
+fn handler() {
+    #[derive(Default)]
+    struct Names {
+        names: Vec<String>,
+        in_name: bool,
+    }
+
+    impl Handler for Names {
+        fn key(&mut self, key: &str) -> ControlFlow<()> {
+            self.in_name = key == "name";
+            ControlFlow::Continue(())
+        }
+
+        fn string(&mut self, value: &str) -> ControlFlow<()> {
+            if self.in_name {
+                self.names.push(value.to_string());
+                self.in_name = false;
+            }
+            ControlFlow::Continue(())
+        }
+    }
+
+    let mut names = Names::default();
+    assert!(drive(File::open("people.json").unwrap(), &mut names).unwrap());
+    assert_eq!(names.names, vec!["John", "Steve"]);
+
+    struct First(Option<i64>);
+
+    impl Handler for First {
+        fn int(&mut self, value: i64) -> ControlFlow<()> {
+            self.0 = Some(value);
+            ControlFlow::Break(())
+        }
+    }
+
+    let mut first = First(None);
+    assert!(!drive(&b"[1, 2, {"[..], &mut first).unwrap());
+    assert_eq!(first.0, Some(1));
+}
+
+#[test]
+fn writer() {
+    let mut writer = Writer::new(vec![]);
+    for event in reference_events() {
+        writer.write_event(event.as_event()).unwrap();
+    }
+    let output = writer.finish().unwrap();
+    let events: Vec<_> = Parser::new(&output[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, reference_events());
+
+    let mut writer = Writer::new(vec![]);
+    for event in [Event::StartMap, Event::Key("a\"\n\u{1}".to_string()), Event::Number(1e100), Event::EndMap] {
+        writer.write_event(event.as_event()).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), br#"{"a\"\n\u0001":1e100}"#.to_vec());
+
+    let invalid: Vec<Vec<Event>> = vec![
+        vec![Event::Key("a".to_string())],
+        vec![Event::StartMap, Event::Null],
+        vec![Event::StartArray, Event::Key("a".to_string())],
+        vec![Event::StartArray, Event::EndMap],
+        vec![Event::Null, Event::Null],
+    ];
+    for events in invalid {
+        let mut writer = Writer::new(vec![]);
+        let result: Result<Vec<_>, _> = events.iter().map(|e| writer.write_event(e.as_event())).collect();
+        assert!(result.is_err(), "Accepted {:?}", events);
+    }
+    assert!(Writer::new(vec![]).finish().is_err());
+}
+
+#[test]
+fn minify() {
+    use std::io::Read;
+
+    let long = "x".repeat(10000);
+    let data = format!("{{\n  \"a\": [1.50, -0, 1e400],\n  \"b\": \"{}\\n\"\n}}\n", long);
+    let mut output = vec![];
+    let mut buf = [0; 7];
+    let mut minify = Minify::new(data.as_bytes());
+    loop {
+        let size = minify.read(&mut buf).unwrap();
+        if size == 0 {
+            break
+        }
+        output.extend_from_slice(&buf[..size]);
+    }
+    assert_eq!(output, format!(r#"{{"a":[1.50,-0,1e400],"b":"{}\n"}}"#, long).into_bytes());
+
+    let mut output = String::new();
+    Minify::from_parser(Parser::new_multi(&b"{ } \n [ 1 ,2 ]\n"[..])).read_to_string(&mut output).unwrap();
+    assert_eq!(output, "{}\n[1,2]");
+
+    let error = Minify::new(&b"[1, 2"[..]).read_to_end(&mut vec![]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn tee() {
+    let data = br#"[{"a": 1} , {"a": [2, 3]}]"#;
+    let mut tee = Parser::new(&data[..]).into_iter().tee(Writer::new(vec![]));
+    assert_eq!((&mut tee).items("item").count(), 2);
+    assert_eq!(tee.finish().unwrap(), br#"[{"a":1},{"a":[2,3]}]"#.to_vec());
+
+    let mut tee = Parser::new_multi(&b"1 [2] 3"[..]).into_iter().tee(Writer::new(vec![]));
+    assert_eq!((&mut tee).count(), 5);
+    assert_eq!(tee.finish().unwrap(), b"1\n[2]\n3".to_vec());
+
+    let events = vec![Event::StartArray, Event::EndMap, Event::Null];
+    let mut tee = events.into_iter().map(Ok).tee(Writer::new(vec![]));
+    assert!(matches!(tee.next(), Some(Ok(Event::StartArray))));
+    assert!(matches!(tee.next(), Some(Err(Error::UnexpectedEvent(..)))));
+    assert!(tee.next().is_none());
+}
+
+#[test]
+fn writer_formats() {
+    let write = |format: Format| {
+        let mut writer = Writer::with_format(vec![], format);
+        let f = File::open("people.json").unwrap();
+        for event in Parser::new(f).into_iter() {
+            writer.write_event(event.unwrap().as_event()).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    };
+    assert_eq!(write(Format::Compact), r#"[{"name":"John","friends":["Mary","Michael"]},{"name":"Steve","friends":["Diane","Jack"]}]"#);
+    assert_eq!(write(Format::ArrayLines), "[\n{\"name\":\"John\",\"friends\":[\n\"Mary\",\n\"Michael\"\n]},\n{\"name\":\"Steve\",\"friends\":[\n\"Diane\",\n\"Jack\"\n]}\n]");
+    assert_eq!(write(Format::Indented("  ".to_string())), r#"[
+  {
+    "name": "John",
+    "friends": [
+      "Mary",
+      "Michael"
+    ]
+  },
+  {
+    "name": "Steve",
+    "friends": [
+      "Diane",
+      "Jack"
+    ]
+  }
+]"#);
+
+    let mut writer = Writer::with_format(vec![], Format::Indented("  ".to_string()));
+    for event in [Event::StartMap, Event::Key("a".to_string()), Event::StartArray, Event::EndArray, Event::EndMap] {
+        writer.write_event(event.as_event()).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), b"{\n  \"a\": []\n}".to_vec());
+}
+
+#[test]
+fn raw_numbers() {
+    let data = b"[9007199254740993, -0.10000000000000000001, 1E+2]";
+    let mut parser = Parser::new(&data[..]);
+    parser.set_raw_numbers(true);
+    let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert_eq!(events[1..4], [
+        Event::RawNumber("9007199254740993".to_string()),
+        Event::RawNumber("-0.10000000000000000001".to_string()),
+        Event::RawNumber("1E+2".to_string()),
+    ]);
+
+    let mut writer = Writer::new(vec![]);
+    for event in &events {
+        writer.write_event(event.as_event()).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), b"[9007199254740993,-0.10000000000000000001,1E+2]".to_vec());
+
+    // Text outside of the JSON grammar isn't written
+    for text in ["0x10", "+1", ".5", "01", "1.", "1e", "1 2", ""] {
+        let result = Writer::new(vec![]).write_event(BorrowedEvent::RawNumber(text));
+        assert!(matches!(result, Err(Error::Number(..))), "{:?}", text);
+    }
+    assert!(Writer::new(vec![]).write_event(BorrowedEvent::RawNumber("NaN")).is_err());
+    let mut writer = Writer::new(vec![]);
+    writer.set_non_finite(true);
+    writer.write_event(BorrowedEvent::RawNumber("-Infinity")).unwrap();
+    assert_eq!(writer.finish().unwrap(), b"-Infinity".to_vec());
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_raw_numbers(true);
+    let result = parser.into_iter().items("item").next().unwrap().unwrap();
+    assert_eq!(result, Json::I64(9007199254740993));
+
+    // Malformed numbers from other streams fail to build
+    let events = vec![Event::StartArray, Event::RawNumber("1x".into()), Event::EndArray, Event::RawNumber("2".into())];
+    let mut items = events.into_iter().map(Ok).items("");
+    assert!(matches!(items.next(), Some(Err(Error::Number(NumberError::TrailingData, _)))));
+    assert_eq!(items.next().unwrap().unwrap(), Json::I64(2));
+}
+
+#[test]
+fn non_finite_numbers() {
+    let data = b"[NaN, Infinity, -Infinity, -1]";
+    let r = Parser::new(&data[..]).into_iter().nth(1).unwrap();
+    assert!(matches!(r, Err(Error::Unknown(..))));
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_non_finite(true);
+    let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert!(matches!(events[1], Event::Number(n) if n.is_nan()));
+    assert_eq!(events[2..], [Event::Number(f64::INFINITY), Event::Number(f64::NEG_INFINITY), Event::Int(-1), Event::EndArray]);
+
+    let mut writer = Writer::new(vec![]);
+    assert!(writer.write_event(BorrowedEvent::Number(f64::NAN)).is_err());
+    let mut writer = Writer::new(vec![]);
+    writer.set_non_finite(true);
+    for event in &events {
+        writer.write_event(event.as_event()).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), b"[NaN,Infinity,-Infinity,-1]".to_vec());
+
+    let mut parser = Parser::new(&b"[-Inf]"[..]);
+    parser.set_non_finite(true);
+    assert!(parser.into_iter().last().unwrap().is_err());
+}
+
+#[test]
+fn bad_numbers() {
+    let data: Vec<&'static [u8]> = vec![b"-", b"1e", b"1.5.5", b"1-2", b"1e+", b"."];
+    for d in data.iter() {
+        test_error(d, Error::Number(NumberError::NoInteger, Position::default()));
+    }
+
+    let data: Vec<(&'static [u8], NumberError)> = vec![
+        (b"+1", NumberError::PlusSign),
+        (b"-01", NumberError::LeadingZero),
+        (b".5", NumberError::NoInteger),
+        (b"-.5", NumberError::NoInteger),
+        (b"1.", NumberError::NoFraction),
+        (b"1.e5", NumberError::NoFraction),
+        (b"1E", NumberError::NoExponent),
+        (b"1-2", NumberError::TrailingData),
+    ];
+    for (d, expected) in data {
+        match Parser::new(d).into_iter().last().unwrap() {
+            Err(Error::Number(e, _)) => assert_eq!(e, expected, "{:?}", d),
+            r => panic!("Not a number error at {:?}: {:?}", d, r),
+        }
+    }
+
+    let data: Vec<&'static [u8]> = vec![b"-", b"1e", b"1.5.5", b"1-2", b"1e+", b"."];
+    for d in data.iter() {
+        let mut parser = Parser::new(*d);
+        parser.set_lenient_numbers(true);
+        assert!(matches!(parser.into_iter().last().unwrap(), Err(Error::Unknown(..))));
+    }
+    for d in [&b"+1"[..], b".5", b"01", b"1."] {
+        let mut parser = Parser::new(d);
+        parser.set_lenient_numbers(true);
+        assert!(parser.into_iter().last().unwrap().is_ok(), "{:?}", d);
+    }
+}
+
+#[test]
+fn integers() {
+    let data = b"[1, -1, 18446744073709551615, 18446744073709551616, 1.0, 1e0]";
+    let events: Vec<_> = Parser::new(&data[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events[1..7], [
+        Event::Int(1),
+        Event::Int(-1),
+        Event::Uint(18446744073709551615),
+        Event::Number(18446744073709551616f64),
+        Event::Number(1f64),
+        Event::Number(1f64),
+    ]);
+}
+
+#[test]
+fn depth_limit() {
+    let data = "[".repeat(200);
+    let r = Parser::new(data.as_bytes()).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::DepthLimit(Position { offset: 128, .. }))));
+
+    let data = br#"{"a": [{"b": 1}]}"#;
+    let mut parser = Parser::new(&data[..]);
+    parser.set_max_depth(3);
+    assert!(parser.into_iter().all(|r| r.is_ok()));
+    let mut parser = Parser::new(&data[..]);
+    parser.set_max_depth(2);
+    assert!(matches!(parser.into_iter().last(), Some(Err(Error::DepthLimit(..)))));
+}
+
+#[test]
+fn malformed_events() {
+    use crate::builder::Items;
+
+    let malformed = vec![
+        vec![Event::Key("a".to_string())],
+        vec![Event::StringEnd],
+        vec![Event::StartMap, Event::Int(1)],
+        vec![Event::StartMap, Event::Key("a".to_string()), Event::EndMap],
+        vec![Event::StartMap, Event::Key("a".to_string()), Event::Key("b".to_string())],
+        vec![Event::StartArray, Event::EndMap],
+        vec![Event::StartArray, Event::Key("a".to_string())],
+        vec![Event::StringChunk("a".to_string()), Event::Int(1)],
+        // Cut off inside containers
+        vec![Event::StartArray, Event::Int(1)],
+        vec![Event::StartMap, Event::Key("a".to_string()), Event::Int(1)],
+        vec![Event::StartArray, Event::StartMap],
+        vec![Event::StringChunk("a".to_string())],
+        vec![Event::StartArray, Event::StringChunk("a".to_string()), Event::StringChunk("b".to_string())],
+    ];
+    for events in malformed {
+        let result = Items::new(events.into_iter().map(Ok)).next();
+        assert!(matches!(result, Some(Err(Error::UnexpectedEvent(..)))));
+    }
+
+    let events = vec![Event::StartMap, Event::Key("a".to_string())];
+    let result = events.into_iter().map(Ok).kvitems("").next();
+    assert!(matches!(result, Some(Err(Error::UnexpectedEvent(..)))));
+}
+
+#[test]
+fn duplicate_keys() {
+    use crate::builder::DuplicateKeys;
+
+    let data = br#"{"a": 1, "b": [2], "a": [3], "a": 4}"#;
+    let build = |policy| {
+        let mut items = Parser::new(&data[..]).into_iter().items("");
+        items.set_duplicate_keys(policy);
+        items.next().unwrap()
+    };
+    assert_eq!(build(DuplicateKeys::First).unwrap().to_string(), r#"{"a":1,"b":[2]}"#);
+    assert_eq!(build(DuplicateKeys::Last).unwrap().to_string(), r#"{"a":4,"b":[2]}"#);
+    assert_eq!(build(DuplicateKeys::Collect).unwrap().to_string(), r#"{"a":[1,[3],4],"b":[2]}"#);
+    assert!(matches!(build(DuplicateKeys::Error), Err(Error::DuplicateKey(ref k, _)) if k == "a"));
+
+    let mut kvitems = Parser::new(&br#"{"x": {"a": 1, "a": 2}}"#[..]).into_iter().kvitems("");
+    kvitems.set_duplicate_keys(DuplicateKeys::Error);
+    assert!(matches!(kvitems.next(), Some(Err(Error::DuplicateKey(..)))));
+
+    // The failed value is skipped as a whole and the error points at it
+    let data = br#"[{"a":1,"a":2,"b":3},{"c":4}]"#;
+    let mut items = Parser::new(&data[..]).into_iter().items("item").with_location();
+    items.set_duplicate_keys(DuplicateKeys::Error);
+    assert!(matches!(items.next(), Some(Err(Error::DuplicateKey(ref k, Position { offset: 13, .. }))) if k == "a"));
+    assert_eq!(items.next().unwrap().unwrap().to_string(), r#"{"c":4}"#);
+    assert!(items.next().is_none());
+
+    #[cfg(feature = "serde")]
+    {
+        let mut values = Parser::new(&data[..]).into_iter().values("item").with_location();
+        values.set_duplicate_keys(DuplicateKeys::Error);
+        assert!(matches!(values.next(), Some(Err(Error::DuplicateKey(ref k, Position { offset: 13, .. }))) if k == "a"));
+        assert_eq!(values.next().unwrap().unwrap(), serde_json::json!({"c": 4}));
+        assert!(values.next().is_none());
+    }
+}
+
+#[test]
+fn reject_duplicate_keys() {
+    let data = br#"{"a": 1, "b": {"a": 2, "b": [{"a": 3}]}, "c": {"x": 1}, "a": 4}"#;
+    let mut parser = Parser::new(&data[..]);
+    parser.set_reject_duplicate_keys(true);
+    let e = parser.into_iter().last().unwrap().unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::DuplicateKey);
+    assert_eq!(e.offending_bytes(), Some(&b"a"[..]));
+    assert_eq!(e.position().offset, 56);
+    assert_eq!(e.path(), Some("a"));
+
+    // The path leads to the repeated member through arrays and skipped values
+    let data = br#"{"x": [1, {"a": [{}], "b": "s"}, {"y": {"k": 1, "k": 2}}]}"#;
+    let mut parser = Parser::new(&data[..]);
+    parser.set_reject_duplicate_keys(true);
+    for _ in 0..3 {
+        parser.next().unwrap().unwrap();
+    }
+    assert!(parser.skip_value().unwrap());
+    assert!(parser.skip_value().unwrap());
+    assert_eq!(parser.into_iter().last().unwrap().unwrap_err().path(), Some("x.2.y.k"));
+    let mut parser = Parser::new(&data[..]);
+    for _ in 0..3 {
+        parser.next().unwrap().unwrap();
+    }
+    parser.set_reject_duplicate_keys(true);
+    let e = parser.into_iter().last().unwrap().unwrap_err();
+    assert_eq!((e.kind(), e.path()), (ErrorKind::DuplicateKey, None));
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_reject_duplicate_keys(true);
+    for _ in 0..5 {
+        parser.next().unwrap().unwrap();
+    }
+    parser.skip_container().unwrap();
+    assert_eq!(parser.into_iter().last().unwrap().unwrap_err().kind(), ErrorKind::DuplicateKey);
+
+    let data = br#"[{"a": 1}, {"a": 2}]"#;
+    let parser = Parser::builder().reject_duplicate_keys(true).build(&data[..]);
+    assert!(parser.into_iter().all(|r| r.is_ok()));
+}
+
+#[test]
+fn ordered_items() {
+    use crate::builder::DuplicateKeys;
+    use crate::ordered::OrderedJson;
+
+    let data = br#"{"z": 1, "a": [true, null, {"y": "s", "b": 1.5}], "m": -2, "z": 3}"#;
+    let value = Parser::new(&data[..]).into_iter().ordered_items("").next().unwrap().unwrap();
+    assert_eq!(value.to_string(), r#"{"z":3,"a":[true,null,{"y":"s","b":1.5}],"m":-2}"#);
+    assert_eq!(value.find("m"), Some(&OrderedJson::I64(-2)));
+    assert_eq!(Json::from(value).to_string(), r#"{"a":[true,null,{"b":1.5,"y":"s"}],"m":-2,"z":3}"#);
+
+    let mut items = Parser::new(&data[..]).into_iter().ordered_items("");
+    items.set_duplicate_keys(DuplicateKeys::Collect);
+    assert_eq!(items.next().unwrap().unwrap().find("z"), Some(&OrderedJson::Array(vec![OrderedJson::I64(1), OrderedJson::I64(3)])));
+}
+
+#[test]
+fn deep_items() {
+    let depth = 50_000;
+    let data = format!("{}{}", r#"{"a": ["#.repeat(depth), "]}".repeat(depth));
+    let mut parser = Parser::new(data.as_bytes());
+    parser.set_max_depth(depth * 2);
+    let mut json = parser.into_iter().items("").next().unwrap().unwrap();
+    let mut levels = 0;
+    while let Json::Object(mut object) = json {
+        match object.remove("a") {
+            Some(Json::Array(mut array)) => json = array.pop().unwrap_or(Json::Null),
+            _ => unreachable!(),
+        }
+        levels += 1;
+    }
+    assert_eq!(levels, depth);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn deep_values() {
+    use serde_json::Value;
+
+    let depth = 50_000;
+    let data = format!("{}{}", r#"{"a": ["#.repeat(depth), "]}".repeat(depth));
+    let mut parser = Parser::new(data.as_bytes());
+    parser.set_max_depth(depth * 2);
+    let mut value = parser.into_iter().values("").next().unwrap().unwrap();
+    let mut levels = 0;
+    while let Value::Object(mut object) = value {
+        match object.remove("a") {
+            Some(Value::Array(mut array)) => value = array.pop().unwrap_or(Value::Null),
+            _ => unreachable!(),
+        }
+        levels += 1;
+    }
+    assert_eq!(levels, depth);
+
+    let data = format!("[{}1]", "/**/".repeat(200_000));
+    let mut parser = Parser::new(data.as_bytes());
+    parser.set_comments(Comments::Report);
+    assert_eq!(parser.into_iter().values("").next().unwrap().unwrap(), serde_json::json!([1]));
+}
+
+#[test]
+fn limits() {
+    let parse = |data: &[u8], limits: Limits| {
+        let mut parser = Parser::with_capacity(data, 4);
+        parser.set_limits(limits);
+        parser.into_iter().last().unwrap()
+    };
+    let data = br#"["abcdefgh", "\u0041bcdefgh", 1234567890, 1.5e10]"#;
+    assert!(parse(data, Limits::default()).is_ok());
+    assert!(parse(data, Limits { max_string: 8, max_number: 10, max_events: 6, ..Limits::default() }).is_ok());
+    assert!(matches!(parse(data, Limits { max_string: 7, ..Limits::default() }), Err(Error::StringLimit(Position { offset: 1, .. }))));
+    assert!(matches!(parse(data, Limits { max_number: 5, ..Limits::default() }), Err(Error::NumberLimit(..))));
+    assert!(matches!(parse(data, Limits { max_events: 5, ..Limits::default() }), Err(Error::EventLimit(..))));
+    assert!(matches!(parse(b"[[[]]]", Limits { max_depth: 2, ..Limits::default() }), Err(Error::DepthLimit(..))));
+
+    // Skipped values count as single events and nest as deep as parsed ones
+    let skip = |data: &[u8], limits: Limits| {
+        let mut parser = Parser::new(data);
+        parser.set_limits(limits);
+        parser.next().unwrap().unwrap();
+        parser.skip_value()
+    };
+    assert!(matches!(skip(b"[[[]], 1]", Limits { max_depth: 3, ..Limits::default() }), Ok(true)));
+    assert!(matches!(skip(b"[[[]], 1]", Limits { max_depth: 2, ..Limits::default() }), Err(Error::DepthLimit(Position { offset: 2, .. }))));
+    assert!(matches!(skip(b"[[[]], 1]", Limits { max_events: 2, ..Limits::default() }), Ok(true)));
+    assert!(matches!(skip(b"[[[]], 1]", Limits { max_events: 1, ..Limits::default() }), Err(Error::EventLimit(..))));
+
+    let mut parser = Parser::new(&b"[[[[]]], 1]"[..]);
+    parser.set_limits(Limits { max_depth: 3, ..Limits::default() });
+    parser.next();
+    parser.next();
+    assert!(matches!(parser.skip_container(), Err(Error::DepthLimit(Position { offset: 3, .. }))));
+}
+
+fn test_error(data: &[u8], error: Error) {
+    let r = Parser::new(Cursor::new(data.to_vec())).into_iter().last().unwrap();
+    assert!(r.is_err(), "Not an error: {:?}", r.ok().unwrap());
+    let rerror = r.err().unwrap();
+    if discriminant(&rerror) != discriminant(&error) {
+        panic!("Not <{:?}> at data: {:?}. Got {:?} instead.", error, data, rerror);
+    }
+}
+
+#[test]
+fn error_position() {
+    let data = b"{\n  \"key\": \"value\",\n  \"other\" 1\n}";
+    let r = Parser::new(Cursor::new(data.to_vec())).into_iter().last().unwrap();
+    let position = r.err().unwrap().position();
+    assert_eq!(position, Position { line: 3, column: 11, offset: 30 });
+}
+
+#[test]
+fn error_context() {
+    let data = b"{\n  \"key\": \"value\",\n  \"other\" 1\n}";
+    let mut parser = Parser::new(Cursor::new(data.to_vec()));
+    parser.set_error_context(true);
+    let error = parser.into_iter().last().unwrap().err().unwrap();
+    assert_eq!(error.position(), Position { line: 3, column: 11, offset: 30 });
+    assert!(matches!(error, Error::Context(ref e, _) if matches!(**e, Error::Unexpected(..))));
+    assert_eq!(error.diagnostic(), [
+        "Unexpected lexeme, expected `:` at line 3, column 11: …ey\": \"value\",   \"other\" 1 }",
+        "                                                                               ^",
+    ].join("\n"));
+
+    // The window ends on whole characters, and the caret goes by them
+    let data = "[\"ééééééééééé€\" x]";
+    let mut parser = Parser::new(data.as_bytes());
+    parser.set_error_context(true);
+    let error = parser.into_iter().last().unwrap().err().unwrap();
+    let snippet = match error {
+        Error::Context(_, ref snippet) => snippet.to_string(),
+        _ => unreachable!(),
+    };
+    assert_eq!(snippet, "…ééééééééé€\" x]\n             ^");
+}
+
+#[test]
+fn unterminated_string() {
+    test_error(br#"{"key": "value"#, Error::Unterminated(Position::default()));
+}
+
+#[test]
+fn additional_data() {
+    test_error(br#"{"key": "value"} stuff"#, Error::AdditionalData(Position::default()));
+}
+
+#[test]
+fn incomplete() {
+    let data: Vec<&'static [u8]> = vec![
+        b"",
+        b"[",
+        b"[1",
+        b"[1,",
+        b"{",
+        br#"{"key""#,
+        br#"{"key":"#,
+        br#"{"key": "value""#,
+        br#"{"key": "value","#,
+    ];
+    for d in data.iter() {
+        test_error(d, Error::Incomplete(Position::default()));
+    }
+
+    let data: Vec<&'static [u8]> = vec![b"[tr", br#"["\"#, br#"["\u12"#, br#"["val"#];
+    for d in data.iter() {
+        let r = Parser::new(Cursor::new(d.to_vec())).into_iter().last().unwrap();
+        assert!(r.err().unwrap().is_incomplete(), "Not incomplete: {:?}", d);
+    }
+    let r = Parser::new(Cursor::new(b"[1 2".to_vec())).into_iter().last().unwrap();
+    assert!(!r.err().unwrap().is_incomplete());
+}
+
+#[test]
+fn bad_escape() {
+    let data: Vec<&'static [u8]> = vec![
+        br#""\w""#,
+        br#""\u""#,
+        br#""\u0""#,
+        br#""\uXXXX""#,
+        br#""\uD83D\uXXXX""#,
+    ];
+    for d in data.iter() {
+        test_error(d, Error::Escape(vec![], Position::default()));
+    }
+}
+
+#[test]
+fn control_characters() {
+    let data = b"[\"ok\", \"tab\\t\", \"raw\ttab\"]";
+    let r = Parser::new(&data[..]).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::ControlCharacter(b'\t', Position { offset: 20, .. }))));
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_control_characters(true);
+    let result: Vec<_> = parser.into_iter().items("item").map(Result::unwrap).collect();
+    assert_eq!(result[2], Json::String("raw\ttab".to_string()));
+}
+
+#[test]
+fn surrogates() {
+    let data = br#"["\uD83D\uDE00", "a\ud834\udd1eb"]"#;
+    let events: Vec<_> = Parser::new(&data[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events[1..3], [Event::String("😀".to_string()), Event::String("a𝄞b".to_string())]);
+
+    let data: Vec<&'static [u8]> = vec![
+        br#""\uD800""#,
+        br#""\uD800x""#,
+        br#""\uD800\n""#,
+        br#""\uD800\u0041""#,
+        br#""\uDE00""#,
+    ];
+    for d in data.iter() {
+        test_error(d, Error::LoneSurrogate(0, Position::default()));
+    }
+}
+
+#[test]
+fn encodings() {
+    let text = std::fs::read_to_string("test.json").unwrap();
+    let utf16le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let utf16be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    let inputs = [
+        [&b"\xEF\xBB\xBF"[..], text.as_bytes()].concat(),
+        [&b"\xFF\xFE"[..], &utf16le].concat(),
+        [&b"\xFE\xFF"[..], &utf16be].concat(),
+        utf16le,
+        utf16be,
+    ];
+    for input in inputs.iter() {
+        for size in &[1, 3, 64] {
+            let events: Vec<_> = Parser::with_capacity(&input[..], *size).into_iter().map(Result::unwrap).collect();
+            assert_eq!(events, reference_events(), "buffer size {}", size);
+        }
+    }
+
+    let data = "[\"😀\"]".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+    let events: Vec<_> = Parser::new(&data[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events[1], Event::String("😀".to_string()));
+
+    // A lone surrogate and an odd byte at the end can't be decoded
+    let data = b"[\x00\"\x00\x00\xD8\"\x00]\x00";
+    assert!(matches!(Parser::new(&data[..]).into_iter().last(), Some(Err(Error::IO(..)))));
+    let data = b"[\x00]";
+    assert!(matches!(Parser::new(&data[..]).into_iter().last(), Some(Err(Error::IO(..)))));
+
+    // The first read is enough to recognize UTF-8, without waiting for more
+    struct Stalled(bool);
+    impl std::io::Read for Stalled {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            assert!(!self.0, "waited for more input");
+            self.0 = true;
+            buf[..2].copy_from_slice(b"[1");
+            Ok(2)
+        }
+    }
+    assert_eq!(Parser::new(Stalled(false)).next().unwrap().unwrap(), BorrowedEvent::StartArray);
+    let events: Vec<_> = Parser::new(Pieces { data: b"\xEF\xBB\xBF[1]".to_vec(), pos: 0, sizes: vec![1], read: 0 })
+        .into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::StartArray, Event::Int(1), Event::EndArray]);
+    let data = "[1]".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+    let events: Vec<_> = Parser::new(Pieces { data, pos: 0, sizes: vec![1], read: 0 }).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::StartArray, Event::Int(1), Event::EndArray]);
+}
+
+#[test]
+fn string_chunks() {
+    let data = r#"{"key": "abcdefghij", "long key": "abcd", "esc": "aé\n😀xyz"}"#;
+    let expected = vec![
+        Event::StartMap,
+            Event::Key("key".to_string()),
+            Event::StringChunk("abcd".to_string()),
+            Event::StringChunk("efgh".to_string()),
+            Event::StringChunk("ij".to_string()),
+            Event::StringEnd,
+            Event::Key("long key".to_string()),
+            Event::String("abcd".to_string()),
+            Event::Key("esc".to_string()),
+            Event::StringChunk("aé\n".to_string()),
+            Event::StringChunk("😀".to_string()),
+            Event::StringChunk("xyz".to_string()),
+            Event::StringEnd,
+        Event::EndMap,
+    ];
+    for size in &[1, 3, 64] {
+        let mut parser = Parser::with_capacity(data.as_bytes(), *size);
+        parser.set_string_chunk_size(4);
+        let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+        assert_eq!(events, expected, "buffer size {}", size);
+    }
+
+    let mut parser = Parser::new(data.as_bytes());
+    parser.set_string_chunk_size(4);
+    let result: Vec<_> = parser.into_iter().items("").map(Result::unwrap).collect();
+    assert_eq!(result, vec![Json::from_str(data).unwrap()]);
+
+    let mut parser = Parser::new(data.as_bytes());
+    parser.set_string_chunk_size(4);
+    let mut writer = Writer::new(vec![]);
+    while let Some(event) = parser.next() {
+        writer.write_event(event.unwrap()).unwrap();
+    }
+    let expected = r#"{"key":"abcdefghij","long key":"abcd","esc":"aé\n😀xyz"}"#;
+    assert_eq!(writer.finish().unwrap(), expected.as_bytes());
+
+    let mut parser = Parser::new(&b"[\"abcdefghij\", 1]"[..]);
+    parser.set_string_chunk_size(4);
+    parser.next();
+    parser.next();
+    parser.skip_container().unwrap();
+    assert!(parser.next().is_none());
+
+    let mut parser = Parser::new(&b"\"abcdefghij\""[..]);
+    parser.set_string_chunk_size(4);
+    parser.set_limits(Limits { max_string: 6, ..Limits::default() });
+    assert!(matches!(parser.into_iter().last(), Some(Err(Error::StringLimit(..)))));
+}
+
+#[test]
+fn string_reader() {
+    use std::io::Read;
+
+    let data = br#"{"name": "file", "data": "aGVslo=", "size": 7}"#;
+    for size in &[1, 3, 64] {
+        let mut parser = Parser::with_capacity(&data[..], *size);
+        while parser.next().unwrap().unwrap() != BorrowedEvent::Key("data") {}
+        let mut contents = String::new();
+        parser.read_string().unwrap().unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "aGVslo=", "buffer size {}", size);
+        assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Key("size"));
+        assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Int(7));
+    }
+
+    let mut parser = Parser::new(&br#"["abc", 1, "x\ny"]"#[..]);
+    parser.next();
+    let mut contents = vec![];
+    parser.read_string().unwrap().unwrap().read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"abc");
+    assert!(parser.read_string().unwrap().is_none());
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Int(1));
+    contents.clear();
+    parser.read_string().unwrap().unwrap().read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"x\ny");
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::EndArray);
+
+    // The rest of a partially read string comes as events
+    let mut parser = Parser::with_capacity(&b"[\"abcdefgh\"]"[..], 4);
+    parser.next();
+    let mut start = [0; 2];
+    parser.read_string().unwrap().unwrap().read_exact(&mut start).unwrap();
+    assert_eq!(&start, b"ab");
+    let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::StringChunk("cdefgh".to_string()), Event::StringEnd, Event::EndArray]);
+
+    let mut parser = Parser::new(&b"[\"a\\x\"]"[..]);
+    parser.next();
+    let error = parser.read_string().unwrap().unwrap().read_to_end(&mut vec![]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(parser.next().is_none());
+
+    // Strings read count against the limit of events
+    let mut parser = Parser::new(&br#"["a", "b"]"#[..]);
+    parser.set_limits(Limits { max_events: 2, ..Limits::default() });
+    parser.next();
+    parser.read_string().unwrap().unwrap().read_to_end(&mut vec![]).unwrap();
+    assert_eq!(parser.counters().events.string, 1);
+    assert!(matches!(parser.read_string(), Err(Error::EventLimit(..))));
+    assert!(parser.next().is_none());
+}
+
+#[test]
+fn lossy_utf8() {
+    let data = b"{\"k\xff\": [\"a\xffb\", \"\xe2\x82\", \"ok\"]}";
+    let r = Parser::new(&data[..]).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::Utf8(..))));
+
+    let expected = vec![
+        Event::StartMap,
+            Event::Key("k\u{FFFD}".to_string()),
+            Event::StartArray,
+                Event::String("a\u{FFFD}b".to_string()),
+                Event::String("\u{FFFD}".to_string()),
+                Event::String("ok".to_string()),
+            Event::EndArray,
+        Event::EndMap,
+    ];
+    for size in &[1, 3, 64] {
+        let mut parser = Parser::with_capacity(&data[..], *size);
+        parser.set_lossy_utf8(true);
+        let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+        assert_eq!(events, expected, "buffer size {}", size);
+    }
+
+    let data = b"[\"abc\xffdef\xe2\x82\", \"\xf0\x9f\x98\x80\xff\"]";
+    let mut parser = Parser::new(&data[..]);
+    parser.set_lossy_utf8(true);
+    parser.set_string_chunk_size(4);
+    let result: Vec<_> = parser.into_iter().items("item").map(Result::unwrap).collect();
+    assert_eq!(result, vec![
+        Json::String("abc\u{FFFD}def\u{FFFD}".to_string()),
+        Json::String("😀\u{FFFD}".to_string()),
+    ]);
+
+    // The limit applies to strings with the replacement characters
+    for chunk_size in [usize::MAX, 4] {
+        let mut parser = Parser::new(&b"[\"ab\xff\xff\"]"[..]);
+        parser.set_lossy_utf8(true);
+        parser.set_string_chunk_size(chunk_size);
+        parser.set_limits(Limits { max_string: 5, ..Limits::default() });
+        assert!(matches!(parser.into_iter().last(), Some(Err(Error::StringLimit(..)))), "{}", chunk_size);
+    }
+}
+
+#[test]
+fn byte_strings() {
+    let data = b"{\"k\": [\"a\xffb\", \"e\\n\"], \"k2\": \"x\"}";
+    let expected = vec![
+        Event::StartMap,
+            Event::Key("k".to_string()),
+            Event::StartArray,
+                Event::Bytes(b"a\xffb".to_vec()),
+                Event::Bytes(b"e\n".to_vec()),
+            Event::EndArray,
+            Event::Key("k2".to_string()),
+            Event::Bytes(b"x".to_vec()),
+        Event::EndMap,
+    ];
+    for size in &[1, 3, 64] {
+        let mut parser = Parser::with_capacity(&data[..], *size);
+        parser.set_byte_strings(true);
+        let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+        assert_eq!(events, expected, "buffer size {}", size);
+    }
+
+    let mut parser = Parser::new(&data[..]);
+    parser.set_byte_strings(true);
+    let mut writer = Writer::new(vec![]);
+    while let Some(event) = parser.next() {
+        writer.write_event(event.unwrap()).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), b"{\"k\":[\"a\xffb\",\"e\\n\"],\"k2\":\"x\"}".to_vec());
+
+    // Keys are still checked
+    let mut parser = Parser::new(&b"{\"\xff\": 1}"[..]);
+    parser.set_byte_strings(true);
+    assert!(matches!(parser.into_iter().last(), Some(Err(Error::Utf8(..)))));
+}
+
+#[test]
+fn slices() {
+    let data = std::fs::read("test.json").unwrap();
+    let events: Vec<_> = Parser::from_slice(&data).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, reference_events());
+
+    let data = b"\xEF\xBB\xBF{\"key\": \"value\", \"esc\": \"a\\nb\"}";
+    let mut parser = Parser::from_slice(data);
+    parser.next();
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Key("key"));
+    match parser.next().unwrap().unwrap() {
+        BorrowedEvent::String(s) => {
+            assert_eq!(s, "value");
+            assert!(data.as_ptr_range().contains(&s.as_ptr()));
+        }
+        event => panic!("{:?}", event),
+    }
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::Key("esc"));
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::String("a\nb"));
+    assert_eq!(parser.next().unwrap().unwrap(), BorrowedEvent::EndMap);
+    assert!(parser.next().is_none());
+
+    let r = Parser::from_slice(&b"[1, 2"[..]).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::Incomplete(Position { offset: 5, .. }))));
+}
+
+#[test]
+fn buf_reader() {
+    use std::io::{BufReader, Read};
+
+    for size in &[1, 3, 64, 256 * 1024] {
+        let f = BufReader::with_capacity(*size, File::open("test.json").unwrap());
+        let events: Vec<_> = Parser::from_buf_reader(f).into_iter().map(Result::unwrap).collect();
+        assert_eq!(events, reference_events(), "buffer size {}", size);
+    }
+
+    let data = b"\xEF\xBB\xBF[\"value\", x]";
+    let events: Vec<_> = Parser::from_buf_reader(BufReader::with_capacity(4, &data[..])).into_iter().collect();
+    assert_eq!(events[1].as_ref().unwrap(), &Event::String("value".to_string()));
+    assert!(matches!(events[2], Err(Error::Unknown(_, Position { offset: 10, .. }))));
+
+    // A byte order mark longer than the buffer or split between reads
+    let events: Vec<_> = Parser::from_buf_reader(BufReader::with_capacity(2, "\u{feff}1".as_bytes())).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::Int(1)]);
+    let pieces = Pieces { data: "\u{feff}[12]".as_bytes().to_vec(), pos: 0, sizes: vec![1], read: 0 };
+    let events: Vec<_> = Parser::from_buf_reader(BufReader::new(pieces)).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::StartArray, Event::Int(12), Event::EndArray]);
+    // And something which only starts like one
+    let events: Vec<_> = Parser::from_buf_reader(BufReader::with_capacity(1, &b"\xEF\xBB1"[..])).into_iter().collect();
+    assert!(matches!(events[..], [Err(Error::Unknown(ref bytes, _))] if bytes[0] == 0xEF));
+
+    // The rest of the input stays with the reader
+    for size in [1, 3, 64] {
+        let mut parser = Parser::from_buf_reader(BufReader::with_capacity(size, &b"{\"size\": 5}hello world"[..]));
+        parser.set_single_value(true);
+        while parser.next().unwrap().unwrap() != BorrowedEvent::EndMap {}
+        let (mut reader, mut rest) = parser.into_buf_reader();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"hello world", "buffer size {}", size);
+    }
+    let mut parser = Parser::from_buf_reader(BufReader::with_capacity(64, &b"\xEF\xBB\xBF[1] [2]"[..]));
+    parser.set_single_value(true);
+    while parser.next().is_some() {}
+    let (reader, rest) = parser.into_buf_reader();
+    assert_eq!((&rest[..], reader.buffer()), (&b" [2]"[..], &b""[..]));
+}
+
+#[test]
+fn parser_builder() {
+    let builder = Parser::builder().capacity(1).max_depth(2);
+    let events: Vec<_> = builder.build(&b"[[1]]"[..]).into_iter().map(Result::unwrap).collect();
+    assert_eq!(events.len(), 5);
+    let r = builder.build(&b"[[[1]]]"[..]).into_iter().last().unwrap();
+    assert!(matches!(r, Err(Error::DepthLimit(..))));
+
+    let data = b"1 // one\n[2,] 3";
+    let parser = Parser::builder().multiple(true).allow_comments(true).trailing_commas(true).build_slice(data);
+    let events: Vec<_> = parser.into_iter().map(Result::unwrap).collect();
+    assert_eq!(events, vec![Event::Int(1), Event::StartArray, Event::Int(2), Event::EndArray, Event::Int(3)]);
+
+    let parser = Parser::builder().raw_numbers(true).error_context(true).build(&b"[1.50, x]"[..]);
+    let events: Vec<_> = parser.into_iter().collect();
+    assert_eq!(events[1].as_ref().unwrap(), &Event::RawNumber("1.50".to_string()));
+    assert!(matches!(events[2], Err(Error::Context(..))));
 }
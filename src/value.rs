@@ -0,0 +1,272 @@
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::mem;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+use serde_json::map::Entry;
+
+use crate::builder::{EventIterator, DuplicateKeys, Located, join_chunks, locate, skip_containers, unexpected};
+use crate::de::Deserializer;
+use crate::errors::{Error, Position, Result};
+use crate::lexer::{Lexeme, raw_number_lexeme};
+use crate::parser::OwnedEvent;
+
+
+/// Builds `serde_json::Value`s out of an event stream, the serde
+/// counterpart of `builder::Items`. A value that can't be built is skipped
+/// to its end, so the next one comes after it.
+pub struct Values<E> where E: EventIterator {
+    events: E,
+    duplicate_keys: DuplicateKeys,
+    locate: fn(&E) -> Position,
+}
+
+impl<E> Values<E> where E: EventIterator {
+    pub fn new(events: E) -> Self {
+        Values {
+            events,
+            duplicate_keys: DuplicateKeys::Last,
+            locate: |_| Position::default(),
+        }
+    }
+
+    pub fn set_duplicate_keys(&mut self, value: DuplicateKeys) {
+        self.duplicate_keys = value;
+    }
+
+    /// Makes errors found while building values point at the event where
+    /// they were found, see `Items::with_location`.
+    pub fn with_location(mut self) -> Self where E: Located {
+        self.locate = E::location;
+        self
+    }
+
+    /// Locates an error found while building a value and skips the rest of
+    /// the value, `depth` containers deep.
+    fn fail(&mut self, e: Error, depth: usize) -> Error {
+        let e = locate(e, (self.locate)(&self.events));
+        skip_containers(&mut self.events, depth);
+        e
+    }
+}
+
+/// A container being built by `Values`.
+enum Frame {
+    Array(Vec<Value>),
+    Object {
+        object: Map<String, Value>,
+        /// The key awaiting its value.
+        key: Option<String>,
+        /// Keys whose values are gathered by `DuplicateKeys::Collect`.
+        collected: BTreeSet<String>,
+    },
+}
+
+impl Frame {
+    fn object() -> Frame {
+        Frame::Object {
+            object: Map::new(),
+            key: None,
+            collected: BTreeSet::new(),
+        }
+    }
+
+    /// Adds a value to the container following the duplicate keys policy.
+    fn push(&mut self, value: Value, duplicate_keys: DuplicateKeys) -> Result<()> {
+        let (object, key, collected) = match self {
+            Frame::Array(array) => {
+                array.push(value);
+                return Ok(())
+            }
+            Frame::Object { object, key, collected } => (object, key.take().ok_or_else(unexpected)?, collected),
+        };
+        let mut entry = match object.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                return Ok(())
+            }
+            Entry::Occupied(entry) => entry,
+        };
+        match duplicate_keys {
+            DuplicateKeys::First => (),
+            DuplicateKeys::Last => {
+                entry.insert(value);
+            }
+            DuplicateKeys::Error => return Err(Error::DuplicateKey(entry.key().clone(), Position::default())),
+            DuplicateKeys::Collect => {
+                if collected.insert(entry.key().clone()) {
+                    let first = mem::replace(entry.get_mut(), Value::Null);
+                    entry.insert(Value::Array(vec![first]));
+                }
+                if let Value::Array(values) = entry.get_mut() {
+                    values.push(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the container on its terminating event.
+    fn close(self, event: &OwnedEvent) -> Result<Value> {
+        match (self, event) {
+            (Frame::Array(array), OwnedEvent::EndArray) => Ok(Value::Array(array)),
+            (Frame::Object { object, key: None, .. }, OwnedEvent::EndMap) => Ok(Value::Object(object)),
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+/// Converts an event of a scalar value, joining a chunked string.
+fn scalar<E: EventIterator>(events: &mut E, event: OwnedEvent) -> Result<Value> {
+    Ok(match event {
+        OwnedEvent::Null => Value::Null,
+        OwnedEvent::Boolean(v) => Value::Bool(v),
+        OwnedEvent::String(v) => Value::String(v),
+        OwnedEvent::StringChunk(v) => Value::String(join_chunks(events, v)?),
+        OwnedEvent::Bytes(v) => Value::String(String::from_utf8_lossy(&v).into_owned()),
+        OwnedEvent::Number(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+        OwnedEvent::Int(v) => Value::from(v),
+        OwnedEvent::Uint(v) => Value::from(v),
+        OwnedEvent::RawNumber(v) => match v.parse() {
+            Ok(number) => Value::Number(number),
+            Err(_) => match raw_number_lexeme(&v).map_err(|e| Error::Number(e, Position::default()))? {
+                Lexeme::Int(v) => Value::from(v),
+                Lexeme::Uint(v) => Value::from(v),
+                Lexeme::Number(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+                _ => return Err(unexpected()),
+            },
+        },
+        _ => return Err(unexpected()),
+    })
+}
+
+impl<E> Iterator for Values<E> where E: EventIterator {
+    type Item = Result<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Nested containers are kept on a heap stack, as in `Items::next`,
+        // so that neither deep documents nor long runs of comments can
+        // overflow the call stack.
+        let mut stack: Vec<Frame> = vec![];
+        loop {
+            let event = match self.events.next() {
+                Some(result) => itry!(result),
+                None if stack.is_empty() => return None,
+                // The stream ending inside a container means it was cut off
+                None => return Some(Err(unexpected())),
+            };
+            let value = match event {
+                OwnedEvent::EndMap | OwnedEvent::EndArray => match stack.pop() {
+                    Some(frame) => frame.close(&event),
+                    None => return None,
+                },
+                OwnedEvent::Comment(_) => continue,
+                OwnedEvent::StartMap => {
+                    stack.push(Frame::object());
+                    continue
+                }
+                OwnedEvent::StartArray => {
+                    stack.push(Frame::Array(vec![]));
+                    continue
+                }
+                OwnedEvent::Key(k) => match stack.last_mut() {
+                    Some(Frame::Object { key: key @ None, .. }) => {
+                        *key = Some(k);
+                        continue
+                    }
+                    _ => Err(unexpected()),
+                },
+                event => scalar(&mut self.events, event),
+            };
+            let result = match (value, stack.last_mut()) {
+                (Ok(value), Some(frame)) => frame.push(value, self.duplicate_keys),
+                (Ok(value), None) => return Some(Ok(value)),
+                (Err(e), _) => Err(e),
+            };
+            if let Err(e) = result {
+                return Some(Err(self.fail(e, stack.len())))
+            }
+        }
+    }
+}
+
+/// Passes events through counting how deep in containers they are.
+struct Counted<'a, E> {
+    events: &'a mut E,
+    depth: usize,
+}
+
+impl<'a, E: EventIterator> Iterator for Counted<'a, E> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.events.next();
+        match result {
+            Some(Ok(OwnedEvent::StartMap)) | Some(Ok(OwnedEvent::StartArray)) => self.depth += 1,
+            Some(Ok(OwnedEvent::EndMap)) | Some(Ok(OwnedEvent::EndArray)) => self.depth = self.depth.saturating_sub(1),
+            _ => (),
+        }
+        result
+    }
+}
+
+/// Deserializes every value of an event stream into `T` right from the
+/// events, without building a `Value` first. A value failing to
+/// deserialize is skipped to its end, so the next one comes after it.
+pub struct ItemsAs<T, E> where E: EventIterator {
+    events: E,
+    locate: fn(&E) -> Position,
+    marker: PhantomData<T>,
+}
+
+impl<T, E> ItemsAs<T, E> where E: EventIterator {
+    /// Takes values of any event stream, whose errors, not knowing where
+    /// it is in the input, have the default position.
+    pub fn new(events: E) -> Self {
+        ItemsAs {
+            events,
+            locate: |_| Position::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Takes values of a stream whose errors point at the event where
+    /// they were found.
+    pub fn located(events: E) -> Self where E: Located {
+        ItemsAs {
+            events,
+            locate: E::location,
+            marker: PhantomData,
+        }
+    }
+
+}
+
+impl<T, E> Iterator for ItemsAs<T, E> where T: DeserializeOwned, E: EventIterator {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match itry!(self.events.next()?) {
+                OwnedEvent::EndMap | OwnedEvent::EndArray => return None,
+                OwnedEvent::Comment(_) => (),
+                event => {
+                    let event = match event {
+                        OwnedEvent::StringChunk(v) => itry!(join_chunks(&mut self.events, v).map(OwnedEvent::String)),
+                        event => event,
+                    };
+                    let depth = matches!(event, OwnedEvent::StartMap | OwnedEvent::StartArray) as usize;
+                    let mut counted = Counted { events: &mut self.events, depth };
+                    let result = T::deserialize(&mut Deserializer::with_peeked(&mut counted, event));
+                    let depth = counted.depth;
+                    return Some(result.map_err(|e| {
+                        let e = locate(e, (self.locate)(&self.events));
+                        skip_containers(&mut self.events, depth);
+                        e
+                    }))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,538 @@
+use std::io::{self, Read, Write};
+
+use crate::builder::EventIterator;
+use crate::errors::{Error, Position, Result};
+use crate::lexer::{check_number, ReadBuffer, BUFSIZE};
+use crate::parser::{Event, OwnedEvent, Parser};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Value,
+    ArrayOpen,
+    ObjectOpen,
+    Colon,
+    Comma,
+    Closed,
+    /// Within a string written in chunks.
+    String,
+}
+
+#[derive(PartialEq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// Layout of the produced JSON.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Format {
+    /// No whitespace at all.
+    #[default]
+    Compact,
+    /// Every array element and object member on its own line, indented
+    /// with the given string per nesting level.
+    Indented(String),
+    /// Every array element on its own line with no indentation, objects are
+    /// compact. Good for large arrays of records.
+    ArrayLines,
+}
+
+/// Serializes a stream of events into JSON, checking that the events form
+/// a single valid document.
+pub struct Writer<W: Write> {
+    out: W,
+    state: State,
+    stack: Vec<Container>,
+    format: Format,
+    non_finite: bool,
+    canonical: bool,
+    sort_keys: bool,
+    /// Members of the open objects written so far, with their values as
+    /// text, when they're sorted before being written out.
+    members: Vec<Vec<(String, Vec<u8>)>>,
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::IO(e, Position::default())
+}
+
+fn unexpected() -> Error {
+    Error::UnexpectedEvent(Position::default())
+}
+
+pub fn write_string<W: Write + ?Sized>(out: &mut W, value: &str) -> std::io::Result<()> {
+    out.write_all(b"\"")?;
+    write_escaped(out, value.as_bytes())?;
+    out.write_all(b"\"")
+}
+
+fn write_escaped<W: Write + ?Sized>(out: &mut W, bytes: &[u8]) -> std::io::Result<()> {
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0..=0x1f => b"",
+            _ => continue,
+        };
+        out.write_all(&bytes[start..i])?;
+        if escape.is_empty() {
+            write!(out, "\\u{:04x}", byte)?;
+        } else {
+            out.write_all(escape)?;
+        }
+        start = i + 1;
+    }
+    out.write_all(&bytes[start..])
+}
+
+/// Writes a float in the shortest form that reads back as the same value.
+/// Integral values keep their fractional part so they don't read back as
+/// integers.
+pub fn write_number<W: Write + ?Sized>(out: &mut W, value: f64) -> std::io::Result<()> {
+    write!(out, "{:?}", value)
+}
+
+/// Writes a float the way ECMAScript's `Number.prototype.toString` does,
+/// as RFC 8785 requires: the shortest digits that read back as the same
+/// value, in plain notation for exponents from -7 to 20 and in scientific
+/// one otherwise.
+fn write_canonical_number<W: Write + ?Sized>(out: &mut W, value: f64) -> std::io::Result<()> {
+    if value == 0.0 {
+        return out.write_all(b"0")
+    }
+    if value < 0.0 {
+        out.write_all(b"-")?;
+    }
+    // Rust gives the same shortest digits, as in "1.2345e-7"
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().unwrap_or(0) + 1;
+    if k <= n && n <= 21 {
+        write!(out, "{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        let (whole, fraction) = digits.split_at(n as usize);
+        write!(out, "{}.{}", whole, fraction)
+    } else if -6 < n && n <= 0 {
+        write!(out, "0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n > 0 { '+' } else { '-' };
+        let (first, rest) = digits.split_at(1);
+        let dot = if rest.is_empty() { "" } else { "." };
+        write!(out, "{}{}{}e{}{}", first, dot, rest, sign, (n - 1).abs())
+    }
+}
+
+impl<W: Write> Writer<W> {
+
+    pub fn new(out: W) -> Writer<W> {
+        Writer::with_format(out, Format::Compact)
+    }
+
+    pub fn with_format(out: W, format: Format) -> Writer<W> {
+        Writer {
+            out,
+            state: State::Value,
+            stack: vec![],
+            format,
+            non_finite: false,
+            canonical: false,
+            sort_keys: false,
+            members: vec![],
+        }
+    }
+
+    /// Makes a writer producing canonical JSON as defined by RFC 8785, for
+    /// hashing and signing: compact, with object members sorted by their
+    /// keys as UTF-16, and numbers formatted the way ECMAScript does it.
+    /// All numbers are treated as doubles, so integers beyond 2^53 may lose
+    /// precision. Non-finite numbers and invalid UTF-8 are rejected.
+    ///
+    /// Only objects are kept in memory until they are closed, so a large
+    /// top-level array of them is still written out as it goes.
+    ///
+    /// ```
+    /// use ijson::parser::Parser;
+    /// use ijson::writer::Writer;
+    ///
+    /// let mut writer = Writer::canonical(vec![]);
+    /// for event in Parser::from_slice(&br#"{"b": [1E3, 0.000001, 1e21], "a": "\u20ac"}"#[..]) {
+    ///     writer.write_event(event.unwrap().as_event()).unwrap();
+    /// }
+    /// let text = writer.finish().unwrap();
+    /// assert_eq!(text, r#"{"a":"€","b":[1000,0.000001,1e+21]}"#.as_bytes());
+    /// ```
+    pub fn canonical(out: W) -> Writer<W> {
+        let mut writer = Writer::new(out);
+        writer.canonical = true;
+        writer
+    }
+
+    /// Makes the writer output non-finite numbers as `NaN`, `Infinity` and
+    /// `-Infinity`, which aren't valid JSON but are accepted by many
+    /// parsers, instead of failing.
+    pub fn set_non_finite(&mut self, value: bool) {
+        self.non_finite = value;
+    }
+
+    /// Makes the writer output object members sorted by their keys, for
+    /// normalized files that diff well. Keys are compared as bytes, which
+    /// is the same as comparing their characters. Unlike `canonical`, it
+    /// changes nothing else.
+    ///
+    /// Only objects are buffered: the text of an object, with everything
+    /// nested in it, is kept in memory until it's closed, as any of its
+    /// members may come first. Arrays outside of objects are written out
+    /// as they go, so the memory taken is about the size of the largest
+    /// object not nested in another one, whatever the size of the document.
+    ///
+    /// ```
+    /// use ijson::parser::Parser;
+    /// use ijson::writer::{Format, Writer};
+    ///
+    /// let mut writer = Writer::with_format(vec![], Format::Indented("  ".to_string()));
+    /// writer.set_sort_keys(true);
+    /// for event in Parser::from_slice(&br#"{"b": 1.50, "a": {"d": [], "c": null}}"#[..]) {
+    ///     writer.write_event(event.unwrap().as_event()).unwrap();
+    /// }
+    /// let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+    /// assert_eq!(text, "{\n  \"a\": {\n    \"c\": null,\n    \"d\": []\n  },\n  \"b\": 1.5\n}");
+    /// ```
+    pub fn set_sort_keys(&mut self, value: bool) {
+        self.sort_keys = value;
+    }
+
+    fn sorting(&self) -> bool {
+        self.canonical || self.sort_keys
+    }
+
+    /// Where the text goes: the value of the member being written of the
+    /// innermost object if it's sorted, or the output itself.
+    fn output(&mut self) -> &mut dyn Write {
+        Self::target(&mut self.out, &mut self.members)
+    }
+
+    fn target<'a>(out: &'a mut W, members: &'a mut [Vec<(String, Vec<u8>)>]) -> &'a mut dyn Write {
+        match members.last_mut().and_then(|members| members.last_mut()) {
+            Some(member) => &mut member.1,
+            None => out,
+        }
+    }
+
+    fn break_line(&mut self, depth: usize, container: &Container) -> Result<()> {
+        match self.format {
+            Format::Compact => Ok(()),
+            Format::Indented(ref indent) => {
+                let indent = indent.as_bytes();
+                let out = Self::target(&mut self.out, &mut self.members);
+                out.write_all(b"\n").map_err(io_error)?;
+                for _ in 0..depth {
+                    out.write_all(indent).map_err(io_error)?;
+                }
+                Ok(())
+            }
+            Format::ArrayLines if *container == Container::Array => self.output().write_all(b"\n").map_err(io_error),
+            Format::ArrayLines => Ok(()),
+        }
+    }
+
+    fn begin_element(&mut self) -> Result<()> {
+        match self.stack.last() {
+            Some(Container::Array) => self.break_line(self.stack.len(), &Container::Array),
+            Some(Container::Object) => self.break_line(self.stack.len(), &Container::Object),
+            None => Ok(()),
+        }
+    }
+
+    fn after_value(&mut self) {
+        self.state = if self.stack.is_empty() { State::Closed } else { State::Comma };
+    }
+
+    fn write_value(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Null => self.output().write_all(b"null"),
+            Event::Boolean(b) => self.output().write_all(if b { b"true" } else { b"false" }),
+            Event::String(s) => write_string(self.output(), s),
+            Event::StringChunk(s) => {
+                self.state = State::String;
+                self.output().write_all(b"\"").map_err(io_error)?;
+                return write_escaped(self.output(), s.as_bytes()).map_err(io_error)
+            }
+            Event::Bytes(b) if self.canonical && std::str::from_utf8(b).is_err() => {
+                return Err(Error::Custom("Can't write invalid UTF-8 in canonical JSON".to_string(), Position::default()))
+            }
+            // Written as is, so invalid UTF-8 stays invalid
+            Event::Bytes(b) => {
+                self.output().write_all(b"\"")
+                    .and_then(|_| write_escaped(self.output(), b))
+                    .and_then(|_| self.output().write_all(b"\""))
+            }
+            Event::Number(n) if !n.is_finite() && (self.canonical || !self.non_finite) => {
+                return Err(Error::Custom(format!("Can't write non-finite number {}", n), Position::default()))
+            }
+            Event::Number(n) if n.is_nan() => self.output().write_all(b"NaN"),
+            Event::Number(n) if n.is_infinite() => {
+                self.output().write_all(if n > 0.0 { b"Infinity" } else { b"-Infinity" })
+            }
+            Event::Number(n) if self.canonical => write_canonical_number(self.output(), n),
+            Event::Int(n) if self.canonical => write_canonical_number(self.output(), n as f64),
+            Event::Uint(n) if self.canonical => write_canonical_number(self.output(), n as f64),
+            Event::RawNumber(s) if self.canonical => match s.parse::<f64>() {
+                Ok(n) if n.is_finite() => write_canonical_number(self.output(), n),
+                _ => return Err(Error::Custom(format!("Can't write number {} in canonical JSON", s), Position::default())),
+            },
+            Event::Number(n) => write_number(self.output(), n),
+            Event::Int(n) => write!(self.output(), "{}", n),
+            Event::Uint(n) => write!(self.output(), "{}", n),
+            // The text may come from a lenient parser or a caller, so only
+            // valid JSON numbers go as is
+            Event::RawNumber(s) => match check_number(s.as_bytes()) {
+                Ok(()) => self.output().write_all(s.as_bytes()),
+                Err(e) => match s.trim_start_matches(['+', '-']) {
+                    "NaN" | "Infinity" => return self.write_value(Event::Number(s.parse().unwrap())),
+                    _ => return Err(Error::Number(e, Position::default())),
+                },
+            },
+            Event::StartArray => {
+                self.stack.push(Container::Array);
+                self.state = State::ArrayOpen;
+                return self.output().write_all(b"[").map_err(io_error)
+            }
+            Event::StartMap => {
+                self.stack.push(Container::Object);
+                self.state = State::ObjectOpen;
+                self.output().write_all(b"{").map_err(io_error)?;
+                if self.sorting() {
+                    self.members.push(vec![]);
+                }
+                return Ok(())
+            }
+            Event::Key(..) | Event::EndArray | Event::EndMap | Event::Comment(..) | Event::StringEnd => {
+                return Err(unexpected())
+            }
+        }.map_err(io_error)?;
+        self.after_value();
+        Ok(())
+    }
+
+    fn write_key(&mut self, key: &str) -> Result<()> {
+        self.state = State::Colon;
+        // Written out along with the value once the object is closed
+        if let Some(members) = self.members.last_mut() {
+            members.push((key.to_string(), vec![]));
+            return Ok(())
+        }
+        self.begin_element()?;
+        self.write_name(key)
+    }
+
+    fn write_name(&mut self, key: &str) -> Result<()> {
+        write_string(self.output(), key).map_err(io_error)?;
+        let colon: &[u8] = if let Format::Indented(..) = self.format { b": " } else { b":" };
+        self.output().write_all(colon).map_err(io_error)
+    }
+
+    /// Writes out the members of the innermost object in order of their
+    /// keys.
+    fn write_members(&mut self) -> Result<()> {
+        let mut members = self.members.pop().unwrap_or_default();
+        if self.canonical {
+            members.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+        } else {
+            members.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        // Values are dropped as they're written, so that an object nested
+        // in another isn't held twice
+        for (i, (key, value)) in members.into_iter().enumerate() {
+            if i > 0 {
+                self.output().write_all(b",").map_err(io_error)?;
+            }
+            self.begin_element()?;
+            self.write_name(&key)?;
+            self.output().write_all(&value).map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    fn write_closing(&mut self, expected: Container) -> Result<()> {
+        if self.stack.last() != Some(&expected) {
+            return Err(unexpected())
+        }
+        if expected == Container::Object && self.sorting() {
+            self.write_members()?;
+        }
+        self.stack.pop();
+        if self.state == State::Comma {
+            self.break_line(self.stack.len(), &expected)?;
+        }
+        self.output().write_all(if expected == Container::Array { b"]" } else { b"}" }).map_err(io_error)?;
+        self.after_value();
+        Ok(())
+    }
+
+    /// Writes out a single event, failing if it is not valid in the current
+    /// position of the document.
+    pub fn write_event(&mut self, event: Event) -> Result<()> {
+        match (self.state, event) {
+            // JSON has no comments, so they are dropped
+            (_, Event::Comment(..)) => Ok(()),
+            (State::String, Event::StringChunk(s)) => write_escaped(self.output(), s.as_bytes()).map_err(io_error),
+            (State::String, Event::StringEnd) => {
+                self.output().write_all(b"\"").map_err(io_error)?;
+                self.after_value();
+                Ok(())
+            }
+            (State::String, _) => Err(unexpected()),
+            (State::Closed, _) => Err(unexpected()),
+            (State::Value, event) | (State::Colon, event) => self.write_value(event),
+            (State::ArrayOpen, Event::EndArray) |
+            (State::Comma, Event::EndArray) => self.write_closing(Container::Array),
+            (State::ObjectOpen, Event::EndMap) |
+            (State::Comma, Event::EndMap) => self.write_closing(Container::Object),
+            (State::ArrayOpen, event) => {
+                self.begin_element()?;
+                self.write_value(event)
+            }
+            (State::ObjectOpen, Event::Key(key)) => self.write_key(key),
+            (State::ObjectOpen, _) => Err(unexpected()),
+            (State::Comma, event) => {
+                let key = match event {
+                    Event::Key(key) if self.stack.last() == Some(&Container::Object) => Some(key),
+                    Event::Key(..) => return Err(unexpected()),
+                    _ if self.stack.last() == Some(&Container::Object) => return Err(unexpected()),
+                    _ => None,
+                };
+                if key.is_none() || !self.sorting() {
+                    self.output().write_all(b",").map_err(io_error)?;
+                }
+                match key {
+                    Some(key) => self.write_key(key),
+                    None => {
+                        self.begin_element()?;
+                        self.write_value(event)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as `write_event` but starts a new document on a new line after
+    /// a complete one, for streams of multiple top-level values.
+    fn write_separated(&mut self, event: Event) -> Result<()> {
+        if self.state == State::Closed && !matches!(event, Event::Comment(_)) {
+            self.output().write_all(b"\n").map_err(io_error)?;
+            self.state = State::Value;
+        }
+        self.write_event(event)
+    }
+
+    /// Checks that a complete document has been written, flushes the output
+    /// and returns it.
+    pub fn finish(mut self) -> Result<W> {
+        if self.state != State::Closed {
+            return Err(Error::Incomplete(Position::default()))
+        }
+        self.out.flush().map_err(io_error)?;
+        Ok(self.out)
+    }
+}
+
+/// Reads the compact form of the JSON coming from a reader, parsing and
+/// writing it on the fly, so that only a little of it is in memory at a
+/// time. Each of multiple top-level values goes on its own line.
+pub struct Minify<R: Read, B: ReadBuffer = Vec<u8>> {
+    parser: Parser<R, B>,
+    writer: Writer<Vec<u8>>,
+    /// Position of the output not yet read from `writer`.
+    pos: usize,
+}
+
+impl<R: Read> Minify<R> {
+    /// Creates a minifier keeping strings and numbers exactly as they are
+    /// in the input, with long strings passed on in pieces.
+    pub fn new(reader: R) -> Minify<R> {
+        let mut parser = Parser::new(reader);
+        parser.set_raw_numbers(true);
+        parser.set_string_chunk_size(BUFSIZE);
+        Minify::from_parser(parser)
+    }
+}
+
+impl<R: Read, B: ReadBuffer> Minify<R, B> {
+    /// Creates a minifier of the events of a configured parser, for example
+    /// one accepting JSON5 or multiple values.
+    pub fn from_parser(parser: Parser<R, B>) -> Minify<R, B> {
+        Minify {
+            parser,
+            writer: Writer::new(vec![]),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read, B: ReadBuffer> Read for Minify<R, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.writer.out.len() {
+            self.writer.out.clear();
+            self.pos = 0;
+            let event = match self.parser.next() {
+                None => return Ok(0),
+                Some(Ok(event)) => event,
+                Some(Err(Error::IO(e, _))) => return Err(e),
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::InvalidData, self.parser.with_context(e))),
+            };
+            self.writer.write_separated(event).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        let size = buf.len().min(self.writer.out.len() - self.pos);
+        buf[..size].copy_from_slice(&self.writer.out[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+/// Passes events through while writing them out, see `Builder::tee`.
+pub struct Tee<E: EventIterator, W: Write> {
+    events: E,
+    writer: Writer<W>,
+    failed: bool,
+}
+
+impl<E: EventIterator, W: Write> Tee<E, W> {
+    pub fn new(events: E, writer: Writer<W>) -> Tee<E, W> {
+        Tee {
+            events,
+            writer,
+            failed: false,
+        }
+    }
+
+    /// Checks that the events written so far form complete documents and
+    /// returns the output, see `Writer::finish`.
+    pub fn finish(self) -> Result<W> {
+        self.writer.finish()
+    }
+}
+
+impl<E: EventIterator, W: Write> Iterator for Tee<E, W> {
+    type Item = Result<OwnedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None
+        }
+        let event = itry!(self.events.next()?);
+        if let Err(e) = self.writer.write_separated(event.as_event()) {
+            self.failed = true;
+            return Some(Err(e))
+        }
+        Some(Ok(event))
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+
+/// Runs the tool with `input` on standard input, returning its exit code,
+/// standard output and standard error.
+fn ijson(args: &[&str], input: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ijson"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The tool may exit without reading all of it
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn usage() {
+    let (code, out, _) = ijson(&["--help"], "");
+    assert_eq!(code, 0);
+    assert!(out.starts_with("Usage: ijson"));
+    let (code, out, err) = ijson(&["unknown"], "");
+    assert_eq!(code, 2);
+    assert!(out.is_empty());
+    assert!(err.starts_with("Usage: ijson"));
+    assert_eq!(ijson(&["extract"], "").0, 2);
+    assert_eq!(ijson(&["--max-depth", "x", "minify"], "").0, 2);
+    assert_eq!(ijson(&["head", "--sort-keys"], "").0, 2);
+}
+
+#[test]
+fn validate() {
+    assert_eq!(ijson(&["validate"], "{\"a\": [1, 2]}"), (0, String::new(), String::new()));
+    assert_eq!(ijson(&["validate", "test.json", "people.json"], "").0, 0);
+
+    let (code, out, err) = ijson(&["validate"], "{\"a\": [1 2]}");
+    assert_eq!((code, &out[..]), (1, ""));
+    assert!(err.starts_with("<stdin>:1:10: Unexpected lexeme, expected `,` or `]`"), "{}", err);
+    let (code, _, err) = ijson(&["validate"], "[1,");
+    assert_eq!(code, 1);
+    assert!(err.starts_with("<stdin>:1:4: Incomplete document"), "{}", err);
+
+    // All files are checked, and each invalid one is reported
+    let (code, _, err) = ijson(&["validate", "missing.json", "test.json", "-"], "[}");
+    assert_eq!(code, 1);
+    assert!(err.starts_with("missing.json:"), "{}", err);
+    assert!(err.contains("\n<stdin>:1:2: "), "{}", err);
+    assert!(!err.contains("test.json"));
+
+    assert_eq!(ijson(&["validate"], "1 2").0, 1);
+    assert_eq!(ijson(&["--multi", "validate"], "1 2").0, 0);
+    assert_eq!(ijson(&["--max-depth", "2", "validate"], "[[[1]]]").0, 1);
+}
+
+#[test]
+fn extract() {
+    let input = r#"{"docs": [{"name": "a\nb", "meta": {"x": 1}}, {"name": "c", "meta": [true]}]}"#;
+    assert_eq!(ijson(&["extract", "docs.item.name"], input), (0, "a\nb\nc\n".to_string(), String::new()));
+    assert_eq!(ijson(&["extract", "--prefix", "docs.item.meta"], input).1, "{\"x\":1}\n[true]\n");
+    assert_eq!(ijson(&["extract", "missing"], input), (0, String::new(), String::new()));
+
+    let (code, out, _) = ijson(&["extract", "docs.item.name"], r#"{"docs": [{"name": "a"}, {"name": "b"#);
+    assert_eq!((code, &out[..]), (1, "a\n"));
+}
+
+#[test]
+fn split() {
+    let input = "[{\"a\": 1}, [2, 3], \"four\"]";
+    assert_eq!(ijson(&["split"], input), (0, "{\"a\":1}\n[2,3]\n\"four\"\n".to_string(), String::new()));
+    assert_eq!(ijson(&["split", "--prefix", "item.item"], input).1, "2\n3\n");
+
+    let dir = std::env::temp_dir().join(format!("ijson-split-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pattern = dir.join("part-{}.jsonl");
+    let (code, out, _) = ijson(&["split", "--lines", "2", "--output", pattern.to_str().unwrap()], input);
+    assert_eq!((code, &out[..]), (0, ""));
+    let part = |n| std::fs::read_to_string(dir.join(format!("part-{:05}.jsonl", n))).unwrap();
+    assert_eq!(part(0), "{\"a\":1}\n[2,3]\n");
+    assert_eq!(part(1), "\"four\"\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Lines need a pattern to go to
+    assert_eq!(ijson(&["split", "--lines", "2"], input).0, 2);
+    assert_eq!(ijson(&["split"], "[1, 2").0, 1);
+}
+
+#[test]
+fn head() {
+    let input = "[1, {\"a\": [2]}, 3, 4]";
+    assert_eq!(ijson(&["head", "-n", "2"], input), (0, "1\n{\"a\":[2]}\n".to_string(), String::new()));
+    assert_eq!(ijson(&["head"], input).1, "1\n{\"a\":[2]}\n3\n4\n");
+    // Input after the values isn't read, so it doesn't matter
+    assert_eq!(ijson(&["head", "-n", "1"], "[1, 2 garbage"), (0, "1\n".to_string(), String::new()));
+    assert_eq!(ijson(&["head", "-n", "x"], input).0, 2);
+}
+
+#[test]
+fn stats() {
+    let (code, out, _) = ijson(&["stats"], r#"[{"a": [1, {"b": "xyz"}], "c": null}, true]"#);
+    assert_eq!(code, 0);
+    assert_eq!(out, [
+        "objects: 2",
+        "arrays: 2",
+        "keys: 3",
+        "strings: 1",
+        "numbers: 1",
+        "booleans: 1",
+        "nulls: 1",
+        "top-level array elements: 2",
+        "max depth: 4",
+        "longest string: 3 bytes",
+        "paths:",
+        "  item: 2",
+        "  item.a: 1",
+        "  item.a.item: 2",
+        "  item.a.item.b: 1",
+        "  item.c: 1",
+        "",
+    ].join("\n"));
+    assert_eq!(ijson(&["stats"], "{\"a\" 1}").0, 1);
+}
+
+#[test]
+fn pretty() {
+    let input = r#"{"b": [1, {}], "a": "x"}"#;
+    assert_eq!(ijson(&["pretty"], input).1, "{\n  \"b\": [\n    1,\n    {}\n  ],\n  \"a\": \"x\"\n}\n");
+    assert_eq!(ijson(&["pretty", "--indent", "1"], "[1]").1, "[\n 1\n]\n");
+    assert_eq!(ijson(&["pretty", "--indent", "0", "--sort-keys"], input).1, "{\n\"a\": \"x\",\n\"b\": [\n1,\n{}\n]\n}\n");
+    // Numbers keep their text
+    assert_eq!(ijson(&["pretty"], "[1.50, 1e2]").1, "[\n  1.50,\n  1e2\n]\n");
+    assert_eq!(ijson(&["pretty"], "").1, "");
+    assert_eq!(ijson(&["pretty"], "[1,]").0, 1);
+}
+
+#[test]
+fn minify() {
+    assert_eq!(ijson(&["minify"], "{ \"a\" : [ 1 , 2 ] }\n"), (0, "{\"a\":[1,2]}\n".to_string(), String::new()));
+    assert_eq!(ijson(&["--multi", "minify"], "1 [2]").1, "1\n[2]\n");
+    assert_eq!(ijson(&["--repair", "minify"], "{\"a\": [1, \"b").1, "{\"a\":[1,\"b\"]}\n");
+    assert_eq!(ijson(&["minify"], "[1, nope]").0, 1);
+}
+
+#[test]
+fn csv() {
+    let input = r#"[{"name": "A, B", "n": 1.50, "tags": ["x"]}, {"n": null, "ok": true}]"#;
+    assert_eq!(ijson(&["csv"], input), (0, "name,n,tags,ok\n\"A, B\",1.50,\"[\"\"x\"\"]\",\n,,,true\n".to_string(), String::new()));
+    assert_eq!(ijson(&["csv", "--columns", "ok,name"], input).1, "ok,name\n,\"A, B\"\ntrue,\n");
+    assert_eq!(ijson(&["csv", "--prefix", "rows.item"], r#"{"rows": [{"a": 1}]}"#).1, "a\n1\n");
+    assert_eq!(ijson(&["csv"], "[]"), (0, String::new(), String::new()));
+
+    let (code, _, err) = ijson(&["csv"], "[1]");
+    assert_eq!(code, 1);
+    assert!(err.contains("Expected an object for a row"), "{}", err);
+    assert_eq!(ijson(&["csv", "--columns"], input).0, 2);
+}
+
+#[test]
+fn grep() {
+    let input = r#"[{"name": "John", "age": 30, "tags": ["admin", "dev"]}, {"name": "Mary", "age": 25}]"#;
+    let (code, out, _) = ijson(&["grep", "--where", "name=John"], input);
+    assert_eq!((code, &out[..]), (0, "{\"name\":\"John\",\"age\":30,\"tags\":[\"admin\",\"dev\"]}\n"));
+    assert_eq!(ijson(&["grep", "--where", "age=25"], input).1, "{\"name\":\"Mary\",\"age\":25}\n");
+    assert_eq!(ijson(&["grep", "--where", "tags.1=dev", "--where", "age=30", "--paths"], input).1, "0.tags.1=\"dev\"\n0.age=30\n");
+    assert_eq!(ijson(&["grep", "--contains", "dmi", "--paths"], input).1, "0.tags.0=\"admin\"\n");
+    assert_eq!(ijson(&["grep", "--where", "name=Nobody"], input), (0, String::new(), String::new()));
+    assert_eq!(ijson(&["grep", "--where", "name"], input).0, 2);
+    assert_eq!(ijson(&["grep", "--where", "name=John"], "[{\"name\": \"John\"}, {").0, 1);
+
+    // Numbers are compared as numbers
+    let input = r#"[{"n": 1.50}, {"n": 100}, {"n": "1.5"}]"#;
+    assert_eq!(ijson(&["grep", "--where", "n=1.5", "--paths"], input).1, "0.n=1.5\n2.n=\"1.5\"\n");
+    assert_eq!(ijson(&["grep", "--where", "n=1.50", "--paths"], input).1, "0.n=1.5\n");
+    assert_eq!(ijson(&["grep", "--where", "n=1e2", "--paths"], input).1, "1.n=100\n");
+
+    // Paths are written like prefixes
+    let input = r#"[{"a.b": 1, "tags": ["x", "y"], "item": 2}, {"tags": ["z"]}]"#;
+    assert_eq!(ijson(&["grep", "--where", "a\\.b=1", "--paths"], input).1, "0.a.b=1\n");
+    assert_eq!(ijson(&["grep", "--where", "tags.item=y", "--paths"], input).1, "0.tags.1=\"y\"\n");
+    assert_eq!(ijson(&["grep", "--where", "*=2", "--paths"], input).1, "0.item=2\n");
+    assert_eq!(ijson(&["grep", "--where", "/tags/0=z", "--paths"], input).1, "1.tags.0=\"z\"\n");
+}